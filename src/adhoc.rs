@@ -0,0 +1,309 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::usage_error;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::result::Result;
+use std::path::Path;
+use std::time::Duration;
+
+/// The command-line option for the time in seconds between the
+/// heartbeats.
+static INTERVAL_OPTION: &str = "--interval";
+
+/// The command-line option for the time in milliseconds to wait for
+/// a reply to a heartbeat.
+static TIMEOUT_OPTION: &str = "--timeout";
+
+/// The command-line option for the endpoint of the command.
+static ENDPOINT_OPTION: &str = "--endpoint";
+
+/// The command-line option for the way to check the health of the
+/// command.
+static CHECK_OPTION: &str = "--check";
+
+/// The default time in seconds between the heartbeats.
+static DEFAULT_INTERVAL: i64 = 5;
+
+/// The default time in milliseconds to wait for a reply to a
+/// heartbeat.
+static DEFAULT_TIMEOUT: i64 = 1000;
+
+/// The default number of restarts within [`DEFAULT_RETRY_INTERVAL`].
+static DEFAULT_MAX_RETRIES: i64 = 5;
+
+/// The default window in seconds over which the restarts count.
+static DEFAULT_RETRY_INTERVAL: i64 = 60;
+
+/// The default time in seconds to wait before a restart.
+static DEFAULT_RESTART_DELAY: i64 = 1;
+
+/// The way a [`Supervisor`](crate::Supervisor) of a command checks
+/// the health of the command.
+#[derive(Clone, Debug)]
+pub enum Check {
+    /// Only watches the process, and restarts it when it crashes.
+    Process,
+    /// Checks that a TCP connection to the endpoint, such as
+    /// `"tcp://127.0.0.1:8080"`, succeeds.
+    Tcp(String),
+    /// Sends the heartbeats of `Heartbeat2` to the endpoint over
+    /// ZeroMQ.
+    Zmq(String),
+    /// Waits for the command to send its liveness to the endpoint.
+    Passive(String),
+    /// Runs the command, such as a Nagios check script, which exits
+    /// with 0 if the target is healthy.
+    Exec(Vec<String>),
+}
+
+/// How many times a [`Supervisor`](crate::Supervisor) of a command
+/// restarts the command before it gives up.
+///
+/// The default is up to 5 restarts within a minute, a second apart.
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    max_retries: u32,
+    within: Duration,
+    delay: Duration,
+}
+
+impl RestartPolicy {
+    /// Creates a policy of up to `max_retries` restarts within the
+    /// window of time `within`, a second apart.  `max_retries` must be
+    /// at least 1.
+    pub fn new(max_retries: u32, within: Duration) -> Self {
+        RestartPolicy {
+            max_retries,
+            within,
+            delay: Duration::from_secs(DEFAULT_RESTART_DELAY as u64),
+        }
+    }
+
+    /// Waits for the `delay` before each restart.  The delay counts in
+    /// whole seconds.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::new(
+            DEFAULT_MAX_RETRIES as u32,
+            Duration::from_secs(DEFAULT_RETRY_INTERVAL as u64),
+        )
+    }
+}
+
+/// Supervises an ad-hoc command without a configuration file, as
+/// `heartbeat2 run -- COMMAND` does.
+///
+/// `AdHoc` makes `Heartbeat2` handy for quick local development and
+/// demos.  It fills in the configuration with the defaults that suit
+/// them: a heartbeat every 5 seconds with a timeout of a second, up
+/// to 5 restarts within a minute a second apart, and the exit code of
+/// `Heartbeat2` mirroring that of the command.  The command runs in
+/// the current directory, and its TARGET-ID is the upper-case name of
+/// its program, such as `:PYTHON3`.
+///
+/// Without an endpoint, `AdHoc` has no way to tell whether the command
+/// hangs, so it only restarts the command when it crashes.  With
+/// `--endpoint`, the heartbeat connects to the endpoint over TCP,
+/// unless `--check` selects another CHECK-TYPE, such as `zmq` for a
+/// target that answers the heartbeats of `Heartbeat2`.
+///
+/// A [`Supervisor`](crate::Supervisor) of a command embeds an `AdHoc`,
+/// and replaces the defaults with a [`Check`] and a [`RestartPolicy`].
+pub(crate) struct AdHoc {
+    command: Vec<String>,
+    interval: i64,
+    timeout: i64,
+    check: Check,
+    policy: RestartPolicy,
+}
+
+impl AdHoc {
+    /// Creates a new `AdHoc` of the `command` with the defaults.
+    pub(crate) fn new(command: Vec<String>) -> Self {
+        AdHoc {
+            command,
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+            check: Check::Process,
+            policy: RestartPolicy::default(),
+        }
+    }
+
+    /// Checks the health of the command with the `check`.
+    pub(crate) fn check(&mut self, check: Check) {
+        self.check = check;
+    }
+
+    /// Restarts the command as the `policy` says.
+    pub(crate) fn restart_policy(&mut self, policy: RestartPolicy) {
+        self.policy = policy;
+    }
+
+    /// Checks the health of the command every `interval`, waiting up
+    /// to `timeout` for each check.
+    pub(crate) fn heartbeat(&mut self, interval: Duration, timeout: Duration) {
+        self.interval = interval.as_secs().try_into().unwrap_or(i64::MAX);
+        self.timeout = timeout.as_millis().try_into().unwrap_or(i64::MAX);
+    }
+
+    /// Creates a new `AdHoc` from the command-line arguments `args`
+    /// after `run`.
+    ///
+    /// # Usage
+    ///
+    /// `heartbeat2 run [--interval SECONDS] [--timeout MILLISECONDS]
+    /// [--endpoint ENDPOINT] [--check TYPE] -- COMMAND [ARG ...]`
+    ///
+    /// The `--` may be left out if the program of the command doesn't
+    /// start with a `-`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a usage error if an option lacks its value or has an
+    /// invalid one, or if the command is missing.
+    pub(crate) fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut adhoc = AdHoc::new(vec![]);
+        let mut endpoint = None;
+        let mut check = None;
+        while let Some(arg) = args.next() {
+            if arg == INTERVAL_OPTION {
+                adhoc.interval = integer(&arg, args.next())?;
+            } else if arg == TIMEOUT_OPTION {
+                adhoc.timeout = integer(&arg, args.next())?;
+            } else if arg == ENDPOINT_OPTION {
+                endpoint = Some(value(&arg, args.next())?);
+            } else if arg == CHECK_OPTION {
+                check = Some(value(&arg, args.next())?);
+            } else if arg == "--" {
+                break;
+            } else if arg.starts_with('-') {
+                return Err(usage_error(&format!("unknown option {}", arg)));
+            } else {
+                adhoc.command.push(arg);
+                break;
+            }
+        }
+        adhoc.command.extend(args);
+        if adhoc.command.is_empty() {
+            return Err(usage_error("run requires a command"));
+        }
+        if let Some(endpoint) = endpoint {
+            adhoc.check = match check.as_deref().unwrap_or("tcp") {
+                "tcp" => Check::Tcp(endpoint),
+                "zmq" => Check::Zmq(endpoint),
+                "passive" => Check::Passive(endpoint),
+                check => {
+                    return Err(usage_error(&format!(
+                        "{} requires tcp, zmq or passive, not {}",
+                        CHECK_OPTION, check
+                    )))
+                }
+            };
+        }
+        Ok(adhoc)
+    }
+
+    /// Returns the configuration of the supervision of the command.
+    /// The init mode is on if `init` is.
+    ///
+    /// # Errors
+    ///
+    /// Returns a usage error if the command or the command of
+    /// [`Check::Exec`] is empty, or if the [`RestartPolicy`] allows no
+    /// retries.
+    pub(crate) fn config(&self, init: bool) -> Result<Config> {
+        if self.command.is_empty() {
+            return Err(usage_error("the command to supervise is empty"));
+        }
+        if self.policy.max_retries == 0 {
+            return Err(usage_error("the restart policy needs at least 1 retry"));
+        }
+        let mut config = Config::new();
+        let section = config.section_mut(section::HEARTBEAT);
+        let program = Path::new(&self.command[0])
+            .file_name()
+            .map_or_else(|| "TARGET".into(), |name| name.to_string_lossy());
+        section.set(
+            key::TARGET_ID,
+            Keyword::from(program.to_uppercase().replace(' ', "-")),
+        );
+        section.set_list(key::COMMAND, &self.command);
+        section.set_string(key::WORKING_DIRECTORY, ".");
+        section.set_integer(key::HEARTBEAT_INTERVAL, self.interval);
+        section.set_integer(key::HEARTBEAT_TIMEOUT, self.timeout);
+        section.set_integer(key::MAX_RETRIES, self.policy.max_retries.into());
+        section.set_integer(
+            key::RETRY_INTERVAL,
+            self.policy.within.as_secs().try_into()?,
+        );
+        section.set_integer(key::RESTART_DELAY, self.policy.delay.as_secs().try_into()?);
+        section.set(key::MIRROR_EXIT_STATUS, true);
+        if init {
+            section.set(key::INIT, true);
+        }
+        match &self.check {
+            // NOTE: The probe stands in for the heartbeat, which has
+            // nowhere to go.  The crashes still lead to restarts.
+            Check::Process => section.set_list(key::PROBE_COMMAND, &["true"]),
+            Check::Tcp(endpoint) => {
+                section.set_string(key::TARGET_ENDPOINT, endpoint);
+                section.set(key::CHECK_TYPE, kw![tcp]);
+            }
+            Check::Zmq(endpoint) => {
+                section.set_string(key::TARGET_ENDPOINT, endpoint);
+                section.set(key::CHECK_TYPE, kw![zmq]);
+            }
+            Check::Passive(endpoint) => {
+                section.set_string(key::TARGET_ENDPOINT, endpoint);
+                section.set(key::CHECK_TYPE, kw![passive]);
+            }
+            Check::Exec(command) => {
+                if command.is_empty() {
+                    return Err(usage_error("the check command is empty"));
+                }
+                section.set(key::CHECK_TYPE, kw![exec]);
+                section.set_list(key::CHECK_COMMAND, command);
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Returns the `value` of the command-line `option`.
+fn value(option: &str, value: Option<String>) -> Result<String> {
+    value.ok_or_else(|| usage_error(&format!("{} requires a value", option)))
+}
+
+/// Returns the `value` of the command-line `option` as a positive
+/// integer.
+fn integer(option: &str, value: Option<String>) -> Result<i64> {
+    self::value(option, value)?
+        .parse()
+        .ok()
+        .filter(|&value| value > 0)
+        .ok_or_else(|| usage_error(&format!("{} requires a positive integer", option)))
+}
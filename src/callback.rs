@@ -0,0 +1,291 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::notify::Webhooks;
+use crate::restart::RestartReason;
+use crate::result::Result;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use std::cell::RefCell;
+use std::future::Future;
+use std::process::ExitStatus;
+use std::rc::Rc;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+
+type Callback<T> = Box<dyn Fn(T) -> LocalBoxFuture<'static, ()>>;
+
+/// Holds the async callbacks of a host application that embeds
+/// `Heartbeat2`.
+///
+/// The callbacks let the host react to the supervision in-process,
+/// where it would otherwise have to run a hook.  A callback runs
+/// after the event, and the supervision doesn't wait for it.  The
+/// callbacks run one at a time in the order of the events.  In a
+/// fleet, the callbacks apply to every target.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use heartbeat2::callback::Callbacks;
+/// use heartbeat2::Supervisor;
+///
+/// let callbacks = Callbacks::new()
+///     .on_start(|pid| async move { println!("started PID {}", pid) })
+///     .on_give_up(|reason| async move { alert(&reason).await });
+/// Supervisor::new("heartbeat.cfg")
+///     .callbacks(callbacks)
+///     .run()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct Callbacks {
+    start: Vec<Callback<u32>>,
+    abort: Vec<Callback<Option<ExitStatus>>>,
+    timeout: Vec<Callback<()>>,
+    restart: Vec<Callback<String>>,
+    restart_pending: Vec<Callback<String>>,
+    give_up: Vec<Callback<String>>,
+    degraded: Vec<Callback<String>>,
+}
+
+impl Callbacks {
+    /// Creates a new `Callbacks` without any callback.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a callback on the start of the managed process.  The
+    /// callback receives the process ID.
+    pub fn on_start<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(u32) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.start.push(boxed(callback));
+        self
+    }
+
+    /// Registers a callback on the abort of the managed process.  The
+    /// callback receives the exit status, if the process has exited.
+    pub fn on_abort<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(Option<ExitStatus>) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.abort.push(boxed(callback));
+        self
+    }
+
+    /// Registers a callback on a heartbeat timeout, just before
+    /// `Heartbeat2` kills the managed process.
+    pub fn on_timeout<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.timeout.push(boxed(move |()| callback()));
+        self
+    }
+
+    /// Registers a callback on the decision to restart the managed
+    /// process.  The callback receives the reason, such as `crash`,
+    /// `heartbeat-timeout` or `manual`.
+    pub fn on_restart<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.restart.push(boxed(callback));
+        self
+    }
+
+    /// Registers a callback on a restart that waits for RESTART-HOURS
+    /// to begin.  The callback receives the time of the day the
+    /// restart is pending until, such as `06:00`.
+    pub fn on_restart_pending<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.restart_pending.push(boxed(callback));
+        self
+    }
+
+    /// Registers a callback on the decision to give up on the managed
+    /// process.  The callback receives the reason.
+    pub fn on_give_up<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.give_up.push(boxed(callback));
+        self
+    }
+
+    /// Registers a callback on the answer of the target that it is
+    /// degraded.  The callback receives the reason the target gave.
+    pub fn on_degraded<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(String) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.degraded.push(boxed(callback));
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start.is_empty()
+            && self.abort.is_empty()
+            && self.timeout.is_empty()
+            && self.restart.is_empty()
+            && self.restart_pending.is_empty()
+            && self.give_up.is_empty()
+            && self.degraded.is_empty()
+    }
+
+    /// Runs the callbacks of the `event` one after another.
+    async fn call(&self, event: Event) {
+        match event {
+            Event::Start(pid) => call_all(&self.start, pid).await,
+            Event::Abort(status) => call_all(&self.abort, status).await,
+            Event::Timeout => call_all(&self.timeout, ()).await,
+            Event::Restart(reason) => call_all(&self.restart, reason.to_string()).await,
+            Event::RestartPending(at) => call_all(&self.restart_pending, at).await,
+            Event::GiveUp(reason) => call_all(&self.give_up, reason).await,
+            Event::Degraded(reason) => call_all(&self.degraded, reason).await,
+        }
+    }
+}
+
+fn boxed<T, F, Fut>(callback: F) -> Callback<T>
+where
+    F: Fn(T) -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    Box::new(move |arg| callback(arg).boxed_local())
+}
+
+async fn call_all<T: Clone>(callbacks: &[Callback<T>], arg: T) {
+    for callback in callbacks {
+        callback(arg.clone()).await;
+    }
+}
+
+/// An event of the supervision with a callback.
+#[derive(Clone, Debug)]
+pub(crate) enum Event {
+    /// The managed process has started with the process ID.
+    Start(u32),
+    /// The managed process has aborted with the exit status, if any.
+    Abort(Option<ExitStatus>),
+    /// A heartbeat has timed out.
+    Timeout,
+    /// `Heartbeat2` restarts the managed process for the reason.
+    Restart(RestartReason),
+    /// The restart of the managed process is pending until the time
+    /// of the day, such as `06:00`.
+    RestartPending(String),
+    /// `Heartbeat2` gives up on the managed process for the reason.
+    GiveUp(String),
+    /// The target answered that it is degraded for the reason.
+    Degraded(String),
+}
+
+/// Queues the events of the supervision for the [`Callbacks`] and
+/// the [`Webhooks`].
+///
+/// Components of `Heartbeat2` notify the events without waiting for
+/// the callbacks.  `Notifier` runs the callbacks in a task of its
+/// own, so that a slow callback doesn't hold up the supervision.
+pub(crate) struct Notifier {
+    callbacks: Rc<Callbacks>,
+    webhooks: Option<Webhooks>,
+    pending: RefCell<Option<Event>>,
+    queue: UnboundedSender<Event>,
+    events: Mutex<UnboundedReceiver<Event>>,
+}
+
+impl Notifier {
+    /// Creates a new `Notifier` of the `callbacks`.
+    pub(crate) fn new(callbacks: Rc<Callbacks>) -> Self {
+        let (queue, events) = mpsc::unbounded_channel();
+        Notifier {
+            callbacks,
+            webhooks: None,
+            pending: RefCell::new(None),
+            queue,
+            events: Mutex::new(events),
+        }
+    }
+
+    /// Posts the events to the `webhooks` as well as to the
+    /// callbacks.
+    pub(crate) fn webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Tells whether any callback or webhook is registered.
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.callbacks.is_empty() || self.webhooks.is_some()
+    }
+
+    /// Queues the `event` for the callbacks.
+    pub(crate) fn notify(&self, event: Event) {
+        if self.is_enabled() {
+            // NOTE: The receiving end lives as long as the sender.
+            let _ = self.queue.send(event);
+        }
+    }
+
+    /// Runs the callbacks of the events as they arrive.  Returns only
+    /// on an error.
+    pub(crate) async fn run(&self) -> Result<()> {
+        let mut events = self.events.lock().await;
+        while let Some(event) = events.recv().await {
+            self.pending.replace(Some(event.clone()));
+            self.dispatch(event).await;
+            self.pending.take();
+        }
+        Ok(())
+    }
+
+    /// Runs the callbacks of the events still in the queue, such as
+    /// the event that ended the supervision.  An event whose callbacks
+    /// [`run`](Notifier::run) was cut off in the middle of runs again.
+    pub(crate) async fn flush(&self) {
+        let pending = self.pending.take();
+        if let Some(event) = pending {
+            self.dispatch(event).await;
+        }
+        let mut events = self.events.lock().await;
+        while let Ok(event) = events.try_recv() {
+            self.dispatch(event).await;
+        }
+    }
+
+    /// Posts the `event` to the webhooks, if any, and then runs its
+    /// callbacks.
+    async fn dispatch(&self, event: Event) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.post(&event).await;
+        }
+        self.callbacks.call(event).await;
+    }
+}
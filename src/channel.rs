@@ -0,0 +1,168 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::event::EventType;
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::time::{timeout, Duration};
+
+/// The time in seconds a component may wait to raise an event before
+/// `Heartbeat2` logs the state of the event queue.
+static STALL_WARNING_SECS: u64 = 10;
+
+/// Records the recent traffic through the event queue, so that a
+/// stalled component can tell what it is waiting for.
+#[derive(Default)]
+struct Traffic {
+    last_raised: RefCell<Option<String>>,
+    last_consumed: RefCell<Option<String>>,
+    waiting: RefCell<Vec<String>>,
+}
+
+impl Display for Traffic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |entry: &Option<String>| entry.clone().unwrap_or_else(|| "none".to_owned());
+        write!(
+            f,
+            "last raised: {}; last consumed: {}; waiting to raise: {}",
+            describe(&self.last_raised.borrow()),
+            describe(&self.last_consumed.borrow()),
+            if self.waiting.borrow().is_empty() {
+                "none".to_owned()
+            } else {
+                self.waiting.borrow().join(", ")
+            }
+        )
+    }
+}
+
+/// Creates the event queue with room for `size` events.
+///
+/// The queue works like a `tokio` `mpsc` channel, but it keeps track
+/// of the events that go through it.  A component that waits too long
+/// to raise an event logs a warning with the state of the queue, so
+/// that a logic error shows up in the log rather than as a silent
+/// hang.
+pub(crate) fn event_queue(size: usize, logger: Rc<dyn Logger>) -> (EventSender, EventReceiver) {
+    let (sender, receiver) = mpsc::channel(size);
+    let traffic = Rc::new(Traffic::default());
+    (
+        EventSender {
+            sender,
+            name: "unnamed",
+            traffic: Rc::clone(&traffic),
+            logger,
+        },
+        EventReceiver { receiver, traffic },
+    )
+}
+
+/// The sending end of the event queue.
+///
+/// Each component raising events holds an `EventSender` with its own
+/// name, which shows up in the diagnostics of the event queue.
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    sender: mpsc::Sender<EventType>,
+    name: &'static str,
+    traffic: Rc<Traffic>,
+    logger: Rc<dyn Logger>,
+}
+
+impl EventSender {
+    /// Returns a copy of the `EventSender` for the component by the
+    /// `name`.
+    pub(crate) fn named(&self, name: &'static str) -> Self {
+        EventSender {
+            name,
+            ..self.clone()
+        }
+    }
+
+    /// Raises the `event`, waiting for room in the event queue if
+    /// necessary.  Logs a warning every [`STALL_WARNING_SECS`]
+    /// seconds spent waiting.
+    pub(crate) async fn send(&self, event: EventType) -> Result<()> {
+        let entry = format!("[{:?}] by {}", event, self.name);
+        let bound = Duration::from_secs(STALL_WARNING_SECS);
+        let mut waited = Duration::ZERO;
+        let permit = loop {
+            match timeout(bound, self.sender.reserve()).await {
+                Ok(permit) => break permit,
+                Err(_elapsed) => {
+                    waited += bound;
+                    if waited == bound {
+                        self.traffic.waiting.borrow_mut().push(entry.clone());
+                    }
+                    self.logger.log_coded(
+                        &message::CH001,
+                        &format!(
+                            "{} has waited {}s to raise an event; {}",
+                            self.name,
+                            waited.as_secs(),
+                            self.traffic
+                        ),
+                    );
+                }
+            }
+        };
+        self.traffic
+            .waiting
+            .borrow_mut()
+            .retain(|other| *other != entry);
+        let permit = permit?;
+        permit.send(event);
+        self.traffic.last_raised.replace(Some(entry));
+        Ok(())
+    }
+}
+
+/// The receiving end of the event queue.
+pub(crate) struct EventReceiver {
+    receiver: mpsc::Receiver<EventType>,
+    traffic: Rc<Traffic>,
+}
+
+impl EventReceiver {
+    /// Receives the next event, waiting for one if the queue is
+    /// empty.  Returns `None` once the queue is closed.
+    pub(crate) async fn recv(&mut self) -> Option<EventType> {
+        let event = self.receiver.recv().await;
+        self.consumed(&event);
+        event
+    }
+
+    /// Receives the next event if there is one.
+    pub(crate) fn try_recv(&mut self) -> std::result::Result<EventType, TryRecvError> {
+        let event = self.receiver.try_recv();
+        self.consumed(&event.as_ref().ok());
+        event
+    }
+
+    fn consumed<E: fmt::Debug>(&self, event: &Option<E>) {
+        if let Some(event) = event {
+            self.traffic
+                .last_consumed
+                .replace(Some(format!("[{:?}]", event)));
+        }
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{section, Config};
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::kw;
+use crate::protocol::Message;
+use crate::result::Result;
+
+/// Encodes the responses on a control endpoint, and the reports
+/// `Heartbeat2` writes.
+///
+/// The responses are [`Expression`]s, and a `Codec` turns them into
+/// the text that goes on the wire.  [`Sexp`] speaks the S-expression
+/// syntax of the configuration file for Lisp services, and [`Json`]
+/// speaks JSON for dashboards and the like.
+pub(crate) trait Codec {
+    /// Encodes the `expression` for the wire.
+    fn encode(&self, expression: &Expression) -> String;
+
+    /// Returns the extension of a file in the format, such as
+    /// `"json"`.
+    fn extension(&self) -> &'static str;
+}
+
+/// Encodes the responses as S-expressions, such as `(:state :running
+/// :pid 1234)`.
+pub(crate) struct Sexp;
+
+impl Codec for Sexp {
+    fn encode(&self, expression: &Expression) -> String {
+        expression.to_string()
+    }
+
+    fn extension(&self) -> &'static str {
+        "sexp"
+    }
+}
+
+/// Encodes the responses as JSON, such as `{"state":"running",
+/// "pid":1234}`.  See [`Expression::to_json`] for the mapping.
+pub(crate) struct Json;
+
+impl Codec for Json {
+    fn encode(&self, expression: &Expression) -> String {
+        expression.to_json()
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+}
+
+/// Returns the `Codec` the configuration item `key` selects, such as
+/// CONTROL-FORMAT.  The item is `:sexp` or `:json`, and the default is
+/// `:sexp`.
+///
+/// # Errors
+///
+/// Returns a configuration error if the item names an unknown format.
+pub(crate) fn from_config(config: &Config, key: &str) -> Result<&'static dyn Codec> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.has_key(key) {
+        return Ok(&Sexp);
+    }
+    let format = section.keyword(key)?;
+    if *format == kw![sexp] {
+        Ok(&Sexp)
+    } else if *format == kw![json] {
+        Ok(&Json)
+    } else {
+        Err(config_format_error(&format!(
+            "unknown {} [{}]",
+            key, format
+        )))
+    }
+}
+
+/// Returns the `Codec` the `message` of a request asks for, such as
+/// `JSON`, if any.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) fn requested(message: Option<&Message>) -> Option<&'static dyn Codec> {
+    match message {
+        Some(message) if *message == kw![sexp] => Some(&Sexp),
+        Some(message) if *message == kw![json] => Some(&Json),
+        _ => None,
+    }
+}
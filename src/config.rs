@@ -34,7 +34,7 @@ use std::collections::HashMap;
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::Config;
 ///
 /// let mut config = Config::new();
@@ -67,7 +67,7 @@ impl Config {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Config;
     ///
     /// let config = Config::new();
@@ -92,7 +92,7 @@ impl Config {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Config;
     ///
     /// let config = Config::new();
@@ -125,7 +125,7 @@ impl Config {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Config;
     ///
     /// let mut config = Config::new();
@@ -133,8 +133,8 @@ impl Config {
     /// let section = config.section_mut("database");
     ///
     /// // Set configuration options within the section
-    /// section.set_key_value("host", "localhost");
-    /// section.set_key_value("port", "5432");
+    /// section.set_string("host", "localhost");
+    /// section.set_integer("port", 5432);
     /// ```
     pub(crate) fn section_mut(&mut self, name: &str) -> &mut Section {
         self.0.entry(name.to_owned()).or_insert_with(Section::new)
@@ -16,26 +16,338 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+/// The key name for the ADAPTIVE-INTERVAL configuration item.
+pub(crate) static ADAPTIVE_INTERVAL: &str = "ADAPTIVE-INTERVAL";
+
+/// The key name for the ADVERTISE-ENDPOINT configuration item.
+pub(crate) static ADVERTISE_ENDPOINT: &str = "ADVERTISE-ENDPOINT";
+
+/// The key name for the ADVERTISE-WHEN-HEALTHY configuration item.
+pub(crate) static ADVERTISE_WHEN_HEALTHY: &str = "ADVERTISE-WHEN-HEALTHY";
+
+/// The key name for the BASE-DIRECTORY configuration item.
+pub(crate) static BASE_DIRECTORY: &str = "BASE-DIRECTORY";
+
+/// The key name for the BEAT-LOG-MAX-SIZE configuration item.
+pub(crate) static BEAT_LOG_MAX_SIZE: &str = "BEAT-LOG-MAX-SIZE";
+
+/// The key name for the BEAT-LOG-PATH configuration item.
+pub(crate) static BEAT_LOG_PATH: &str = "BEAT-LOG-PATH";
+
+/// The key name for the BUSY-TIMEOUT configuration item.
+pub(crate) static BUSY_TIMEOUT: &str = "BUSY-TIMEOUT";
+
+/// The key name for the CAPTURE-OUTPUT configuration item.
+pub(crate) static CAPTURE_OUTPUT: &str = "CAPTURE-OUTPUT";
+
+/// The key name for the CHECK-COMMAND configuration item.
+pub(crate) static CHECK_COMMAND: &str = "CHECK-COMMAND";
+
+/// The key name for the CHECK-TYPE configuration item.
+pub(crate) static CHECK_TYPE: &str = "CHECK-TYPE";
+
+/// The key name for the CLEAN-ENVIRONMENT configuration item.
+pub(crate) static CLEAN_ENVIRONMENT: &str = "CLEAN-ENVIRONMENT";
+
+/// The key name for the CLOCK-JUMP-THRESHOLD configuration item.
+pub(crate) static CLOCK_JUMP_THRESHOLD: &str = "CLOCK-JUMP-THRESHOLD";
+
 /// The key name for the COMMAND configuration item.
 pub(crate) static COMMAND: &str = "COMMAND";
 
 /// The key name for the COMMS-TIMEOUT configuration item.
+#[cfg_attr(not(any(feature = "http", feature = "sup")), allow(dead_code))]
 pub(crate) static COMMS_TIMEOUT: &str = "COMMS-TIMEOUT";
 
+/// The key name for the CONTROL-ENDPOINT configuration item.
+pub(crate) static CONTROL_ENDPOINT: &str = "CONTROL-ENDPOINT";
+
+/// The key name for the CONTROL-FORMAT configuration item.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) static CONTROL_FORMAT: &str = "CONTROL-FORMAT";
+
+/// The key name for the CRASH-REPORT-DIRECTORY configuration item.
+pub(crate) static CRASH_REPORT_DIRECTORY: &str = "CRASH-REPORT-DIRECTORY";
+
+/// The key name for the CRASH-REPORT-FORMAT configuration item.
+pub(crate) static CRASH_REPORT_FORMAT: &str = "CRASH-REPORT-FORMAT";
+
+/// The key name for the CRASH-REPORT-LINES configuration item.
+pub(crate) static CRASH_REPORT_LINES: &str = "CRASH-REPORT-LINES";
+
+/// The key name for the DEREGISTER-COMMAND configuration item.
+pub(crate) static DEREGISTER_COMMAND: &str = "DEREGISTER-COMMAND";
+
+/// The key name for the DIAGNOSTIC-COMMAND configuration item.
+pub(crate) static DIAGNOSTIC_COMMAND: &str = "DIAGNOSTIC-COMMAND";
+
+/// The key name for the DIAGNOSTIC-SIGNAL configuration item.
+pub(crate) static DIAGNOSTIC_SIGNAL: &str = "DIAGNOSTIC-SIGNAL";
+
+/// The key name for the DIAGNOSTIC-WAIT configuration item.
+pub(crate) static DIAGNOSTIC_WAIT: &str = "DIAGNOSTIC-WAIT";
+
+/// The key name for the DIRECTORY-MODE configuration item.
+pub(crate) static DIRECTORY_MODE: &str = "DIRECTORY-MODE";
+
 /// The key name for the ENDPOINT configuration item.
+#[cfg_attr(not(feature = "sup"), allow(dead_code))]
 pub(crate) static ENDPOINT: &str = "ENDPOINT";
 
+/// The key name for the ENVIRONMENT configuration item.
+pub(crate) static ENVIRONMENT: &str = "ENVIRONMENT";
+
+/// The key name for the ESCALATION-COMMAND configuration item.
+pub(crate) static ESCALATION_COMMAND: &str = "ESCALATION-COMMAND";
+
+/// The key name for the EXIT-TIMEOUT configuration item.
+pub(crate) static EXIT_TIMEOUT: &str = "EXIT-TIMEOUT";
+
+/// The key name for the GROUP configuration item.
+pub(crate) static GROUP: &str = "GROUP";
+
+/// The key name for the HEALTH-MARKER configuration item.
+pub(crate) static HEALTH_MARKER: &str = "HEALTH-MARKER";
+
 /// The key name for the HEARTBEAT-INTERVAL configuration item.
 pub(crate) static HEARTBEAT_INTERVAL: &str = "HEARTBEAT-INTERVAL";
 
+/// The key name for the HEARTBEAT-MAX-INTERVAL configuration item.
+pub(crate) static HEARTBEAT_MAX_INTERVAL: &str = "HEARTBEAT-MAX-INTERVAL";
+
+/// The key name for the HEARTBEAT-MIN-INTERVAL configuration item.
+pub(crate) static HEARTBEAT_MIN_INTERVAL: &str = "HEARTBEAT-MIN-INTERVAL";
+
+/// The key name for the HEARTBEAT-RATIO-WINDOW configuration item.
+pub(crate) static HEARTBEAT_RATIO_WINDOW: &str = "HEARTBEAT-RATIO-WINDOW";
+
+/// The key name for the HEARTBEAT-TIMEOUT configuration item.
+pub(crate) static HEARTBEAT_TIMEOUT: &str = "HEARTBEAT-TIMEOUT";
+
+/// The key name for the HEARTBEAT-RETRIES configuration item.
+pub(crate) static HEARTBEAT_RETRIES: &str = "HEARTBEAT-RETRIES";
+
+/// The key name for the HEARTBEAT-RETRY-SPACING configuration item.
+pub(crate) static HEARTBEAT_RETRY_SPACING: &str = "HEARTBEAT-RETRY-SPACING";
+
+/// The key name for the HEARTBEAT-SEQUENCE configuration item.
+pub(crate) static HEARTBEAT_SEQUENCE: &str = "HEARTBEAT-SEQUENCE";
+
+/// The key name for the HEARTBEAT-SUCCESS-RATIO configuration item.
+pub(crate) static HEARTBEAT_SUCCESS_RATIO: &str = "HEARTBEAT-SUCCESS-RATIO";
+
+/// The key name for the HISTORY-DIRECTORY configuration item.
+pub(crate) static HISTORY_DIRECTORY: &str = "HISTORY-DIRECTORY";
+
+/// The key name for the HISTORY-INTERVAL configuration item.
+pub(crate) static HISTORY_INTERVAL: &str = "HISTORY-INTERVAL";
+
+/// The key name for the HOOK-TIMEOUT configuration item.
+pub(crate) static HOOK_TIMEOUT: &str = "HOOK-TIMEOUT";
+
+/// The key name for the IGNORE-EXIT-CODES configuration item.
+pub(crate) static IGNORE_EXIT_CODES: &str = "IGNORE-EXIT-CODES";
+
+/// The key name for the IGNORE-EXIT-SIGNALS configuration item.
+pub(crate) static IGNORE_EXIT_SIGNALS: &str = "IGNORE-EXIT-SIGNALS";
+
+/// The key name for the INCLUDE configuration item.
+pub(crate) static INCLUDE: &str = "INCLUDE";
+
+/// The key name for the INIT configuration item.
+pub(crate) static INIT: &str = "INIT";
+
+/// The key name for the INSTANCE configuration item.
+pub(crate) static INSTANCE: &str = "INSTANCE";
+
+/// The key name for the INSTANCE-ID configuration item.
+pub(crate) static INSTANCE_ID: &str = "INSTANCE-ID";
+
+/// The key name for the KILL-PROCESS-GROUP configuration item.
+pub(crate) static KILL_PROCESS_GROUP: &str = "KILL-PROCESS-GROUP";
+
+/// The key name for the KILL-TIMEOUT configuration item.
+pub(crate) static KILL_TIMEOUT: &str = "KILL-TIMEOUT";
+
+/// The key name for the LEASE configuration item.
+pub(crate) static LEASE: &str = "LEASE";
+
+/// The key name for the LEASE-TERM configuration item.
+pub(crate) static LEASE_TERM: &str = "LEASE-TERM";
+
+/// The key name for the LOG-BUFFER-SIZE configuration item.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) static LOG_BUFFER_SIZE: &str = "LOG-BUFFER-SIZE";
+
+/// The key name for the LOG-DIRECTORY configuration item.
+pub(crate) static LOG_DIRECTORY: &str = "LOG-DIRECTORY";
+
+/// The key name for the LOG-SERVICE configuration item.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) static LOG_SERVICE: &str = "LOG-SERVICE";
+
+/// The key name for the LOG-TIMEOUT configuration item.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) static LOG_TIMEOUT: &str = "LOG-TIMEOUT";
+
+/// The key name for the LOGGER configuration item.
+pub(crate) static LOGGER: &str = "LOGGER";
+
+/// The key name for the MAX-CONCURRENT-RESTARTS configuration item.
+pub(crate) static MAX_CONCURRENT_RESTARTS: &str = "MAX-CONCURRENT-RESTARTS";
+
 /// The key name for the MAX-RETRIES configuration item.
 pub(crate) static MAX_RETRIES: &str = "MAX-RETRIES";
 
+/// The key name for the METRICS configuration item.
+pub(crate) static METRICS: &str = "METRICS";
+
+/// The key name for the METRICS-PATH configuration item.
+pub(crate) static METRICS_PATH: &str = "METRICS-PATH";
+
+/// The key name for the MIRROR-EXIT-STATUS configuration item.
+pub(crate) static MIRROR_EXIT_STATUS: &str = "MIRROR-EXIT-STATUS";
+
+/// The key name for the NAMING-ADDRESS configuration item.
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub(crate) static NAMING_ADDRESS: &str = "NAMING-ADDRESS";
+
+/// The key name for the NAMING-SERVICE configuration item.
+pub(crate) static NAMING_SERVICE: &str = "NAMING-SERVICE";
+
+/// The key name for the OUTPUT-LOG configuration item.
+pub(crate) static OUTPUT_LOG: &str = "OUTPUT-LOG";
+
+/// The key name for the PASSIVE-BIND configuration item.
+pub(crate) static PASSIVE_BIND: &str = "PASSIVE-BIND";
+
+/// The key name for the PASSIVE-SOCKET configuration item.
+pub(crate) static PASSIVE_SOCKET: &str = "PASSIVE-SOCKET";
+
+/// The key name for the PORT-RELEASE-TIMEOUT configuration item.
+pub(crate) static PORT_RELEASE_TIMEOUT: &str = "PORT-RELEASE-TIMEOUT";
+
+/// The key name for the PROBE-COMMAND configuration item.
+pub(crate) static PROBE_COMMAND: &str = "PROBE-COMMAND";
+
+/// The key name for the PROFILES configuration item.
+pub(crate) static PROFILES: &str = "PROFILES";
+
+/// The key name for the PUSH-INTERVAL configuration item.
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub(crate) static PUSH_INTERVAL: &str = "PUSH-INTERVAL";
+
+/// The key name for the PUSH-TIMEOUT configuration item.
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub(crate) static PUSH_TIMEOUT: &str = "PUSH-TIMEOUT";
+
+/// The key name for the PUSH-URL configuration item.
+pub(crate) static PUSH_URL: &str = "PUSH-URL";
+
+/// The key name for the REAP-ZOMBIES configuration item.
+pub(crate) static REAP_ZOMBIES: &str = "REAP-ZOMBIES";
+
+/// The key name for the REGISTER-COMMAND configuration item.
+pub(crate) static REGISTER_COMMAND: &str = "REGISTER-COMMAND";
+
+/// The key name for the RELAY-SIGNALS configuration item.
+pub(crate) static RELAY_SIGNALS: &str = "RELAY-SIGNALS";
+
+/// The key name for the RELEASE-ENDPOINT configuration item.
+pub(crate) static RELEASE_ENDPOINT: &str = "RELEASE-ENDPOINT";
+
+/// The key name for the RESTART-DELAY configuration item.
+pub(crate) static RESTART_DELAY: &str = "RESTART-DELAY";
+
+/// The key name for the RESTART-HOURS configuration item.
+pub(crate) static RESTART_HOURS: &str = "RESTART-HOURS";
+
+/// The key name for the RESTART-ON-EXIT-CODES configuration item.
+pub(crate) static RESTART_ON_EXIT_CODES: &str = "RESTART-ON-EXIT-CODES";
+
+/// The key name for the RESTART-POLICY-TYPE configuration item.
+pub(crate) static RESTART_POLICY_TYPE: &str = "RESTART-POLICY-TYPE";
+
+/// The key name for the RESTART-REFILL-INTERVAL configuration item.
+pub(crate) static RESTART_REFILL_INTERVAL: &str = "RESTART-REFILL-INTERVAL";
+
+/// The key name for the RETRIES configuration item.
+#[cfg_attr(not(feature = "sup"), allow(dead_code))]
+pub(crate) static RETRIES: &str = "RETRIES";
+
+/// The key name for the RETRY-BACKOFF configuration item.
+#[cfg_attr(not(feature = "sup"), allow(dead_code))]
+pub(crate) static RETRY_BACKOFF: &str = "RETRY-BACKOFF";
+
 /// The key name for the RETRY-INTERVAL configuration item.
 pub(crate) static RETRY_INTERVAL: &str = "RETRY-INTERVAL";
 
+/// The key name for the SHUTDOWN-ORDER configuration item.
+pub(crate) static SHUTDOWN_ORDER: &str = "SHUTDOWN-ORDER";
+
+/// The key name for the SHUTDOWN-TIMEOUT configuration item.
+pub(crate) static SHUTDOWN_TIMEOUT: &str = "SHUTDOWN-TIMEOUT";
+
+/// The key name for the SIGINT-ACTION configuration item.
+pub(crate) static SIGINT_ACTION: &str = "SIGINT-ACTION";
+
+/// The key name for the SIGNAL-HOOKS configuration item.
+pub(crate) static SIGNAL_HOOKS: &str = "SIGNAL-HOOKS";
+
+/// The key name for the SPARE-ENDPOINT configuration item.
+pub(crate) static SPARE_ENDPOINT: &str = "SPARE-ENDPOINT";
+
+/// The key name for the STARTUP-GRACE configuration item.
+pub(crate) static STARTUP_GRACE: &str = "STARTUP-GRACE";
+
+/// The key name for the STARTUP-TIMEOUT configuration item.
+pub(crate) static STARTUP_TIMEOUT: &str = "STARTUP-TIMEOUT";
+
+/// The key name for the STUCK-TIMEOUT configuration item.
+pub(crate) static STUCK_TIMEOUT: &str = "STUCK-TIMEOUT";
+
+/// The key name for the SWEEP-DESCENDANTS configuration item.
+pub(crate) static SWEEP_DESCENDANTS: &str = "SWEEP-DESCENDANTS";
+
+/// The key name for the TARGET-CONTROL configuration item.
+pub(crate) static TARGET_CONTROL: &str = "TARGET-CONTROL";
+
 /// The key name for the TARGET-ENDPOINT configuration item.
 pub(crate) static TARGET_ENDPOINT: &str = "TARGET-ENDPOINT";
 
+/// The key name for the TARGET-ID configuration item.
+pub(crate) static TARGET_ID: &str = "TARGET-ID";
+
+/// The key name for the TARGETS configuration item.
+pub(crate) static TARGETS: &str = "TARGETS";
+
+/// The key name for the USAGE-GROWTH-LIMIT configuration item.
+pub(crate) static USAGE_GROWTH_LIMIT: &str = "USAGE-GROWTH-LIMIT";
+
+/// The key name for the USAGE-INTERVAL configuration item.
+pub(crate) static USAGE_INTERVAL: &str = "USAGE-INTERVAL";
+
+/// The key name for the USER configuration item.
+pub(crate) static USER: &str = "USER";
+
+/// The key name for the WARM-SPARE configuration item.
+pub(crate) static WARM_SPARE: &str = "WARM-SPARE";
+
+/// The key name for the WATCHDOG-DEVICE configuration item.
+pub(crate) static WATCHDOG_DEVICE: &str = "WATCHDOG-DEVICE";
+
+/// The key name for the WATCHDOG-DEVICE-INTERVAL configuration item.
+pub(crate) static WATCHDOG_DEVICE_INTERVAL: &str = "WATCHDOG-DEVICE-INTERVAL";
+
+/// The key name for the WATCHDOG-TIMEOUT configuration item.
+pub(crate) static WATCHDOG_TIMEOUT: &str = "WATCHDOG-TIMEOUT";
+
+/// The key name for the WEBHOOK-TIMEOUT configuration item.
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub(crate) static WEBHOOK_TIMEOUT: &str = "WEBHOOK-TIMEOUT";
+
+/// The key name for the WEBHOOKS configuration item.
+pub(crate) static WEBHOOKS: &str = "WEBHOOKS";
+
 /// The key name for the WORKING-DIRECTORY configuration item.
 pub(crate) static WORKING_DIRECTORY: &str = "WORKING-DIRECTORY";
@@ -22,17 +22,20 @@ use crate::plist::KeywordPlist;
 use crate::plist::{Indicator, Value};
 use crate::result::Result;
 use sexp::Sexp;
-use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use super::key;
 
 /// The name of the section configuring Heartbeat2 application.
 pub(crate) static HEARTBEAT: &str = "heartbeat";
 
+/// The name of the section configuring the export of the metrics.
+pub(crate) static METRICS: &str = "metrics";
+
 /// The name of the section configuration the sup service
+#[cfg_attr(not(feature = "sup"), allow(dead_code))]
 pub(crate) static SUP: &str = "sup";
 
 /// Represents a configuration section within a `Config` object.
@@ -41,8 +44,9 @@ pub(crate) static SUP: &str = "sup";
 /// key-value pairs within a specific section of a `Config`
 /// object. Each section is identified by a unique name and contains
 /// configuration options represented by indicators (keys) and
-/// corresponding values.
-pub(crate) struct Section(HashMap<Indicator, Value>);
+/// corresponding values.  A `Section` keeps the options in the order
+/// they appear in the configuration file.
+pub(crate) struct Section(KeywordPlist);
 
 impl Section {
     /// Creates a new instance of the `Section` struct.
@@ -53,7 +57,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::new();
@@ -79,7 +83,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let mut section = Section::new();
@@ -107,25 +111,74 @@ impl Section {
     /// either be an IO error or a SEXP error parsing the
     /// configuration file.
     ///
+    /// The file may include other files with INCLUDE, such as
+    /// `:include "limits.cfg"` or `:include ("limits.cfg"
+    /// "hooks.cfg")`.  A relative path is relative to the directory
+    /// of the including file.  The options in an included file take
+    /// effect as if they appeared in place of INCLUDE, so that the
+    /// options after INCLUDE override them.  A file including itself,
+    /// directly or not, is a configuration error.
+    ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::from_file("config.ini").unwrap();
     /// ```
     fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut section = Section::new();
+        section.include(path.as_ref(), &mut vec![])?;
+        Ok(section)
+    }
+
+    /// Reads the options in the file at `path` into the section,
+    /// following INCLUDE.  The last of repeated keys takes effect,
+    /// but at the position of the first.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the file to read.
+    /// * `including` - The canonical paths of the files including
+    ///   the file, for detecting cycles.
+    fn include(&mut self, path: &Path, including: &mut Vec<PathBuf>) -> Result<()> {
+        let canonical = path.canonicalize()?;
+        if including.contains(&canonical) {
+            return Err(config_format_error(&format!(
+                "{} cycle at [{}]",
+                key::INCLUDE,
+                path.display()
+            )));
+        }
         let mut file = OpenOptions::new().read(true).open(path)?;
         let mut buf = String::new();
         let _ = file.read_to_string(&mut buf)?;
-        Self::from_sexp(sexp::parse(&buf)?)
+        including.push(canonical);
+        for (key, value) in Self::keyword_plist(Self::list_of_sexps(sexp::parse(&buf)?)?)? {
+            if key == Indicator::new(key::INCLUDE) {
+                let included = match value.string() {
+                    Ok(included) => vec![included.to_owned()],
+                    Err(_) => value.string_list()?,
+                };
+                for included in included {
+                    let included = path
+                        .parent()
+                        .map_or_else(|| PathBuf::from(&included), |dir| dir.join(&included));
+                    self.include(&included, including)?;
+                }
+            } else {
+                self.0.insert(key, value);
+            }
+        }
+        including.pop();
+        Ok(())
     }
 
     /// Looks up the key HEARTBEAT-TIMEOUT and returns its value.
     pub(crate) fn heartbeat_timeout(&self) -> Result<u64> {
         self.0
-            .get(&Indicator::new("HEARTBEAT-TIMEOUT"))
-            .ok_or_else(|| missing_key_error("HEARTBEAT-TIMEOUT"))
+            .get(&Indicator::new(key::HEARTBEAT_TIMEOUT))
+            .ok_or_else(|| missing_key_error(key::HEARTBEAT_TIMEOUT))
             .and_then(Value::integer)
             .map(|v| v as u64)
     }
@@ -133,17 +186,48 @@ impl Section {
     /// Looks up the key TARGET-ID and returns its value.
     pub(crate) fn target_id(&self) -> Result<&Keyword> {
         self.0
-            .get(&Indicator::new("TARGET-ID"))
-            .ok_or_else(|| missing_key_error("TARGET-ID"))
+            .get(&Indicator::new(key::TARGET_ID))
+            .ok_or_else(|| missing_key_error(key::TARGET_ID))
             .and_then(Value::keyword)
     }
 
-    /// Looks up the key TARGET-ENDPOINT and returns its value.
-    pub(crate) fn target_endpoint(&self) -> Result<&str> {
-        self.0
+    /// Returns the identity of this supervision in the outputs that
+    /// aggregate many hosts, such as the logs and the metrics.  It is
+    /// INSTANCE-ID if present, or the host name and the lower-case
+    /// TARGET-ID, such as `web-3/app`.
+    pub(crate) fn instance_id(&self) -> Result<String> {
+        if self.has_key(key::INSTANCE_ID) {
+            return Ok(self.string(key::INSTANCE_ID)?.to_owned());
+        }
+        let host = nix::unistd::gethostname()
+            .ok()
+            .and_then(|host| host.into_string().ok())
+            .unwrap_or_else(|| "localhost".to_owned());
+        Ok(format!(
+            "{}/{}",
+            host,
+            self.target_id()?.name().to_lowercase()
+        ))
+    }
+
+    /// Looks up the key TARGET-ENDPOINT and returns its value, either
+    /// a single endpoint or a list of them in the order of
+    /// preference.
+    pub(crate) fn target_endpoints(&self) -> Result<Vec<String>> {
+        let value = self
+            .0
             .get(&Indicator::new(key::TARGET_ENDPOINT))
-            .ok_or_else(|| missing_key_error(key::TARGET_ENDPOINT))
-            .and_then(Value::string)
+            .ok_or_else(|| missing_key_error(key::TARGET_ENDPOINT))?;
+        match value.string() {
+            Ok(endpoint) => Ok(vec![endpoint.to_owned()]),
+            Err(_) => value.string_list(),
+        }
+    }
+
+    /// Looks up the key INIT and returns its value, or false if it is
+    /// absent.
+    pub(crate) fn init(&self) -> Result<bool> {
+        self.boolean_or(key::INIT, false)
     }
 
     /// Retrieves the value associated with the specified `key` as an
@@ -164,7 +248,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::from_file("heartbeat.cfg").unwrap();
@@ -185,6 +269,67 @@ impl Section {
             .and_then(Value::integer)
     }
 
+    /// Retrieves the value associated with the specified `key` as an
+    /// integer, or `default` if the key is absent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    /// * `default` - The value to return if the key is absent.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the integer value associated
+    /// with the `key`, or `default` if the section doesn't have the
+    /// `key`.  If the value cannot be converted to an integer, an
+    /// `Err` variant is returned with a specific error message.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// let retries = section.integer_or("heartbeat-retries", 0).unwrap();
+    /// ```
+    pub(crate) fn integer_or(&self, key: &str, default: i64) -> Result<i64> {
+        if self.has_key(key) {
+            self.integer(key)
+        } else {
+            Ok(default)
+        }
+    }
+
+    /// Retrieves the value associated with the specified `key` as a
+    /// boolean, or `default` if the key is absent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    /// * `default` - The value to return if the key is absent.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the boolean value associated
+    /// with the `key`, or `default` if the section doesn't have the
+    /// `key`.  The value `t` is true, and `nil` is false.  If the
+    /// value is neither, an `Err` variant is returned with a specific
+    /// error message.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// let reap = section.boolean_or("reap-zombies", false).unwrap();
+    /// ```
+    pub(crate) fn boolean_or(&self, key: &str, default: bool) -> Result<bool> {
+        self.0
+            .get(&Indicator::new(key))
+            .map_or(Ok(default), Value::boolean)
+    }
+
     /// Retrieves the value associated with the specified `key` as a
     /// list of strings.
     ///
@@ -203,7 +348,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::from_file("heartbeat.cfg").unwrap();
@@ -224,6 +369,64 @@ impl Section {
             .and_then(Value::string_list)
     }
 
+    /// Retrieves the value associated with the specified `key` as a
+    /// list of integers, or the empty list if the key is absent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the integers associated with the
+    /// `key`, or the empty list if the section doesn't have the
+    /// `key`.  If the value cannot be converted to a list of
+    /// integers, an `Err` variant is returned with a specific error
+    /// message.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// let codes = section.integer_list_or_empty("ignore-exit-codes").unwrap();
+    /// ```
+    pub(crate) fn integer_list_or_empty(&self, key: &str) -> Result<Vec<i64>> {
+        self.0
+            .get(&Indicator::new(key))
+            .map_or(Ok(vec![]), Value::integer_list)
+    }
+
+    /// Retrieves the value associated with the specified `key` as a
+    /// list of lists of strings.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the lists of strings associated
+    /// with the `key` if it exists.  If the key does not exist or the
+    /// value cannot be converted to lists of strings, an `Err`
+    /// variant is returned with a specific error message.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// let hooks = section.string_lists("signal-hooks").unwrap();
+    /// ```
+    pub(crate) fn string_lists(&self, key: &str) -> Result<Vec<Vec<String>>> {
+        self.0
+            .get(&Indicator::new(key))
+            .ok_or_else(|| missing_key_error(key))
+            .and_then(Value::string_lists)
+    }
+
     /// Retrieves the value associated with the specified `key` as a
     /// string reference.
     ///
@@ -242,7 +445,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::from_file("heartbeat.cfg").unwrap();
@@ -263,6 +466,35 @@ impl Section {
             .and_then(Value::string)
     }
 
+    /// Retrieves the value associated with the specified `key` as a
+    /// keyword.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a reference to the keyword
+    /// associated with the `key` if it exists.  If the key does not
+    /// exist or the value is not a keyword, an `Err` variant is
+    /// returned with a specific error message.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// let action = section.keyword("sigint-action").unwrap();
+    /// ```
+    pub(crate) fn keyword(&self, key: &str) -> Result<&Keyword> {
+        self.0
+            .get(&Indicator::new(key))
+            .ok_or_else(|| missing_key_error(key))
+            .and_then(Value::keyword)
+    }
+
     /// Checks if the section contains a specific configuration option
     /// key.
     ///
@@ -278,7 +510,7 @@ impl Section {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::Section;
     ///
     /// let section = Section::from_file("heartbeat.cfg").unwrap();
@@ -290,13 +522,172 @@ impl Section {
     /// }
     /// ```
     pub(crate) fn has_key(&self, key_name: &str) -> bool {
-        self.0.contains_key(&Indicator::new(key_name))
+        self.0.get(&Indicator::new(key_name)).is_some()
     }
 
-    fn from_sexp(sexp: Sexp) -> Result<Self> {
-        Ok(Section(
-            Self::keyword_plist(Self::list_of_sexps(sexp)?)?.into_hash_map(),
-        ))
+    /// Sets the configuration option `key` to `value`, replacing the
+    /// value in the section, if any.  Lets command-line options
+    /// override the configuration file.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the configuration option.
+    /// * `value` - The new value of the configuration option.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let mut section = Section::from_file("heartbeat.cfg").unwrap();
+    /// section.set("init", true);
+    /// ```
+    pub(crate) fn set<V: Into<Value>>(&mut self, key: &str, value: V) {
+        self.0.insert(Indicator::new(key), value.into());
+    }
+
+    /// Sets the configuration option `key` to the integer `value`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let mut section = Section::new();
+    /// section.set_integer("heartbeat-timeout", 1000);
+    /// assert_eq!(section.integer("heartbeat-timeout").unwrap(), 1000);
+    /// ```
+    #[allow(dead_code)]
+    pub(crate) fn set_integer(&mut self, key: &str, value: i64) {
+        self.set(key, value);
+    }
+
+    /// Sets the configuration option `key` to the string `value`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let mut section = Section::new();
+    /// section.set_string("working-directory", "/srv/app");
+    /// assert_eq!(section.string("working-directory").unwrap(), "/srv/app");
+    /// ```
+    #[allow(dead_code)]
+    pub(crate) fn set_string(&mut self, key: &str, value: &str) {
+        self.set(key, value);
+    }
+
+    /// Sets the configuration option `key` to the list of strings
+    /// `values`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let mut section = Section::new();
+    /// section.set_list("command", &["app", "--verbose"]);
+    /// assert_eq!(section.string_list("command").unwrap(), ["app", "--verbose"]);
+    /// ```
+    #[allow(dead_code)]
+    pub(crate) fn set_list<S: AsRef<str>>(&mut self, key: &str, values: &[S]) {
+        self.set(
+            key,
+            Value::List(
+                values
+                    .iter()
+                    .map(|value| Value::from(value.as_ref()))
+                    .collect(),
+            ),
+        );
+    }
+
+    /// Applies the profile by the `name` from PROFILES, overriding
+    /// the configuration options the profile sets.
+    ///
+    /// PROFILES is a property list of named profiles, each a property
+    /// list of configuration options, such as `(:dev
+    /// (:heartbeat-timeout 5000) :prod (:heartbeat-timeout 1000))`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if PROFILES is missing or malformed, or if it
+    /// has no profile by the `name`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let mut section = Section::from_file("heartbeat.cfg").unwrap();
+    /// section.apply_profile("dev").unwrap();
+    /// ```
+    pub(crate) fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let options: Vec<(Indicator, Value)> = self
+            .0
+            .get(&Indicator::new(key::PROFILES))
+            .ok_or_else(|| missing_key_error(key::PROFILES))?
+            .plist_pairs()?
+            .into_iter()
+            .find(|(profile, _)| profile.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                config_format_error(&format!("unknown profile [{}] in {}", name, key::PROFILES))
+            })?
+            .1
+            .plist_pairs()?
+            .into_iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (key, value) in options {
+            self.0.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Returns the options in the property list under the `key` as a
+    /// section of their own, such as the METRICS section out of
+    /// `:metrics (:push-url "http://pushgateway:9091/metrics/job/app")`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `key` is missing or its value isn't a
+    /// property list.
+    pub(crate) fn subsection(&self, key: &str) -> Result<Section> {
+        let mut section = Section::new();
+        for (key, value) in self
+            .0
+            .get(&Indicator::new(key))
+            .ok_or_else(|| missing_key_error(key))?
+            .plist_pairs()?
+        {
+            section.0.insert(key.clone(), value.clone());
+        }
+        Ok(section)
+    }
+
+    /// Returns an iterator over the configuration options in the
+    /// order they appear in the configuration file.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use crate::Section;
+    ///
+    /// let section = Section::from_file("heartbeat.cfg").unwrap();
+    /// for (key, value) in section.iter() {
+    ///     println!("{} {}", key, value);
+    /// }
+    /// ```
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Indicator, &Value)> {
+        self.0.iter()
+    }
+
+    /// Returns an iterator over the keys of the configuration options
+    /// in the order they appear in the configuration file.
+    #[allow(dead_code)]
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &Indicator> {
+        self.iter().map(|(key, _)| key)
     }
 
     fn list_of_sexps(sexp: Sexp) -> Result<Vec<Sexp>> {
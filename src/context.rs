@@ -0,0 +1,34 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(feature = "zmq")]
+pub(crate) use tmq::Context;
+
+/// Stands in for the ZeroMQ context without the `zmq` feature, so
+/// that the components keep their shape either way.
+#[cfg(not(feature = "zmq"))]
+#[derive(Clone)]
+pub(crate) struct Context;
+
+#[cfg(not(feature = "zmq"))]
+impl Context {
+    /// Creates a new `Context`.
+    pub(crate) fn new() -> Self {
+        Context
+    }
+}
@@ -0,0 +1,389 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::channel::EventSender;
+use crate::codec::{self, Codec};
+use crate::config::{key, section, Config};
+use crate::context::Context;
+#[cfg(not(feature = "zmq"))]
+use crate::error::config_format_error;
+use crate::event::EventType;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, Logger};
+use crate::metrics::Metrics;
+use crate::process::ProcessManager;
+use crate::protocol::Message;
+use crate::result::Result;
+use crate::signal::Signal;
+#[cfg(feature = "zmq")]
+use crate::socket::SocketBuilder;
+use crate::state::StateMachine;
+use crate::timeline::Timeline;
+use crate::usage::Usage;
+use std::rc::Rc;
+
+/// Answers requests about `Heartbeat2` on the control socket.
+///
+/// The control socket is a ZeroMQ REP socket.  A request is a
+/// multipart message whose first part names the request, as in the
+/// heartbeat protocol.  The response is a property list in the
+/// S-expression syntax of the configuration file, so that the
+/// configuration, the heartbeats and the control traffic all speak
+/// the same language.  With CONTROL-FORMAT set to `:json`, the
+/// response is in JSON instead, for tooling that doesn't speak
+/// S-expressions.  See [`Codec`].
+///
+/// # Requests
+///
+/// * `STATUS`: Responds with the status of the supervision, such as
+///   `(:target "app" :instance "web-3/app" :state :running :pid 1234
+///   :restarts 2)`.  The PID is `nil` while there is no running
+///   process.  `STATUS` followed by `JSON` or `SEXP` responds with
+///   the same status in that format regardless of CONTROL-FORMAT,
+///   such as `{"target":"app","instance":"web-3/app",
+///   "state":"running","pid":1234,"restarts":2}`.
+///   `:supervisor` adds the resource usage of `Heartbeat2` itself,
+///   such as `(:rss-bytes 4194304 :cpu-ms 120 :open-fds 14)`.
+///   `:telemetry` adds the latest telemetry the target attached to
+///   its answers to the heartbeats, such as `(:queue-depth "12"
+///   :version "1.2.3")`.
+/// * `TIMELINE`: Responds with the [`Timeline`] of the supervision
+///   session as a list of property lists.  `TIMELINE` followed by
+///   `JSON` responds with the timeline in JSON, and followed by
+///   `TEXT` responds with an entry per line.
+/// * `RESTART`: Kills the managed process and starts it again.  The
+///   restart doesn't count against MAX-RETRIES.
+/// * `STOP`: Stops the managed process and `Heartbeat2` as `SIGTERM`
+///   does.
+/// * `PAUSE`: Pauses the heartbeats, so that the target isn't
+///   restarted while an operator works on it.
+/// * `RESUME`: Resumes the heartbeats.
+/// * `DRILL`: Makes the next heartbeat time out as if the target
+///   hung, so that the kill, the crash report, the restart, the
+///   notifications and the hooks can be rehearsed without a real
+///   hang.  The restart counts against MAX-RETRIES as that after a
+///   real hang does.
+/// * `TELL`: Followed by a control keyword and its value, such as
+///   `TELL SET-LOG-LEVEL DEBUG`, queues the control for the target.
+///   The next heartbeat carries it, as in `HEARTBEAT SET-LOG-LEVEL
+///   DEBUG`, and so do those after it until the target answers one.
+///   Only a target that opts in with TARGET-CONTROL understands the
+///   controls, so the request is refused without it.
+///
+/// * `DEBUG`: Pauses the heartbeats as `PAUSE` does, and responds
+///   with the PID of the managed process and ready-made commands to
+///   attach a debugger, such as `(:result :accepted :pid 1234 :gdb
+///   "gdb -p 1234" :lldb "lldb -p 1234")`.  `RESUME` resumes the
+///   heartbeats once the debugging is over.
+/// * `RELOAD`: Loads the configuration file again, and resolves the
+///   endpoints of the target by it from the next heartbeat on.  A
+///   reload that removes TARGET-ENDPOINT switches the heartbeats to
+///   the naming service, which loads and validates the configuration
+///   of Sup when it is first asked.  The other options take a
+///   restart of `Heartbeat2`.  A configuration that fails to load
+///   gets logged, and the previous one stays in effect.
+///
+/// These respond with `(:result :accepted)` once the request is
+/// queued for the `EventHandler`, or with an error if the managed
+/// process isn't running.
+///
+/// A request `ControlServer` doesn't know yields a response such as
+/// `(:error "unknown request [FOO]")`.
+///
+/// # Configuration
+///
+/// * CONTROL-ENDPOINT: Optional.  The endpoint to bind the control
+///   socket to, such as `"ipc:///run/heartbeat2/app.ctl"`.
+///   `ControlServer` is disabled without it.
+/// * CONTROL-FORMAT: Optional.  `:sexp` or `:json`, the format of
+///   the responses.  The default is `:sexp`.
+pub(crate) struct ControlServer {
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    context: Context,
+    state: Rc<StateMachine>,
+    process_manager: Rc<ProcessManager>,
+    metrics: Rc<Metrics>,
+    timeline: Rc<Timeline>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+    event_sender: Option<EventSender>,
+}
+
+impl ControlServer {
+    /// Creates a new `ControlServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The ZeroMQ context to create the socket in.
+    /// * `state` - A shared reference to the target state machine.
+    /// * `process_manager` - A shared reference to the process
+    ///   manager.
+    /// * `metrics` - A shared reference to the metrics.
+    /// * `timeline` - A shared reference to the timeline.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(
+        context: Context,
+        state: Rc<StateMachine>,
+        process_manager: Rc<ProcessManager>,
+        metrics: Rc<Metrics>,
+        timeline: Rc<Timeline>,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
+    ) -> Self {
+        ControlServer {
+            context,
+            state,
+            process_manager,
+            metrics,
+            timeline,
+            config,
+            logger,
+            event_sender: None,
+        }
+    }
+
+    /// Lets the `ControlServer` raise events for the requests that
+    /// act on the supervision, such as `RESTART`.  Without it, such
+    /// requests yield an error.
+    pub(crate) fn event_sender(mut self, event_sender: EventSender) -> Self {
+        self.event_sender = Some(event_sender);
+        self
+    }
+
+    /// Returns whether the `ControlServer` should run.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config
+            .section(section::HEARTBEAT)
+            .is_ok_and(|section| section.has_key(key::CONTROL_ENDPOINT))
+    }
+
+    /// Binds the control socket and answers requests until
+    /// `Heartbeat2` exits.
+    #[cfg(feature = "zmq")]
+    pub(crate) async fn run(&self) -> Result<()> {
+        let endpoint = self
+            .config
+            .section(section::HEARTBEAT)?
+            .string(key::CONTROL_ENDPOINT)?;
+        let codec = codec::from_config(&self.config, key::CONTROL_FORMAT)?;
+        self.logger.log_coded(
+            &message::CT001,
+            &format!("serve control requests on {}", endpoint),
+        );
+        let mut socket = SocketBuilder::new(self.context.clone())
+            .endpoint(endpoint)
+            .linger(false)
+            .rep()
+            .bind()?;
+        loop {
+            let (request, sender) = socket.recv_request().await?;
+            socket = sender
+                .send_string(&self.respond(&request, codec).await)
+                .await?;
+        }
+    }
+
+    /// Stands in for the control socket without the `zmq` feature.
+    #[cfg(not(feature = "zmq"))]
+    pub(crate) async fn run(&self) -> Result<()> {
+        Err(config_format_error(&format!(
+            "{} needs the zmq feature",
+            key::CONTROL_ENDPOINT
+        )))
+    }
+
+    /// Returns the response to the `request` encoded with the
+    /// `codec` of the endpoint, or in the format the second part of
+    /// the `request` asks for, if any.
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    pub(crate) async fn respond(&self, request: &[Message], codec: &dyn Codec) -> String {
+        let format = request.get(1);
+        let response = match request.first() {
+            Some(message) if *message == kw![timeline] => {
+                if format.is_some_and(|format| *format == kw![text]) {
+                    return self.timeline.to_text();
+                }
+                self.timeline.to_expression()
+            }
+            Some(message) if *message == kw![tell] => self.tell(&request[1..]).await,
+            request => self.answer(request).await,
+        };
+        codec::requested(format).unwrap_or(codec).encode(&response)
+    }
+
+    /// Returns the answer to the request named by the `message`.
+    async fn answer(&self, message: Option<&Message>) -> Expression {
+        match message {
+            Some(message) if *message == kw![status] => self.status(),
+            Some(message) if *message == kw![restart] => self.command(EventType::Restart).await,
+            Some(message) if *message == kw![stop] => {
+                self.command(EventType::Signalled(Signal::Term)).await
+            }
+            Some(message) if *message == kw![pause] => self.command(EventType::Pause).await,
+            Some(message) if *message == kw![resume] => self.command(EventType::Resume).await,
+            Some(message) if *message == kw![debug] => self.debug().await,
+            Some(message) if *message == kw![drill] => self.command(EventType::Drill).await,
+            Some(message) if *message == kw![reload] => self.command(EventType::Reload).await,
+            Some(message) => {
+                self.logger.log_coded(
+                    &message::CT002,
+                    &format!("unknown control request [{}]", message.as_str()),
+                );
+                Expression::plist(vec![(
+                    kw![error],
+                    Expression::from(format!("unknown request [{}]", message.as_str()).as_str()),
+                )])
+            }
+            None => Expression::plist(vec![(kw![error], Expression::from("empty request"))]),
+        }
+    }
+
+    /// Raises the `event` for the `EventHandler`, and returns the
+    /// answer to the request that asked for it.
+    async fn command(&self, event: EventType) -> Expression {
+        match self.raise(event).await {
+            Ok(()) => Expression::plist(vec![(kw![result], Expression::from(kw![accepted]))]),
+            Err(err) => self.refuse(&err),
+        }
+    }
+
+    /// Queues the control keyword and its value in the `arguments`
+    /// for the target, such as `SET-LOG-LEVEL DEBUG`.
+    async fn tell(&self, arguments: &[Message]) -> Expression {
+        let accepted = self
+            .config
+            .section(section::HEARTBEAT)
+            .and_then(|section| section.boolean_or(key::TARGET_CONTROL, false));
+        match (accepted, arguments) {
+            (Ok(true), [name, value]) => {
+                let name = Keyword::new(&name.as_str().to_uppercase());
+                let value = value.as_str().to_owned();
+                self.command(EventType::Control(name, value)).await
+            }
+            (Ok(true), _) => self.refuse("TELL needs a control keyword and its value"),
+            (Ok(false), _) => self.refuse(&format!(
+                "target doesn't accept controls without {}",
+                key::TARGET_CONTROL
+            )),
+            (Err(err), _) => self.refuse(&err.to_string()),
+        }
+    }
+
+    /// Pauses the heartbeats, and answers with the PID of the managed
+    /// process and the commands to attach a debugger to it, such as
+    /// `(:result :accepted :pid 1234 :gdb "gdb -p 1234" :lldb "lldb
+    /// -p 1234")`.  The heartbeats stay paused until `RESUME`, so that
+    /// the target isn't killed as hung while it sits at a breakpoint.
+    async fn debug(&self) -> Expression {
+        let pid = match self.process_manager.pid() {
+            Some(pid) => pid,
+            None => return self.refuse("no running process"),
+        };
+        if let Err(err) = self.raise(EventType::Pause).await {
+            return self.refuse(&err);
+        }
+        self.logger.log_coded(
+            &message::CT003,
+            &format!("heartbeat paused to debug process [{}]", pid),
+        );
+        Expression::plist(vec![
+            (kw![result], Expression::from(kw![accepted])),
+            (kw![pid], Expression::from(pid as i64)),
+            (
+                kw![gdb],
+                Expression::from(format!("gdb -p {}", pid).as_str()),
+            ),
+            (
+                kw![lldb],
+                Expression::from(format!("lldb -p {}", pid).as_str()),
+            ),
+        ])
+    }
+
+    /// Raises the `event` for the `EventHandler`.  Refuses the
+    /// request while there is no running process, as no
+    /// `EventHandler` would consume the `event` until the next
+    /// restart.
+    async fn raise(&self, event: EventType) -> std::result::Result<(), String> {
+        let state = self.state.get();
+        match &self.event_sender {
+            Some(_) if state.is_stopped() => Err(format!("target is {}", state)),
+            Some(event_sender) => event_sender
+                .send(event)
+                .await
+                .map_err(|err| err.to_string()),
+            None => Err("request not supported".to_owned()),
+        }
+    }
+
+    /// Logs the refusal of a request, and returns the answer with the
+    /// reason `err`.
+    fn refuse(&self, err: &str) -> Expression {
+        self.logger.log_coded(
+            &message::CT004,
+            &format!("control request refused: {}", err),
+        );
+        Expression::plist(vec![(kw![error], Expression::from(err))])
+    }
+
+    /// Returns the status of the supervision as a property list.
+    pub(crate) fn status(&self) -> Expression {
+        let section = self.config.section(section::HEARTBEAT).ok();
+        let target = section
+            .and_then(|section| Some(section.target_id().ok()?.name().to_owned()))
+            .unwrap_or_default();
+        let instance = section
+            .and_then(|section| section.instance_id().ok())
+            .unwrap_or_default();
+        Expression::plist(vec![
+            (kw![target], Expression::from(target.as_str())),
+            (kw![instance], Expression::from(instance.as_str())),
+            (
+                kw![state],
+                Expression::from(Keyword::new(&self.state.get().to_string().to_uppercase())),
+            ),
+            (
+                kw![pid],
+                self.process_manager
+                    .pid()
+                    .map_or(Expression::from(false), |pid| Expression::from(pid as i64)),
+            ),
+            (
+                kw![restarts],
+                Expression::from(self.metrics.restarts() as i64),
+            ),
+            (
+                kw![telemetry],
+                Expression::plist(
+                    self.metrics
+                        .telemetry()
+                        .into_iter()
+                        .map(|(name, value)| (name, Expression::from(value.as_str())))
+                        .collect(),
+                ),
+            ),
+            (
+                kw![supervisor],
+                Usage::sample().map_or(Expression::from(false), Usage::to_expression),
+            ),
+        ])
+    }
+}
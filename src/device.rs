@@ -0,0 +1,145 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use crate::state::{StateMachine, TargetState};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::rc::Rc;
+use tokio::time::{sleep, Duration};
+
+/// The default time in seconds between the writes to the watchdog
+/// device.
+static DEFAULT_WATCHDOG_DEVICE_INTERVAL: i64 = 10;
+
+/// The character that disarms the watchdog device as it closes.
+static MAGIC_CLOSE: &[u8] = b"V";
+
+/// Pets the watchdog device of the OS while the supervision is
+/// healthy, so that a hang of the whole machine leads to a reboot.
+///
+/// `Heartbeat2` restarts the target when it hangs, and an outer
+/// supervisor restarts `Heartbeat2`.  But nothing recovers a machine
+/// whose kernel hangs.  `WatchdogDevice` opens a watchdog device, such
+/// as the one of a hardware watchdog timer or of the `softdog`
+/// module, which arms it.  It then writes to the device every
+/// WATCHDOG-DEVICE-INTERVAL seconds while the target is running.  The
+/// writes stop when the event loop of `Heartbeat2` wedges, and when
+/// the target is down, such as during a restart.  The device reboots
+/// the machine once the writes stop for longer than its timeout, which
+/// the driver configures.  The timeout should exceed the longest
+/// restart of the target.
+///
+/// When the target completes, or `Heartbeat2` stops on a signal,
+/// `WatchdogDevice` disarms the device as it closes it.  When
+/// `Heartbeat2` gives up on the target, the device stays armed, and
+/// reboots the machine.  This suits appliances, where a reboot is the
+/// last resort of recovery.  Only one target of a fleet may pet the
+/// device.
+///
+/// # Configuration
+///
+/// * WATCHDOG-DEVICE: Optional.  The path of the watchdog device,
+///   such as `"/dev/watchdog"`.  `WatchdogDevice` is disabled without
+///   it.
+/// * WATCHDOG-DEVICE-INTERVAL: Optional.  The time in seconds between
+///   the writes to the device.  The default is 10 seconds.
+pub(crate) struct WatchdogDevice {
+    device: RefCell<Option<File>>,
+    state: Rc<StateMachine>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl WatchdogDevice {
+    /// Creates a new `WatchdogDevice`.  The device stays closed until
+    /// [`run`](Self::run).
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - A shared reference to the target state machine.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(state: Rc<StateMachine>, config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        WatchdogDevice {
+            device: RefCell::new(None),
+            state,
+            config,
+            logger,
+        }
+    }
+
+    /// Returns whether the `WatchdogDevice` should run.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config
+            .section(section::HEARTBEAT)
+            .is_ok_and(|section| section.has_key(key::WATCHDOG_DEVICE))
+    }
+
+    /// Opens the device, and writes to it while the target is running
+    /// until `Heartbeat2` exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device can't be opened or written to.
+    pub(crate) async fn run(&self) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let path = section.string(key::WATCHDOG_DEVICE)?;
+        let interval = Duration::from_secs(
+            section
+                .integer_or(
+                    key::WATCHDOG_DEVICE_INTERVAL,
+                    DEFAULT_WATCHDOG_DEVICE_INTERVAL,
+                )?
+                .try_into()?,
+        );
+        self.device
+            .replace(Some(OpenOptions::new().write(true).open(path)?));
+        self.logger.log_coded(
+            &message::WD003,
+            &format!("pet {} every {}s", path, interval.as_secs()),
+        );
+        loop {
+            if matches!(
+                self.state.get(),
+                TargetState::Running | TargetState::Degraded
+            ) {
+                if let Some(device) = self.device.borrow_mut().as_mut() {
+                    device.write_all(b"\0")?;
+                }
+            }
+            sleep(interval).await;
+        }
+    }
+
+    /// Disarms and closes the device, if open, so that it doesn't
+    /// reboot the machine after `Heartbeat2` exits.
+    pub(crate) fn disarm(&self) {
+        if let Some(mut device) = self.device.take() {
+            if let Err(err) = device.write_all(MAGIC_CLOSE) {
+                self.logger.log_coded(
+                    &message::WD004,
+                    &format!("unable to disarm watchdog device: {}", err),
+                );
+            }
+        }
+    }
+}
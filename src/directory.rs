@@ -0,0 +1,137 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::section::Section;
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::result::Result;
+use std::fs::DirBuilder;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+/// The instance name when INSTANCE is absent.
+static DEFAULT_INSTANCE: &str = "0";
+
+/// The mode of the directories `Directories` creates when
+/// DIRECTORY-MODE is absent, before the umask applies.
+static DEFAULT_DIRECTORY_MODE: u32 = 0o777;
+
+/// The directories of the managed process.
+///
+/// When a host runs many instances of `Heartbeat2`, each needs its
+/// own working directory and log directory.  Rather than naming each
+/// directory by hand, WORKING-DIRECTORY and LOG-DIRECTORY can be
+/// templates with the following placeholders:
+///
+/// * `{base}`: BASE-DIRECTORY.
+/// * `{target-id}`: TARGET-ID in lower case.
+/// * `{instance}`: INSTANCE.
+///
+/// For example, `"{base}/{target-id}/{instance}"` with a base of
+/// `"/srv"`, a target of `:app` and an instance of `"2"` makes
+/// `/srv/app/2`.  `Directories` creates a templated working directory
+/// and the log directory if they don't exist.  It doesn't create a
+/// working directory without placeholders, so that a typo in it
+/// still fails to start the process.
+///
+/// # Configuration
+///
+/// * WORKING-DIRECTORY: Required.  The working directory of the
+///   managed process.
+/// * LOG-DIRECTORY: Optional.  The log directory of the managed
+///   process.  The process learns it from the environment variable
+///   `HEARTBEAT2_LOG_DIR`.
+/// * BASE-DIRECTORY: Optional.  The value of `{base}`.
+/// * INSTANCE: Optional.  The value of `{instance}`.  The default is
+///   `"0"`.
+/// * DIRECTORY-MODE: Optional.  The permissions of the directories
+///   `Directories` creates, as an octal string such as `"0750"`.
+pub(crate) struct Directories {
+    /// The working directory of the managed process.
+    pub(crate) working: PathBuf,
+    /// The log directory of the managed process, if any.
+    pub(crate) log: Option<PathBuf>,
+}
+
+impl Directories {
+    /// Expands the templates in the configuration, and creates the
+    /// directories as necessary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, or if a
+    /// directory can't be created.
+    pub(crate) fn prepare(config: &Config) -> Result<Self> {
+        let section = config.section(section::HEARTBEAT)?;
+        let template = section.string(key::WORKING_DIRECTORY)?;
+        let working = PathBuf::from(expand(section, template)?);
+        if working.as_os_str() != template {
+            create(section, &working)?;
+        }
+        let log = if section.has_key(key::LOG_DIRECTORY) {
+            let log = PathBuf::from(expand(section, section.string(key::LOG_DIRECTORY)?)?);
+            create(section, &log)?;
+            Some(log)
+        } else {
+            None
+        };
+        Ok(Directories { working, log })
+    }
+}
+
+/// Replaces the placeholders in the `template`.
+fn expand(section: &Section, template: &str) -> Result<String> {
+    let mut path = template.to_owned();
+    if path.contains("{base}") {
+        path = path.replace("{base}", section.string(key::BASE_DIRECTORY)?);
+    }
+    if path.contains("{target-id}") {
+        path = path.replace("{target-id}", &section.target_id()?.name().to_lowercase());
+    }
+    if path.contains("{instance}") {
+        let instance = if section.has_key(key::INSTANCE) {
+            section.string(key::INSTANCE)?
+        } else {
+            DEFAULT_INSTANCE
+        };
+        path = path.replace("{instance}", instance);
+    }
+    if path.contains('{') {
+        Err(config_format_error(&format!(
+            "unknown placeholder in [{}]",
+            template
+        )))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Creates the directory at `path` and its parents with
+/// DIRECTORY-MODE.
+fn create(section: &Section, path: &Path) -> Result<()> {
+    let mode = if section.has_key(key::DIRECTORY_MODE) {
+        let mode = section.string(key::DIRECTORY_MODE)?;
+        u32::from_str_radix(mode, 8).map_err(|_| {
+            config_format_error(&format!("{} [{}] is not octal", key::DIRECTORY_MODE, mode))
+        })?
+    } else {
+        DEFAULT_DIRECTORY_MODE
+    };
+    DirBuilder::new().recursive(true).mode(mode).create(path)?;
+    Ok(())
+}
@@ -43,6 +43,9 @@ pub(crate) enum ErrorType {
     ConfigFormat(String),
     /// Error indicating an illegal state.
     IllegalState(String),
+    /// Error indicating an illegal transition from one state to
+    /// another.
+    IllegalTransition(String, String),
     /// Error indicating a missing name to endpoint mapping for a
     /// service.
     MappingMissing(String),
@@ -55,12 +58,18 @@ pub(crate) enum ErrorType {
     /// Error indicating that the peer channel is closed for the
     /// internal MPSC communications channel.
     PeerChannelClosed,
-    /// Error indicating a string encoding issue.
-    StringEncoding,
+    /// Error indicating an action on an incarnation of the process
+    /// that has since been replaced.
+    StaleIncarnation(u64, u64),
+    /// Error indicating that a service didn't answer, even after
+    /// retries.
+    ServiceUnreachable(String),
     /// Error indicating a type errors processing S expressions.
     Type(String),
     /// Error indicating an unknown response received from a service.
     UnknownResponse(String),
+    /// Error indicating a misuse of the command-line options.
+    Usage(String),
     /// Error wrapping a `std::io::Error` instance.
     Io(std::io::Error),
 }
@@ -71,16 +80,23 @@ impl Display for ErrorType {
         match self {
             ConfigFormat(message) => write!(f, "config format error: {}", message),
             IllegalState(state) => write!(f, "illegal state [{}]", state),
-            MappingMissing(id) => write!(f, "mapping missing for [{}] in Sup", id),
+            IllegalTransition(from, to) => write!(f, "illegal transition [{} -> {}]", from, to),
+            MappingMissing(id) => write!(f, "mapping missing for [{}] in the naming service", id),
             MissingKey(key) => write!(f, "the key [{}] is missing in the config", key),
             MissingSection(section) => {
                 write!(f, "the section [{}] is missing in the config", section)
             }
             NoRunningProcess => write!(f, "no running process"),
             PeerChannelClosed => write!(f, "peer channel is closed"),
-            StringEncoding => write!(f, "invalid string encoding"),
+            StaleIncarnation(stale, current) => write!(
+                f,
+                "stale incarnation [{}] of the process (current: {})",
+                stale, current
+            ),
+            ServiceUnreachable(message) => write!(f, "service unreachable: {}", message),
             Type(expected) => write!(f, "type error (expected: {})", expected),
             UnknownResponse(response) => write!(f, "unknown response [{}]", response),
+            Usage(message) => write!(f, "usage error: {}", message),
             Io(error) => error.fmt(f),
         }
     }
@@ -105,6 +121,7 @@ pub(crate) fn illegal_state_error(state: &str) -> Error {
 }
 
 /// Creates a new mapping_missing_error.
+#[cfg_attr(not(any(feature = "http", feature = "sup")), allow(dead_code))]
 pub(crate) fn mapping_missing_error(id: &str) -> Error {
     Box::new(ErrorType::MappingMissing(id.to_owned()))
 }
@@ -124,9 +141,10 @@ pub(crate) fn peer_channel_closed_error() -> Error {
     Box::new(ErrorType::PeerChannelClosed)
 }
 
-/// Creates a new string_encoding_error.
-pub(crate) fn string_encoding_error() -> Error {
-    Box::new(ErrorType::StringEncoding)
+/// Creates a new service_unreachable_error.
+#[cfg_attr(not(feature = "sup"), allow(dead_code))]
+pub(crate) fn service_unreachable_error(message: &str) -> Error {
+    Box::new(ErrorType::ServiceUnreachable(message.to_owned()))
 }
 
 /// Creates a new type_error.
@@ -135,6 +153,12 @@ pub(crate) fn type_error(expected: &str) -> Error {
 }
 
 /// Creates a new unknown_response_error.
+#[cfg_attr(not(any(feature = "http", feature = "zmq")), allow(dead_code))]
 pub(crate) fn unknown_response_error(response: &str) -> Error {
     Box::new(ErrorType::UnknownResponse(response.to_owned()))
 }
+
+/// Creates a new usage_error.
+pub(crate) fn usage_error(message: &str) -> Error {
+    Box::new(ErrorType::Usage(message.to_owned()))
+}
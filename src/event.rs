@@ -16,27 +16,71 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::channel::EventReceiver;
+use crate::config::{key, section, Config};
 use crate::heartbeat::Heartbeat;
-use crate::logger::{LocalLogger, LogLevel};
+use crate::hook::HookRunner;
+use crate::keyword::Keyword;
+use crate::logger::{message, LogLevel, Logger};
+use crate::process::ProcessManager;
+use crate::process::{Ack, ActionHandle};
+use crate::report::{CrashReport, ExitReport};
 use crate::result::Result;
+use crate::shutdown::ShutdownTimer;
 use crate::signal::{Signal, SignalHandler};
-use crate::ProcessManager;
+use crate::state::StateMachine;
+use std::collections::VecDeque;
 use std::rc::Rc;
-use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+/// The default time in seconds to wait for the managed process to
+/// exit after killing or signalling it.
+static DEFAULT_EXIT_TIMEOUT: i64 = 10;
+
+/// The time to wait for the last lines of the standard error of an
+/// aborted process to come through the pipe for the crash report.
+static STDERR_DRAIN_TIME: Duration = Duration::from_millis(100);
 
 /// EventType describes the type of event that affects the health or
 /// lifecycle of the monitored process.
+///
+/// The events about the process carry the incarnation of the process
+/// they are about, as [`ProcessManager::incarnation`] counts it.
 #[derive(Debug)]
 pub(crate) enum EventType {
     /// Event indicating a heartbeat timeout.
-    Timeout,
+    Timeout(u64),
     /// Event indicating a process abortion.
-    Aborted,
+    Aborted(u64),
     /// Event indicating a process completion.
-    Complete,
+    Complete(u64),
     /// Event indicating a process signal with the associated signal
     /// type.
     Signalled(Signal),
+    /// Event checking that `EventHandler` makes progress.  The
+    /// handler answers through the associated channel.
+    Ping(oneshot::Sender<()>),
+    /// Event requesting a restart of the process, such as from the
+    /// control socket.
+    Restart,
+    /// Event indicating that the process asked for a restart in its
+    /// answer to a heartbeat.
+    RestartRequested(u64),
+    /// Event requesting a pause of the heartbeats.
+    Pause,
+    /// Event requesting the heartbeats to resume.
+    Resume,
+    /// Event requesting a drill, in which the next heartbeat times
+    /// out as if the target hung.
+    Drill,
+    /// Event requesting a control keyword with its value, such as
+    /// `SET-LOG-LEVEL DEBUG`, for the target on the next heartbeat.
+    Control(Keyword, String),
+    /// Event requesting a reload of the configuration for resolving
+    /// the endpoints of the target.
+    Reload,
 }
 
 /// Receives events from various components of the heartbeat2
@@ -51,13 +95,14 @@ pub(crate) enum EventType {
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::{EventHandler, EventType, ProcessManager, Heartbeat, SignalHandler, LocalLogger};
 /// use std::sync::mpsc;
 /// use std::rc::Rc;
 ///
 /// // Create necessary components and event receiver
 /// let (event_sender, event_receiver) = mpsc::channel();
+/// let state = Rc::new(StateMachine::new());
 /// let process_manager = Rc::new(ProcessManager::new());
 /// let heartbeat = Rc::new(Heartbeat::new());
 /// let signal_handler = Rc::new(SignalHandler::new());
@@ -65,20 +110,45 @@ pub(crate) enum EventType {
 ///
 /// // Create and initialize the event handler
 /// let event_handler = EventHandler::new(event_receiver,
+///                                       state.clone(),
 ///                                       process_manager.clone(),
 ///                                       heartbeat.clone(),
 ///                                       signal_handler.clone(),
+///                                       config.clone(),
 ///                                       logger.clone());
 ///
 /// // Spawn a thread or start an event loop to handle events
 /// // ...
 /// ```
 pub(crate) struct EventHandler {
-    event_receiver: mpsc::Receiver<EventType>,
+    event_receiver: EventReceiver,
+    pending: VecDeque<EventType>,
+    state: Rc<StateMachine>,
     process_manager: Rc<ProcessManager>,
     heartbeat: Rc<Heartbeat>,
     signal_handler: Rc<SignalHandler>,
-    logger: Rc<LocalLogger>,
+    shutdown_timer: ShutdownTimer,
+    hook_runner: HookRunner,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl EventType {
+    /// Returns the incarnation of the process the event is about, if
+    /// it is about the process.
+    fn incarnation(&self) -> Option<u64> {
+        match self {
+            EventType::Timeout(incarnation)
+            | EventType::Aborted(incarnation)
+            | EventType::Complete(incarnation) => Some(*incarnation),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the event tells that the process has exited.
+    fn is_exit(&self) -> bool {
+        matches!(self, EventType::Aborted(_) | EventType::Complete(_))
+    }
 }
 
 impl EventHandler {
@@ -87,27 +157,36 @@ impl EventHandler {
     /// # Arguments
     ///
     /// * `event_receiver` - The receiver channel to receive
-    ///                      `EventType` events.
+    ///   `EventType` events.
+    /// * `state` - The shared target `StateMachine` instance.
     /// * `process_manager` - The shared `ProcessManager` instance.
     /// * `heartbeat` - The shared `Heartbeat` instance.
     /// * `signal_handler` - The shared `SignalHandler` instance.
+    /// * `config` - The shared configuration.
     /// * `logger` - The shared `LocalLogger` instance.
     ///
     /// # Returns
     ///
     /// Returns a new `EventHandler` object.
     pub(crate) fn new(
-        event_receiver: mpsc::Receiver<EventType>,
+        event_receiver: EventReceiver,
+        state: Rc<StateMachine>,
         process_manager: Rc<ProcessManager>,
         heartbeat: Rc<Heartbeat>,
         signal_handler: Rc<SignalHandler>,
-        logger: Rc<LocalLogger>,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
     ) -> Self {
         EventHandler {
             event_receiver,
+            pending: VecDeque::new(),
+            state,
             process_manager,
             heartbeat,
             signal_handler,
+            shutdown_timer: ShutdownTimer::new(Rc::clone(&config), Rc::clone(&logger)),
+            hook_runner: HookRunner::new(Rc::clone(&config), Rc::clone(&logger)),
+            config,
             logger,
         }
     }
@@ -116,11 +195,13 @@ impl EventHandler {
     ///
     /// The `run` method runs the event handling loop for the
     /// `EventHandler`. It continuously listens for events from the
-    /// event receiver and processes them until either the process is
-    /// terminated or killed. Upon receiving an event, the method logs
-    /// and handles the event according to the specification. The
-    /// specification details the event handling logic and can be
-    /// found in spec/heartbeat.pdf in the source repository.
+    /// event receiver and processes them until the target state
+    /// becomes either terminated or killed. Upon receiving an event,
+    /// the method logs and handles the event according to the
+    /// specification. The specification details the event handling
+    /// logic and can be found in spec/heartbeat.pdf in the source
+    /// repository.  The method enables the `SignalHandler` as it
+    /// starts, and the handling of the last event disables it again.
     ///
     /// # Returns
     ///
@@ -140,13 +221,14 @@ impl EventHandler {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{EventHandler, EventType, ProcessManager, LocalLogger};
     /// use std::sync::mpsc;
     /// use std::rc::Rc;
     ///
     /// // Create necessary components and event receiver
     /// let (event_sender, event_receiver) = mpsc::channel();
+    /// let state = Rc::new(StateMachine::new());
     /// let process_manager = Rc::new(ProcessManager::new());
     /// let heartbeat = Rc::new(Heartbeat::new());
     /// let signal_handler = Rc::new(SignalHandler::new());
@@ -154,9 +236,11 @@ impl EventHandler {
     ///
     /// // Create and initialize the event handler
     /// let mut event_handler = EventHandler::new(event_receiver,
+    ///                                           state.clone(),
     ///                                           process_manager.clone(),
     ///                                           heartbeat.clone(),
     ///                                           signal_handler.clone(),
+    ///                                           config.clone(),
     ///                                           logger.clone());
     ///
     /// // Start the event handling loop
@@ -165,15 +249,30 @@ impl EventHandler {
     /// }
     /// ```
     pub(crate) async fn run(&mut self) -> Result<()> {
-        while !self.process_manager.is_terminated() && !self.process_manager.is_killed() {
-            if let Some(event_type) = self.event_receiver.recv().await {
+        self.signal_handler.enable();
+        while !self.state.get().is_stopped() {
+            if let Some(event_type) = self.next_event().await {
                 self.logger
                     .log(LogLevel::Debug, &format!("[{:?}] event raised", event_type));
                 match event_type {
-                    EventType::Timeout => self.consume_timeout_event()?,
-                    EventType::Aborted => self.consume_aborted_event()?,
-                    EventType::Complete => self.consume_complete_event()?,
-                    EventType::Signalled(sig) => self.consume_signaled_event(sig)?,
+                    EventType::Timeout(incarnation) => {
+                        self.consume_timeout_event(incarnation).await?
+                    }
+                    EventType::Aborted(_) => self.consume_aborted_event().await?,
+                    EventType::Complete(_) => self.consume_complete_event()?,
+                    EventType::Signalled(sig) => self.consume_signaled_event(sig).await?,
+                    EventType::Ping(pong) => {
+                        let _ = pong.send(());
+                    }
+                    EventType::Restart => self.consume_restart_event().await?,
+                    EventType::RestartRequested(incarnation) => {
+                        self.consume_restart_requested_event(incarnation).await?
+                    }
+                    EventType::Pause => self.heartbeat.pause(),
+                    EventType::Resume => self.heartbeat.resume(),
+                    EventType::Drill => self.heartbeat.drill(),
+                    EventType::Control(name, value) => self.heartbeat.control(name, value),
+                    EventType::Reload => self.heartbeat.reload(),
                 }
             } else {
                 // Queue is closed, and no more messages are in the
@@ -193,12 +292,13 @@ impl EventHandler {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{EventHandler, LogLevel, LocalLogger};
     /// use std::sync::mpsc;
     ///
     /// // Create necessary components and event receiver
     /// let (event_sender, event_receiver) = mpsc::channel();
+    /// let state = Rc::new(StateMachine::new());
     /// let process_manager = Rc::new(ProcessManager::new());
     /// let heartbeat = Rc::new(Heartbeat::new());
     /// let signal_handler = Rc::new(SignalHandler::new());
@@ -206,9 +306,11 @@ impl EventHandler {
     ///
     /// // Create and initialize the event handler
     /// let mut event_handler = EventHandler::new(event_receiver,
+    ///                                           state.clone(),
     ///                                           process_manager.clone(),
     ///                                           heartbeat.clone(),
     ///                                           signal_handler.clone(),
+    ///                                           config.clone(),
     ///                                           logger.clone());
     ///
     /// // Reset the event handler
@@ -219,42 +321,322 @@ impl EventHandler {
         self.clear_queue();
     }
 
-    fn consume_timeout_event(&self) -> Result<()> {
-        self.process_manager.kill_process()?;
-        self.signal_handler.close();
-        Ok(())
+    async fn consume_timeout_event(&mut self, incarnation: u64) -> Result<()> {
+        let current = self.process_manager.incarnation();
+        if incarnation != current {
+            self.logger.log_coded(
+                &message::EV001,
+                &format!(
+                    "ignore heartbeat timeout of process #{}; process #{} runs now",
+                    incarnation, current
+                ),
+            );
+            return Ok(());
+        }
+        // NOTE: The kill destroys the evidence of what the process was
+        // blocked on, so the report and the diagnostics come first.
+        if let Some(pid) = self.process_manager.pid() {
+            if let Err(err) = CrashReport::capture(pid).save(&self.config, self.logger.as_ref()) {
+                self.logger.log_coded(
+                    &message::EV009,
+                    &format!("unable to save hang report: {}", err),
+                );
+            }
+            if let Err(err) = self.hook_runner.run_diagnostics(pid).await {
+                self.logger.log_coded(
+                    &message::EV010,
+                    &format!("unable to collect diagnostics: {}", err),
+                );
+            }
+        }
+        let handle = self.process_manager.kill_process(incarnation)?;
+        self.signal_handler.disable();
+        let grace = self.kill_timeout()?;
+        self.await_exit(handle, grace).await
     }
 
-    fn consume_aborted_event(&self) -> Result<()> {
+    async fn consume_restart_event(&mut self) -> Result<()> {
+        self.logger
+            .log_coded(&message::EV002, "restart process on request");
+        let handle = self.process_manager.restart_process()?;
+        self.heartbeat.stop()?;
+        self.signal_handler.disable();
+        let grace = self.kill_timeout()?;
+        self.await_exit(handle, grace).await
+    }
+
+    /// Stops the process gracefully so that it starts again, as it
+    /// asked for.  The restart doesn't count against MAX-RETRIES.
+    async fn consume_restart_requested_event(&mut self, incarnation: u64) -> Result<()> {
+        let current = self.process_manager.incarnation();
+        if incarnation != current {
+            self.logger.log(
+                LogLevel::Debug,
+                &format!(
+                    "ignore restart request of process #{}; process #{} runs now",
+                    incarnation, current
+                ),
+            );
+            return Ok(());
+        }
+        self.logger
+            .log_coded(&message::EV008, "restart process at its own request");
+        let handle = self.process_manager.request_restart(incarnation)?;
+        self.heartbeat.stop()?;
+        self.signal_handler.disable();
+        let grace = self.process_manager.restart_grace()?;
+        self.await_exit(handle, grace).await
+    }
+
+    async fn consume_aborted_event(&self) -> Result<()> {
         self.logger
             .log(LogLevel::Trace, "EventHandler::consume_aborted_event()");
-        self.process_manager.set_killed();
+        let section = self.config.section(section::HEARTBEAT)?;
+        let stderr = self.process_manager.stderr_tail(STDERR_DRAIN_TIME).await;
+        ExitReport::new(
+            section.target_id()?.name(),
+            self.process_manager.last_pid(),
+            self.process_manager.exit_status(),
+            self.process_manager.uptime(),
+            self.process_manager.incarnation().saturating_sub(1),
+            &self.heartbeat.round_trips(),
+            &stderr,
+        )
+        .save(&self.config, self.logger.as_ref())?;
+        self.process_manager.abort()?;
         self.heartbeat.stop()?;
-        self.signal_handler.close();
+        self.signal_handler.disable();
         Ok(())
     }
 
     fn consume_complete_event(&self) -> Result<()> {
         self.logger
             .log(LogLevel::Trace, "EventHandler::consume_complete_event()");
-        self.process_manager.set_terminated();
+        self.process_manager.complete()?;
         self.heartbeat.stop()?;
-        self.signal_handler.close();
+        self.signal_handler.disable();
         Ok(())
     }
 
-    fn consume_signaled_event(&self, signal: Signal) -> Result<()> {
+    async fn consume_signaled_event(&mut self, signal: Signal) -> Result<()> {
         self.logger.log(
             LogLevel::Trace,
             &format!("EventHandler::consume_signaled_event({:#?})", signal),
         );
-        self.process_manager.raise_signal(signal)?;
+        if !signal.terminates() {
+            return self.forward_signal(signal).await;
+        }
+        // The process keeps running after a signal that detaches.
+        self.shutdown_timer.arm(if signal.detaches() {
+            None
+        } else {
+            self.process_manager.pid()
+        })?;
+        self.run_signal_hook(signal).await?;
+        let handle = self.process_manager.raise_signal(signal)?;
         self.heartbeat.stop()?;
-        self.signal_handler.close();
+        // NOTE: Keep the SignalHandler enabled until the process exits,
+        // so that a repeated signal can hurry the shutdown.
+        let result = self.await_exit(handle, Duration::ZERO).await;
+        self.signal_handler.disable();
+        result
+    }
+
+    /// Runs the hook of the `signal`, if any, and then forwards the
+    /// `signal` to the managed process unless only the hook handles
+    /// it.  Waits for the acknowledgement of its delivery.  Neither
+    /// the heartbeat nor the `SignalHandler` is stopped, as the
+    /// supervision goes on.
+    async fn forward_signal(&self, signal: Signal) -> Result<()> {
+        self.run_signal_hook(signal).await?;
+        if !signal.relays() {
+            return Ok(());
+        }
+        let handle = self.process_manager.forward_signal(signal)?;
+        let action = handle.to_string();
+        match handle.wait(self.exit_timeout()?).await {
+            Some(ack) => self.log_ack(ack),
+            None => self.logger.log_coded(
+                &message::EV003,
+                &format!(
+                    "signal delivery {} not acknowledged; {}",
+                    action,
+                    self.process_manager.diagnose()
+                ),
+            ),
+        }
+        Ok(())
+    }
+
+    /// Runs the hook of the `signal`, if any.
+    async fn run_signal_hook(&self, signal: Signal) -> Result<()> {
+        self.hook_runner
+            .run_signal_hook(signal.into(), self.process_manager.pid())
+            .await
+    }
+
+    /// Returns EXIT-TIMEOUT as a `Duration`.
+    fn exit_timeout(&self) -> Result<Duration> {
+        Ok(Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::EXIT_TIMEOUT, DEFAULT_EXIT_TIMEOUT)?
+                .try_into()?,
+        ))
+    }
+
+    /// Returns KILL-TIMEOUT as a `Duration`, or zero without it.
+    fn kill_timeout(&self) -> Result<Duration> {
+        Ok(Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::KILL_TIMEOUT, 0)?
+                .try_into()?,
+        ))
+    }
+
+    /// Waits for the acknowledgement of an action on the managed
+    /// process for up to EXIT-TIMEOUT seconds past the `grace` period
+    /// the action gives the process.  Escalates to killing
+    /// the process if it is still running by then.  Also escalates as
+    /// soon as another `SIGTERM` arrives in the meantime, as an
+    /// operator who repeats the signal expects a prompt exit.
+    /// Forwards signals that arrive in the meantime, and ignores any
+    /// other event.
+    async fn await_exit(&mut self, handle: ActionHandle, grace: Duration) -> Result<()> {
+        let bound = self.exit_timeout()?;
+        let action = handle.to_string();
+        let wait = handle.wait(bound + grace);
+        tokio::pin!(wait);
+        let reason = loop {
+            tokio::select! {
+                ack = &mut wait => match ack {
+                    Some(ack) => {
+                        self.log_ack(ack);
+                        return Ok(());
+                    }
+                    None => break format!(
+                        "process still running {}s after the action {}; {}",
+                        (bound + grace).as_secs(),
+                        action,
+                        self.process_manager.diagnose()
+                    ),
+                },
+                event = self.event_receiver.recv() => match event {
+                    Some(EventType::Signalled(Signal::Term)) => break "signal repeated during shutdown".to_owned(),
+                    Some(EventType::Signalled(signal)) if !signal.terminates() => self.forward_signal(signal).await?,
+                    Some(EventType::Ping(pong)) => {
+                        let _ = pong.send(());
+                    }
+                    Some(event) => self.logger.log(
+                        LogLevel::Debug,
+                        &format!("[{:?}] event ignored during shutdown", event),
+                    ),
+                    None => panic!("event queue closed"),
+                },
+            }
+        };
+        self.logger
+            .log_coded(&message::EV004, &format!("{}; killing it", reason));
+        let handle = self.process_manager.force_kill()?;
+        match handle.wait(bound).await {
+            Some(ack) => self.log_ack(ack),
+            None => self.logger.log_coded(
+                &message::EV005,
+                &format!(
+                    "process still running {}s after kill; {}",
+                    bound.as_secs(),
+                    self.process_manager.diagnose()
+                ),
+            ),
+        }
         Ok(())
     }
 
+    fn log_ack(&self, ack: Ack) {
+        match ack {
+            Ack::Exited(status) => self
+                .logger
+                .log_coded(&message::EV006, &format!("process exited ({})", status)),
+            Ack::Delivered => self.logger.log(LogLevel::Debug, "signal delivered"),
+            Ack::Vanished => self
+                .logger
+                .log(LogLevel::Debug, "process exited before the action"),
+            Ack::Queued => self
+                .logger
+                .log(LogLevel::Debug, "signal queued for the next process"),
+            Ack::Stuck => self
+                .logger
+                .log_coded(&message::EV007, "process did not exit after kill"),
+        }
+    }
+
+    /// Returns the next event to handle, after coalescing it with the
+    /// events already in the queue.
+    ///
+    /// Events raised at nearly the same time arrive in the order the
+    /// components got to raise them, such as a heartbeat timeout
+    /// right before the process aborts by itself.  Handling them one
+    /// by one would kill a process that has already exited.  So
+    /// `EventHandler` collects the events already raised, and applies
+    /// the following rules before handling the oldest:
+    ///
+    /// * An event about an earlier incarnation of the process is
+    ///   stale, and gets dropped.
+    /// * The exit of an incarnation supersedes a heartbeat timeout of
+    ///   the same incarnation.
+    ///
+    /// The other events keep their order.
+    async fn next_event(&mut self) -> Option<EventType> {
+        loop {
+            if self.pending.is_empty() {
+                let event = self.event_receiver.recv().await?;
+                self.pending.push_back(event);
+            }
+            // NOTE: A component that waits for room in the queue only
+            // raises its event once it gets to run.
+            tokio::task::yield_now().await;
+            while let Ok(event) = self.event_receiver.try_recv() {
+                self.pending.push_back(event);
+            }
+            self.coalesce();
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Drops the pending events that are stale or superseded.
+    fn coalesce(&mut self) {
+        let current = self.process_manager.incarnation();
+        let exited: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|event| event.is_exit())
+            .filter_map(EventType::incarnation)
+            .collect();
+        let mut kept = VecDeque::with_capacity(self.pending.len());
+        for event in self.pending.drain(..) {
+            let dropped = match (&event, event.incarnation()) {
+                (_, Some(incarnation)) if incarnation != current => Some("stale"),
+                (EventType::Timeout(incarnation), _) if exited.contains(incarnation) => {
+                    Some("superseded by the exit of the process")
+                }
+                _ => None,
+            };
+            match dropped {
+                Some(reason) => self.logger.log(
+                    LogLevel::Debug,
+                    &format!("[{:?}] event dropped as {}", event, reason),
+                ),
+                None => kept.push_back(event),
+            }
+        }
+        self.pending = kept;
+    }
+
     fn clear_queue(&mut self) {
+        self.pending.clear();
         loop {
             match self.event_receiver.try_recv() {
                 Err(TryRecvError::Empty) => break,
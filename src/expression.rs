@@ -20,6 +20,7 @@ use crate::error::type_error;
 use crate::keyword::Keyword;
 use crate::result::Result;
 use sexp::Sexp;
+use std::fmt::{self, Display};
 
 /// Represents an atomic value in an S-expression configuration file.
 ///
@@ -31,7 +32,7 @@ use sexp::Sexp;
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::Atom;
 ///
 /// let atom = Atom::String("Hello, World!".to_owned());
@@ -42,12 +43,14 @@ use sexp::Sexp;
 ///     Atom::Keyword(value) => println!("Keyword: {:?}", value),
 /// }
 /// ```
+#[derive(Clone)]
 pub(crate) enum Atom {
     /// Represents a string value in the configuration file.
     String(String),
     /// Represents an integer value in the configuration file.
     Int(i64),
     /// Represents a float value in the configuration file.
+    #[allow(dead_code)]
     Float(f64),
     /// Represents a keyword value in the configuration file.
     Keyword(Keyword),
@@ -65,7 +68,7 @@ pub(crate) enum Atom {
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::{List, Expression, Atom};
 ///
 /// let list: List = vec![
@@ -92,7 +95,7 @@ pub(crate) type List = Vec<Expression>;
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::{Expression, Atom};
 ///
 /// let expression = Expression::Atom(Atom::Int(42));
@@ -101,6 +104,7 @@ pub(crate) type List = Vec<Expression>;
 ///     Expression::List(_) => println!("List expression found!"),
 /// }
 /// ```
+#[derive(Clone)]
 pub(crate) enum Expression {
     /// Represents an atomic value within an expression.
     Atom(Atom),
@@ -132,7 +136,7 @@ impl Expression {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{Sexp, Expression};
     ///
     /// // Example S-expression object
@@ -183,6 +187,18 @@ impl Expression {
         }
     }
 
+    /// Asserts the given expression to be a boolean, and returns the
+    /// boolean if it really is.  Otherwise returns a type error.  As
+    /// in Lisp, `t` is true, and `nil` or the empty list is false.
+    pub(crate) fn boolean(&self) -> Result<bool> {
+        match self {
+            Expression::Atom(Atom::String(s)) if s.eq_ignore_ascii_case("t") => Ok(true),
+            Expression::Atom(Atom::String(s)) if s.eq_ignore_ascii_case("nil") => Ok(false),
+            Expression::List(list) if list.is_empty() => Ok(false),
+            _ => Err(type_error("boolean")),
+        }
+    }
+
     /// Asserts the given expression to be a list of strings, and
     /// returns the list of strings if it really is.  Otherwise
     /// returns a type error.
@@ -198,6 +214,96 @@ impl Expression {
         }
     }
 
+    /// Asserts the given expression to be a list of integers, and
+    /// returns the list of integers if it really is.  Otherwise
+    /// returns a type error.
+    pub(crate) fn integer_list(&self) -> Result<Vec<i64>> {
+        if let Expression::List(list) = self {
+            list.iter().map(Expression::integer).collect()
+        } else {
+            Err(type_error("integer_list"))
+        }
+    }
+
+    /// Asserts the given expression to be a list of lists of strings,
+    /// and returns them if it really is.  Otherwise returns a type
+    /// error.
+    pub(crate) fn string_lists(&self) -> Result<Vec<Vec<String>>> {
+        if let Expression::List(list) = self {
+            list.iter().map(Expression::string_list).collect()
+        } else {
+            Err(type_error("list of string_list"))
+        }
+    }
+
+    /// Asserts the given expression to be a property list, and
+    /// returns its indicator-value pairs if it really is.  Otherwise
+    /// returns a type error.
+    pub(crate) fn plist_pairs(&self) -> Result<Vec<(&Keyword, &Expression)>> {
+        match self {
+            Expression::List(list) if is_plist(list) => Ok(list
+                .chunks(2)
+                .map(|pair| (pair[0].keyword().expect("plist indicator"), &pair[1]))
+                .collect()),
+            _ => Err(type_error("plist")),
+        }
+    }
+
+    /// Builds a property list out of the `pairs` of indicators and
+    /// values, such as `(:state :running :pid 1234)`.
+    pub(crate) fn plist(pairs: Vec<(Keyword, Expression)>) -> Self {
+        Expression::List(
+            pairs
+                .into_iter()
+                .flat_map(|(indicator, value)| [Expression::from(indicator), value])
+                .collect(),
+        )
+    }
+
+    /// Converts the expression to JSON for consumers that don't
+    /// speak S-expressions.
+    ///
+    /// A property list becomes an object keyed by the lower case
+    /// names of its indicators, and any other list becomes an array.
+    /// A keyword becomes the string of its lower case name.  `t`
+    /// becomes `true`, and `nil` or the empty list becomes `null`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let status = Expression::plist(vec![
+    ///     (kw![state], Expression::from(kw![running])),
+    ///     (kw![pid], Expression::from(false)),
+    /// ]);
+    /// assert_eq!(status.to_json(), r#"{"state":"running","pid":null}"#);
+    /// ```
+    pub(crate) fn to_json(&self) -> String {
+        match self {
+            Expression::Atom(Atom::String(s)) if s.eq_ignore_ascii_case("t") => "true".to_owned(),
+            Expression::Atom(Atom::String(s)) if s.eq_ignore_ascii_case("nil") => "null".to_owned(),
+            Expression::Atom(Atom::String(s)) => json_string(s),
+            Expression::Atom(Atom::Int(i)) => i.to_string(),
+            Expression::Atom(Atom::Float(x)) if x.is_finite() => x.to_string(),
+            Expression::Atom(Atom::Float(_)) => "null".to_owned(),
+            Expression::Atom(Atom::Keyword(keyword)) => json_string(&keyword.name().to_lowercase()),
+            Expression::List(list) if list.is_empty() => "null".to_owned(),
+            Expression::List(list) if is_plist(list) => format!(
+                "{{{}}}",
+                list.chunks(2)
+                    .map(|pair| format!("{}:{}", pair[0].to_json(), pair[1].to_json()))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            Expression::List(list) => format!(
+                "[{}]",
+                list.iter()
+                    .map(Expression::to_json)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
     fn from_atom(atom: sexp::Atom) -> Result<Atom> {
         match atom {
             sexp::Atom::I(i) => Ok(Atom::Int(i)),
@@ -220,3 +326,120 @@ impl Expression {
         Ok(v)
     }
 }
+
+impl Display for Atom {
+    /// Writes the atom the way the configuration file would spell
+    /// it.  Quotes a string that would otherwise read back as
+    /// something else, such as a number or a keyword.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Atom::String(s) if needs_quotes(s) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Atom::String(s) => write!(f, "{}", s),
+            Atom::Int(i) => write!(f, "{}", i),
+            Atom::Float(x) => write!(f, "{}", x),
+            Atom::Keyword(keyword) => write!(f, ":{}", keyword.name().to_lowercase()),
+        }
+    }
+}
+
+/// Returns whether the string `s` must be quoted to read back as the
+/// same string.
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.starts_with(':')
+        || s.parse::<f64>().is_ok()
+        || s.chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\\' | '(' | ')'))
+}
+
+/// Returns whether the `list` is a property list, that is, a list of
+/// keywords each followed by a value.
+fn is_plist(list: &[Expression]) -> bool {
+    list.len().is_multiple_of(2)
+        && list
+            .iter()
+            .step_by(2)
+            .all(|expr| matches!(expr, Expression::Atom(Atom::Keyword(_))))
+}
+
+/// Quotes the string `s` as a JSON string.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Display for Expression {
+    /// Serializes the expression as an S-expression that
+    /// [`from_sexp`](#method.from_sexp) reads back as the same
+    /// expression.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let status = Expression::plist(vec![
+    ///     (kw![state], Expression::from(kw![running])),
+    ///     (kw![pid], Expression::from(1234)),
+    /// ]);
+    /// assert_eq!(status.to_string(), "(:state :running :pid 1234)");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Atom(atom) => write!(f, "{}", atom),
+            Expression::List(list) => {
+                write!(f, "(")?;
+                for (i, expr) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl From<Keyword> for Expression {
+    fn from(value: Keyword) -> Self {
+        Expression::Atom(Atom::Keyword(value))
+    }
+}
+
+impl From<i64> for Expression {
+    fn from(value: i64) -> Self {
+        Expression::Atom(Atom::Int(value))
+    }
+}
+
+impl From<&str> for Expression {
+    fn from(value: &str) -> Self {
+        Expression::Atom(Atom::String(value.to_owned()))
+    }
+}
+
+impl From<bool> for Expression {
+    /// Converts a boolean to `t` or `nil`, the way Lisp represents
+    /// it.
+    fn from(value: bool) -> Self {
+        if value {
+            Expression::Atom(Atom::String("t".to_owned()))
+        } else {
+            Expression::List(List::new())
+        }
+    }
+}
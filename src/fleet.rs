@@ -0,0 +1,427 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::codec::{self, Codec};
+use crate::config::{key, section, Config};
+use crate::context::Context;
+use crate::control::ControlServer;
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, Logger};
+use crate::protocol::Message;
+use crate::result::Result;
+use crate::signal::{self, Signal};
+#[cfg(feature = "zmq")]
+use crate::socket::SocketBuilder;
+use futures::stream::StreamExt;
+use nix::libc::{SIGINT, SIGPWR, SIGQUIT, SIGTERM};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tokio::sync::{mpsc, watch, Semaphore, SemaphorePermit};
+use tokio::time::{Duration, Instant};
+
+/// Returns whether the `config` is a manifest of a fleet rather than
+/// the configuration of a single target.
+pub(crate) fn is_manifest(config: &Config) -> Result<bool> {
+    Ok(config.section(section::HEARTBEAT)?.has_key(key::TARGETS))
+}
+
+/// A target in a [`Fleet`].
+struct Target {
+    id: Keyword,
+    /// The control server of the target, once it has started.
+    control_server: Option<Rc<ControlServer>>,
+    /// Passes the termination signals on to the target, once it has
+    /// started.
+    signals: Option<mpsc::UnboundedSender<Signal>>,
+    /// How the supervision of the target ended, once it has.
+    outcome: Option<String>,
+    /// The time the target took to stop when the fleet stopped it.
+    stopped_in: Option<Duration>,
+}
+
+/// Supervises a fleet of targets in a single `Heartbeat2` process.
+///
+/// A manifest is a configuration file that lists the configuration
+/// files of the targets in TARGETS.  `Heartbeat2` supervises each
+/// target concurrently as if it ran a `Heartbeat2` process for each.
+/// `Fleet` keeps track of the targets by their TARGET-ID, and
+/// answers requests about them on the control socket of the fleet.
+///
+/// # Requests
+///
+/// * `STATUS`: Responds with a list of the statuses of all the
+///   targets.  `STATUS` followed by `JSON` or `SEXP` responds in
+///   that format.
+/// * The TARGET-ID of a target followed by a request: Responds as the
+///   [`ControlServer`] of the target would to the request, such as
+///   `app TIMELINE TEXT`.
+///
+/// # Shutdown
+///
+/// `Fleet` handles `SIGTERM`, `SIGINT`, `SIGQUIT` and `SIGPWR` in
+/// place of the targets.  It passes the signal on to one target at a
+/// time, and waits for the target to stop before moving on to the
+/// next.  Each target stops within its own EXIT-TIMEOUT and
+/// SHUTDOWN-TIMEOUT.  `SIGINT` and `SIGPWR` stand for `SIGTERM`.
+/// Once all the targets stop, `Fleet` logs a summary of how each of
+/// them ended.
+///
+/// # Configuration
+///
+/// * TARGETS: Required.  The list of the paths to the configuration
+///   files of the targets.  A relative path is relative to the
+///   directory of the manifest.
+/// * CONTROL-ENDPOINT: Optional.  The endpoint to bind the control
+///   socket of the fleet to.
+/// * CONTROL-FORMAT: Optional.  `:sexp` or `:json`, the format of
+///   the responses on the control socket of the fleet, including
+///   those of the targets it passes requests on to.  The default is
+///   `:sexp`.
+/// * MAX-CONCURRENT-RESTARTS: Optional.  The number of targets that
+///   may restart at the same time.  The other targets queue up for
+///   their turn.  This keeps an outage of a dependency all the
+///   targets share from restarting them all at once and starving the
+///   host.  There is no limit without it.
+/// * SHUTDOWN-ORDER: Optional.  The list of the TARGET-IDs of the
+///   targets to stop first, in order.  The rest stop in the reverse
+///   order of TARGETS.
+pub(crate) struct Fleet {
+    targets: RefCell<Vec<Target>>,
+    /// Notifies that a target has finished.
+    finished: watch::Sender<()>,
+    restarts: Option<Semaphore>,
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    context: Context,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl Fleet {
+    /// Creates a new `Fleet` from the manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The ZeroMQ context to create the socket in.
+    /// * `config` - The manifest.
+    /// * `logger` - A shared reference to the logger.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if MAX-CONCURRENT-RESTARTS isn't a positive
+    /// integer.
+    pub(crate) fn new(
+        context: Context,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
+    ) -> Result<Self> {
+        let section = config.section(section::HEARTBEAT)?;
+        let restarts = if section.has_key(key::MAX_CONCURRENT_RESTARTS) {
+            match section.integer(key::MAX_CONCURRENT_RESTARTS)? {
+                limit if limit > 0 => Some(Semaphore::new(limit.try_into()?)),
+                _ => {
+                    return Err(config_format_error(&format!(
+                        "{} must be positive",
+                        key::MAX_CONCURRENT_RESTARTS
+                    )))
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Fleet {
+            targets: Default::default(),
+            finished: watch::channel(()).0,
+            restarts,
+            context,
+            config,
+            logger,
+        })
+    }
+
+    /// Returns the paths to the configuration files of the targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `manifest` - The path to the manifest.
+    pub(crate) fn target_paths(&self, manifest: &Path) -> Result<Vec<PathBuf>> {
+        let directory = manifest.parent().unwrap_or_else(|| Path::new(""));
+        Ok(self
+            .config
+            .section(section::HEARTBEAT)?
+            .string_list(key::TARGETS)?
+            .into_iter()
+            .map(|path| directory.join(path))
+            .collect())
+    }
+
+    /// Adds the target `id` to the fleet, in the order of TARGETS.
+    pub(crate) fn add(&self, id: Keyword) {
+        self.targets.borrow_mut().push(Target {
+            id,
+            control_server: None,
+            signals: None,
+            outcome: None,
+            stopped_in: None,
+        });
+    }
+
+    /// Records that the target `id` has started, answering control
+    /// requests with the `control_server` and taking the termination
+    /// signals through `signals`.
+    pub(crate) fn register(
+        &self,
+        id: &Keyword,
+        control_server: Rc<ControlServer>,
+        signals: mpsc::UnboundedSender<Signal>,
+    ) {
+        if let Some(target) = self.targets.borrow_mut().iter_mut().find(|t| t.id == *id) {
+            target.control_server = Some(control_server);
+            target.signals = Some(signals);
+        }
+    }
+
+    /// Records that the supervision of the target `id` has ended with
+    /// the `outcome`.
+    pub(crate) fn finish(&self, id: &Keyword, outcome: String) {
+        if let Some(target) = self.targets.borrow_mut().iter_mut().find(|t| t.id == *id) {
+            target.outcome = Some(outcome);
+        }
+        self.finished.send_replace(());
+    }
+
+    /// Returns the indices of the targets in the order to stop them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if SHUTDOWN-ORDER names a target
+    /// that isn't in the fleet.
+    pub(crate) fn shutdown_order(&self) -> Result<Vec<usize>> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let targets = self.targets.borrow();
+        let mut order = vec![];
+        if section.has_key(key::SHUTDOWN_ORDER) {
+            for name in section.string_list(key::SHUTDOWN_ORDER)? {
+                let index = targets
+                    .iter()
+                    .position(|target| name.eq_ignore_ascii_case(target.id.name()))
+                    .ok_or_else(|| {
+                        config_format_error(&format!(
+                            "unknown target [{}] in {}",
+                            name,
+                            key::SHUTDOWN_ORDER
+                        ))
+                    })?;
+                if !order.contains(&index) {
+                    order.push(index);
+                }
+            }
+        }
+        let rest: Vec<usize> = (0..targets.len())
+            .rev()
+            .filter(|index| !order.contains(index))
+            .collect();
+        order.extend(rest);
+        Ok(order)
+    }
+
+    /// Waits for a termination signal, and then stops the targets
+    /// one by one in the shutdown order.
+    pub(crate) async fn stop_on_signal(&self) -> Result<()> {
+        let mut signals = signal::register(&[SIGTERM, SIGINT, SIGQUIT, SIGPWR])?;
+        let signal = match signals.next().await {
+            Some(SIGQUIT) => Signal::Quit,
+            Some(_) => Signal::Term,
+            None => return Ok(()),
+        };
+        self.logger.log_coded(
+            &message::FL001,
+            &format!("stop the fleet on [{:?}]", signal),
+        );
+        for index in self.shutdown_order()? {
+            self.stop(index, signal).await?;
+        }
+        Ok(())
+    }
+
+    /// Passes the `signal` on to the target at the `index`, and waits
+    /// for it to stop.
+    async fn stop(&self, index: usize, signal: Signal) -> Result<()> {
+        let (id, signals) = {
+            let target = &self.targets.borrow()[index];
+            if target.outcome.is_some() {
+                return Ok(());
+            }
+            (target.id.clone(), target.signals.clone())
+        };
+        let Some(signals) = signals else {
+            self.logger.log_coded(
+                &message::FL002,
+                &format!("target [{}] hasn't started; unable to stop it", id),
+            );
+            return Ok(());
+        };
+        self.logger
+            .log_coded(&message::FL003, &format!("stop target [{}]", id));
+        let started = Instant::now();
+        let mut finished = self.finished.subscribe();
+        // NOTE: The target may have finished in the meantime, closing
+        // the channel.
+        if signals.send(signal).is_ok() {
+            while self.targets.borrow()[index].outcome.is_none() {
+                finished.changed().await?;
+            }
+        }
+        self.targets.borrow_mut()[index].stopped_in = Some(started.elapsed());
+        Ok(())
+    }
+
+    /// Logs how the supervision of each target ended.
+    pub(crate) fn log_summary(&self) {
+        for target in self.targets.borrow().iter() {
+            let status = target
+                .control_server
+                .as_ref()
+                .map_or_else(|| "never started".to_owned(), |c| c.status().to_string());
+            let stopped = target.stopped_in.map_or_else(String::new, |elapsed| {
+                format!("; stopped in {:.1}s", elapsed.as_secs_f64())
+            });
+            self.logger.log_coded(
+                &message::FL004,
+                &format!(
+                    "target [{}] {}: {}{}",
+                    target.id,
+                    target.outcome.as_deref().unwrap_or("unfinished"),
+                    status,
+                    stopped
+                ),
+            );
+        }
+    }
+
+    /// Waits for the turn of a target to restart under
+    /// MAX-CONCURRENT-RESTARTS.
+    ///
+    /// # Returns
+    ///
+    /// Returns the permit to restart, which ends the turn when
+    /// dropped, or `None` if there is no limit.
+    pub(crate) async fn restart_permit(&self, id: &Keyword) -> Result<Option<SemaphorePermit<'_>>> {
+        let Some(restarts) = &self.restarts else {
+            return Ok(None);
+        };
+        if let Ok(permit) = restarts.try_acquire() {
+            return Ok(Some(permit));
+        }
+        self.logger.log_coded(
+            &message::FL005,
+            &format!("target [{}] waits for its turn to restart", id),
+        );
+        Ok(Some(restarts.acquire().await?))
+    }
+
+    /// Returns whether the control socket of the fleet should run.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config
+            .section(section::HEARTBEAT)
+            .is_ok_and(|section| section.has_key(key::CONTROL_ENDPOINT))
+    }
+
+    /// Binds the control socket of the fleet and answers requests
+    /// until `Heartbeat2` exits.
+    #[cfg(feature = "zmq")]
+    pub(crate) async fn run(&self) -> Result<()> {
+        let endpoint = self
+            .config
+            .section(section::HEARTBEAT)?
+            .string(key::CONTROL_ENDPOINT)?;
+        let codec = codec::from_config(&self.config, key::CONTROL_FORMAT)?;
+        self.logger.log_coded(
+            &message::FL006,
+            &format!("serve fleet control requests on {}", endpoint),
+        );
+        let mut socket = SocketBuilder::new(self.context.clone())
+            .endpoint(endpoint)
+            .linger(false)
+            .rep()
+            .bind()?;
+        loop {
+            let (request, sender) = socket.recv_request().await?;
+            socket = sender
+                .send_string(&self.respond(&request, codec).await)
+                .await?;
+        }
+    }
+
+    /// Stands in for the control socket without the `zmq` feature.
+    #[cfg(not(feature = "zmq"))]
+    pub(crate) async fn run(&self) -> Result<()> {
+        Err(config_format_error(&format!(
+            "{} needs the zmq feature",
+            key::CONTROL_ENDPOINT
+        )))
+    }
+
+    /// Returns the response to the `request`, either from the target
+    /// it addresses or about the whole fleet, encoded with the
+    /// `codec` of the control socket of the fleet.
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    async fn respond(&self, request: &[Message], codec: &dyn Codec) -> String {
+        // NOTE: Release the targets before awaiting the response of a
+        // target, as the target may register with the fleet meanwhile.
+        let control_server = request.first().and_then(|id| {
+            self.targets
+                .borrow()
+                .iter()
+                .find(|target| id.as_str().eq_ignore_ascii_case(target.id.name()))
+                .and_then(|target| target.control_server.clone())
+        });
+        if let Some(control_server) = control_server {
+            return control_server.respond(&request[1..], codec).await;
+        }
+        let targets = self.targets.borrow();
+        let response = match request.first() {
+            Some(message) if *message == kw![status] => Expression::List(
+                targets
+                    .iter()
+                    .filter_map(|target| target.control_server.as_ref())
+                    .map(|control_server| control_server.status())
+                    .collect(),
+            ),
+            Some(message) => {
+                self.logger.log_coded(
+                    &message::FL007,
+                    &format!("unknown fleet request [{}]", message.as_str()),
+                );
+                Expression::plist(vec![(
+                    kw![error],
+                    Expression::from(
+                        format!("unknown target or request [{}]", message.as_str()).as_str(),
+                    ),
+                )])
+            }
+            None => Expression::plist(vec![(kw![error], Expression::from("empty request"))]),
+        };
+        codec::requested(request.get(1))
+            .unwrap_or(codec)
+            .encode(&response)
+    }
+}
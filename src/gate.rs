@@ -0,0 +1,125 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::hook::HookRunner;
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::rc::Rc;
+
+/// Tells a load balancer whether to send traffic to the target.
+///
+/// Restarting a target that still receives traffic turns the restart
+/// into errors the users see.  `HealthGate` opens once the target
+/// answers a heartbeat, and closes as soon as it misses one, before
+/// `Heartbeat2` kills it.  It also closes while the target is
+/// degraded, and when the heartbeat stops.  Opening the gate creates
+/// the marker file and runs the register command.  Closing it removes
+/// the marker file and runs the deregister command.  A load balancer
+/// can check the marker file, or the commands can tell it directly,
+/// such as by deregistering the service from Consul or draining an
+/// nginx upstream.
+///
+/// The commands run as hooks under HOOK-TIMEOUT.  A failed command,
+/// or a marker file that can't be created or removed, gets logged, but
+/// doesn't affect the supervision.
+///
+/// # Configuration
+///
+/// * HEALTH-MARKER: Optional.  The path of the marker file.
+/// * REGISTER-COMMAND: Optional.  The command to run when the gate
+///   opens, such as `("consul" "services" "register" "app.json")`.
+/// * DEREGISTER-COMMAND: Optional.  The command to run when the gate
+///   closes.
+pub(crate) struct HealthGate {
+    /// Whether the gate is open, or `None` until it first opens or
+    /// closes.  A marker file may be left over from a previous run.
+    open: Cell<Option<bool>>,
+    hook_runner: HookRunner,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl HealthGate {
+    /// Creates a new `HealthGate`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        HealthGate {
+            open: Cell::new(None),
+            hook_runner: HookRunner::new(Rc::clone(&config), Rc::clone(&logger)),
+            config,
+            logger,
+        }
+    }
+
+    /// Opens the gate, unless it is already open.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, or if the
+    /// marker file can't be created.
+    pub(crate) async fn open(&self) -> Result<()> {
+        if self.open.get() == Some(true) {
+            return Ok(());
+        }
+        self.open.set(Some(true));
+        self.logger.log_coded(&message::GT001, "open health gate");
+        let section = self.config.section(section::HEARTBEAT)?;
+        if section.has_key(key::HEALTH_MARKER) {
+            File::create(section.string(key::HEALTH_MARKER)?)?;
+        }
+        if section.has_key(key::REGISTER_COMMAND) {
+            let command = section.string_list(key::REGISTER_COMMAND)?;
+            self.hook_runner.run("register", &command, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Closes the gate, unless it is already closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, or if the
+    /// marker file can't be removed.
+    pub(crate) async fn close(&self) -> Result<()> {
+        if self.open.get() == Some(false) {
+            return Ok(());
+        }
+        self.open.set(Some(false));
+        self.logger.log_coded(&message::GT002, "close health gate");
+        let section = self.config.section(section::HEARTBEAT)?;
+        if section.has_key(key::HEALTH_MARKER) {
+            match fs::remove_file(section.string(key::HEALTH_MARKER)?) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+        if section.has_key(key::DEREGISTER_COMMAND) {
+            let command = section.string_list(key::DEREGISTER_COMMAND)?;
+            self.hook_runner.run("deregister", &command, None).await?;
+        }
+        Ok(())
+    }
+}
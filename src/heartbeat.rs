@@ -16,19 +16,64 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::callback::{Event, Notifier};
+use crate::channel::EventSender;
 use crate::config::{key, section, Config};
-use crate::error::{illegal_state_error, peer_channel_closed_error};
+use crate::context::Context;
+use crate::error::{config_format_error, illegal_state_error, peer_channel_closed_error};
 use crate::event::EventType;
+use crate::gate::HealthGate;
+use crate::history::History;
+use crate::keyword::Keyword;
 use crate::kw;
-use crate::logger::{LocalLogger, LogLevel};
+use crate::logger::{message, LogLevel, Logger};
+use crate::metrics::Metrics;
+use crate::naming::{Advertiser, LoadConfig, Naming, Resolver};
+use crate::process::ProcessManager;
+use crate::protocol;
+use crate::reaper::OwnChild;
 use crate::result::Result;
-use crate::socket::{RecvError, SocketBuilder};
-use crate::Sup;
+use crate::sampling::BeatLog;
+use crate::state::{StateMachine, TargetState};
+use crate::timeline::Timeline;
+use crate::transport::{PassiveSocket, Reply, Transport, ZmqTransport};
 use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::process::Stdio;
 use std::rc::Rc;
-use tmq::{self, Context};
-use tokio::sync::{mpsc, oneshot};
-use tokio::time::{sleep, Duration};
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::oneshot;
+use tokio::time::{interval_at, sleep, Duration, Instant, Interval, MissedTickBehavior};
+
+/// The default spacing between retries of a timed-out heartbeat in
+/// milliseconds.
+static DEFAULT_RETRY_SPACING: i64 = 200;
+
+/// The default discrepancy in seconds between the expected and the
+/// actual wake-up times that counts as a clock jump.
+static DEFAULT_CLOCK_JUMP_THRESHOLD: i64 = 5;
+
+/// The default shortest interval in seconds between heartbeats in the
+/// adaptive mode.
+static DEFAULT_MIN_INTERVAL: i64 = 1;
+
+/// The factor of HEARTBEAT-TIMEOUT that makes the default of
+/// BUSY-TIMEOUT.
+static DEFAULT_BUSY_TIMEOUT_FACTOR: u64 = 2;
+
+/// The default term of a lease on the target in the longest intervals
+/// between heartbeats.
+static DEFAULT_LEASE_INTERVALS: i64 = 3;
+
+/// The default window in seconds over which the success ratio of the
+/// heartbeats counts.
+static DEFAULT_RATIO_WINDOW: i64 = 120;
+
+/// The number of the latest round trip times of the heartbeats to
+/// keep for the crash report.
+static RECENT_ROUND_TRIPS: usize = 10;
 
 /// Represents the status of the Heartbeat at a given point in time.
 ///
@@ -45,7 +90,7 @@ use tokio::time::{sleep, Duration};
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::Status;
 ///
 /// let status = Status::Ready;
@@ -54,6 +99,12 @@ use tokio::time::{sleep, Duration};
 ///     Status::Ready => println!("Heartbeat is ready."),
 ///     Status::Req => println!("Heartbeat is waiting for a response."),
 ///     Status::Timeout => println!("Heartbeat has timed out."),
+///     Status::NotReady => println!("Target isn't ready."),
+///     Status::Dead => println!("Target is dead."),
+///     Status::Degraded => println!("Target serves, but is impaired."),
+///     Status::Busy => println!("Target is busy."),
+///     Status::RestartRequested => println!("Target wants a restart."),
+///     Status::Rejected => println!("Target is leased to another supervisor."),
 /// }
 /// ```
 #[derive(Clone, Copy, Debug)]
@@ -66,10 +117,96 @@ pub(crate) enum Status {
     /// Indicates that the Heartbeat has timed out without receiving a
     /// response.
     Timeout,
+    /// Indicates that the target is alive, but not ready to serve.
+    NotReady,
+    /// Indicates that the exec probe found the target dead.
+    Dead,
+    /// Indicates that the target is alive and serves, but is
+    /// impaired.
+    Degraded,
+    /// Indicates that the target is alive, but may be slow to answer
+    /// the next heartbeat.
+    Busy,
+    /// Indicates that the target is alive, but wants to be
+    /// restarted.
+    RestartRequested,
+    /// Indicates that the target is alive, but rejected the heartbeat
+    /// as another supervisor owns its lease.
+    Rejected,
+}
+
+/// Returns the reason a degraded target gave with REASON in the
+/// `telemetry` of its answer, if any.
+fn degraded_reason(telemetry: &[(Keyword, String)]) -> String {
+    telemetry
+        .iter()
+        .find(|(name, _)| *name == kw![reason])
+        .map_or_else(|| "no reason given".to_owned(), |(_, value)| value.clone())
+}
+
+/// Returns an [`Interval`] that ticks every `period` from the `start`
+/// on, and skips the ticks it misses.
+fn deadlines(start: Instant, period: Duration) -> Interval {
+    let mut ticker = interval_at(start, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticker
+}
+
+/// Enumerates the ways to check the health of the target.
+#[derive(Clone, Copy, Debug)]
+enum CheckType {
+    /// Sends heartbeats over ZeroMQ.
+    Zmq,
+    /// Connects to the endpoint over TCP.
+    Tcp,
+    /// Runs CHECK-COMMAND.
+    Exec,
+    /// Waits for the liveness messages the target sends on its own.
+    Passive,
+}
+
+/// Tells the health of the target by the exit code of PROBE-COMMAND,
+/// as the exec probes of Kubernetes do:
+///
+/// * 0: The target is healthy.
+/// * 1: The target is alive, but not ready to serve.  The target
+///   becomes degraded, and the health gate closes until it is ready
+///   again.  `Heartbeat2` doesn't restart it.
+/// * Any other exit code, or a signal: The target is dead.
+///   `Heartbeat2` kills and restarts it without a retry.
+fn probe_status(code: Option<i32>) -> Status {
+    match code {
+        Some(0) => Status::Ready,
+        Some(1) => Status::NotReady,
+        _ => Status::Dead,
+    }
+}
+
+/// Tells the health of the target by the exit code of CHECK-COMMAND.
+/// 0 means healthy.  Anything else counts as a missed heartbeat, which
+/// HEARTBEAT-RETRIES may retry.
+fn check_status(code: Option<i32>) -> Status {
+    match code {
+        Some(0) => Status::Ready,
+        _ => Status::Timeout,
+    }
+}
+
+/// The verdict of the success ratio of the heartbeats.
+enum SuccessRatio {
+    /// HEARTBEAT-SUCCESS-RATIO is absent, and a missed heartbeat
+    /// means a hang.
+    Off,
+    /// The ratio holds, or the heartbeats don't span the window yet.
+    Holds,
+    /// The ratio in percent fell below HEARTBEAT-SUCCESS-RATIO.
+    Below(usize),
 }
 
 enum TimerFuncResult {
     Continue,
+    /// The target answered, but only after a retry.
+    Recovered,
     Break,
 }
 
@@ -80,17 +217,41 @@ enum TimerFuncResult {
 /// application. It is responsible for sending regular heartbeats to
 /// the target application and raising timeout events if no response
 /// is received within the configured time. The `Heartbeat` struct
-/// contains various fields such as the ZeroMQ context, configuration,
+/// contains various fields such as the transport, configuration,
 /// the proxy object to the naming service (Sup), logger, status and
 /// channels for quiting Heartbeat loop and event notifications.
 pub(crate) struct Heartbeat {
-    context: Context,
+    transport: Rc<dyn Transport>,
+    state: Rc<StateMachine>,
     config: Rc<Config>,
-    sup: Rc<Sup>,
-    logger: Rc<LocalLogger>,
+    resolver: Resolver,
+    timeline: Rc<Timeline>,
+    beat_log: BeatLog,
+    health_gate: HealthGate,
+    logger: Rc<dyn Logger>,
     status: Cell<Status>,
+    paused: Cell<bool>,
+    drill: Cell<bool>,
+    controls: RefCell<Vec<(Keyword, String)>>,
+    sequence: Cell<u64>,
+    answers: RefCell<VecDeque<(Instant, bool)>>,
+    answers_since: Cell<Option<Instant>>,
+    round_trips: RefCell<VecDeque<Duration>>,
+    process_manager: Option<Rc<ProcessManager>>,
+    metrics: Option<Rc<Metrics>>,
+    history: Option<Rc<History>>,
+    advertiser: Option<Rc<Advertiser>>,
+    notifier: Option<Rc<Notifier>>,
+    degraded: Cell<bool>,
+    busy: Cell<bool>,
+    owner: RefCell<Option<(String, Instant)>>,
+    suspended: Cell<bool>,
+    jumped: Cell<bool>,
+    starting: Cell<bool>,
+    endpoint_in_use: RefCell<Option<String>>,
+    redirected: RefCell<Option<String>>,
     send_stop: RefCell<Option<oneshot::Sender<()>>>,
-    send_event: mpsc::Sender<EventType>,
+    send_event: EventSender,
 }
 
 impl Heartbeat {
@@ -99,16 +260,19 @@ impl Heartbeat {
     /// The `new` function creates a new `Heartbeat` instance with the
     /// specified parameters.  It takes a ZeroMQ context (`context`),
     /// a channel for sending event notifications (`send_event`), a
+    /// shared reference to the target state machine (`state`), a
     /// shared reference to the configuration (`config`), a shared
-    /// reference to the naming service (`sup`), and a shared
+    /// reference to the naming service (`naming`), and a shared
     /// reference to the logger (`logger`).
     ///
     /// # Arguments
     ///
     /// * `context` - The ZeroMQ context for the Heartbeat.
     /// * `send_event` - The channel for sending event notifications.
+    /// * `state` - A shared reference to the target state machine.
     /// * `config` - A shared reference to the configuration.
-    /// * `sup` - A shared reference to the naming service.
+    /// * `naming` - A shared reference to the naming service.
+    /// * `timeline` - A shared reference to the timeline.
     /// * `logger` - A shared reference to the logger.
     ///
     /// # Returns
@@ -117,35 +281,116 @@ impl Heartbeat {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{Context, EventType, Config, Sup, LocalLogger};
     ///
-    /// let (send_event, recv_event) = mpsc::channel();
+    /// let (send_event, recv_event) = event_queue(1, Rc::clone(&logger));
     /// let context = Context::new();
     /// let config = Rc::new(Config::new());
-    /// let sup = Rc::new(Sup::new());
     /// let logger = Rc::new(LocalLogger::new());
+    /// let naming = naming_service(context.clone(), Rc::clone(&config), logger.clone())?;
     ///
-    /// let heartbeat = Heartbeat::new(context, send_event, config, sup, logger);
+    /// let heartbeat = Heartbeat::new(context, send_event, state, config, naming, timeline, logger);
     /// ```
     pub(crate) fn new(
         context: Context,
-        send_event: mpsc::Sender<EventType>,
+        send_event: EventSender,
+        state: Rc<StateMachine>,
         config: Rc<Config>,
-        sup: Rc<Sup>,
-        logger: Rc<LocalLogger>,
+        naming: Rc<dyn Naming>,
+        timeline: Rc<Timeline>,
+        logger: Rc<dyn Logger>,
     ) -> Self {
         Heartbeat {
-            context,
+            transport: Rc::new(ZmqTransport::new(context)),
+            state,
+            beat_log: BeatLog::new(Rc::clone(&config), Rc::clone(&logger)),
+            health_gate: HealthGate::new(Rc::clone(&config), Rc::clone(&logger)),
+            resolver: Resolver::new(naming, Rc::clone(&config), Rc::clone(&logger)),
             config,
-            sup,
+            timeline,
             logger,
             status: Cell::new(Status::Ready),
+            paused: Cell::new(false),
+            drill: Cell::new(false),
+            controls: RefCell::new(Vec::new()),
+            sequence: Cell::new(0),
+            answers: RefCell::new(VecDeque::new()),
+            answers_since: Cell::new(None),
+            round_trips: RefCell::new(VecDeque::with_capacity(RECENT_ROUND_TRIPS)),
+            process_manager: None,
+            metrics: None,
+            history: None,
+            advertiser: None,
+            notifier: None,
+            degraded: Cell::new(false),
+            busy: Cell::new(false),
+            owner: RefCell::new(None),
+            suspended: Cell::new(false),
+            jumped: Cell::new(false),
+            starting: Cell::new(false),
+            endpoint_in_use: RefCell::new(None),
+            redirected: RefCell::new(None),
             send_stop: RefCell::new(None),
             send_event,
         }
     }
 
+    /// Lets the `Heartbeat` task watch the managed process, so that it
+    /// holds the heartbeats while the process is stopped.  Without
+    /// it, a process stopped for debugging misses the heartbeats and
+    /// gets killed as hung.
+    pub(crate) fn process_manager(mut self, process_manager: Rc<ProcessManager>) -> Self {
+        self.process_manager = Some(process_manager);
+        self
+    }
+
+    /// Lets the `Heartbeat` task publish the telemetry the target
+    /// attaches to its answers, such as the depth of its queue, with
+    /// the `metrics`.
+    pub(crate) fn metrics(mut self, metrics: Rc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Lets the `Heartbeat` task register the endpoint of the target
+    /// with the `advertiser` once the target answers a heartbeat.
+    pub(crate) fn advertiser(mut self, advertiser: Rc<Advertiser>) -> Self {
+        self.advertiser = Some(advertiser);
+        self
+    }
+
+    /// Lets the `Heartbeat` task count the heartbeats and the missed
+    /// heartbeats in the `history`.
+    pub(crate) fn history(mut self, history: Rc<History>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Lets the `Heartbeat` task notify the callbacks and the webhooks
+    /// with the `notifier` when the target answers that it is
+    /// degraded.
+    pub(crate) fn notifier(mut self, notifier: Rc<Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Lets [`reload`](Self::reload) load the configuration with
+    /// `load`.  See [`Resolver`].
+    pub(crate) fn config_loader(mut self, load: LoadConfig) -> Self {
+        self.resolver = self.resolver.config_loader(load);
+        self
+    }
+
+    /// Carries the heartbeats over the `transport` in place of
+    /// ZeroMQ, such as a [`ChannelTransport`](crate::transport::ChannelTransport)
+    /// that replays a script of replies.
+    #[cfg(test)]
+    pub(crate) fn transport(mut self, transport: Rc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
     /// Runs the heartbeat process.
     ///
     /// The `run` function starts the `Heartbeat` task, kicking off
@@ -168,7 +413,7 @@ impl Heartbeat {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{LogLevel, Result};
     ///
     /// async fn example_run() -> Result<()> {
@@ -179,8 +424,23 @@ impl Heartbeat {
     /// ```
     pub(crate) async fn run(&self) -> Result<()> {
         if self.is_ready() {
-            self.logger.log(LogLevel::Info, "start heartbeat");
+            // NOTE: The tests carry the heartbeats without ZeroMQ.
+            #[cfg(not(any(feature = "zmq", test)))]
+            if !self
+                .config
+                .section(section::HEARTBEAT)?
+                .has_key(key::PROBE_COMMAND)
+                && matches!(self.check_type()?, CheckType::Zmq | CheckType::Passive)
+            {
+                return Err(config_format_error(&format!(
+                    "{} or another {} is required without the zmq feature",
+                    key::PROBE_COMMAND,
+                    key::CHECK_TYPE
+                )));
+            }
+            self.logger.log_coded(&message::HB001, "start heartbeat");
             self.timer_loop().await?;
+            self.close_gate().await;
             Ok(())
         } else {
             Err(illegal_state_error(&format!("{:?}", self.status)))
@@ -205,7 +465,7 @@ impl Heartbeat {
     ///
     /// # Example
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::{LogLevel, Result};
     ///
     /// fn example_stop(heartbeat: &Heartbeat) -> Result<()> {
@@ -226,10 +486,120 @@ impl Heartbeat {
         }
     }
 
+    /// Pauses the heartbeats without stopping the `Heartbeat` task,
+    /// so that an operator can work on the target without it being
+    /// restarted.  The pause lasts across the restarts of the target
+    /// until [`resume`](Heartbeat::resume).
+    pub(crate) fn pause(&self) {
+        if !self.paused.replace(true) {
+            self.logger.log_coded(&message::HB002, "pause heartbeat");
+            self.timeline.record(kw![pause], "heartbeat paused");
+        }
+    }
+
+    /// Resumes the heartbeats after [`pause`](Heartbeat::pause).
+    pub(crate) fn resume(&self) {
+        if self.paused.replace(false) {
+            self.logger.log_coded(&message::HB003, "resume heartbeat");
+            self.timeline.record(kw![resume], "heartbeat resumed");
+        }
+    }
+
+    /// Makes the next heartbeat time out as if the target hung, so
+    /// that the operators can rehearse the kill and the restart of the
+    /// target, along with the alerts and the hooks that come with
+    /// them.  The drill takes effect at the next heartbeat, or once the
+    /// heartbeats resume if they are paused.
+    pub(crate) fn drill(&self) {
+        self.logger
+            .log_coded(&message::HB018, "drill: the next heartbeat will time out");
+        self.timeline.record(kw![drill], "heartbeat timeout drill");
+        self.drill.set(true);
+    }
+
+    /// Queues the control keyword `name` with its `value`, such as
+    /// `SET-LOG-LEVEL DEBUG`, for the target.  The heartbeats carry
+    /// the control until the target answers one of them, so that a
+    /// target that opts in with TARGET-CONTROL can be tuned without
+    /// separate tooling.  A control replaces the one of the same name
+    /// that is still queued.
+    pub(crate) fn control(&self, name: Keyword, value: String) {
+        self.logger.log_coded(
+            &message::HB025,
+            &format!("control [{} {}] queued for target", name.name(), value),
+        );
+        self.timeline
+            .record(kw![control], &format!("{} {}", name.name(), value));
+        let mut controls = self.controls.borrow_mut();
+        controls.retain(|(queued, _)| *queued != name);
+        controls.push((name, value));
+    }
+
+    /// Puts back the `controls` a heartbeat failed to deliver, ahead
+    /// of those queued in the meantime.
+    fn requeue_controls(&self, controls: Vec<(Keyword, String)>) {
+        let mut queued = self.controls.borrow_mut();
+        let mut controls: Vec<_> = controls
+            .into_iter()
+            .filter(|(name, _)| !queued.iter().any(|(newer, _)| newer == name))
+            .collect();
+        controls.append(&mut queued);
+        *queued = controls;
+    }
+
+    /// Reloads the configuration, and resolves the endpoints of the
+    /// target by it from the next heartbeat on, such as with Sup once
+    /// TARGET-ENDPOINT is removed.  See [`Resolver`].  Logs a failure,
+    /// and keeps the previous configuration.
+    pub(crate) fn reload(&self) {
+        if let Err(err) = self.resolver.reload() {
+            self.logger.log_coded(
+                &message::NM006,
+                &format!("unable to reload config: {}", err),
+            );
+        }
+    }
+
+    /// Sends the heartbeats to the `endpoint` from now on, in place of
+    /// the endpoints of the target, such as after the promotion of a
+    /// spare.
+    pub(crate) fn redirect(&self, endpoint: String) {
+        self.logger.log_coded(
+            &message::HB004,
+            &format!("redirect heartbeat to {}", endpoint),
+        );
+        self.redirected.replace(Some(endpoint));
+    }
+
+    /// Returns whether the managed process is stopped, such as by
+    /// `SIGSTOP`, in which case the heartbeats are held until it
+    /// continues.  Logs when the process stops and continues.
+    fn is_target_stopped(&self) -> bool {
+        let stopped = self
+            .process_manager
+            .as_ref()
+            .is_some_and(|process_manager| process_manager.is_stopped());
+        if stopped && !self.suspended.replace(true) {
+            self.logger.log_coded(
+                &message::HB005,
+                "TARGET IS STOPPED; hold heartbeat until it continues",
+            );
+            self.timeline
+                .record(kw![suspend], "target stopped; heartbeat held");
+        } else if !stopped && self.suspended.replace(false) {
+            self.logger
+                .log_coded(&message::HB006, "target continues; resume heartbeat");
+            self.timeline
+                .record(kw![continue], "target continued; heartbeat resumed");
+        }
+        stopped
+    }
+
     /// Resets the status of the `Heartbeat` task so that it can start
     /// again.
     pub(crate) fn reset(&self) {
         self.set_status(Status::Ready);
+        self.round_trips.borrow_mut().clear();
     }
 
     /// Returns whether the instance of the `Heartbeat` task is in a
@@ -243,98 +613,948 @@ impl Heartbeat {
         matches!(self.status(), Status::Ready)
     }
 
-    /// Returns the target service's endpoint by looking up
+    /// Returns the target service's endpoints by looking up
     /// :target-endpoint key.  If this key is missing, looks up
     /// :target-id key, and then uses SUP to resolve its value to an
-    /// endpoint.  Returns the endpoint.  Having heartbeat2 check
+    /// endpoint.  Returns the endpoints.  Having heartbeat2 check
     /// :target-endpoint setting first is meant to liberate it from a
-    /// tight dependency on SUP.
+    /// tight dependency on SUP.  The [`Resolver`] checks it on every
+    /// heartbeat, so that a [`reload`](Heartbeat::reload) takes
+    /// effect.
     ///
     /// # Returns
     ///
-    /// Returns the endpoint, or an error if something goes wrong
-    /// reading the configuration or looking up the application ID
-    /// with the naming service.
-    async fn app_endpoint(&self) -> Result<String> {
-        let heartbeat_section = self.config.section(section::HEARTBEAT)?;
-        if heartbeat_section.has_key(key::TARGET_ENDPOINT) {
-            let endpoint = heartbeat_section.target_endpoint()?;
+    /// Returns the endpoints in the order of preference, or an error
+    /// if something goes wrong reading the configuration or looking
+    /// up the application ID with the naming service.  Returns the
+    /// endpoint of a [`redirect`](Heartbeat::redirect) instead, if
+    /// any.
+    async fn app_endpoints(&self) -> Result<Vec<String>> {
+        if let Some(endpoint) = self.redirected.borrow().as_ref() {
+            return Ok(vec![endpoint.clone()]);
+        }
+        self.resolver.endpoints().await
+    }
+
+    /// Sends a heartbeat to each of the endpoints of the target in
+    /// the order of preference, until one of them answers.  A target
+    /// can listen on several transports, such as IPC for the local
+    /// deployments and TCP for the remote ones.  `Heartbeat` uses the
+    /// first endpoint that works, and logs the change when it has to
+    /// switch the endpoint.  Each endpoint may take up to
+    /// HEARTBEAT-TIMEOUT to answer.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ready`, `NotReady`, `Degraded`, `Busy`,
+    /// `RestartRequested` or `Rejected` once an endpoint answers.
+    /// Returns `Timeout` if none answers, or the error of the last
+    /// endpoint if all of them fail.
+    async fn beat_endpoints(&self) -> Result<Status> {
+        let endpoints = self.app_endpoints().await?;
+        let connect_only = matches!(self.check_type()?, CheckType::Tcp);
+        let mut timed_out = false;
+        let mut failure = None;
+        for endpoint in &endpoints {
+            let result = if connect_only {
+                self.connect_endpoint(endpoint).await
+            } else {
+                self.beat_endpoint(endpoint).await
+            };
+            match result {
+                Ok(
+                    status @ (Status::Ready
+                    | Status::NotReady
+                    | Status::Degraded
+                    | Status::Busy
+                    | Status::RestartRequested
+                    | Status::Rejected),
+                ) => {
+                    self.use_endpoint(endpoint);
+                    return Ok(status);
+                }
+                Ok(_) => timed_out = true,
+                Err(err) => {
+                    if endpoints.len() > 1 {
+                        self.logger.log_coded(
+                            &message::HB007,
+                            &format!("heartbeat to {} failed: {}", endpoint, err),
+                        );
+                    }
+                    failure = Some(err);
+                }
+            }
+        }
+        match failure {
+            Some(err) if !timed_out => Err(err),
+            _ => Ok(Status::Timeout),
+        }
+    }
+
+    /// Returns the kind of the check CHECK-TYPE selects.
+    ///
+    /// # Configuration
+    ///
+    /// * CHECK-TYPE: Optional.  `:zmq` sends heartbeats over ZeroMQ.
+    ///   `:tcp` only checks that a TCP connection to the endpoint
+    ///   succeeds within HEARTBEAT-TIMEOUT, for the daemons that speak
+    ///   no request/reply protocol.  `:exec` runs CHECK-COMMAND, such
+    ///   as an existing Nagios check script.  `:passive` sends
+    ///   nothing, and waits for the target to send its liveness, for
+    ///   the targets that a request perturbs.  The default is `:zmq`.
+    /// * CHECK-COMMAND: Required by `:exec`.  The command and its
+    ///   arguments as a list of strings.
+    fn check_type(&self) -> Result<CheckType> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::CHECK_TYPE) {
+            return Ok(CheckType::Zmq);
+        }
+        let check = section.keyword(key::CHECK_TYPE)?;
+        if *check == kw![zmq] {
+            Ok(CheckType::Zmq)
+        } else if *check == kw![tcp] {
+            Ok(CheckType::Tcp)
+        } else if *check == kw![exec] {
+            Ok(CheckType::Exec)
+        } else if *check == kw![passive] {
+            Ok(CheckType::Passive)
+        } else {
+            Err(config_format_error(&format!(
+                "unknown {} [{}]",
+                key::CHECK_TYPE,
+                check
+            )))
+        }
+    }
+
+    /// Checks that a TCP connection to the `endpoint`, such as
+    /// `"tcp://127.0.0.1:8080"`, succeeds within HEARTBEAT-TIMEOUT.
+    /// A refused connection counts as a missed heartbeat, as the
+    /// target may be about to listen again.
+    async fn connect_endpoint(&self, endpoint: &str) -> Result<Status> {
+        let timeout = self.probe_timeout()?;
+        let address = endpoint.strip_prefix("tcp://").ok_or_else(|| {
+            config_format_error(&format!(
+                "{} [:TCP] needs a tcp:// endpoint, not [{}]",
+                key::CHECK_TYPE,
+                endpoint
+            ))
+        })?;
+        self.set_status(Status::Req);
+        match tokio::time::timeout(Duration::from_millis(timeout), TcpStream::connect(address))
+            .await
+        {
+            Ok(Ok(_)) => Ok(Status::Ready),
+            Ok(Err(err)) => {
+                self.logger.log(
+                    LogLevel::Debug,
+                    &format!("connection to {} failed: {}", endpoint, err),
+                );
+                Ok(Status::Timeout)
+            }
+            Err(_elapsed) => Ok(Status::Timeout),
+        }
+    }
+
+    /// Returns the time in milliseconds to wait for the target to
+    /// answer.  The first heartbeat after the start of the target
+    /// waits up to STARTUP-TIMEOUT, so that a target that is slow to
+    /// boot isn't killed before it can answer.  The heartbeats after
+    /// the target answers that it is busy wait up to BUSY-TIMEOUT,
+    /// until it answers otherwise.  The rest wait up to
+    /// HEARTBEAT-TIMEOUT.
+    ///
+    /// # Configuration
+    ///
+    /// * STARTUP-TIMEOUT: Optional.  The default is HEARTBEAT-TIMEOUT.
+    /// * BUSY-TIMEOUT: Optional.  The time in milliseconds to wait
+    ///   for a busy target to answer.  The default is twice
+    ///   HEARTBEAT-TIMEOUT.
+    fn probe_timeout(&self) -> Result<u64> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let timeout = section.heartbeat_timeout()?;
+        if self.starting.get() {
+            Ok(section
+                .integer_or(key::STARTUP_TIMEOUT, timeout as i64)?
+                .try_into()?)
+        } else if self.busy.get() {
+            Ok(section
+                .integer_or(
+                    key::BUSY_TIMEOUT,
+                    (timeout * DEFAULT_BUSY_TIMEOUT_FACTOR) as i64,
+                )?
+                .try_into()?)
+        } else {
+            Ok(timeout)
+        }
+    }
+
+    /// Records the `endpoint` that answers the heartbeats.
+    fn use_endpoint(&self, endpoint: &str) {
+        if self.endpoint_in_use.borrow().as_deref() != Some(endpoint) {
             self.logger
-                .log(LogLevel::Debug, &format!("endpoint: {}", endpoint));
-            Ok(endpoint.to_owned())
+                .log_coded(&message::HB008, &format!("heartbeat over {}", endpoint));
+            self.timeline.record(kw![endpoint], endpoint);
+            self.endpoint_in_use.replace(Some(endpoint.to_owned()));
+        }
+    }
+
+    async fn beat(&self) -> Result<Status> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if section.has_key(key::PROBE_COMMAND) {
+            return self.exec_beat(key::PROBE_COMMAND, probe_status).await;
+        }
+        match self.check_type()? {
+            CheckType::Exec => self.exec_beat(key::CHECK_COMMAND, check_status).await,
+            CheckType::Zmq | CheckType::Tcp => self.beat_endpoints().await,
+            CheckType::Passive => self.listen_endpoint().await,
+        }
+    }
+
+    /// Sends a heartbeat to the `endpoint`, and tells the status of
+    /// the target by the reply.
+    ///
+    /// # Configuration
+    ///
+    /// * HEARTBEAT-SEQUENCE: Optional.  If true, numbers the
+    ///   heartbeats, and discards an answer that doesn't echo the
+    ///   number of the heartbeat as if the target didn't answer.  Each
+    ///   heartbeat goes over a socket of its own, so that a late
+    ///   answer to an earlier one never arrives; the check is a
+    ///   defensive one against a target that echoes a wrong number.
+    ///   The target must echo the number, as
+    ///   [`responder`](crate::responder) does.  The default is false.
+    /// * TARGET-CONTROL: Optional.  If true, the target understands
+    ///   the control keywords on the heartbeats, such as `HEARTBEAT
+    ///   SET-LOG-LEVEL DEBUG`, and the control requests may queue
+    ///   them; see [`control`](Heartbeat::control).  The default is
+    ///   false.
+    /// * LEASE: Optional.  If true, asks the target for a lease with
+    ///   every heartbeat on behalf of INSTANCE-ID, so that only one
+    ///   supervisor owns a target that several can reach, such as
+    ///   through an endpoint the hosts share.  The target must keep
+    ///   the lease, as [`Lease`](crate::protocol::Lease) does.  While
+    ///   another supervisor owns the lease, the target rejects the
+    ///   heartbeats, and `Heartbeat2` leaves the target to the owner:
+    ///   it closes the health gate, and never kills the target over a
+    ///   missed heartbeat.  The default is false.
+    /// * LEASE-TERM: Optional.  The time in milliseconds the lease
+    ///   lasts without a renewal.  The default is three times
+    ///   HEARTBEAT-MAX-INTERVAL, or HEARTBEAT-INTERVAL without it.
+    async fn beat_endpoint(&self, endpoint: &str) -> Result<Status> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let timeout = self.probe_timeout()?;
+        let mut request = if section.boolean_or(key::HEARTBEAT_SEQUENCE, false)? {
+            self.sequence.set(self.sequence.get() + 1);
+            protocol::numbered_heartbeat(self.sequence.get())
+        } else {
+            protocol::heartbeat()
+        };
+        if section.boolean_or(key::LEASE, false)? {
+            let term = self.lease_term()?.as_millis().try_into()?;
+            request = protocol::with_lease(request, &section.instance_id()?, term);
+        }
+        let controls = self.controls.take();
+        for (name, value) in &controls {
+            request = protocol::with_control(request, name, value);
+        }
+        self.set_status(Status::Req);
+        let reply = self.transport.beat(endpoint, request, timeout).await;
+        if !matches!(
+            reply,
+            Ok(Reply::Ready(_)
+                | Reply::NotReady(_)
+                | Reply::Degraded(_)
+                | Reply::Busy(_)
+                | Reply::RestartMe(_))
+        ) {
+            self.requeue_controls(controls);
+        }
+        Ok(self.answer(reply?))
+    }
+
+    /// Waits for the liveness messages the target sends on its own to
+    /// the first of its endpoints, instead of sending it a heartbeat.
+    /// Any message counts, and the latest since the last heartbeat
+    /// tells whether the target is ready.  The target misses the
+    /// heartbeat if it has sent none since the last heartbeat, and
+    /// none arrives within HEARTBEAT-TIMEOUT either.
+    ///
+    /// # Configuration
+    ///
+    /// * PASSIVE-SOCKET: Optional.  `:sub` subscribes to the messages
+    ///   the target publishes on a PUB socket.  `:pull` pulls the
+    ///   messages the target pushes on a PUSH socket.  The default is
+    ///   `:sub`.
+    /// * PASSIVE-BIND: Optional.  `t` binds the socket to the
+    ///   endpoint for the target to connect to, and `nil` connects to
+    ///   the endpoint the target binds to.  The default is `t` for
+    ///   `:pull` and `nil` for `:sub`.
+    async fn listen_endpoint(&self) -> Result<Status> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let socket = if section.has_key(key::PASSIVE_SOCKET) {
+            let socket = section.keyword(key::PASSIVE_SOCKET)?;
+            if *socket == kw![sub] {
+                PassiveSocket::Sub
+            } else if *socket == kw![pull] {
+                PassiveSocket::Pull
+            } else {
+                return Err(config_format_error(&format!(
+                    "unknown {} [{}]",
+                    key::PASSIVE_SOCKET,
+                    socket
+                )));
+            }
         } else {
-            let app_id = heartbeat_section.target_id()?;
-            let endpoint = self.sup.sget(app_id).await?;
+            PassiveSocket::Sub
+        };
+        let bind = section.boolean_or(key::PASSIVE_BIND, socket == PassiveSocket::Pull)?;
+        let endpoints = self.app_endpoints().await?;
+        let endpoint = endpoints
+            .first()
+            .ok_or_else(|| config_format_error(&format!("{} is empty", key::TARGET_ENDPOINT)))?;
+        let timeout = self.probe_timeout()?;
+        self.set_status(Status::Req);
+        let reply = self
+            .transport
+            .listen(endpoint, socket, bind, timeout)
+            .await?;
+        let status = self.answer(reply);
+        if matches!(
+            status,
+            Status::Ready | Status::Degraded | Status::Busy | Status::RestartRequested
+        ) {
+            self.use_endpoint(endpoint);
+        }
+        Ok(status)
+    }
+
+    /// Tells the status of the target by its `reply`, and publishes
+    /// the telemetry attached to it.  A target answers that it is
+    /// degraded or busy with a keyword, such as `DEGRADED REASON
+    /// "replica lost"`; see [`protocol`](crate::protocol).
+    fn answer(&self, reply: Reply) -> Status {
+        let (status, telemetry) = match reply {
+            Reply::Ready(telemetry) => (Status::Ready, telemetry),
+            Reply::NotReady(telemetry) => (Status::NotReady, telemetry),
+            Reply::Degraded(telemetry) => (Status::Degraded, telemetry),
+            Reply::Busy(telemetry) => (Status::Busy, telemetry),
+            Reply::RestartMe(telemetry) => (Status::RestartRequested, telemetry),
+            Reply::Rejected(owner) => {
+                self.set_owner(Some(owner.unwrap_or_else(|| "unknown".to_owned())));
+                return Status::Rejected;
+            }
+            Reply::Timeout => return Status::Timeout,
+            Reply::Stale(echo) => {
+                let echo = echo.map_or_else(
+                    || "without a sequence number".to_owned(),
+                    |echo| format!("to heartbeat #{}", echo),
+                );
+                self.logger.log_coded(
+                    &message::HB019,
+                    &format!(
+                        "discard answer {}; heartbeat #{} was sent",
+                        echo,
+                        self.sequence.get()
+                    ),
+                );
+                return Status::Timeout;
+            }
+        };
+        self.set_degraded(matches!(status, Status::Degraded).then(|| degraded_reason(&telemetry)));
+        self.set_busy(matches!(status, Status::Busy));
+        self.set_owner(None);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_telemetry(telemetry);
+        }
+        status
+    }
+
+    /// Records whether the target is degraded, for the `reason` if it
+    /// is.  Logs the change, and notifies the callbacks and the
+    /// webhooks when the target becomes degraded.
+    fn set_degraded(&self, reason: Option<String>) {
+        match reason {
+            Some(reason) if !self.degraded.replace(true) => {
+                self.logger
+                    .log_coded(&message::HB022, &format!("target is degraded: {}", reason));
+                self.timeline.record(kw![impaired], &reason);
+                if let Some(notifier) = &self.notifier {
+                    notifier.notify(Event::Degraded(reason));
+                }
+            }
+            None if self.degraded.replace(false) => {
+                self.logger
+                    .log_coded(&message::HB023, "target is no longer degraded");
+                self.timeline
+                    .record(kw![recover], "target is no longer degraded");
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the time the lease on the target lasts without a
+    /// renewal.
+    fn lease_term(&self) -> Result<Duration> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let longest = section.integer_or(
+            key::HEARTBEAT_MAX_INTERVAL,
+            section.integer(key::HEARTBEAT_INTERVAL)?,
+        )?;
+        Ok(Duration::from_millis(
+            section
+                .integer_or(key::LEASE_TERM, longest * 1000 * DEFAULT_LEASE_INTERVALS)?
+                .try_into()?,
+        ))
+    }
+
+    /// Returns whether another supervisor owned the lease on the
+    /// target at the last answer within the term of the lease.  The
+    /// owner may have died since, and its lease run out.
+    fn is_owned_elsewhere(&self) -> Result<bool> {
+        match &*self.owner.borrow() {
+            Some((_, since)) => Ok(since.elapsed() < self.lease_term()?),
+            None => Ok(false),
+        }
+    }
+
+    /// Records the supervisor that owns the lease on the target, or
+    /// `None` if the target accepts this one.  Logs the change.
+    fn set_owner(&self, owner: Option<String>) {
+        let previous = self
+            .owner
+            .replace(owner.clone().map(|owner| (owner, Instant::now())))
+            .map(|(previous, _)| previous);
+        match (previous, owner) {
+            (previous, Some(owner)) if previous.as_ref() != Some(&owner) => {
+                self.logger.log_coded(
+                    &message::HB027,
+                    &format!(
+                        "target is leased to supervisor [{}]; leave it to the owner",
+                        owner
+                    ),
+                );
+                self.timeline.record(Keyword::new("LEASE-REJECTED"), &owner);
+            }
+            (Some(_), None) => {
+                self.logger
+                    .log_coded(&message::HB028, "target accepted the lease");
+                self.timeline
+                    .record(kw![lease], "target accepted the lease");
+            }
+            _ => {}
+        }
+    }
+
+    /// Records whether the target is `busy`, which extends the timeout
+    /// of the heartbeats until it answers otherwise.  Logs the change.
+    fn set_busy(&self, busy: bool) {
+        if busy && !self.busy.replace(true) {
+            self.logger
+                .log_coded(&message::HB024, "target is busy; extend heartbeat timeout");
+        } else if !busy && self.busy.replace(false) {
             self.logger.log(
                 LogLevel::Debug,
-                &format!("endpoint of app {}: {}", app_id, endpoint),
+                "target is no longer busy; restore heartbeat timeout",
             );
-            Ok(endpoint)
         }
     }
 
-    async fn beat(&self) -> Result<Status> {
-        let endpoint = self.app_endpoint().await?;
-        let timeout = self
-            .config
-            .section(section::HEARTBEAT)?
-            .heartbeat_timeout()?;
-        let socket = SocketBuilder::new(self.context.clone())
-            .endpoint(&endpoint)
-            .timeout(timeout)
-            .linger(false)
-            .req()
-            .connect()?;
-        let recv_sock = socket.send_keyword(kw![heartbeat]).await?;
+    /// Probes the target by running the command under the `key`, such
+    /// as PROBE-COMMAND, instead of sending it a heartbeat.  `classify`
+    /// tells the health of the target by the exit code of the command,
+    /// or `None` if a signal killed it.  A command that runs beyond
+    /// HEARTBEAT-TIMEOUT times out like a heartbeat.
+    async fn exec_beat(&self, key: &str, classify: fn(Option<i32>) -> Status) -> Result<Status> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let timeout = self.probe_timeout()?;
+        let command = section.string_list(key)?;
+        let (exec, args) = command
+            .split_first()
+            .ok_or_else(|| config_format_error(&format!("{} is empty", key)))?;
+        let mut child = Command::new(exec)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let _own = OwnChild::new(child.id());
         self.set_status(Status::Req);
-        match recv_sock.recv_string().await {
-            Ok(_) => Ok(Status::Ready),
-            Err(RecvError::Timeout) => Ok(Status::Timeout),
-            Err(RecvError::Other(err)) => Err(err),
+        match tokio::time::timeout(Duration::from_millis(timeout), child.wait()).await {
+            Ok(Ok(status)) => Ok(classify(status.code())),
+            Ok(Err(err)) => Err(err.into()),
+            Err(_elapsed) => Ok(Status::Timeout),
+        }
+    }
+
+    /// Probes the target, and classifies any failure to do so as a
+    /// missed beat.  Failing to resolve the endpoint with Sup or to
+    /// build the socket says nothing certain about the target.  But
+    /// propagating the error would bring down the supervision loop,
+    /// and leave the target running unsupervised.
+    async fn probe(&self) -> Status {
+        let started = Instant::now();
+        let (status, outcome) = match self.beat().await {
+            Ok(Status::Timeout) => (Status::Timeout, "timeout"),
+            Ok(Status::NotReady) => (Status::NotReady, "not-ready"),
+            Ok(Status::Dead) => (Status::Dead, "dead"),
+            Ok(Status::Degraded) => (Status::Degraded, "degraded"),
+            Ok(Status::Busy) => (Status::Busy, "busy"),
+            Ok(Status::RestartRequested) => (Status::RestartRequested, "restart-me"),
+            Ok(Status::Rejected) => (Status::Rejected, "rejected"),
+            Ok(status) => (status, "ok"),
+            Err(err) => {
+                self.logger
+                    .log_coded(&message::HB010, &format!("heartbeat probe failed: {}", err));
+                (Status::Timeout, "error")
+            }
+        };
+        self.beat_log.record(started.elapsed(), outcome);
+        if let Some(history) = &self.history {
+            history.add_beat(matches!(status, Status::Timeout));
+        }
+        let mut round_trips = self.round_trips.borrow_mut();
+        if round_trips.len() == RECENT_ROUND_TRIPS {
+            round_trips.pop_front();
+        }
+        round_trips.push_back(started.elapsed());
+        status
+    }
+
+    /// Returns the round trip times of the latest heartbeats, oldest
+    /// first.  A heartbeat that timed out counts with the time it
+    /// waited.
+    pub(crate) fn round_trips(&self) -> Vec<Duration> {
+        self.round_trips.borrow().iter().copied().collect()
+    }
+
+    /// Probes the target, retrying a timed-out probe up to
+    /// HEARTBEAT-RETRIES times with HEARTBEAT-RETRY-SPACING
+    /// milliseconds in between.  This tells a lost packet or a short
+    /// GC pause apart from a genuine hang without lengthening the
+    /// heartbeat interval.  Only declares the interval missed when
+    /// all the attempts time out.
+    ///
+    /// # Returns
+    ///
+    /// Returns the status of the last attempt, and whether it took a
+    /// retry.
+    async fn probe_with_retries(&self) -> Result<(Status, bool)> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let retries = section.integer_or(key::HEARTBEAT_RETRIES, 0)?;
+        let spacing = Duration::from_millis(
+            section
+                .integer_or(key::HEARTBEAT_RETRY_SPACING, DEFAULT_RETRY_SPACING)?
+                .try_into()?,
+        );
+        let mut status = self.probe().await;
+        let mut retried = false;
+        for attempt in 1..=retries {
+            if !matches!(status, Status::Timeout) {
+                break;
+            }
+            retried = true;
+            self.logger.log_coded(
+                &message::HB011,
+                &format!("heartbeat missed; retry {} of {}", attempt, retries),
+            );
+            sleep(spacing).await;
+            status = self.probe().await;
+        }
+        Ok((status, retried))
+    }
+
+    /// Opens the health gate.  Logs a failure, which doesn't stop the
+    /// heartbeat.
+    async fn open_gate(&self) {
+        if let Err(err) = self.health_gate.open().await {
+            self.logger.log_coded(
+                &message::GT003,
+                &format!("unable to open health gate: {}", err),
+            );
+        }
+    }
+
+    /// Closes the health gate.  Logs a failure, which doesn't stop the
+    /// heartbeat.
+    async fn close_gate(&self) {
+        if let Err(err) = self.health_gate.close().await {
+            self.logger.log_coded(
+                &message::GT003,
+                &format!("unable to close health gate: {}", err),
+            );
         }
     }
 
     async fn timer_func(&self) -> Result<TimerFuncResult> {
         self.logger.log(LogLevel::Trace, "timer_func");
-        let new_status = self.beat().await?;
+        let drill = self.drill.replace(false);
+        let (new_status, retried) = if drill {
+            (Status::Timeout, false)
+        } else {
+            self.probe_with_retries().await?
+        };
         self.set_status(new_status);
+        let alive = matches!(
+            new_status,
+            Status::Ready
+                | Status::NotReady
+                | Status::Degraded
+                | Status::Busy
+                | Status::RestartRequested
+                | Status::Rejected
+        );
+        if alive {
+            self.jumped.set(false);
+            self.success_ratio(true)?;
+        }
+        let owned_elsewhere = self.is_owned_elsewhere()?;
         match new_status {
-            Status::Ready => Ok(TimerFuncResult::Continue),
-            Status::Timeout => {
-                self.logger.log(LogLevel::Error, "heartbeat timed out");
-                self.send_event.send(EventType::Timeout).await?;
+            Status::Ready | Status::Degraded | Status::Busy => {
+                self.timeline.beat();
+                if self.state.get() == TargetState::Degraded {
+                    self.logger
+                        .log_coded(&message::HB012, "target is ready again");
+                    self.state.transition(TargetState::Running)?;
+                }
+                self.open_gate().await;
+                if let Some(advertiser) = &self.advertiser {
+                    advertiser.register().await?;
+                }
+                if retried {
+                    Ok(TimerFuncResult::Recovered)
+                } else {
+                    Ok(TimerFuncResult::Continue)
+                }
+            }
+            Status::NotReady => {
+                if self.state.get() == TargetState::Running {
+                    self.logger.log_coded(&message::HB013, "target isn't ready");
+                    self.timeline.record(kw![degraded], "target isn't ready");
+                    self.state.transition(TargetState::Degraded)?;
+                }
+                self.close_gate().await;
+                Ok(TimerFuncResult::Continue)
+            }
+            Status::RestartRequested => {
+                self.logger
+                    .log_coded(&message::HB026, "target asks for a restart");
+                self.timeline.record(
+                    Keyword::new("RESTART-REQUEST"),
+                    "target asked for a restart",
+                );
+                self.close_gate().await;
+                let incarnation = self
+                    .process_manager
+                    .as_ref()
+                    .map_or(0, |process_manager| process_manager.incarnation());
+                self.send_event
+                    .send(EventType::RestartRequested(incarnation))
+                    .await?;
+                Ok(TimerFuncResult::Break)
+            }
+            Status::Rejected => {
+                self.close_gate().await;
+                Ok(TimerFuncResult::Continue)
+            }
+            // NOTE: The target may stop while a probe is in flight.
+            Status::Timeout | Status::Dead if self.is_target_stopped() => {
+                Ok(TimerFuncResult::Continue)
+            }
+            Status::Timeout | Status::Dead if !drill && owned_elsewhere => {
+                self.logger.log_coded(
+                    &message::HB029,
+                    "target missed a heartbeat; leave it to the supervisor that owns it",
+                );
+                self.close_gate().await;
+                Ok(TimerFuncResult::Continue)
+            }
+            Status::Timeout | Status::Dead if !drill && self.jumped.replace(false) => {
+                self.logger.log_coded(
+                    &message::HB014,
+                    "target missed a heartbeat right after a clock jump; not counted",
+                );
+                Ok(TimerFuncResult::Continue)
+            }
+            Status::Timeout | Status::Dead => {
+                let ratio = if matches!(new_status, Status::Timeout) && !drill {
+                    self.success_ratio(false)?
+                } else {
+                    SuccessRatio::Off
+                };
+                let (message, reason) = match ratio {
+                    SuccessRatio::Holds => {
+                        self.logger.log_coded(
+                            &message::HB020,
+                            "target missed a heartbeat; success ratio holds",
+                        );
+                        self.timeline
+                            .record(kw![miss], "heartbeat timed out; success ratio holds");
+                        self.close_gate().await;
+                        return Ok(TimerFuncResult::Continue);
+                    }
+                    SuccessRatio::Below(ratio) => (
+                        &message::HB021,
+                        format!("success ratio of heartbeats fell to {}%", ratio),
+                    ),
+                    SuccessRatio::Off if matches!(new_status, Status::Dead) => {
+                        (&message::HB016, "probe found target dead".to_owned())
+                    }
+                    SuccessRatio::Off if drill => {
+                        (&message::HB015, "heartbeat timed out in a drill".to_owned())
+                    }
+                    SuccessRatio::Off => (&message::HB015, "heartbeat timed out".to_owned()),
+                };
+                self.logger.log_coded(message, &reason);
+                self.timeline.record(kw![miss], &reason);
+                self.state.transition(TargetState::Unresponsive)?;
+                self.close_gate().await;
+                let incarnation = self
+                    .process_manager
+                    .as_ref()
+                    .map_or(0, |process_manager| process_manager.incarnation());
+                self.send_event
+                    .send(EventType::Timeout(incarnation))
+                    .await?;
                 Ok(TimerFuncResult::Break)
             }
             _ => Err(illegal_state_error(&format!("{:?}", new_status))),
         }
     }
 
-    async fn timer_loop(&self) -> Result<()> {
-        use TimerFuncResult::*;
-        let interval = Duration::from_secs(
-            self.config
-                .section(section::HEARTBEAT)?
-                .integer(key::HEARTBEAT_INTERVAL)?
+    /// Records whether the target `answered` a heartbeat, and returns
+    /// the verdict of the success ratio of the heartbeats over the
+    /// window.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if HEARTBEAT-SUCCESS-RATIO isn't
+    /// between 1 and 100, or if HEARTBEAT-RATIO-WINDOW is malformed.
+    fn success_ratio(&self, answered: bool) -> Result<SuccessRatio> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::HEARTBEAT_SUCCESS_RATIO) {
+            return Ok(SuccessRatio::Off);
+        }
+        let limit = section.integer(key::HEARTBEAT_SUCCESS_RATIO)?;
+        if !(1..=100).contains(&limit) {
+            return Err(config_format_error(&format!(
+                "{} must be between 1 and 100",
+                key::HEARTBEAT_SUCCESS_RATIO
+            )));
+        }
+        let window = Duration::from_secs(
+            section
+                .integer_or(key::HEARTBEAT_RATIO_WINDOW, DEFAULT_RATIO_WINDOW)?
                 .try_into()?,
         );
+        let now = Instant::now();
+        let since = self.answers_since.get().unwrap_or(now);
+        self.answers_since.set(Some(since));
+        let mut answers = self.answers.borrow_mut();
+        answers.push_back((now, answered));
+        while answers
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > window)
+        {
+            answers.pop_front();
+        }
+        if now.duration_since(since) < window {
+            return Ok(SuccessRatio::Holds);
+        }
+        let ratio = answers.iter().filter(|(_, answered)| *answered).count() * 100 / answers.len();
+        if (ratio as i64) < limit {
+            Ok(SuccessRatio::Below(ratio))
+        } else {
+            Ok(SuccessRatio::Holds)
+        }
+    }
+
+    /// Sends heartbeats every HEARTBEAT-INTERVAL seconds until
+    /// stopped or the target misses one.
+    ///
+    /// The heartbeats go out at fixed deadlines rather than an
+    /// interval after the end of the last one, so that a slow answer
+    /// doesn't make the heartbeats drift apart.  A heartbeat that
+    /// overruns the interval makes the next go out at once, and the
+    /// ones it overran are skipped rather than sent in a burst.  The
+    /// metrics publish how late each heartbeat goes out, and count the
+    /// skipped ones.
+    ///
+    /// In the adaptive mode, shortens the interval to
+    /// HEARTBEAT-MIN-INTERVAL seconds as soon as the target needs a
+    /// retry to answer, so that a target on the verge of a hang is
+    /// confirmed quickly.  Relaxes the interval back by doubling it
+    /// after every heartbeat answered at the first attempt, up to
+    /// HEARTBEAT-MAX-INTERVAL seconds.  This balances the detection
+    /// latency against the load of the probes.
+    ///
+    /// # Configuration
+    ///
+    /// * ADAPTIVE-INTERVAL: Optional.  `t` turns on the adaptive
+    ///   mode.
+    /// * HEARTBEAT-MIN-INTERVAL: Optional.  The default is 1.
+    /// * HEARTBEAT-MAX-INTERVAL: Optional.  The default is
+    ///   HEARTBEAT-INTERVAL.
+    /// * STARTUP-GRACE: Optional.  The time in seconds to wait after
+    ///   the start of the target before the first heartbeat, for a
+    ///   target that is slow to boot.  The default is
+    ///   HEARTBEAT-INTERVAL.
+    /// * HEARTBEAT-SUCCESS-RATIO: Optional.  The percentage of the
+    ///   heartbeats the target must answer over HEARTBEAT-RATIO-WINDOW.
+    ///   With it, a missed heartbeat only closes the health gate, and
+    ///   the target is restarted once the ratio falls below it.  This
+    ///   catches a target that is wedged intermittently, but answers
+    ///   just often enough not to miss a heartbeat in a row.  The
+    ///   ratio holds until the heartbeats span the window, so that a
+    ///   target hung from the start is restarted after the window.
+    /// * HEARTBEAT-RATIO-WINDOW: Optional.  The time in seconds over
+    ///   which the success ratio counts.  The default is 120.
+    async fn timer_loop(&self) -> Result<()> {
+        use TimerFuncResult::*;
+        let section = self.config.section(section::HEARTBEAT)?;
+        let base = section.integer(key::HEARTBEAT_INTERVAL)?;
+        let adaptive = section.boolean_or(key::ADAPTIVE_INTERVAL, false)?;
+        let min =
+            section.integer_or(key::HEARTBEAT_MIN_INTERVAL, DEFAULT_MIN_INTERVAL.min(base))?;
+        let max = section.integer_or(key::HEARTBEAT_MAX_INTERVAL, base)?;
+        if base < 1 || (adaptive && min < 1) {
+            return Err(config_format_error(&format!(
+                "{} and {} must be positive",
+                key::HEARTBEAT_INTERVAL,
+                key::HEARTBEAT_MIN_INTERVAL
+            )));
+        }
+        if adaptive && !(min <= base && base <= max) {
+            return Err(config_format_error(&format!(
+                "{} <= {} <= {} doesn't hold",
+                key::HEARTBEAT_MIN_INTERVAL,
+                key::HEARTBEAT_INTERVAL,
+                key::HEARTBEAT_MAX_INTERVAL
+            )));
+        }
+        let min = Duration::from_secs(min.try_into()?);
+        let max = Duration::from_secs(max.try_into()?);
+        let mut interval = Duration::from_secs(base.try_into()?);
+        let grace = Duration::from_secs(section.integer_or(key::STARTUP_GRACE, base)?.try_into()?);
+        let mut ticker = deadlines(Instant::now() + grace, interval);
+        self.starting.set(true);
+        self.answers.borrow_mut().clear();
+        self.answers_since.set(None);
+        self.degraded.set(false);
+        self.busy.set(false);
+        self.owner.replace(None);
 
         loop {
-            let (send_stop, recv_stop) = oneshot::channel();
+            let (send_stop, mut recv_stop) = oneshot::channel();
             self.send_stop.replace(Some(send_stop));
 
-            tokio::select! {
-                _ = sleep(interval) => (),
-                _ = recv_stop => break,
-            }
+            let slept = Instant::now();
+            let wall = SystemTime::now();
+            let deadline = tokio::select! {
+                deadline = ticker.tick() => deadline,
+                _ = &mut recv_stop => break,
+            };
             self.logger.log(LogLevel::Trace, "heartbeat wakes up");
-            match self.timer_func().await? {
-                Continue => self.logger.log(
-                    LogLevel::Trace,
-                    &format!("next heartbeat in {}s", interval.as_secs()),
-                ),
+            self.detect_jump(
+                deadline.saturating_duration_since(slept),
+                slept.elapsed(),
+                wall.elapsed().unwrap_or_default(),
+            )?;
+            self.record_drift(deadline, interval);
+            if self.paused.get() || self.is_target_stopped() {
+                continue;
+            }
+            let result = self.timer_func().await?;
+            self.starting.set(false);
+            // NOTE: A stop while the heartbeat is in flight, such as
+            // on the exit of the process, ends the loop after it.
+            if recv_stop.try_recv().is_ok() {
+                break;
+            }
+            let next = match result {
+                Continue if adaptive => (interval * 2).min(max),
+                Recovered if adaptive => min,
+                Continue | Recovered => interval,
                 Break => break,
+            };
+            if next != interval {
+                interval = next;
+                ticker = deadlines(deadline + interval, interval);
+            }
+            self.logger.log(
+                LogLevel::Trace,
+                &format!("next heartbeat in {}s", interval.as_secs()),
+            );
+        }
+        // NOTE: A stop after the loop has ended on its own finds the
+        // task stopped already.
+        self.send_stop.replace(None);
+        Ok(())
+    }
+
+    /// Publishes how late the heartbeat due at the `deadline` goes
+    /// out, and counts the heartbeats skipped as the one before it
+    /// overran the `interval`.
+    fn record_drift(&self, deadline: Instant, interval: Duration) {
+        let drift = deadline.elapsed();
+        let skipped = (drift.as_millis() / interval.as_millis().max(1)) as u64;
+        if skipped > 0 {
+            self.logger.log(
+                LogLevel::Debug,
+                &format!(
+                    "heartbeat overran the interval by {}ms; skip {} heartbeat(s)",
+                    drift.as_millis(),
+                    skipped
+                ),
+            );
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.set_drift(drift);
+            if skipped > 0 {
+                metrics.add_skipped_heartbeats(skipped);
             }
         }
+    }
+
+    /// Detects a jump of the clocks across a sleep of `interval`,
+    /// such as from a suspended host or a paused VM.  `Heartbeat2`
+    /// wakes up late after either, or the monotonic clock misses the
+    /// suspension the wall clock saw.  A target that wakes up along
+    /// with `Heartbeat2` can't answer at once, so the next missed
+    /// heartbeat doesn't count.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The time the `Heartbeat` task meant to sleep.
+    /// * `monotonic` - The time the sleep took by the monotonic clock.
+    /// * `wall` - The time the sleep took by the wall clock.
+    ///
+    /// # Configuration
+    ///
+    /// * CLOCK-JUMP-THRESHOLD: Optional.  The discrepancy in seconds
+    ///   that counts as a jump.  The default is 5.
+    fn detect_jump(&self, interval: Duration, monotonic: Duration, wall: Duration) -> Result<()> {
+        let threshold = Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::CLOCK_JUMP_THRESHOLD, DEFAULT_CLOCK_JUMP_THRESHOLD)?
+                .try_into()?,
+        );
+        let jump = monotonic
+            .saturating_sub(interval)
+            .max(wall.saturating_sub(monotonic))
+            .max(monotonic.saturating_sub(wall));
+        if jump > threshold {
+            self.logger.log_coded(
+                &message::HB017,
+                &format!(
+                    "clock jumped by {}s, as after a suspension; forgive the next missed heartbeat",
+                    jump.as_secs()
+                ),
+            );
+            self.timeline
+                .record(Keyword::new("CLOCK-JUMP"), &format!("{}s", jump.as_secs()));
+            self.jumped.set(true);
+        }
         Ok(())
     }
 
@@ -0,0 +1,308 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::kw;
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use chrono::Local;
+use std::cell::Cell;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tokio::time::{sleep, Duration};
+
+/// The default time in seconds between the writes of the history.
+static DEFAULT_HISTORY_INTERVAL: i64 = 60;
+
+/// The format of the dates in the history.
+static DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The aggregates of the supervision of a target over a day.
+struct Day {
+    date: String,
+    beats: u64,
+    misses: u64,
+    restarts: u64,
+}
+
+impl Day {
+    /// Reads a day out of a line of the history, such as
+    /// `(:date "2024-05-01" :beats 86400 :misses 3 :restarts 1)`.
+    fn parse(line: &str) -> Result<Self> {
+        let expression = Expression::from_sexp(sexp::parse(line)?)?;
+        let mut day = Day {
+            date: String::new(),
+            beats: 0,
+            misses: 0,
+            restarts: 0,
+        };
+        for (key, value) in expression.plist_pairs()? {
+            if *key == kw![date] {
+                day.date = value.string()?.to_owned();
+            } else if *key == kw![beats] {
+                day.beats = value.integer()?.try_into()?;
+            } else if *key == kw![misses] {
+                day.misses = value.integer()?.try_into()?;
+            } else if *key == kw![restarts] {
+                day.restarts = value.integer()?.try_into()?;
+            }
+        }
+        if day.date.is_empty() {
+            return Err(config_format_error(&format!(
+                "history line without a date: {}",
+                line
+            )));
+        }
+        Ok(day)
+    }
+
+    fn to_expression(&self) -> Expression {
+        Expression::plist(vec![
+            (kw![date], Expression::from(self.date.as_str())),
+            (kw![beats], Expression::from(self.beats as i64)),
+            (kw![misses], Expression::from(self.misses as i64)),
+            (kw![restarts], Expression::from(self.restarts as i64)),
+        ])
+    }
+}
+
+/// Keeps the aggregates of the supervision of the target per day
+/// across the runs of `Heartbeat2`.
+///
+/// The metrics start from zero each time `Heartbeat2` starts, which
+/// hides a service that grows flaky over weeks.  `History` counts the
+/// heartbeats, the missed heartbeats and the restarts, and adds them
+/// to the line of the day in a flat file every HISTORY-INTERVAL
+/// seconds and as the supervision ends.  The file holds a line per
+/// day, such as:
+///
+/// ```lisp
+/// (:date "2024-05-01" :beats 86400 :misses 3 :restarts 1)
+/// ```
+///
+/// The counts go to the day of the write, so that a count near
+/// midnight may land on the next day.  `heartbeat2 history` summarises
+/// the last days with [`summarise`].
+///
+/// # Configuration
+///
+/// * HISTORY-DIRECTORY: Optional.  The directory to keep the history
+///   in, as `<target-id>.history`.  `History` is disabled without it.
+/// * HISTORY-INTERVAL: Optional.  The time in seconds between the
+///   writes of the history.  The default is 60.
+pub(crate) struct History {
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+    beats: Cell<u64>,
+    misses: Cell<u64>,
+    restarts: Cell<u64>,
+}
+
+impl History {
+    /// Creates a new `History`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        History {
+            config,
+            logger,
+            beats: Cell::new(0),
+            misses: Cell::new(0),
+            restarts: Cell::new(0),
+        }
+    }
+
+    /// Returns whether the `History` should run.
+    pub(crate) fn is_enabled(&self) -> Result<bool> {
+        Ok(self
+            .config
+            .section(section::HEARTBEAT)?
+            .has_key(key::HISTORY_DIRECTORY))
+    }
+
+    /// Counts a heartbeat, which the target `missed` or not.
+    pub(crate) fn add_beat(&self, missed: bool) {
+        self.beats.set(self.beats.get() + 1);
+        if missed {
+            self.misses.set(self.misses.get() + 1);
+        }
+    }
+
+    /// Counts a restart of the managed process.
+    pub(crate) fn add_restart(&self) {
+        self.restarts.set(self.restarts.get() + 1);
+    }
+
+    /// Writes the history every HISTORY-INTERVAL seconds until
+    /// `Heartbeat2` exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if HISTORY-INTERVAL is malformed.
+    pub(crate) async fn run(&self) -> Result<()> {
+        let interval = Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::HISTORY_INTERVAL, DEFAULT_HISTORY_INTERVAL)?
+                .try_into()?,
+        );
+        loop {
+            sleep(interval).await;
+            self.flush();
+        }
+    }
+
+    /// Writes the counts since the last write, if enabled.  Logs a
+    /// failure to write, and keeps the counts for the next write.
+    pub(crate) fn flush(&self) {
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::HS001,
+                &format!("unable to write history: {}", err),
+            );
+        }
+    }
+
+    /// Adds the counts to the line of the day.  Writes to a temporary
+    /// file first and renames it, so that readers never see a partial
+    /// file.
+    fn write(&self) -> Result<()> {
+        let (beats, misses, restarts) = (self.beats.get(), self.misses.get(), self.restarts.get());
+        if !self.is_enabled()? || beats == 0 && restarts == 0 {
+            return Ok(());
+        }
+        let path = path(&self.config)?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut days = read(&path)?;
+        let today = Local::now().format(DATE_FORMAT).to_string();
+        match days.last_mut() {
+            Some(day) if day.date == today => {
+                day.beats += beats;
+                day.misses += misses;
+                day.restarts += restarts;
+            }
+            _ => days.push(Day {
+                date: today,
+                beats,
+                misses,
+                restarts,
+            }),
+        }
+        let text: String = days
+            .iter()
+            .map(|day| format!("{}\n", day.to_expression()))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &path)?;
+        self.beats.set(0);
+        self.misses.set(0);
+        self.restarts.set(0);
+        Ok(())
+    }
+}
+
+/// Prints the aggregates of the last `days` days in the history of
+/// the target the `config` describes to `out`, a line per day and a
+/// line for the total.  Days without supervision are left out.
+///
+/// # Errors
+///
+/// Returns an error if HISTORY-DIRECTORY is missing, if the history
+/// is malformed, or if `out` fails.
+pub(crate) fn summarise<W: Write>(config: &Config, days: u32, mut out: W) -> Result<()> {
+    let path = path(config)?;
+    let since = Local::now()
+        .date_naive()
+        .checked_sub_signed(chrono::Duration::days(days.saturating_sub(1).into()))
+        .map_or_else(String::new, |date| date.format(DATE_FORMAT).to_string());
+    let recorded: Vec<Day> = read(&path)?
+        .into_iter()
+        .filter(|day| day.date >= since)
+        .collect();
+    writeln!(
+        out,
+        "{:<10}  {:>10}  {:>8}  {:>8}  {:>7}",
+        "DATE", "BEATS", "MISSES", "RESTARTS", "MISSED"
+    )?;
+    let mut total = Day {
+        date: "TOTAL".to_owned(),
+        beats: 0,
+        misses: 0,
+        restarts: 0,
+    };
+    for day in &recorded {
+        print_day(&mut out, day)?;
+        total.beats += day.beats;
+        total.misses += day.misses;
+        total.restarts += day.restarts;
+    }
+    print_day(&mut out, &total)?;
+    Ok(())
+}
+
+/// Prints a line of the summary for the `day`, with the share of the
+/// heartbeats missed.
+fn print_day<W: Write>(out: &mut W, day: &Day) -> Result<()> {
+    let missed = if day.beats == 0 {
+        0.0
+    } else {
+        day.misses as f64 * 100.0 / day.beats as f64
+    };
+    writeln!(
+        out,
+        "{:<10}  {:>10}  {:>8}  {:>8}  {:>6.2}%",
+        day.date, day.beats, day.misses, day.restarts, missed
+    )?;
+    Ok(())
+}
+
+/// Returns the path to the history of the target the `config`
+/// describes.
+fn path(config: &Config) -> Result<PathBuf> {
+    let section = config.section(section::HEARTBEAT)?;
+    Ok(
+        PathBuf::from(section.string(key::HISTORY_DIRECTORY)?).join(format!(
+            "{}.history",
+            section.target_id()?.name().to_lowercase()
+        )),
+    )
+}
+
+/// Reads the days in the history at `path`, oldest first.  A missing
+/// file holds no days.
+fn read(path: &Path) -> Result<Vec<Day>> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(Day::parse)
+            .collect(),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err.into()),
+    }
+}
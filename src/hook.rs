@@ -0,0 +1,309 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::logger::{message, LogLevel, Logger};
+use crate::reaper::OwnChild;
+use crate::result::Result;
+use crate::signal::parse_signal;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::rc::Rc;
+use tokio::process::Command;
+use tokio::time::{sleep, timeout, Duration};
+
+/// The default time in seconds a hook may run for.
+static DEFAULT_HOOK_TIMEOUT: i64 = 10;
+
+/// The default time in milliseconds to wait after DIAGNOSTIC-SIGNAL.
+static DEFAULT_DIAGNOSTIC_WAIT: i64 = 1000;
+
+/// The placeholder in the arguments of DIAGNOSTIC-COMMAND for the
+/// process ID of the hung process.
+static PID_PLACEHOLDER: &str = "$PID";
+
+/// The environment variable that tells a hook the process ID of the
+/// managed process.
+static PID_VARIABLE: &str = "HEARTBEAT2_PID";
+
+/// The environment variable that tells a hook the instance ID of the
+/// supervision.
+static INSTANCE_ID_VARIABLE: &str = "HEARTBEAT2_INSTANCE_ID";
+
+/// The environment variable that tells the escalation command why
+/// `Heartbeat2` gave up on the managed process.
+static REASON_VARIABLE: &str = "HEARTBEAT2_REASON";
+
+/// Returns the signal hooks SIGNAL-HOOKS configures.
+///
+/// # Returns
+///
+/// Returns a list of pairs of a signal and the command of its hook.
+///
+/// # Errors
+///
+/// Returns a configuration error if a hook names an unknown signal or
+/// lacks a command.  `SIGCHLD` belongs to
+/// [`Reaper`](crate::reaper::Reaper), and `SIGINT` takes the hook of
+/// the signal SIGINT-ACTION makes it stand for, so neither may have a
+/// hook.
+pub(crate) fn signal_hooks(config: &Config) -> Result<Vec<(Signal, Vec<String>)>> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.has_key(key::SIGNAL_HOOKS) {
+        return Ok(vec![]);
+    }
+    section
+        .string_lists(key::SIGNAL_HOOKS)?
+        .into_iter()
+        .map(|mut hook| {
+            if hook.len() < 2 {
+                return Err(config_format_error(&format!(
+                    "{} needs a signal and a command in {:?}",
+                    key::SIGNAL_HOOKS,
+                    hook
+                )));
+            }
+            let command = hook.split_off(1);
+            let signal = parse_signal(&hook[0], key::SIGNAL_HOOKS)?;
+            if matches!(signal, Signal::SIGCHLD | Signal::SIGINT) {
+                Err(config_format_error(&format!(
+                    "{} may not hook [{}]",
+                    key::SIGNAL_HOOKS,
+                    hook[0]
+                )))
+            } else {
+                Ok((signal, command))
+            }
+        })
+        .collect()
+}
+
+/// Runs hooks, the commands the configuration associates with events
+/// in the supervision.
+///
+/// `Heartbeat2` carries on handling the event once the hook exits, or
+/// once HOOK-TIMEOUT seconds pass, whichever comes first.
+/// `HookRunner` kills a hook that runs for too long.  A hook that
+/// fails or times out gets logged, but doesn't affect the
+/// supervision.  The environment variable `HEARTBEAT2_PID` holds the
+/// process ID of the managed process for the hook, if it is running,
+/// and `HEARTBEAT2_INSTANCE_ID` holds the instance ID of the
+/// supervision, so that a hook that sends notifications can tell
+/// where they come from.
+///
+/// # Configuration
+///
+/// * DIAGNOSTIC-COMMAND: Optional.  The command to run on a hung
+///   process before `Heartbeat2` kills it, such as `("jstack" "$PID")`
+///   or `("gcore" "$PID")`.  An argument `$PID` stands for the process
+///   ID of the hung process.
+/// * DIAGNOSTIC-SIGNAL: Optional.  The name of the signal to send to a
+///   hung process before `Heartbeat2` kills it, such as `"SIGQUIT"` to
+///   have a JVM dump its threads.  The signal should be one the
+///   process handles, or the process dies of it before the kill.
+/// * DIAGNOSTIC-WAIT: Optional.  The time in milliseconds to wait
+///   after DIAGNOSTIC-SIGNAL for the process to act on it.  The
+///   default is 1000.
+/// * ESCALATION-COMMAND: Optional.  The command to run as `Heartbeat2`
+///   gives up on the managed process, such as to page an operator,
+///   to power-cycle a device or to collect diagnostics.  The
+///   environment variable `HEARTBEAT2_REASON` holds the reason, such
+///   as `too many retries`.
+/// * HOOK-TIMEOUT: Optional.  The time in seconds a hook may run for.
+///   The default is 10 seconds.
+/// * SIGNAL-HOOKS: Optional.  A list of hooks to run on signals.  Each
+///   hook is a list of the name of a signal followed by the command,
+///   such as `(("SIGHUP" "snapshot" "--all"))`.  The hook runs before
+///   `Heartbeat2` relays the signal to the managed process, or
+///   instead of relaying it if `Heartbeat2` otherwise ignores the
+///   signal.
+pub(crate) struct HookRunner {
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl HookRunner {
+    /// Creates a new `HookRunner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        HookRunner { config, logger }
+    }
+
+    /// Runs the hook of the `signal`, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `signal` - The signal `Heartbeat2` has received.
+    /// * `pid` - The process ID of the managed process, if running.
+    pub(crate) async fn run_signal_hook(&self, signal: Signal, pid: Option<u32>) -> Result<()> {
+        if let Some((_, command)) = signal_hooks(&self.config)?
+            .into_iter()
+            .find(|(hooked, _)| *hooked == signal)
+        {
+            self.run(&format!("{:?} hook", signal), &command, pid)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Runs ESCALATION-COMMAND, if any, as `Heartbeat2` gives up on
+    /// the managed process for the `reason`.
+    pub(crate) async fn run_escalation(&self, reason: &str) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if section.has_key(key::ESCALATION_COMMAND) {
+            let command = section.string_list(key::ESCALATION_COMMAND)?;
+            self.run_with(
+                "escalation command",
+                &command,
+                None,
+                &[(REASON_VARIABLE, reason)],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Collects the evidence of what the hung process `pid` is
+    /// blocked on before the kill.  Sends DIAGNOSTIC-SIGNAL, if any,
+    /// and waits DIAGNOSTIC-WAIT for the process to act on it, then
+    /// runs DIAGNOSTIC-COMMAND, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error only if the configuration is invalid.  A
+    /// failure to collect the diagnostics is logged instead.
+    pub(crate) async fn run_diagnostics(&self, pid: u32) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if section.has_key(key::DIAGNOSTIC_SIGNAL) {
+            let name = section.string(key::DIAGNOSTIC_SIGNAL)?;
+            let signal = parse_signal(name, key::DIAGNOSTIC_SIGNAL)?;
+            let wait = Duration::from_millis(
+                section
+                    .integer_or(key::DIAGNOSTIC_WAIT, DEFAULT_DIAGNOSTIC_WAIT)?
+                    .try_into()?,
+            );
+            match kill(Pid::from_raw(pid.try_into()?), signal) {
+                Ok(()) => {
+                    self.logger.log_coded(
+                        &message::HK006,
+                        &format!("sent {} to hung process [{}]", name, pid),
+                    );
+                    sleep(wait).await;
+                }
+                Err(err) => self.logger.log_coded(
+                    &message::HK007,
+                    &format!("unable to send {} to hung process [{}]: {}", name, pid, err),
+                ),
+            }
+        }
+        if section.has_key(key::DIAGNOSTIC_COMMAND) {
+            let command = section
+                .string_list(key::DIAGNOSTIC_COMMAND)?
+                .into_iter()
+                .map(|arg| arg.replace(PID_PLACEHOLDER, &pid.to_string()))
+                .collect::<Vec<_>>();
+            self.run("diagnostic command", &command, Some(pid)).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the hook `command` until it exits or HOOK-TIMEOUT
+    /// elapses.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the hook for the log.
+    /// * `command` - The executable of the hook and its arguments.
+    /// * `pid` - The process ID of the managed process, if running.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error only if the configuration is invalid.  The
+    /// failures of the hook itself are logged instead.
+    pub(crate) async fn run(&self, name: &str, command: &[String], pid: Option<u32>) -> Result<()> {
+        self.run_with(name, command, pid, &[]).await
+    }
+
+    /// Runs the hook `command` as [`run`](HookRunner::run) does, with
+    /// the environment variables `vars` on top.
+    async fn run_with(
+        &self,
+        name: &str,
+        command: &[String],
+        pid: Option<u32>,
+        vars: &[(&str, &str)],
+    ) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let bound = Duration::from_secs(
+            section
+                .integer_or(key::HOOK_TIMEOUT, DEFAULT_HOOK_TIMEOUT)?
+                .try_into()?,
+        );
+        let (exec, args) = command
+            .split_first()
+            .ok_or_else(|| config_format_error(&format!("{} has no command", name)))?;
+        self.logger
+            .log_coded(&message::HK001, &format!("run {}", name));
+        let mut hook = Command::new(exec);
+        hook.args(args)
+            .env(INSTANCE_ID_VARIABLE, section.instance_id()?)
+            .envs(vars.iter().copied())
+            .kill_on_drop(true);
+        if let Some(pid) = pid {
+            hook.env(PID_VARIABLE, pid.to_string());
+        }
+        let mut child = match hook.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                self.logger
+                    .log_coded(&message::HK002, &format!("unable to run {}: {}", name, err));
+                return Ok(());
+            }
+        };
+        let _own = OwnChild::new(child.id());
+        match timeout(bound, child.wait()).await {
+            Ok(Ok(status)) if status.success() => {
+                self.logger.log(LogLevel::Debug, &format!("{} done", name))
+            }
+            Ok(Ok(status)) => self
+                .logger
+                .log_coded(&message::HK003, &format!("{} failed ({})", name, status)),
+            Ok(Err(err)) => self.logger.log_coded(
+                &message::HK004,
+                &format!("unable to wait for {}: {}", name, err),
+            ),
+            Err(_elapsed) => {
+                self.logger.log_coded(
+                    &message::HK005,
+                    &format!(
+                        "{} still running after {}s; killing it",
+                        name,
+                        bound.as_secs()
+                    ),
+                );
+                let _ = child.kill().await;
+            }
+        }
+        Ok(())
+    }
+}
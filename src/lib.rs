@@ -0,0 +1,86 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The library of `Heartbeat2`.
+//!
+//! `Heartbeat2` supervises a target application by sending it
+//! heartbeats over ZeroMQ.  The [`protocol`] module defines the
+//! messages on the wire, so that a target written in Rust can answer
+//! the heartbeats without re-deriving the format.  With the
+//! `responder` feature, the `responder` module answers them in a
+//! single call.
+//!
+//! A host application can also embed the supervision itself with a
+//! [`Supervisor`], and react to its events with
+//! [`Callbacks`](callback::Callbacks).  A `Supervisor` takes either a
+//! configuration file, or a command with a [`Check`] of its health and
+//! a [`RestartPolicy`].
+
+mod adhoc;
+pub mod callback;
+mod channel;
+mod codec;
+mod config;
+mod context;
+mod control;
+mod device;
+mod directory;
+mod error;
+mod event;
+mod expression;
+mod fleet;
+mod gate;
+mod heartbeat;
+mod history;
+mod hook;
+mod keyword;
+pub mod logger;
+mod metrics;
+mod naming;
+mod notify;
+mod output;
+mod plist;
+mod process;
+pub mod protocol;
+mod reaper;
+mod release;
+mod report;
+#[cfg(feature = "responder")]
+pub mod responder;
+mod restart;
+mod result;
+mod sampling;
+mod selftest;
+mod setup;
+mod shutdown;
+mod signal;
+#[cfg(feature = "zmq")]
+mod socket;
+mod spare;
+mod state;
+#[cfg(feature = "sup")]
+mod sup;
+mod supervisor;
+mod timeline;
+mod transport;
+mod tree;
+mod usage;
+mod watchdog;
+
+pub use adhoc::{Check, RestartPolicy};
+pub use supervisor::Supervisor;
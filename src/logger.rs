@@ -19,6 +19,12 @@
 use chrono::Local;
 use core::fmt::{self, Display};
 
+pub(crate) mod message;
+mod remote;
+
+pub use message::Message;
+pub(crate) use remote::RemoteLogger;
+
 /// Represents the log level for logging messages.
 ///
 /// The `LogLevel` enum represents different levels of log
@@ -30,11 +36,12 @@ use core::fmt::{self, Display};
 /// # Examples
 ///
 /// ```rust
-/// use crate::LogLevel;
+/// use heartbeat2::logger::{LocalLogger, LogLevel, Logger};
 ///
-/// let logger = Logger::new("MyApp");
+/// let logger = LocalLogger::new("MyApp");
 /// logger.log(LogLevel::Error, "Error description");
 /// ```
+#[derive(Clone, Copy)]
 pub enum LogLevel {
     /// Represents debug-level log messages used for debugging
     /// purposes.
@@ -73,6 +80,55 @@ impl Display for LogLevel {
     }
 }
 
+/// Logs messages with a log level.
+///
+/// `Heartbeat2` logs through a `Logger` without knowing where the log
+/// messages go.  The implementations are [`LocalLogger`] and
+/// `RemoteLogger`, which LOGGER chooses between.
+///
+/// # Configuration
+///
+/// * LOGGER: Optional.  `:local` or `:remote`.  The default is
+///   `:local`.
+pub trait Logger {
+    /// Logs a message with the specified log level.
+    ///
+    /// The `level` represents the severity of the logged message,
+    /// and `message` is its content.  You can use
+    /// [format!](format!) macro to format a log message as in the
+    /// example below.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use heartbeat2::logger::{LocalLogger, LogLevel, Logger};
+    ///
+    /// let logger = LocalLogger::new("my_app");
+    /// logger.log(LogLevel::Info, "Initializing application");
+    /// logger.log(LogLevel::Info, &format!("Application ID: {}", "my_app"));
+    /// ```
+    fn log(&self, level: LogLevel, message: &str);
+
+    /// Logs the `text` of a `message` at its level, with its stable
+    /// code in brackets before the text.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// logger.log_coded(&message::HB015, "heartbeat timed out");
+    /// // ... Error: [HB015] heartbeat timed out
+    /// ```
+    fn log_coded(&self, message: &Message, text: &str) {
+        self.log(message.level(), &format!("[{}] {}", message, text));
+    }
+
+    /// Returns a `LocalLogger` for logging from another thread.  The
+    /// other threads of `Heartbeat2` log when its main thread is
+    /// stuck, so the log messages can't wait for the main thread to
+    /// ship them elsewhere.
+    fn local(&self) -> LocalLogger;
+}
+
 /// Logs messages to a local logging destination, such as standard
 /// error or a local file.
 ///
@@ -86,19 +142,21 @@ impl Display for LogLevel {
 /// `RemoteLogger` has the same interface as `LocalLogger`.  But it
 /// logs to a remote logging service instead of a local destination.
 /// It relies on IPC over an asynchronous message queue to log
-/// messages behind the scene.  `Heartbeat2` has `LocalLogger`
-/// implemented, but not `RemoteLogger`, at the moment.
+/// messages behind the scene, and falls back to `LocalLogger` when
+/// the remote logging service is unreachable.
 ///
 /// # Examples
 ///
 /// ```rust
-/// use crate::{LocalLogger, LogLevel};
+/// use heartbeat2::logger::{LocalLogger, LogLevel, Logger};
 ///
 /// let logger = LocalLogger::new("my_app");
 /// logger.log(LogLevel::Info, "Initializing application");
 /// ```
+#[derive(Clone)]
 pub struct LocalLogger {
     app_id: String,
+    instance_id: Option<String>,
 }
 
 impl LocalLogger {
@@ -117,40 +175,63 @@ impl LocalLogger {
     /// # Examples
     ///
     /// ```rust
-    /// use crate::LocalLogger;
+    /// use heartbeat2::logger::LocalLogger;
     ///
     /// let logger = LocalLogger::new("my_app");
     /// ```
     pub fn new(app_id: &str) -> Self {
         LocalLogger {
             app_id: app_id.to_owned(),
+            instance_id: None,
         }
     }
 
-    /// Logs a message with the specified log level.
-    ///
-    /// The log function logs a message with the given level and
-    /// message.  The level represents the severity of the logged
-    /// message, and message is its content.  You can use
-    /// [format!](format!) macro to format a log message as in the
-    /// example below.
-    ///
-    /// # Examples
-    ///
-    /// ```rust
-    /// use crate::{LocalLogger, LogLevel};
-    ///
-    /// let logger = LocalLogger::new("my_app");
-    /// logger.log(LogLevel::Info, "Initializing application");
-    /// logger.log(LogLevel::Info, &format!("Application ID: {}", "my_app"));
-    /// ```
-    pub fn log(&self, level: LogLevel, message: &str) {
-        eprintln!(
-            "[{}] [{}] {}: {}",
-            self.app_id,
-            Local::now(),
-            level,
-            message
-        );
+    /// Returns a `LocalLogger` that also presents the `instance_id` in
+    /// the log messages, so that the logs aggregated from many hosts
+    /// remain attributable.
+    pub(crate) fn with_instance(&self, instance_id: &str) -> Self {
+        LocalLogger {
+            app_id: self.app_id.clone(),
+            instance_id: Some(instance_id.to_owned()),
+        }
+    }
+
+    /// Formats a log message with the specified log level into a
+    /// line, which presents the `app_id`, the instance ID if any, the
+    /// time, the `level` and the `message`.
+    pub(crate) fn line(&self, level: LogLevel, message: &str) -> String {
+        match &self.instance_id {
+            Some(instance_id) => format!(
+                "[{}] [{}] [{}] {}: {}",
+                self.app_id,
+                instance_id,
+                Local::now(),
+                level,
+                message
+            ),
+            None => format!(
+                "[{}] [{}] {}: {}",
+                self.app_id,
+                Local::now(),
+                level,
+                message
+            ),
+        }
+    }
+
+    /// Writes a formatted log `line` to the local logging
+    /// destination.
+    pub(crate) fn write(&self, line: &str) {
+        eprintln!("{}", line);
+    }
+}
+
+impl Logger for LocalLogger {
+    fn log(&self, level: LogLevel, message: &str) {
+        self.write(&self.line(level, message));
+    }
+
+    fn local(&self) -> LocalLogger {
+        self.clone()
     }
 }
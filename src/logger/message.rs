@@ -0,0 +1,243 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::logger::LogLevel;
+use core::fmt::{self, Display};
+
+/// Identifies a kind of log message, or of notification, by a stable
+/// code.
+///
+/// The text of a message may change from a release to the next, but
+/// its code doesn't.  Alerting rules and runbooks match on the code,
+/// such as `HB015`, rather than on the text.  The letters of a code
+/// tell the component that logs the message, and the digits tell the
+/// message apart within the component.  A code is never reused for
+/// another message.  [`Logger::log_coded`](super::Logger::log_coded)
+/// presents the code in brackets before the text, such as `[HB015]
+/// heartbeat timed out`.
+///
+/// Only the messages at `Info` or above carry a code.  The messages
+/// at `Debug` and `Trace` are meant for the developers of
+/// `Heartbeat2`, and may change without notice.  Neither does the
+/// output of the managed process carry a code.  [`table`] renders
+/// the reference table of all codes.
+pub struct Message {
+    code: &'static str,
+    level: LogLevel,
+    summary: &'static str,
+}
+
+impl Message {
+    /// Returns the code of the message, such as `HB015`.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Returns the level the message is logged at.
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+/// Defines a static [`Message`] for each code, and [`MESSAGES`], the
+/// list of them all in the order of definition.
+macro_rules! messages {
+    ($($code:ident $level:ident $summary:literal,)*) => {
+        $(
+            #[doc = $summary]
+            pub(crate) static $code: Message = Message {
+                code: stringify!($code),
+                level: LogLevel::$level,
+                summary: $summary,
+            };
+        )*
+
+        /// All the messages, in the order of their codes.
+        pub(crate) static MESSAGES: &[&Message] = &[$(&$code),*];
+    };
+}
+
+messages! {
+    BL001 Warning "The beat log couldn't be written.",
+    CH001 Warning "A component has waited long to raise an event.",
+    CT001 Info "The control server serves requests on an endpoint.",
+    CT002 Warning "The control server got an unknown request.",
+    CT003 Info "The heartbeat was paused to debug the process.",
+    CT004 Warning "The control server refused a request.",
+    EV001 Warning "A heartbeat timeout of an earlier process was ignored.",
+    EV002 Info "An operator requested a restart of the process.",
+    EV003 Warning "A signal to the process wasn't acknowledged in time.",
+    EV004 Severe "The process outlived the action on it and is being killed.",
+    EV005 Severe "The process is still running after the kill.",
+    EV006 Info "The process exited after an action on it.",
+    EV007 Severe "The process did not exit after the kill; it may be stuck.",
+    EV008 Info "The target requested a restart of the process.",
+    EV009 Error "The hang report couldn't be saved; the kill goes ahead.",
+    EV010 Error "The diagnostics couldn't be collected; the kill goes ahead.",
+    FL001 Info "The fleet stops on a signal.",
+    FL002 Warning "A target of the fleet hasn't started, so it can't be stopped.",
+    FL003 Info "A target of the fleet stops.",
+    FL004 Info "How the supervision of a target of the fleet ended.",
+    FL005 Info "A target of the fleet waits for its turn to restart.",
+    FL006 Info "The fleet serves control requests on an endpoint.",
+    FL007 Warning "The fleet got an unknown control request.",
+    GT001 Info "The health gate opens.",
+    GT002 Info "The health gate closes.",
+    GT003 Error "The health gate failed to open or close; the heartbeat goes on.",
+    HB001 Info "The heartbeat starts.",
+    HB002 Info "The heartbeat pauses.",
+    HB003 Info "The heartbeat resumes.",
+    HB004 Info "The heartbeat is redirected to another endpoint.",
+    HB005 Warning "The target is stopped; the heartbeat is held.",
+    HB006 Info "The target continues; the heartbeat resumes.",
+    HB007 Warning "A heartbeat to an endpoint failed.",
+    HB008 Info "The heartbeat goes over an endpoint.",
+    HB010 Error "The probe command failed to run.",
+    HB011 Warning "A heartbeat was missed and is being retried.",
+    HB012 Info "The target is ready again.",
+    HB013 Warning "The target isn't ready.",
+    HB014 Warning "A missed heartbeat right after a clock jump wasn't counted.",
+    HB015 Error "The heartbeat timed out.",
+    HB016 Error "The probe found the target dead.",
+    HB017 Warning "The clock jumped, as after a suspension.",
+    HB018 Warning "A drill makes the next heartbeat time out.",
+    HB019 Warning "A stale answer to a heartbeat was discarded.",
+    HB020 Warning "A missed heartbeat was tolerated as the success ratio holds.",
+    HB021 Error "The success ratio of the heartbeats fell below the limit.",
+    HB022 Warning "The target is degraded.",
+    HB023 Info "The target is no longer degraded.",
+    HB024 Info "The target is busy, and the heartbeat timeout is extended.",
+    HB025 Info "A control keyword is queued for the target.",
+    HB026 Warning "The target asked for a restart.",
+    HB027 Severe "The target is leased to another supervisor.",
+    HB028 Info "The target accepted the lease.",
+    HB029 Warning "A missed heartbeat was left to the supervisor that owns the target.",
+    HK001 Info "A hook runs.",
+    HK002 Error "A hook couldn't be run.",
+    HK003 Warning "A hook failed.",
+    HK004 Error "A hook couldn't be waited for.",
+    HK005 Error "A hook ran too long and is being killed.",
+    HK006 Info "A diagnostic signal was sent to the hung process.",
+    HK007 Error "A diagnostic signal couldn't be sent to the hung process.",
+    HR001 Info "The summary of a hang report.",
+    HR002 Info "A hang report was written to a file.",
+    HR003 Error "A hang report couldn't be written to a file.",
+    HR004 Info "A crash report, logged in full.",
+    HR005 Info "A crash report was written to a file.",
+    HR006 Error "A crash report couldn't be written to a file.",
+    HS001 Warning "The history couldn't be written.",
+    LG001 Warning "The logging service is unreachable; logging locally.",
+    MT001 Warning "The metrics couldn't be written.",
+    MT002 Info "The metrics are pushed to an HTTP sink.",
+    MT003 Warning "A push of the metrics failed.",
+    NM001 Info "The endpoint of the healthy target is registered.",
+    NM002 Warning "The endpoint of the target couldn't be registered.",
+    NM003 Info "The registration of the endpoint of the target is removed.",
+    NM004 Warning "The registration of the endpoint of the target couldn't be removed.",
+    NM005 Info "The configuration is reloaded for resolving the endpoints of the target.",
+    NM006 Warning "The configuration couldn't be reloaded; the previous one stays in effect.",
+    NT001 Warning "A webhook isn't an http:// URL.",
+    NT002 Warning "A webhook failed.",
+    NT003 Info "Notification: the process aborted.",
+    NT004 Info "Notification: a heartbeat timed out.",
+    NT005 Info "Notification: the process is being restarted.",
+    NT006 Info "Notification: the supervision gave up on the process.",
+    NT007 Info "Notification: the restart of the process is pending.",
+    NT008 Info "Notification: the target is degraded.",
+    OL001 Error "The output log couldn't be opened.",
+    OL002 Error "The output log couldn't be written.",
+    PM001 Info "A spare is promoted to the process.",
+    PM002 Info "The process starts.",
+    PM003 Info "A spare starts.",
+    PM004 Warning "The spare exited before its promotion.",
+    PM005 Warning "An action on an earlier process was ignored.",
+    PM006 Warning "A signal couldn't be raised as the process already exited.",
+    PM007 Warning "The process is still running after SIGTERM and is being killed.",
+    PM008 Fatal "The process is still running after the kill; it may be stuck.",
+    PM009 Info "A signal is queued for the next process.",
+    PM010 Info "A queued signal is replayed to the process.",
+    PM011 Warning "A signal was dropped.",
+    PM012 Info "The process exited normally.",
+    PM013 Error "The process exited abnormally.",
+    PM014 Error "The process manager rejected a state transition.",
+    PM015 Warning "USER and GROUP were ignored as heartbeat2 isn't root.",
+    PM016 Warning "The rest of the process group outlived the process.",
+    PM017 Warning "Descendants outlived the process and were killed.",
+    PM018 Info "The exit of the process was reclassified by the configuration.",
+    PM019 Error "The process was terminated by a signal.",
+    PM020 Warning "The spare failed to start; the primary runs without one.",
+    PR001 Warning "An endpoint is still in use after the wait.",
+    RP001 Info "The zombie reaper starts.",
+    RP002 Warning "The zombies couldn't be reaped.",
+    RS001 Info "The restart policy decided whether to restart the process.",
+    SD001 Fatal "The shutdown took too long; heartbeat2 exits.",
+    SG001 Warning "Power failure; the host is going down.",
+    ST001 Error "An illegal transition of the target state.",
+    SV001 Info "The supervision of a target starts.",
+    SV002 Info "The endpoint of the target is advertised.",
+    SV003 Info "The host is shutting down, so the process isn't restarted.",
+    SV004 Info "The process is about to restart.",
+    SV005 Info "The restart waits for a delay.",
+    SV006 Info "The supervision gives up after too many retries.",
+    SV007 Fatal "The supervision gives up on a stuck process.",
+    SV008 Info "A summary of the supervision on exit.",
+    SV009 Info "The configuration is loaded from a file.",
+    SV010 Info "A configuration profile is applied.",
+    SV011 Info "The configuration of Sup is loaded from a file.",
+    SV012 Fatal "The supervision of a target of the fleet failed.",
+    SV013 Info "The restart is pending until RESTART-HOURS begin.",
+    SV014 Info "A signal ended the supervision while a restart was pending.",
+    SV015 Warning "The profile from the environment is ignored, as the configuration has no profiles.",
+    US001 Warning "The resource usage couldn't be sampled.",
+    US002 Warning "The resident memory of heartbeat2 grew; possible leak.",
+    US003 Warning "The open file descriptors of heartbeat2 grew; possible leak.",
+    WD001 Info "The watchdog starts.",
+    WD002 Fatal "Heartbeat2 made no progress and aborts.",
+    WD003 Info "Heartbeat2 pets the watchdog device.",
+    WD004 Warning "The watchdog device couldn't be disarmed.",
+}
+
+/// Renders the reference table of all codes, a line per code with
+/// its level and a summary of the message.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// print!("{}", table());
+/// // CODE   LEVEL    MESSAGE
+/// // BL001  Warning  The beat log couldn't be written.
+/// // ...
+/// ```
+pub(crate) fn table() -> String {
+    let mut text = format!("{:<6} {:<8} {}\n", "CODE", "LEVEL", "MESSAGE");
+    for message in MESSAGES {
+        text.push_str(&format!(
+            "{:<6} {:<8} {}\n",
+            message.code,
+            message.level.to_string(),
+            message.summary
+        ));
+    }
+    text
+}
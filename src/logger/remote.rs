@@ -0,0 +1,331 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::context::Context;
+use crate::error::config_format_error;
+#[cfg(feature = "zmq")]
+use crate::error::unknown_response_error;
+#[cfg(feature = "zmq")]
+use crate::keyword::Keyword;
+use crate::kw;
+#[cfg(feature = "zmq")]
+use crate::logger::message;
+use crate::logger::{LocalLogger, LogLevel, Logger};
+use crate::naming::Naming;
+#[cfg(feature = "zmq")]
+use crate::protocol;
+use crate::result::Result;
+#[cfg(feature = "zmq")]
+use crate::socket::{SocketBuilder, SocketSender};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use tokio::sync::Notify;
+#[cfg(feature = "zmq")]
+use tokio::time::{sleep, Duration};
+
+/// The default number of log messages to buffer.
+static DEFAULT_BUFFER_SIZE: i64 = 1000;
+
+/// The default timeout in milliseconds of the requests to the logging
+/// service.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+static DEFAULT_LOG_TIMEOUT: i64 = 1000;
+
+/// The time in seconds to log locally after the logging service turns
+/// out unreachable, before trying it again.
+#[cfg(feature = "zmq")]
+static RETRY_INTERVAL_SECS: u64 = 5;
+
+/// Tells whether LOGGER selects `RemoteLogger`.
+///
+/// # Errors
+///
+/// Returns a configuration error if LOGGER names an unknown logger.
+pub(crate) fn logs_remotely(config: &Config) -> Result<bool> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.has_key(key::LOGGER) {
+        return Ok(false);
+    }
+    let logger = section.keyword(key::LOGGER)?;
+    if *logger == kw![local] {
+        Ok(false)
+    } else if *logger == kw![remote] {
+        Ok(true)
+    } else {
+        Err(config_format_error(&format!(
+            "unknown {} [{}]",
+            key::LOGGER,
+            logger
+        )))
+    }
+}
+
+/// Logs messages to a remote logging service over ZeroMQ.
+///
+/// `RemoteLogger` has the same interface as [`LocalLogger`], and logs
+/// the same lines.  It resolves the endpoint of the logging service
+/// with the naming service, and sends it each line as a `LOG` request
+/// of the [`protocol`](crate::protocol), which the logging service
+/// answers with `OK`.
+///
+/// [`log`](Logger::log) never waits for the logging service.  It
+/// buffers the line, and [`run`](RemoteLogger::run) ships the buffer
+/// in the background.  Once the buffer is full, the oldest line goes
+/// to the `LocalLogger` to make room.  If the logging service is
+/// unreachable, `RemoteLogger` writes the buffer to the `LocalLogger`,
+/// and logs locally for a while before it tries the logging service
+/// again.  No line is lost either way.
+///
+/// `Heartbeat2` logs locally until it has loaded the configuration.
+///
+/// # Configuration
+///
+/// * LOG-SERVICE: Required by `RemoteLogger`.  The name of the
+///   logging service to resolve with the naming service, such as
+///   `:log`.
+/// * LOG-BUFFER-SIZE: Optional.  The maximum number of lines to
+///   buffer.  The default is 1000.
+/// * LOG-TIMEOUT: Optional.  The timeout in milliseconds of the
+///   requests to the logging service.  The default is 1000.
+pub(crate) struct RemoteLogger {
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    context: Context,
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    naming: Rc<dyn Naming>,
+    config: Rc<Config>,
+    local: LocalLogger,
+    buffer: RefCell<VecDeque<String>>,
+    capacity: Cell<usize>,
+    reachable: Cell<bool>,
+    arrived: Notify,
+}
+
+impl RemoteLogger {
+    /// Creates a new `RemoteLogger`.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The ZeroMQ context to create the sockets in.
+    /// * `naming` - The naming service to resolve LOG-SERVICE with.
+    /// * `config` - A shared reference to the configuration.
+    /// * `local` - The logger to fall back to.
+    pub(crate) fn new(
+        context: Context,
+        naming: Rc<dyn Naming>,
+        config: Rc<Config>,
+        local: LocalLogger,
+    ) -> Self {
+        RemoteLogger {
+            context,
+            naming,
+            config,
+            local,
+            buffer: RefCell::new(VecDeque::new()),
+            capacity: Cell::new(DEFAULT_BUFFER_SIZE as usize),
+            reachable: Cell::new(true),
+            arrived: Notify::new(),
+        }
+    }
+
+    /// Returns whether the `RemoteLogger` should run.
+    pub(crate) fn is_enabled(&self) -> Result<bool> {
+        logs_remotely(&self.config)
+    }
+
+    /// Ships the buffered lines to the logging service until
+    /// `Heartbeat2` exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed.  An
+    /// unreachable logging service isn't an error.
+    #[cfg(feature = "zmq")]
+    pub(crate) async fn run(&self) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let service = section.keyword(key::LOG_SERVICE)?;
+        let timeout = section
+            .integer_or(key::LOG_TIMEOUT, DEFAULT_LOG_TIMEOUT)?
+            .try_into()?;
+        self.capacity.set(
+            section
+                .integer_or(key::LOG_BUFFER_SIZE, DEFAULT_BUFFER_SIZE)?
+                .try_into()?,
+        );
+        let mut connection = None;
+        loop {
+            if self.buffer.borrow().is_empty() {
+                self.arrived.notified().await;
+                continue;
+            }
+            let socket = match connection.take() {
+                Some(socket) => Ok(socket),
+                None => self.connect(service, timeout).await,
+            };
+            let result = match socket {
+                Ok(socket) => self.ship(socket).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(socket) => connection = Some(socket),
+                Err(err) => {
+                    self.fall_back(&err.to_string());
+                    sleep(Duration::from_secs(RETRY_INTERVAL_SECS)).await;
+                    self.reachable.set(true);
+                }
+            }
+        }
+    }
+
+    /// Stands in for the logging over ZeroMQ without the `zmq`
+    /// feature.
+    #[cfg(not(feature = "zmq"))]
+    pub(crate) async fn run(&self) -> Result<()> {
+        Err(config_format_error(&format!(
+            "{} [:REMOTE] needs the zmq feature",
+            key::LOGGER
+        )))
+    }
+
+    /// Ships the lines left in the buffer as `Heartbeat2` exits, or
+    /// writes them to the `LocalLogger` if the logging service is
+    /// unreachable.
+    #[cfg(feature = "zmq")]
+    pub(crate) async fn flush(&self) {
+        if self.buffer.borrow().is_empty() {
+            return;
+        }
+        let result = async {
+            let section = self.config.section(section::HEARTBEAT)?;
+            let timeout = section
+                .integer_or(key::LOG_TIMEOUT, DEFAULT_LOG_TIMEOUT)?
+                .try_into()?;
+            let socket = self
+                .connect(section.keyword(key::LOG_SERVICE)?, timeout)
+                .await?;
+            self.ship(socket).await
+        }
+        .await;
+        if let Err(err) = result {
+            self.fall_back(&err.to_string());
+        }
+    }
+
+    /// Writes the lines left in the buffer to the `LocalLogger`
+    /// without the `zmq` feature.
+    #[cfg(not(feature = "zmq"))]
+    pub(crate) async fn flush(&self) {
+        for line in self.buffer.borrow_mut().drain(..) {
+            self.local.write(&line);
+        }
+    }
+
+    /// Resolves the endpoint of the logging `service`, and connects
+    /// to it.
+    #[cfg(feature = "zmq")]
+    async fn connect(&self, service: &Keyword, timeout: u64) -> Result<SocketSender> {
+        let endpoint = self.naming.resolve(service).await?;
+        SocketBuilder::new(self.context.clone())
+            .endpoint(&endpoint)
+            .timeout(timeout)
+            .linger(false)
+            .req()
+            .connect()
+    }
+
+    /// Sends the buffered lines to the logging service one at a time
+    /// until the buffer is empty.  Writes the line in flight to the
+    /// `LocalLogger` if the logging service fails to acknowledge it.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `socket` to send the next lines with.
+    #[cfg(feature = "zmq")]
+    async fn ship(&self, mut socket: SocketSender) -> Result<SocketSender> {
+        loop {
+            let line = match self.buffer.borrow_mut().pop_front() {
+                Some(line) => line,
+                None => return Ok(socket),
+            };
+            match self.send(socket, &line).await {
+                Ok(sender) => socket = sender,
+                Err(err) => {
+                    self.local.write(&line);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Sends the `line` to the logging service, and waits for the
+    /// acknowledgement.
+    #[cfg(feature = "zmq")]
+    async fn send(&self, socket: SocketSender, line: &str) -> Result<SocketSender> {
+        let (reply, sender) = socket
+            .send_multipart(protocol::log(line))
+            .await?
+            .recv_multipart()
+            .await?;
+        if reply.is_empty() || reply[0] != kw![ok] {
+            return Err(unknown_response_error(
+                reply.first().map_or("", |message| message.as_str()),
+            ));
+        }
+        Ok(sender)
+    }
+
+    /// Writes the buffered lines to the `LocalLogger`, and logs
+    /// locally until the next attempt on the logging service.
+    #[cfg(feature = "zmq")]
+    fn fall_back(&self, reason: &str) {
+        self.reachable.set(false);
+        self.local.log_coded(
+            &message::LG001,
+            &format!(
+                "logging service unreachable ({}); log locally for {}s",
+                reason, RETRY_INTERVAL_SECS
+            ),
+        );
+        for line in self.buffer.borrow_mut().drain(..) {
+            self.local.write(&line);
+        }
+    }
+}
+
+impl Logger for RemoteLogger {
+    fn log(&self, level: LogLevel, message: &str) {
+        let line = self.local.line(level, message);
+        if !self.reachable.get() {
+            self.local.write(&line);
+            return;
+        }
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.len() >= self.capacity.get() {
+            if let Some(oldest) = buffer.pop_front() {
+                self.local.write(&oldest);
+            }
+        }
+        buffer.push_back(line);
+        self.arrived.notify_one();
+    }
+
+    fn local(&self) -> LocalLogger {
+        self.local.clone()
+    }
+}
@@ -16,172 +16,89 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-mod config;
-mod error;
-mod event;
-mod expression;
-mod heartbeat;
-mod keyword;
-pub mod logger;
-mod plist;
-mod process;
-mod restart;
-mod result;
-mod signal;
-mod socket;
-mod sup;
-
-use crate::config::{key, section};
-use crate::event::EventHandler;
-use crate::heartbeat::Heartbeat;
-use crate::logger::{LocalLogger, LogLevel, LogLevel::Info};
-use crate::process::{ProcessManager, RunProcess};
-use crate::restart::RestartManager;
-use crate::result::Result;
-use crate::signal::SignalHandler;
-use crate::sup::Sup;
-use config::Config;
-use std::rc::Rc;
-use tmq::Context;
-use tokio::sync::mpsc::channel;
-
-/// The unique app identifier
-static APP_ID: &str = "HEARTBEAT";
-
-/// The path to the configuration file.
-static DEFAULT_CONFIG_FILE_NAME: &str = "heartbeat.cfg";
-
-/// The size of the event queue
-static EVENT_QUEUE_SIZE: usize = 1;
-
-async fn main_impl(config: Config, logger: Rc<LocalLogger>) -> Result<()> {
-    let config = Rc::new(config);
-    let context = Context::new();
-    let sup = Rc::new(Sup::with_context(context.clone(), Rc::clone(&config)));
-    logger.log(
-        LogLevel::Info,
-        &format!(
-            "start heartbeat process (PID {}) for target [{}]",
-            std::process::id(),
-            config.section(section::HEARTBEAT)?.target_id()?
-        ),
-    );
-
-    let (event_sender, event_receiver) = channel(EVENT_QUEUE_SIZE);
-    let heartbeat = Rc::new(Heartbeat::new(
-        context.clone(),
-        event_sender.clone(),
-        Rc::clone(&config),
-        Rc::clone(&sup),
-        Rc::clone(&logger),
-    ));
-    let signal_handler = Rc::new(SignalHandler::new(event_sender.clone(), Rc::clone(&logger)));
-    let process_manager = Rc::new(ProcessManager::new(
-        event_sender.clone(),
-        Rc::clone(&config),
-        Rc::clone(&logger),
-    ));
-
-    let mut event_handler = EventHandler::new(
-        event_receiver,
-        Rc::clone(&process_manager),
-        Rc::clone(&heartbeat),
-        Rc::clone(&signal_handler),
-        Rc::clone(&logger),
-    );
-
-    let mut restart_manager = RestartManager::new(Rc::clone(&config), Rc::clone(&logger));
-
-    loop {
-        let (_, run_process, _, _) = tokio::try_join!(
-            heartbeat.run(),
-            process_manager.run_process(),
-            signal_handler.run(),
-            event_handler.run(),
-        )?;
-        match run_process {
-            RunProcess::Abort => {
-                restart_manager.add_process_abort()?;
-                if restart_manager.should_process_restart()? {
-                    logger.log(LogLevel::Info, "attempt to restart process");
-                    process_manager.reset()?;
-                    heartbeat.reset();
-                    event_handler.reset();
-                    // Drop through to the beginning of the loop.
-                } else {
-                    logger.log(LogLevel::Info, "giving up due to too many retries");
-                    process_manager.set_terminated();
-                    break;
-                }
-            }
-            RunProcess::Complete => {
-                break;
-            }
-        }
-    }
-    Ok(())
-}
-
-/// Checks if the provided `config` requires the "sup" service to
-/// resolve a service name and produce an endpoint address for IPC.
-/// If the `config` provides the endpoint of the target service,
-/// the "sup" service isn't required.
-///
-/// # Arguments
-///
-/// * `config` - A reference to the `Config` struct containing the
-/// configuration information.
-///
-/// # Returns
-///
-/// Returns a `Result` indicating whether the "sup" service is
-/// required or not. The result is `Ok(true)` if the service is
-/// required, and `Ok(false)` if it is not required. If there is an
-/// error while accessing the configuration section or key, an `Err`
-/// variant is returned with the specific error information.  This
-/// would usually be a case of the HEARTBEAT section missing in the
-/// `config`.
-///
-/// # Example
-///
-/// ```rust
-/// use crate::Config;
-///
-/// let config = Config::load_from_file("config.cfg").unwrap();
-/// let requires_sup = requires_sup(&config).unwrap();
-///
-/// if requires_sup {
-///     println!("The 'sup' service is required.");
-/// } else {
-///     println!("The 'sup' service is not required.");
-/// }
-/// ```
-fn requires_sup(config: &Config) -> Result<bool> {
-    Ok(!config
-        .section(section::HEARTBEAT)?
-        .has_key(key::TARGET_ENDPOINT))
-}
+use heartbeat2::Supervisor;
+use std::error::Error;
+use std::process::ExitCode;
 
+/// Runs `Heartbeat2`.
+///
+/// # Usage
+///
+/// `heartbeat2 [--init] [--profile NAME] [--messages] [CONFIG-FILE]`
+///
+/// `heartbeat2 [--init] run [--interval SECONDS] [--timeout
+/// MILLISECONDS] [--endpoint ENDPOINT] [--check TYPE] -- COMMAND [ARG
+/// ...]`
+///
+/// `heartbeat2 setup [CONFIG-FILE]`
+///
+/// `heartbeat2 selftest`
+///
+/// `heartbeat2 [--profile NAME] history [--days N] [CONFIG-FILE]`
+///
+/// `heartbeat2 sup-lookup ID`
+///
+/// `--init` runs `Heartbeat2` in the init mode, meant for running it
+/// as the entrypoint of a container.  In the init mode, `Heartbeat2`
+/// reaps zombie processes, forwards all catchable signals to the
+/// managed process, and exits with the exit code of the managed
+/// process.  Setting INIT to `t` in the configuration file has the
+/// same effect.
+///
+/// `--profile` applies the profile by the `NAME` from PROFILES in the
+/// configuration file over the rest of the configuration.  This lets
+/// a single configuration file carry the tunings for several
+/// environments, such as development and production.  Without
+/// `--profile`, the environment variable `HEARTBEAT2_PROFILE` selects
+/// the profile, if set.  A configuration file without PROFILES
+/// ignores the variable with a warning, as the variable may be set
+/// for every target on the host.  A profile missing from PROFILES is
+/// an error either way.
+///
+/// `--messages` prints the reference table of the stable codes of the
+/// log messages, such as `HB015`, and exits.  Alerting rules and
+/// runbooks match on the codes rather than on the text of the
+/// messages.
+///
+/// MIRROR-EXIT-STATUS in the configuration file turns the exit code
+/// mirroring on (`t`) or off (`nil`) regardless of the init mode.
+/// This suits CI systems and job schedulers that judge a job by the
+/// exit code of `Heartbeat2`.
+///
+/// `run` supervises the `COMMAND` without a configuration file, for
+/// quick local development and demos.  It restarts the command when
+/// it crashes, and, with `--endpoint`, when it stops accepting TCP
+/// connections on the endpoint.  `--check zmq` sends the heartbeats
+/// of `Heartbeat2` to the endpoint instead.  The exit code of
+/// `Heartbeat2` mirrors that of the command.
+///
+/// `setup` asks for the command, the way to check its health, the
+/// intervals of the heartbeat and the restart policy, and writes a
+/// `CONFIG-FILE` for them, heartbeat.cfg by default.  It writes a
+/// systemd unit that runs `Heartbeat2` on the file, too, if asked.
+///
+/// `selftest` checks that the host provides what `Heartbeat2` relies
+/// on: the ZeroMQ library, a REQ/REP exchange over the loopback
+/// interface, spawning and reaping a child process, and catching a
+/// signal.  It prints `PASS`, `FAIL` or `SKIP` for each check, and
+/// exits with 1 if any check fails.  This is a quick sanity check of
+/// a new host or container image.
+///
+/// `history` prints the heartbeats, the missed heartbeats and the
+/// restarts of the target per day over the last `N` days, 7 by
+/// default, from the history HISTORY-DIRECTORY in the configuration
+/// file keeps across the runs of `Heartbeat2`.  This shows whether a
+/// service grows flaky over time.
+///
+/// `sup-lookup` resolves the service `ID`, such as `:app`, with Sup
+/// as the supervision would, with the configuration of Sup and its
+/// timeouts, and prints the endpoint.  This debugs the resolution of
+/// an endpoint apart from the supervision.
+///
+/// A `CONFIG-FILE` with TARGETS is a manifest of a fleet of targets,
+/// which `Heartbeat2` supervises together.  The options apply to every
+/// target in the fleet.
 #[tokio::main()]
-async fn main() -> Result<()> {
-    let logger = Rc::new(LocalLogger::new(APP_ID));
-    let mut config = Config::new();
-    let config_path = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| DEFAULT_CONFIG_FILE_NAME.to_owned());
-    logger.log(Info, &format!("Load config from path: {}", config_path));
-    config
-        .section_mut(section::HEARTBEAT)
-        .load_from_path(&config_path)?;
-
-    if requires_sup(&config)? {
-        let mut path = dirs::config_dir().expect("no config directory in this platform");
-        path.push("sup");
-        path.push("sup.cfg");
-        logger.log(Info, &format!("sup config: {}", path.to_string_lossy()));
-        config.section_mut(section::SUP).load_from_path(&path)?;
-        main_impl(config, logger).await
-    } else {
-        main_impl(config, logger).await
-    }
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    Supervisor::from_args(std::env::args().skip(1))?.run().await
 }
@@ -0,0 +1,672 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::keyword::Keyword;
+use crate::logger::{message, LogLevel, Logger};
+#[cfg(feature = "http")]
+use crate::naming::http;
+use crate::restart::{RestartReason, Tally};
+use crate::result::Result;
+use crate::state::TargetState;
+use crate::usage::Usage;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+#[cfg(feature = "http")]
+use tokio::time::sleep;
+
+/// The default time in seconds between the pushes of the metrics.
+#[cfg(feature = "http")]
+static DEFAULT_PUSH_INTERVAL: i64 = 15;
+
+/// The default time in seconds a push of the metrics may take.
+#[cfg(feature = "http")]
+static DEFAULT_PUSH_TIMEOUT: i64 = 5;
+
+/// The content type of the Prometheus text exposition format.
+#[cfg(feature = "http")]
+static EXPOSITION_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// The name of the counter for the outcomes of the managed process.
+static PROCESS_OUTCOMES_TOTAL: &str = "heartbeat2_process_outcomes_total";
+
+/// The name of the counter for the restart decisions.
+static RESTART_DECISIONS_TOTAL: &str = "heartbeat2_restart_decisions_total";
+
+/// The name of the counter for the restarts by their reasons.
+static RESTARTS_TOTAL: &str = "heartbeat2_restarts_total";
+
+/// The name of the counter for the signals dropped instead of
+/// relayed.
+static SIGNALS_DROPPED_TOTAL: &str = "heartbeat2_signals_dropped_total";
+
+/// The name of the counter for the transitions of the target state.
+static STATE_TRANSITIONS_TOTAL: &str = "heartbeat2_state_transitions_total";
+
+/// The name of the counter for the CPU time of `Heartbeat2` itself.
+static CPU_SECONDS_TOTAL: &str = "heartbeat2_cpu_seconds_total";
+
+/// The name of the gauge for the open file descriptors of
+/// `Heartbeat2` itself.
+static OPEN_FDS: &str = "heartbeat2_open_fds";
+
+/// The name of the gauge for the resident memory of `Heartbeat2`
+/// itself.
+static RESIDENT_MEMORY_BYTES: &str = "heartbeat2_resident_memory_bytes";
+
+/// The name of the gauge for how late the latest heartbeat went out
+/// against its deadline.
+static HEARTBEAT_DRIFT_SECONDS: &str = "heartbeat2_heartbeat_drift_seconds";
+
+/// The name of the gauge for the aborts the restart policy counted
+/// at its latest decision.
+static RESTART_WINDOW_ABORTS: &str = "heartbeat2_restart_window_aborts";
+
+/// The name of the gauge for the window of time the restart policy
+/// counted the aborts over at its latest decision.
+static RESTART_WINDOW_SECONDS: &str = "heartbeat2_restart_window_seconds";
+
+/// The name of the gauge for the number of aborts at which the
+/// restart policy gives up.
+static RESTART_LIMIT: &str = "heartbeat2_restart_limit";
+
+/// The name of the gauge for the latest decision of the restart
+/// policy, 1 to restart and 0 to give up.
+static RESTART_ALLOWED: &str = "heartbeat2_restart_allowed";
+
+/// The name of the counter for the heartbeats skipped as the ones
+/// before them overran the interval.
+static HEARTBEATS_SKIPPED_TOTAL: &str = "heartbeat2_heartbeats_skipped_total";
+
+/// The prefix of the gauges for the numeric telemetry of the target.
+static TARGET_PREFIX: &str = "heartbeat2_target_";
+
+/// The name of the gauge whose labels carry the textual telemetry of
+/// the target, such as its version.
+static TARGET_INFO: &str = "heartbeat2_target_info";
+
+/// The labels that identify the supervision, which an item of the
+/// telemetry can't take.
+static IDENTITY_LABELS: [&str; 2] = ["target", "instance"];
+
+/// The prefix of the label of an item of the telemetry named as one
+/// of [`IDENTITY_LABELS`].
+static TELEMETRY_LABEL_PREFIX: &str = "telemetry_";
+
+/// Enumerates the ways a run of the managed process can end.
+///
+/// `Outcome` is finer grained than
+/// [`RunProcess`](crate::process::RunProcess).  `RunProcess` only
+/// tells whether the process should be restarted, whereas `Outcome`
+/// also tells why.  Both a heartbeat timeout and a crash lead to
+/// [`RunProcess::Abort`](crate::process::RunProcess::Abort), for
+/// example, though with different [`RestartReason`]s.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Outcome {
+    /// The process exited by itself with a success status.
+    Complete,
+    /// The process exited by itself with a failure status.
+    Abort,
+    /// `Heartbeat2` killed the process after a heartbeat timeout.
+    TimeoutKill,
+    /// `Heartbeat2` killed the process to restart it at the request
+    /// of an operator.
+    RestartKill,
+    /// `Heartbeat2` stopped the process to restart it at the request
+    /// of the target.
+    RestartRequest,
+    /// `Heartbeat2` relayed a signal it received to the process.
+    SignalRelay,
+    /// The process didn't exit after `Heartbeat2` killed it.
+    Stuck,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Outcome::*;
+        match self {
+            Complete => write!(f, "complete"),
+            Abort => write!(f, "abort"),
+            TimeoutKill => write!(f, "timeout-kill"),
+            RestartKill => write!(f, "restart-kill"),
+            RestartRequest => write!(f, "restart-request"),
+            SignalRelay => write!(f, "signal-relay"),
+            Stuck => write!(f, "stuck"),
+        }
+    }
+}
+
+/// Enumerates the decisions the supervisor makes after the managed
+/// process aborts.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Decision {
+    /// The process is restarted straight away.
+    Restarted,
+    /// `Heartbeat2` gave up on the process due to too many retries.
+    GaveUp,
+    /// The restart is pending until RESTART-HOURS begin.
+    Pending,
+    /// The restart is deferred until a delay has elapsed.
+    #[allow(dead_code)]
+    BackoffWaiting,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Decision::*;
+        match self {
+            Restarted => write!(f, "restarted"),
+            GaveUp => write!(f, "gave-up"),
+            Pending => write!(f, "pending"),
+            BackoffWaiting => write!(f, "backoff-waiting"),
+        }
+    }
+}
+
+/// Counts what the supervisor does to the managed process.
+///
+/// `Metrics` keeps a set of monotonic counters, each identified by a
+/// name and a set of labels.  It also publishes the latest [`Usage`]
+/// of `Heartbeat2` itself, how late the latest heartbeat went out,
+/// and the latest telemetry the target attached to its answers to the
+/// heartbeats.  A numeric item of the
+/// telemetry, such as `QUEUE-DEPTH`, becomes a gauge such as
+/// `heartbeat2_target_queue_depth`.  The others, such as `VERSION`,
+/// become the labels of `heartbeat2_target_info`.  Every counter
+/// carries the `target` and the `instance` labels so that the
+/// counters of several supervisors can be told apart on a dashboard.
+/// A label of the telemetry named `TARGET` or `INSTANCE` becomes
+/// `telemetry_target` or `telemetry_instance` instead.
+/// `Metrics` renders the counters in the Prometheus text exposition
+/// format.
+///
+/// # Configuration
+///
+/// * METRICS-PATH: Optional.  When present, `Metrics` rewrites the
+///   file at this path with the rendered counters after every change.
+///   The file suits the textfile collector of the Prometheus node
+///   exporter.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use crate::metrics::{Metrics, Outcome};
+///
+/// let metrics = Metrics::new(config, logger)?;
+/// metrics.add_outcome(Outcome::Complete);
+/// println!("{}", metrics.render());
+/// ```
+pub(crate) struct Metrics {
+    target: String,
+    instance: String,
+    path: Option<PathBuf>,
+    counters: RefCell<BTreeMap<(&'static str, String), u64>>,
+    usage: Cell<Option<Usage>>,
+    drift: Cell<Option<Duration>>,
+    restart_tally: Cell<Option<(Tally, bool)>>,
+    telemetry: RefCell<Vec<(Keyword, String)>>,
+    logger: Rc<dyn Logger>,
+}
+
+impl Metrics {
+    /// Creates a new `Metrics` instance with all counters at zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HEARTBEAT section or its TARGET-ID key
+    /// is missing in the configuration.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Result<Self> {
+        let section = config.section(section::HEARTBEAT)?;
+        let path = if section.has_key(key::METRICS_PATH) {
+            Some(PathBuf::from(section.string(key::METRICS_PATH)?))
+        } else {
+            None
+        };
+        Ok(Metrics {
+            target: section.target_id()?.name().to_owned(),
+            instance: section.instance_id()?,
+            path,
+            counters: Default::default(),
+            usage: Cell::new(None),
+            drift: Cell::new(None),
+            restart_tally: Cell::new(None),
+            telemetry: Default::default(),
+            logger,
+        })
+    }
+
+    /// Counts an outcome of the managed process.
+    pub(crate) fn add_outcome(&self, outcome: Outcome) {
+        self.increment(PROCESS_OUTCOMES_TOTAL, &[("outcome", &outcome.to_string())]);
+    }
+
+    /// Counts a restart decision.
+    pub(crate) fn add_decision(&self, decision: Decision) {
+        self.increment(
+            RESTART_DECISIONS_TOTAL,
+            &[("decision", &decision.to_string())],
+        );
+    }
+
+    /// Counts a restart of the managed process for the `reason`.
+    pub(crate) fn add_restart(&self, reason: RestartReason) {
+        self.increment(RESTARTS_TOTAL, &[("reason", &reason.to_string())]);
+    }
+
+    /// Counts a signal `Heartbeat2` dropped instead of relaying it to
+    /// the managed process.
+    pub(crate) fn add_dropped_signal(&self, signal: nix::sys::signal::Signal) {
+        self.increment(SIGNALS_DROPPED_TOTAL, &[("signal", signal.as_str())]);
+    }
+
+    /// Counts a transition of the target state.
+    pub(crate) fn add_transition(&self, from: TargetState, to: TargetState) {
+        self.increment(
+            STATE_TRANSITIONS_TOTAL,
+            &[("from", &from.to_string()), ("to", &to.to_string())],
+        );
+    }
+
+    /// Records the latest resource usage of `Heartbeat2` itself.
+    pub(crate) fn set_usage(&self, usage: &Usage) {
+        self.usage.set(Some(*usage));
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::MT001,
+                &format!("unable to write metrics: {}", err),
+            );
+        }
+    }
+
+    /// Records how late the latest heartbeat went out against its
+    /// deadline, to the millisecond.
+    pub(crate) fn set_drift(&self, drift: Duration) {
+        let drift = Duration::from_millis(drift.as_millis().try_into().unwrap_or(u64::MAX));
+        if self.drift.replace(Some(drift)) == Some(drift) {
+            return;
+        }
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::MT001,
+                &format!("unable to write metrics: {}", err),
+            );
+        }
+    }
+
+    /// Records the `tally` of the restart policy at its latest
+    /// decision, and whether it decided to `restart`.
+    pub(crate) fn set_restart_tally(&self, tally: &Tally, restart: bool) {
+        self.restart_tally.set(Some((*tally, restart)));
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::MT001,
+                &format!("unable to write metrics: {}", err),
+            );
+        }
+    }
+
+    /// Counts the `skipped` heartbeats, as the ones before them
+    /// overran the interval.
+    pub(crate) fn add_skipped_heartbeats(&self, skipped: u64) {
+        self.add(HEARTBEATS_SKIPPED_TOTAL, &[], skipped);
+    }
+
+    /// Records the latest `telemetry` of the target, such as the
+    /// depth of its queue.
+    pub(crate) fn set_telemetry(&self, telemetry: Vec<(Keyword, String)>) {
+        if *self.telemetry.borrow() == telemetry {
+            return;
+        }
+        self.telemetry.replace(telemetry);
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::MT001,
+                &format!("unable to write metrics: {}", err),
+            );
+        }
+    }
+
+    /// Returns the latest telemetry of the target.
+    pub(crate) fn telemetry(&self) -> Vec<(Keyword, String)> {
+        self.telemetry.borrow().clone()
+    }
+
+    /// Returns the number of times the managed process has been
+    /// restarted.
+    pub(crate) fn restarts(&self) -> u64 {
+        let label = format!("decision=\"{}\"", Decision::Restarted);
+        self.counters
+            .borrow()
+            .iter()
+            .filter(|((name, labels), _)| {
+                *name == RESTART_DECISIONS_TOTAL && labels.split(',').any(|l| l == label)
+            })
+            .map(|(_, value)| value)
+            .sum()
+    }
+
+    /// Returns the number of times the managed process ended with
+    /// each outcome, such as `("abort", 2)`.
+    pub(crate) fn outcomes(&self) -> Vec<(String, u64)> {
+        self.counters
+            .borrow()
+            .iter()
+            .filter(|((name, _), _)| *name == PROCESS_OUTCOMES_TOTAL)
+            .filter_map(|((_, labels), value)| {
+                let outcome = labels.split(',').find_map(|l| l.strip_prefix("outcome="))?;
+                Some((outcome.trim_matches('"').to_owned(), *value))
+            })
+            .collect()
+    }
+
+    /// Renders all counters in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let mut text = String::new();
+        let mut last_name = "";
+        for ((name, labels), value) in self.counters.borrow().iter() {
+            if *name != last_name {
+                let _ = writeln!(text, "# TYPE {} counter", name);
+                last_name = name;
+            }
+            let _ = writeln!(text, "{}{{{}}} {}", name, labels, value);
+        }
+        if let Some(usage) = self.usage.get() {
+            let target = self.identity();
+            for (name, kind, value) in [
+                (
+                    CPU_SECONDS_TOTAL,
+                    "counter",
+                    format!("{:.3}", usage.cpu_millis as f64 / 1000.0),
+                ),
+                (OPEN_FDS, "gauge", usage.open_fds.to_string()),
+                (RESIDENT_MEMORY_BYTES, "gauge", usage.rss_bytes.to_string()),
+            ] {
+                let _ = writeln!(text, "# TYPE {} {}", name, kind);
+                let _ = writeln!(text, "{}{{{}}} {}", name, target, value);
+            }
+        }
+        if let Some(drift) = self.drift.get() {
+            let _ = writeln!(text, "# TYPE {} gauge", HEARTBEAT_DRIFT_SECONDS);
+            let _ = writeln!(
+                text,
+                "{}{{{}}} {:.3}",
+                HEARTBEAT_DRIFT_SECONDS,
+                self.identity(),
+                drift.as_secs_f64()
+            );
+        }
+        if let Some((tally, restart)) = self.restart_tally.get() {
+            let target = format!(
+                "{},policy=\"{}\"",
+                self.identity(),
+                tally.policy.to_lowercase()
+            );
+            for (name, value) in [
+                (RESTART_WINDOW_ABORTS, tally.aborts.to_string()),
+                (RESTART_WINDOW_SECONDS, tally.window.as_secs().to_string()),
+                (RESTART_LIMIT, tally.limit.to_string()),
+                (RESTART_ALLOWED, u8::from(restart).to_string()),
+            ] {
+                let _ = writeln!(text, "# TYPE {} gauge", name);
+                let _ = writeln!(text, "{}{{{}}} {}", name, target, value);
+            }
+        }
+        let mut info = String::new();
+        for (name, value) in self.telemetry.borrow().iter() {
+            let name = name
+                .name()
+                .to_lowercase()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+            if value.parse::<f64>().is_ok() {
+                let _ = writeln!(text, "# TYPE {}{} gauge", TARGET_PREFIX, name);
+                let _ = writeln!(
+                    text,
+                    "{}{}{{{}}} {}",
+                    TARGET_PREFIX,
+                    name,
+                    self.identity(),
+                    value
+                );
+            } else if IDENTITY_LABELS.contains(&name.as_str()) {
+                let _ = write!(
+                    info,
+                    ",{}{}=\"{}\"",
+                    TELEMETRY_LABEL_PREFIX,
+                    name,
+                    escape(value)
+                );
+            } else {
+                let _ = write!(info, ",{}=\"{}\"", name, escape(value));
+            }
+        }
+        if !info.is_empty() {
+            let _ = writeln!(text, "# TYPE {} gauge", TARGET_INFO);
+            let _ = writeln!(text, "{}{{{}{}}} 1", TARGET_INFO, self.identity(), info);
+        }
+        text
+    }
+
+    fn increment(&self, name: &'static str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    fn add(&self, name: &'static str, labels: &[(&str, &str)], amount: u64) {
+        let labels = labels.iter().fold(self.identity(), |acc, (k, v)| {
+            format!("{},{}=\"{}\"", acc, k, v)
+        });
+        self.logger.log(
+            LogLevel::Debug,
+            &format!("metrics: {}{{{}}} += {}", name, labels, amount),
+        );
+        *self
+            .counters
+            .borrow_mut()
+            .entry((name, labels))
+            .or_default() += amount;
+        if let Err(err) = self.write() {
+            self.logger.log_coded(
+                &message::MT001,
+                &format!("unable to write metrics: {}", err),
+            );
+        }
+    }
+
+    /// Returns the labels that identify the supervision, which every
+    /// counter carries.
+    fn identity(&self) -> String {
+        format!(
+            "target=\"{}\",instance=\"{}\"",
+            escape(&self.target),
+            escape(&self.instance)
+        )
+    }
+
+    /// Writes the rendered counters to METRICS-PATH, if configured.
+    /// Writes to a temporary file first and renames it, so that
+    /// readers never see a partial file.
+    fn write(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, self.render())?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the label `value` for the Prometheus text exposition
+/// format, in which a backslash, a double quote and a newline have
+/// to be escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Pushes the [`Metrics`] to a Prometheus pushgateway or any other
+/// HTTP sink.
+///
+/// Where Prometheus can't scrape the host, such as behind a NAT or
+/// for a short-lived batch job, `MetricsPusher` posts what
+/// [`Metrics::render`] renders to PUSH-URL every PUSH-INTERVAL
+/// seconds, and once more when `Heartbeat2` exits, so that the last
+/// counts aren't lost.  The pushgateway groups the metrics by the
+/// path of the URL, such as `/metrics/job/heartbeat2`.  A push that
+/// fails or times out gets logged, but doesn't affect the
+/// supervision.
+///
+/// # Configuration
+///
+/// In the METRICS section, such as `:metrics (:push-url
+/// "http://pushgateway:9091/metrics/job/heartbeat2")`:
+///
+/// * PUSH-URL: Optional.  The URL to post the metrics to.  Only plain
+///   HTTP is supported.  `MetricsPusher` is disabled without it.
+///   Needs the `http` feature.
+/// * PUSH-INTERVAL: Optional.  The time in seconds between the
+///   pushes.  The default is 15.
+/// * PUSH-TIMEOUT: Optional.  The time in seconds a push may take.
+///   The default is 5.
+pub(crate) struct MetricsPusher {
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    metrics: Rc<Metrics>,
+    config: Rc<Config>,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    logger: Rc<dyn Logger>,
+}
+
+impl MetricsPusher {
+    /// Creates a new `MetricsPusher`.
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - A shared reference to the metrics.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(metrics: Rc<Metrics>, config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        MetricsPusher {
+            metrics,
+            config,
+            logger,
+        }
+    }
+
+    /// Returns whether the `MetricsPusher` should run.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config
+            .section(section::METRICS)
+            .is_ok_and(|section| section.has_key(key::PUSH_URL))
+    }
+
+    /// Pushes the metrics every PUSH-INTERVAL seconds until
+    /// `Heartbeat2` exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if PUSH-URL isn't an `http://`
+    /// URL, or if PUSH-INTERVAL or PUSH-TIMEOUT is malformed.
+    #[cfg(feature = "http")]
+    pub(crate) async fn run(&self) -> Result<()> {
+        let section = self.config.section(section::METRICS)?;
+        let url = section.string(key::PUSH_URL)?;
+        if http::split_url(url).is_none() {
+            return Err(config_format_error(&format!(
+                "{} [{}] isn't an http:// URL",
+                key::PUSH_URL,
+                url
+            )));
+        }
+        let interval = Duration::from_secs(
+            section
+                .integer_or(key::PUSH_INTERVAL, DEFAULT_PUSH_INTERVAL)?
+                .try_into()?,
+        );
+        self.logger.log_coded(
+            &message::MT002,
+            &format!("push metrics to {} every {}s", url, interval.as_secs()),
+        );
+        loop {
+            sleep(interval).await;
+            self.push().await;
+        }
+    }
+
+    /// Stands in for the `MetricsPusher` without the `http` feature.
+    #[cfg(not(feature = "http"))]
+    pub(crate) async fn run(&self) -> Result<()> {
+        Err(config_format_error(&format!(
+            "{} needs the http feature",
+            key::PUSH_URL
+        )))
+    }
+
+    /// Pushes the metrics a last time, if enabled.
+    pub(crate) async fn flush(&self) {
+        #[cfg(feature = "http")]
+        if self.is_enabled() {
+            self.push().await;
+        }
+    }
+
+    /// Posts the rendered metrics to PUSH-URL, and logs a failure.
+    #[cfg(feature = "http")]
+    async fn push(&self) {
+        if let Err(err) = self.try_push().await {
+            self.logger
+                .log_coded(&message::MT003, &format!("unable to push metrics: {}", err));
+        }
+    }
+
+    #[cfg(feature = "http")]
+    async fn try_push(&self) -> Result<()> {
+        let section = self.config.section(section::METRICS)?;
+        let url = section.string(key::PUSH_URL)?;
+        let timeout = Duration::from_secs(
+            section
+                .integer_or(key::PUSH_TIMEOUT, DEFAULT_PUSH_TIMEOUT)?
+                .try_into()?,
+        );
+        let (address, path) = http::split_url(url)
+            .ok_or_else(|| config_format_error(&format!("{} [{}]", key::PUSH_URL, url)))?;
+        let body = self.metrics.render();
+        self.logger.log(
+            LogLevel::Debug,
+            &format!("push {} bytes of metrics to {}", body.len(), url),
+        );
+        http::send(
+            &address,
+            "POST",
+            path,
+            Some((EXPOSITION_FORMAT, &body)),
+            timeout,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,414 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::context::Context;
+use crate::error::config_format_error;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, LogLevel, Logger};
+use crate::result::Result;
+#[cfg(feature = "sup")]
+use crate::sup::Sup;
+use futures::future::LocalBoxFuture;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+#[cfg(feature = "http")]
+mod consul;
+#[cfg(feature = "http")]
+mod etcd;
+#[cfg(feature = "http")]
+pub(crate) mod http;
+#[cfg(feature = "http")]
+mod json;
+
+#[cfg(feature = "http")]
+use consul::Consul;
+#[cfg(feature = "http")]
+use etcd::Etcd;
+
+/// The default timeout in milliseconds for the requests to an HTTP
+/// naming service.
+#[cfg(feature = "http")]
+static DEFAULT_COMMS_TIMEOUT: i64 = 1000;
+
+/// Resolves the name of a service to its endpoint.
+///
+/// `Heartbeat2` finds the endpoint of the target through a naming
+/// service unless TARGET-ENDPOINT gives it.  The implementations are
+/// [`Sup`], Consul and etcd.  Some of them can also register the
+/// endpoint of the target, so that its clients can find it.
+///
+/// # Configuration
+///
+/// * NAMING-SERVICE: Optional.  `:sup`, `:consul` or `:etcd`.  The
+///   default is `:sup`.
+/// * NAMING-ADDRESS: Optional.  The `host:port` of the HTTP API of
+///   Consul or etcd.  The default is the local agent on the standard
+///   port.
+/// * COMMS-TIMEOUT: Optional.  The timeout in milliseconds of the
+///   requests to Consul or etcd.  The default is 1000.
+/// * ADVERTISE-ENDPOINT: Optional.  The endpoint to register under
+///   TARGET-ID when `Heartbeat2` starts.
+/// * ADVERTISE-WHEN-HEALTHY: Optional.  If true, registers
+///   ADVERTISE-ENDPOINT once the target first answers a heartbeat
+///   rather than when `Heartbeat2` starts, and removes the
+///   registration when `Heartbeat2` exits.  This registers a target
+///   that can't register itself only once it can serve.  The default
+///   is false.
+pub(crate) trait Naming {
+    /// Resolves the service `id` to its endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns a mapping missing error if the naming service doesn't
+    /// know the service, or an error if the naming service fails to
+    /// answer.
+    fn resolve<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<String>>;
+
+    /// Registers the `endpoint` of the service `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the naming service rejects the
+    /// registration or fails to answer.
+    fn register<'a>(&'a self, id: &'a Keyword, endpoint: &'a str)
+        -> LocalBoxFuture<'a, Result<()>>;
+
+    /// Removes the registration of the service `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the naming service rejects the removal or
+    /// fails to answer.
+    fn deregister<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<()>>;
+
+    /// Takes the reloaded `config` for the later requests.  A naming
+    /// service that keeps no configuration of its own ignores it.
+    fn reload(&self, _config: &Rc<Config>) {}
+}
+
+/// Registers the endpoint of the target once it is healthy, and
+/// removes the registration when `Heartbeat2` exits.
+///
+/// A target that can't register itself with the naming service, such
+/// as a third-party program, relies on `Heartbeat2` to do it.
+/// Registering ADVERTISE-ENDPOINT when `Heartbeat2` starts would send
+/// the clients to a target that doesn't serve yet.  With
+/// ADVERTISE-WHEN-HEALTHY, `Advertiser` registers it once the target
+/// first answers a heartbeat instead, and keeps the registration
+/// across the restarts of the target.  A failed registration gets
+/// logged, and is tried again at the next answered heartbeat.
+pub(crate) struct Advertiser {
+    naming: Rc<dyn Naming>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+    registered: Cell<bool>,
+}
+
+impl Advertiser {
+    /// Creates a new `Advertiser`.
+    ///
+    /// # Arguments
+    ///
+    /// * `naming` - The naming service to register the endpoint with.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(naming: Rc<dyn Naming>, config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        Advertiser {
+            naming,
+            config,
+            logger,
+            registered: Cell::new(false),
+        }
+    }
+
+    /// Returns whether the endpoint waits for the target to be
+    /// healthy before it is registered.
+    pub(crate) fn is_enabled(&self) -> Result<bool> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        Ok(section.has_key(key::ADVERTISE_ENDPOINT)
+            && section.boolean_or(key::ADVERTISE_WHEN_HEALTHY, false)?)
+    }
+
+    /// Registers ADVERTISE-ENDPOINT under TARGET-ID, unless it is
+    /// registered already.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed.  A failure
+    /// of the naming service only gets logged.
+    pub(crate) async fn register(&self) -> Result<()> {
+        if self.registered.get() || !self.is_enabled()? {
+            return Ok(());
+        }
+        let section = self.config.section(section::HEARTBEAT)?;
+        let endpoint = section.string(key::ADVERTISE_ENDPOINT)?;
+        match self.naming.register(section.target_id()?, endpoint).await {
+            Ok(()) => {
+                self.logger.log_coded(
+                    &message::NM001,
+                    &format!("advertise endpoint of healthy target: {}", endpoint),
+                );
+                self.registered.set(true);
+            }
+            Err(err) => self.logger.log_coded(
+                &message::NM002,
+                &format!("unable to advertise endpoint {}: {}", endpoint, err),
+            ),
+        }
+        Ok(())
+    }
+
+    /// Removes the registration, if any.  Logs a failure rather than
+    /// failing, as `Heartbeat2` is on its way out.
+    pub(crate) async fn deregister(&self) {
+        if !self.registered.replace(false) {
+            return;
+        }
+        match self.withdraw().await {
+            Ok(()) => self
+                .logger
+                .log_coded(&message::NM003, "withdraw advertised endpoint"),
+            Err(err) => self.logger.log_coded(
+                &message::NM004,
+                &format!("unable to withdraw advertised endpoint: {}", err),
+            ),
+        }
+    }
+
+    async fn withdraw(&self) -> Result<()> {
+        let id = self.config.section(section::HEARTBEAT)?.target_id()?;
+        self.naming.deregister(id).await
+    }
+}
+
+/// Loads the configuration again from the file it came from.
+pub(crate) type LoadConfig = Box<dyn Fn() -> Result<Config>>;
+
+/// Resolves the endpoints of the target from TARGET-ENDPOINT, or with
+/// the naming service without it.
+///
+/// `Resolver` decides between the two on every heartbeat rather than
+/// once at startup, so that a [`reload`](Self::reload) that removes
+/// TARGET-ENDPOINT from the configuration switches the supervision
+/// to the naming service, and one that adds it switches back.  The
+/// naming service takes the reloaded configuration as well, so that
+/// Sup resolves and validates its SUP section again when it is next
+/// asked.  Only TARGET-ID, TARGET-ENDPOINT and the SUP section take
+/// effect on a reload; the other options take a restart of
+/// `Heartbeat2`.
+pub(crate) struct Resolver {
+    naming: Rc<dyn Naming>,
+    config: RefCell<Rc<Config>>,
+    load: Option<LoadConfig>,
+    logger: Rc<dyn Logger>,
+}
+
+impl Resolver {
+    /// Creates a new `Resolver`.
+    ///
+    /// # Arguments
+    ///
+    /// * `naming` - The naming service to resolve TARGET-ID with.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(naming: Rc<dyn Naming>, config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        Resolver {
+            naming,
+            config: RefCell::new(config),
+            load: None,
+            logger,
+        }
+    }
+
+    /// Lets [`reload`](Self::reload) load the configuration with
+    /// `load`.  Without it, as for an ad hoc supervision, there is
+    /// nothing to reload.
+    pub(crate) fn config_loader(mut self, load: LoadConfig) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    /// Returns the endpoints of the target in the order of
+    /// preference.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, or if the
+    /// naming service fails to resolve TARGET-ID.
+    pub(crate) async fn endpoints(&self) -> Result<Vec<String>> {
+        let config = Rc::clone(&self.config.borrow());
+        let section = config.section(section::HEARTBEAT)?;
+        if section.has_key(key::TARGET_ENDPOINT) {
+            let endpoints = section.target_endpoints()?;
+            self.logger
+                .log(LogLevel::Debug, &format!("endpoints: {:?}", endpoints));
+            Ok(endpoints)
+        } else {
+            let id = section.target_id()?;
+            let endpoint = self.naming.resolve(id).await?;
+            self.logger.log(
+                LogLevel::Debug,
+                &format!("endpoint of app {}: {}", id, endpoint),
+            );
+            Ok(vec![endpoint])
+        }
+    }
+
+    /// Loads the configuration again, and resolves the endpoints of
+    /// the target by it from the next heartbeat on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no configuration file to reload,
+    /// if it can't be loaded, or if its TARGET-ID or TARGET-ENDPOINT
+    /// is malformed.  The previous configuration stays in effect
+    /// then.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let load = self
+            .load
+            .as_ref()
+            .ok_or_else(|| config_format_error("no configuration file to reload"))?;
+        let config = Rc::new(load()?);
+        let section = config.section(section::HEARTBEAT)?;
+        let id = section.target_id()?;
+        let resolution = if section.has_key(key::TARGET_ENDPOINT) {
+            format!("at {}", section.target_endpoints()?.join(" "))
+        } else {
+            "from naming service".to_owned()
+        };
+        self.logger.log_coded(
+            &message::NM005,
+            &format!("reload config; endpoints of target [{}] {}", id, resolution),
+        );
+        self.naming.reload(&config);
+        *self.config.borrow_mut() = config;
+        Ok(())
+    }
+}
+
+/// Tells whether NAMING-SERVICE selects Sup.
+pub(crate) fn uses_sup(config: &Config) -> Result<bool> {
+    let section = config.section(section::HEARTBEAT)?;
+    Ok(!section.has_key(key::NAMING_SERVICE) || *section.keyword(key::NAMING_SERVICE)? == kw![sup])
+}
+
+/// Creates the naming service NAMING-SERVICE selects.  Sup loads its
+/// own configuration when first asked, and logs it to the `logger`.
+///
+/// # Errors
+///
+/// Returns a configuration error if NAMING-SERVICE names an unknown
+/// naming service, or one left out of the build.
+#[cfg_attr(not(feature = "sup"), allow(unused_variables))]
+pub(crate) fn naming_service(
+    context: Context,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+) -> Result<Rc<dyn Naming>> {
+    if uses_sup(&config)? {
+        #[cfg(feature = "sup")]
+        return Ok(Rc::new(Sup::with_context(context, config).logger(logger)));
+        #[cfg(not(feature = "sup"))]
+        return Ok(Rc::new(NoSup));
+    }
+    let service = config
+        .section(section::HEARTBEAT)?
+        .keyword(key::NAMING_SERVICE)?;
+    if *service == kw![consul] || *service == kw![etcd] {
+        http_naming_service(service, &config)
+    } else {
+        Err(config_format_error(&format!(
+            "unknown {} [{}]",
+            key::NAMING_SERVICE,
+            service
+        )))
+    }
+}
+
+/// Creates the naming service over HTTP, Consul or etcd, by the name
+/// of the `service`.
+#[cfg(feature = "http")]
+fn http_naming_service(service: &Keyword, config: &Config) -> Result<Rc<dyn Naming>> {
+    let section = config.section(section::HEARTBEAT)?;
+    let address = if section.has_key(key::NAMING_ADDRESS) {
+        Some(section.string(key::NAMING_ADDRESS)?.to_owned())
+    } else {
+        None
+    };
+    let timeout = section
+        .integer_or(key::COMMS_TIMEOUT, DEFAULT_COMMS_TIMEOUT)?
+        .try_into()?;
+    if *service == kw![consul] {
+        Ok(Rc::new(Consul::new(
+            address,
+            timeout,
+            section.instance_id()?,
+        )))
+    } else {
+        Ok(Rc::new(Etcd::new(address, timeout)))
+    }
+}
+
+/// Stands in for the naming services over HTTP without the `http`
+/// feature.
+#[cfg(not(feature = "http"))]
+fn http_naming_service(service: &Keyword, _config: &Config) -> Result<Rc<dyn Naming>> {
+    Err(config_format_error(&format!(
+        "{} [{}] needs the http feature",
+        key::NAMING_SERVICE,
+        service
+    )))
+}
+
+/// Stands in for Sup without the `sup` feature.  Only a target with
+/// TARGET-ENDPOINT or PROBE-COMMAND does without a naming service.
+#[cfg(not(feature = "sup"))]
+struct NoSup;
+
+#[cfg(not(feature = "sup"))]
+impl Naming for NoSup {
+    fn resolve<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            Err(config_format_error(&format!(
+                "resolving [{}] with Sup needs the sup feature",
+                id
+            )))
+        })
+    }
+
+    fn register<'a>(&'a self, id: &'a Keyword, _: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            Err(config_format_error(&format!(
+                "registering [{}] with Sup needs the sup feature",
+                id
+            )))
+        })
+    }
+
+    fn deregister<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            Err(config_format_error(&format!(
+                "deregistering [{}] with Sup needs the sup feature",
+                id
+            )))
+        })
+    }
+}
@@ -0,0 +1,121 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::http::request;
+use super::json::Json;
+use super::Naming;
+use crate::error::{config_format_error, mapping_missing_error, type_error};
+use crate::expression::json_string;
+use crate::keyword::Keyword;
+use crate::result::Result;
+use futures::future::LocalBoxFuture;
+use tokio::time::Duration;
+
+/// The address of the local Consul agent.
+static DEFAULT_ADDRESS: &str = "127.0.0.1:8500";
+
+/// Resolves services with the catalog of Consul.
+///
+/// The service by the name of the lower-case ID resolves to
+/// `tcp://ADDRESS:PORT` of its first instance.  Registering a service
+/// registers it with the local agent, and so only takes a TCP
+/// endpoint.  The registration carries the instance ID as the ID of
+/// the service instance, so that the instances on many hosts don't
+/// replace one another, and deregistering removes only the instance
+/// of this supervision.
+pub(crate) struct Consul {
+    address: String,
+    timeout: Duration,
+    instance_id: String,
+}
+
+impl Consul {
+    /// Creates a new `Consul` proxy.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The `host:port` of the agent, or `None` for the
+    ///   local agent.
+    /// * `timeout` - The timeout of the requests in milliseconds.
+    /// * `instance_id` - The instance ID of the supervision.
+    pub(crate) fn new(address: Option<String>, timeout: u64, instance_id: String) -> Self {
+        Consul {
+            address: address.unwrap_or_else(|| DEFAULT_ADDRESS.to_owned()),
+            timeout: Duration::from_millis(timeout),
+            instance_id,
+        }
+    }
+}
+
+impl Naming for Consul {
+    fn resolve<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let path = format!("/v1/catalog/service/{}", id.name().to_lowercase());
+            let body = request(&self.address, "GET", &path, None, self.timeout).await?;
+            let services = Json::parse(&body)?;
+            let service = services
+                .elements()
+                .first()
+                .ok_or_else(|| mapping_missing_error(id.name()))?;
+            let address = match service.get("ServiceAddress") {
+                Some(address) if !address.as_str()?.is_empty() => address,
+                _ => service
+                    .get("Address")
+                    .ok_or_else(|| type_error("Address"))?,
+            };
+            let port = service
+                .get("ServicePort")
+                .ok_or_else(|| type_error("ServicePort"))?;
+            Ok(format!("tcp://{}:{}", address.as_str()?, port.as_i64()?))
+        })
+    }
+
+    fn register<'a>(
+        &'a self,
+        id: &'a Keyword,
+        endpoint: &'a str,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let (host, port) = endpoint
+                .strip_prefix("tcp://")
+                .and_then(|address| address.rsplit_once(':'))
+                .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+                .ok_or_else(|| {
+                    config_format_error(&format!("Consul needs a TCP endpoint: {}", endpoint))
+                })?;
+            let body = format!(
+                "{{\"ID\":{},\"Name\":{},\"Address\":{},\"Port\":{}}}",
+                json_string(&self.instance_id),
+                json_string(&id.name().to_lowercase()),
+                json_string(host),
+                port
+            );
+            let path = "/v1/agent/service/register";
+            request(&self.address, "PUT", path, Some(&body), self.timeout).await?;
+            Ok(())
+        })
+    }
+
+    fn deregister<'a>(&'a self, _: &'a Keyword) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = format!("/v1/agent/service/deregister/{}", self.instance_id);
+            request(&self.address, "PUT", &path, None, self.timeout).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::http::request;
+use super::json::Json;
+use super::Naming;
+use crate::error::{mapping_missing_error, type_error};
+use crate::expression::json_string;
+use crate::keyword::Keyword;
+use crate::result::Result;
+use futures::future::LocalBoxFuture;
+use tokio::time::Duration;
+
+/// The address of the local etcd server.
+static DEFAULT_ADDRESS: &str = "127.0.0.1:2379";
+
+/// The alphabet of the standard Base64 encoding.
+static BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Resolves services with the key-value store of etcd.
+///
+/// The service resolves to the value under the key of its lower-case
+/// ID, which is its endpoint.  `Etcd` uses the JSON gateway of the v3
+/// API, which takes the keys and the values in Base64.
+pub(crate) struct Etcd {
+    address: String,
+    timeout: Duration,
+}
+
+impl Etcd {
+    /// Creates a new `Etcd` proxy.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The `host:port` of the server, or `None` for the
+    ///   local server.
+    /// * `timeout` - The timeout of the requests in milliseconds.
+    pub(crate) fn new(address: Option<String>, timeout: u64) -> Self {
+        Etcd {
+            address: address.unwrap_or_else(|| DEFAULT_ADDRESS.to_owned()),
+            timeout: Duration::from_millis(timeout),
+        }
+    }
+}
+
+impl Naming for Etcd {
+    fn resolve<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let body = format!(
+                "{{\"key\":{}}}",
+                json_string(&encode(&id.name().to_lowercase()))
+            );
+            let path = "/v3/kv/range";
+            let response = request(&self.address, "POST", path, Some(&body), self.timeout).await?;
+            let response = Json::parse(&response)?;
+            let value = response
+                .get("kvs")
+                .and_then(|kvs| kvs.elements().first())
+                .ok_or_else(|| mapping_missing_error(id.name()))?
+                .get("value")
+                .ok_or_else(|| type_error("value"))?;
+            decode(value.as_str()?)
+        })
+    }
+
+    fn register<'a>(
+        &'a self,
+        id: &'a Keyword,
+        endpoint: &'a str,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let body = format!(
+                "{{\"key\":{},\"value\":{}}}",
+                json_string(&encode(&id.name().to_lowercase())),
+                json_string(&encode(endpoint))
+            );
+            let path = "/v3/kv/put";
+            request(&self.address, "POST", path, Some(&body), self.timeout).await?;
+            Ok(())
+        })
+    }
+
+    fn deregister<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let body = format!(
+                "{{\"key\":{}}}",
+                json_string(&encode(&id.name().to_lowercase()))
+            );
+            let path = "/v3/kv/deleterange";
+            request(&self.address, "POST", path, Some(&body), self.timeout).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Encodes the string `s` in Base64.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len().div_ceil(3) * 4);
+    for chunk in s.as_bytes().chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes the Base64 string `s` as a UTF-8 string.
+fn decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in s.bytes().filter(|&c| c != b'=') {
+        let index = BASE64_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| type_error("Base64"))?;
+        bits = bits << 6 | index as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
@@ -0,0 +1,112 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::error::unknown_response_error;
+use crate::result::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+/// Sends an HTTP request to `address` with a JSON `body`, if any, and
+/// returns the body of the response.
+///
+/// Speaks HTTP/1.0, so that the server closes the connection at the
+/// end of the response instead of chunking it.  This is all the
+/// naming services need.
+///
+/// # Arguments
+///
+/// * `address` - The `host:port` of the server.
+/// * `method` - The HTTP method, such as `"GET"`.
+/// * `path` - The path and the query of the request.
+/// * `body` - The JSON body of the request, if any.
+/// * `bound` - The time limit of the whole exchange.
+///
+/// # Errors
+///
+/// Returns an error if the exchange fails or times out, or an
+/// unknown response error if the status isn't a success.
+pub(crate) async fn request(
+    address: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    bound: Duration,
+) -> Result<String> {
+    let body = body.map(|body| ("application/json", body));
+    send(address, method, path, body, bound).await
+}
+
+/// Sends an HTTP request to `address` as [`request`] does, but with a
+/// `body` of any content type, given along with it, such as
+/// `("text/plain", "up 1\n")`.
+pub(crate) async fn send(
+    address: &str,
+    method: &str,
+    path: &str,
+    body: Option<(&str, &str)>,
+    bound: Duration,
+) -> Result<String> {
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, address);
+    if let Some((content_type, body)) = body {
+        request.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        ));
+    } else {
+        request.push_str("\r\n");
+    }
+    let exchange = async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = timeout(bound, exchange).await??;
+    let response = String::from_utf8(response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| unknown_response_error(&response))?;
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(body.to_owned()),
+        _ => Err(unknown_response_error(status)),
+    }
+}
+
+/// Splits the `url`, such as `"http://alerts.local/heartbeat2"`, into
+/// the `host:port` of the server and the path.  The port defaults to
+/// 80, and the path to `/`.  Returns `None` unless the `url` is a
+/// plain `http://` URL.
+pub(crate) fn split_url(url: &str) -> Option<(String, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        None
+    } else if host.contains(':') {
+        Some((host.to_owned(), path))
+    } else {
+        Some((format!("{}:80", host), path))
+    }
+}
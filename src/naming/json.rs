@@ -0,0 +1,190 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::error::type_error;
+use crate::result::Result;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value, as far as the naming services need one.
+///
+/// `Json` reads the responses of Consul and etcd.  It keeps numbers
+/// as `f64`, and the members of an object in their order.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses the JSON `text`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a type error if `text` isn't a JSON value.
+    pub(crate) fn parse(text: &str) -> Result<Json> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(_) => Err(type_error("end of JSON")),
+        }
+    }
+
+    /// Returns the member `name` of an object, if any.
+    pub(crate) fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of an array, or an empty slice otherwise.
+    pub(crate) fn elements(&self) -> &[Json] {
+        match self {
+            Json::Array(elements) => elements,
+            _ => &[],
+        }
+    }
+
+    /// Returns the string, or a type error if it isn't one.
+    pub(crate) fn as_str(&self) -> Result<&str> {
+        match self {
+            Json::String(s) => Ok(s),
+            _ => Err(type_error("JSON string")),
+        }
+    }
+
+    /// Returns the number as an integer, or a type error if it isn't
+    /// one.
+    pub(crate) fn as_i64(&self) -> Result<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+            _ => Err(type_error("JSON integer")),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, word: &str) -> Result<()> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(type_error(word));
+        }
+    }
+    Ok(())
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('n') => expect(chars, "null").map(|_| Json::Null),
+        Some('t') => expect(chars, "true").map(|_| Json::Boolean(true)),
+        Some('f') => expect(chars, "false").map(|_| Json::Boolean(false)),
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('[') => {
+            chars.next();
+            let mut elements = vec![];
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_none() {
+                loop {
+                    elements.push(parse_value(chars)?);
+                    skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => (),
+                        Some(']') => break,
+                        _ => return Err(type_error("JSON array")),
+                    }
+                }
+            }
+            Ok(Json::Array(elements))
+        }
+        Some('{') => {
+            chars.next();
+            let mut members = vec![];
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_none() {
+                loop {
+                    skip_whitespace(chars);
+                    let key = parse_string(chars)?;
+                    skip_whitespace(chars);
+                    expect(chars, ":")?;
+                    members.push((key, parse_value(chars)?));
+                    skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => (),
+                        Some('}') => break,
+                        _ => return Err(type_error("JSON object")),
+                    }
+                }
+            }
+            Ok(Json::Object(members))
+        }
+        Some(_) => {
+            let mut number = String::new();
+            while let Some(c) =
+                chars.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            {
+                number.push(c);
+            }
+            number
+                .parse()
+                .map(Json::Number)
+                .map_err(|_| type_error("JSON value"))
+        }
+        None => Err(type_error("JSON value")),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String> {
+    expect(chars, "\"")?;
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let code =
+                        u32::from_str_radix(&code, 16).map_err(|_| type_error("JSON escape"))?;
+                    s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                Some(c) => s.push(c),
+                None => return Err(type_error("JSON string")),
+            },
+            Some(c) => s.push(c),
+            None => return Err(type_error("JSON string")),
+        }
+    }
+}
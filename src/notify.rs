@@ -0,0 +1,240 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::callback::Event;
+use crate::config::{key, section, Config};
+#[cfg(not(feature = "http"))]
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, Logger};
+#[cfg(feature = "http")]
+use crate::naming::http;
+use crate::result::Result;
+use crate::signal::signal_name;
+use std::cell::Cell;
+use std::os::unix::process::ExitStatusExt;
+use std::rc::Rc;
+#[cfg(feature = "http")]
+use tokio::time::Duration;
+
+/// The default time in seconds a webhook may take to answer.
+#[cfg(feature = "http")]
+static DEFAULT_WEBHOOK_TIMEOUT: i64 = 5;
+
+/// Posts the events of the supervision to webhooks.
+///
+/// A crash in the small hours should page someone rather than wait in
+/// the log.  `Webhooks` posts a JSON object to each of WEBHOOKS when
+/// the managed process aborts, when a heartbeat times out, when the
+/// target answers that it is degraded, when `Heartbeat2` restarts the
+/// process or holds the restart for RESTART-HOURS and when it gives
+/// up, such as:
+///
+/// ```json
+/// {"event":"abort","code":"NT003","target":"app","instance":"web-3/app",
+///  "pid":1234,"exit-code":null,"signal":11,"signal-name":"SIGSEGV",
+///  "restarts":2}
+/// ```
+///
+/// `"code"` is the stable code of the event, as in the log messages.
+/// `"reason"` tells why `Heartbeat2` restarts the process, such as
+/// `"heartbeat-timeout"`, why it gave up or why the target is
+/// degraded, and `"at"` the time of the day a pending restart waits
+/// for.  A webhook that fails or times out gets logged, but doesn't
+/// affect the supervision.
+///
+/// # Configuration
+///
+/// * WEBHOOKS: Optional.  A list of the URLs to post the events to,
+///   such as `("http://alerts.local:8080/heartbeat2")`.  Only plain
+///   HTTP is supported.  Needs the `http` feature.
+/// * WEBHOOK-TIMEOUT: Optional.  The time in seconds a webhook may take
+///   to answer.  The default is 5.
+pub(crate) struct Webhooks {
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    urls: Vec<String>,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    timeout: u64,
+    target: String,
+    instance: String,
+    pid: Cell<Option<u32>>,
+    restarts: Cell<u64>,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    logger: Rc<dyn Logger>,
+}
+
+impl Webhooks {
+    /// Creates new `Webhooks` if the configuration lists any.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if WEBHOOKS or WEBHOOK-TIMEOUT is
+    /// malformed, or if WEBHOOKS is present without the `http`
+    /// feature.
+    #[cfg_attr(not(feature = "http"), allow(unused_variables))]
+    pub(crate) fn from_config(config: &Config, logger: Rc<dyn Logger>) -> Result<Option<Self>> {
+        let section = config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::WEBHOOKS) {
+            return Ok(None);
+        }
+        #[cfg(not(feature = "http"))]
+        return Err(config_format_error(&format!(
+            "{} needs the http feature",
+            key::WEBHOOKS
+        )));
+        #[cfg(feature = "http")]
+        Ok(Some(Webhooks {
+            urls: section.string_list(key::WEBHOOKS)?,
+            timeout: section
+                .integer_or(key::WEBHOOK_TIMEOUT, DEFAULT_WEBHOOK_TIMEOUT)?
+                .try_into()?,
+            target: section.target_id()?.name().to_owned(),
+            instance: section.instance_id()?,
+            pid: Cell::new(None),
+            restarts: Cell::new(0),
+            logger,
+        }))
+    }
+
+    /// Posts the `event` to each of the webhooks in turn.  Only keeps
+    /// track of the process ID on the start of the process.
+    pub(crate) async fn post(&self, event: &Event) {
+        if let Some(payload) = self.payload(event) {
+            self.send(&payload).await;
+        }
+    }
+
+    /// Returns the JSON object to post for the `event`, or `None` if
+    /// the `event` isn't worth a notification.
+    fn payload(&self, event: &Event) -> Option<String> {
+        let (name, message, mut details) = match event {
+            Event::Start(pid) => {
+                self.pid.set(Some(*pid));
+                return None;
+            }
+            Event::Abort(status) => (
+                kw![abort],
+                &message::NT003,
+                vec![
+                    (
+                        Keyword::new("EXIT-CODE"),
+                        status
+                            .and_then(|status| status.code())
+                            .map_or(Expression::from(false), |code| {
+                                Expression::from(code as i64)
+                            }),
+                    ),
+                    (
+                        kw![signal],
+                        status
+                            .and_then(|status| status.signal())
+                            .map_or(Expression::from(false), |signal| {
+                                Expression::from(signal as i64)
+                            }),
+                    ),
+                    (
+                        Keyword::new("SIGNAL-NAME"),
+                        status
+                            .and_then(|status| status.signal())
+                            .map_or(Expression::from(false), |signal| {
+                                Expression::from(signal_name(signal).as_str())
+                            }),
+                    ),
+                ],
+            ),
+            Event::Timeout => (kw![timeout], &message::NT004, vec![]),
+            Event::Restart(reason) => {
+                self.restarts.set(self.restarts.get() + 1);
+                (
+                    kw![restart],
+                    &message::NT005,
+                    vec![(kw![reason], Expression::from(reason.to_string().as_str()))],
+                )
+            }
+            Event::RestartPending(at) => (
+                Keyword::new("RESTART-PENDING"),
+                &message::NT007,
+                vec![(kw![at], Expression::from(at.as_str()))],
+            ),
+            Event::GiveUp(reason) => (
+                Keyword::new("GIVE-UP"),
+                &message::NT006,
+                vec![(kw![reason], Expression::from(reason.as_str()))],
+            ),
+            Event::Degraded(reason) => (
+                kw![degraded],
+                &message::NT008,
+                vec![(kw![reason], Expression::from(reason.as_str()))],
+            ),
+        };
+        let mut pairs = vec![
+            (kw![event], Expression::from(name)),
+            (kw![code], Expression::from(message.code())),
+            (kw![target], Expression::from(self.target.as_str())),
+            (kw![instance], Expression::from(self.instance.as_str())),
+            (
+                kw![pid],
+                self.pid
+                    .get()
+                    .map_or(Expression::from(false), |pid| Expression::from(pid as i64)),
+            ),
+        ];
+        pairs.append(&mut details);
+        pairs.push((kw![restarts], Expression::from(self.restarts.get() as i64)));
+        Some(Expression::plist(pairs).to_json())
+    }
+
+    /// Posts the `payload` to each of the webhooks.
+    #[cfg(feature = "http")]
+    async fn send(&self, payload: &str) {
+        for url in &self.urls {
+            let result = match http::split_url(url) {
+                Some((address, path)) => {
+                    http::request(
+                        &address,
+                        "POST",
+                        path,
+                        Some(payload),
+                        Duration::from_secs(self.timeout),
+                    )
+                    .await
+                }
+                None => {
+                    self.logger.log_coded(
+                        &message::NT001,
+                        &format!("webhook [{}] isn't an http:// URL", url),
+                    );
+                    continue;
+                }
+            };
+            if let Err(err) = result {
+                self.logger.log_coded(
+                    &message::NT002,
+                    &format!("webhook [{}] failed: {}", url, err),
+                );
+            }
+        }
+    }
+
+    /// Stands in for the webhooks without the `http` feature, which
+    /// [`from_config`](Webhooks::from_config) refuses to create.
+    #[cfg(not(feature = "http"))]
+    async fn send(&self, _payload: &str) {}
+}
@@ -0,0 +1,217 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::keyword::Keyword;
+use crate::logger::{message, LocalLogger, LogLevel, Logger};
+use crate::result::Result;
+use chrono::Local;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+/// The default number of the last lines of the standard error to keep
+/// for the crash report.
+static DEFAULT_CRASH_REPORT_LINES: i64 = 20;
+
+/// Captures the standard output and the standard error of the managed
+/// process.
+///
+/// Without `OutputCapture`, the managed process inherits the standard
+/// streams of `Heartbeat2`, and its last words before a crash mingle
+/// with the log of `Heartbeat2` or go nowhere.  `OutputCapture` pipes
+/// the streams, and tags each line with the target and the stream,
+/// such as `:APP stderr: segmentation fault`.  The lines go to the
+/// log of `Heartbeat2`, or to OUTPUT-LOG.
+///
+/// The lines are read on the tasks of the runtime rather than on the
+/// supervision, so that a chatty process never holds up the
+/// heartbeats.  For the same reason, they go to the [`LocalLogger`]
+/// even with LOGGER set to `:remote`.
+///
+/// # Configuration
+///
+/// * CAPTURE-OUTPUT: Optional.  `t` captures the output of the
+///   managed process.  The default is `nil`, unless OUTPUT-LOG is
+///   present.
+/// * CRASH-REPORT-LINES: Optional.  The number of the last lines of
+///   the standard error to keep for the crash report of the managed
+///   process.  The default is 20.
+/// * OUTPUT-LOG: Optional.  The file to append the captured lines to,
+///   in place of the log.  A relative path is relative to
+///   LOG-DIRECTORY, if any.
+pub(crate) struct OutputCapture {
+    target: Keyword,
+    path: Option<PathBuf>,
+    tail_lines: usize,
+    logger: LocalLogger,
+}
+
+impl OutputCapture {
+    /// Creates a new `OutputCapture` if the configuration asks for
+    /// one.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration.
+    /// * `log_directory` - The log directory of the managed process,
+    ///   if any.
+    /// * `logger` - The logger to log the lines with.
+    pub(crate) fn from_config(
+        config: &Config,
+        log_directory: Option<&Path>,
+        logger: &dyn Logger,
+    ) -> Result<Option<Self>> {
+        let section = config.section(section::HEARTBEAT)?;
+        let path = if section.has_key(key::OUTPUT_LOG) {
+            let path = Path::new(section.string(key::OUTPUT_LOG)?);
+            Some(match log_directory {
+                Some(directory) => directory.join(path),
+                None => path.to_owned(),
+            })
+        } else {
+            None
+        };
+        if path.is_none() && !section.boolean_or(key::CAPTURE_OUTPUT, false)? {
+            return Ok(None);
+        }
+        Ok(Some(OutputCapture {
+            target: section.target_id()?.clone(),
+            path,
+            tail_lines: section
+                .integer_or(key::CRASH_REPORT_LINES, DEFAULT_CRASH_REPORT_LINES)?
+                .try_into()?,
+            logger: logger.local(),
+        }))
+    }
+
+    /// Pipes the standard output and the standard error of the
+    /// `command`.
+    pub(crate) fn prepare(&self, command: &mut Command) {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    /// Starts reading the lines the `child` writes until it closes
+    /// its streams.
+    ///
+    /// # Returns
+    ///
+    /// Returns the [`StderrTail`] of the `child`.
+    pub(crate) fn attach(&self, child: &mut Child) -> Option<StderrTail> {
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(self.relay(stdout, "stdout", None));
+        }
+        let stderr = child.stderr.take()?;
+        let lines = Arc::new(Mutex::new(VecDeque::with_capacity(self.tail_lines)));
+        let relay = tokio::spawn(self.relay(
+            stderr,
+            "stderr",
+            Some((Arc::clone(&lines), self.tail_lines)),
+        ));
+        Some(StderrTail { lines, relay })
+    }
+
+    /// Returns a task that relays the lines of the `stream` by the
+    /// `name`, and keeps the last of them in the `tail`, if any.
+    /// Writes the lines to the log if OUTPUT-LOG can't be opened.
+    fn relay(
+        &self,
+        stream: impl AsyncRead + Send + Unpin + 'static,
+        name: &'static str,
+        tail: Option<(Arc<Mutex<VecDeque<String>>>, usize)>,
+    ) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let target = self.target.clone();
+        let path = self.path.clone();
+        let logger = self.logger.clone();
+        async move {
+            let mut file = match &path {
+                Some(path) => match OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                {
+                    Ok(file) => Some(file),
+                    Err(err) => {
+                        logger.log_coded(
+                            &message::OL001,
+                            &format!("failed to open [{}]: {}", path.display(), err),
+                        );
+                        None
+                    }
+                },
+                None => None,
+            };
+            let mut lines = BufReader::new(stream).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some((tail, capacity)) = tail.as_ref().filter(|(_, capacity)| *capacity > 0)
+                {
+                    let mut tail = tail.lock().expect("stderr tail poisoned");
+                    if tail.len() == *capacity {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line.clone());
+                }
+                let tagged = format!("{} {}: {}", target, name, line);
+                match &mut file {
+                    Some(file) => {
+                        let entry = format!("[{}] {}\n", Local::now(), tagged);
+                        if let Err(err) = file.write_all(entry.as_bytes()).await {
+                            logger.log_coded(
+                                &message::OL002,
+                                &format!("failed to write output log: {}", err),
+                            );
+                            logger.log(LogLevel::Info, &tagged);
+                        }
+                    }
+                    None => logger.log(LogLevel::Info, &tagged),
+                }
+            }
+        }
+    }
+}
+
+/// The last lines a managed process wrote to its standard error, as
+/// [`OutputCapture`] relays them.
+pub(crate) struct StderrTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    relay: JoinHandle<()>,
+}
+
+impl StderrTail {
+    /// Returns the last lines of the standard error.  Waits up to
+    /// `wait` for the relay to read the lines the process wrote just
+    /// before it exited, which tend to be the telling ones.
+    pub(crate) async fn lines(&mut self, wait: Duration) -> Vec<String> {
+        if !self.relay.is_finished() {
+            let _ = timeout(wait, &mut self.relay).await;
+        }
+        self.lines
+            .lock()
+            .expect("stderr tail poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
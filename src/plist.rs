@@ -20,7 +20,6 @@ use crate::error::config_format_error;
 use crate::expression::Expression;
 use crate::keyword::Keyword;
 use sexp::Sexp;
-use std::collections::HashMap;
 use std::error::Error;
 
 /// Represents an indicator in a plist.
@@ -32,7 +31,7 @@ use std::error::Error;
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::plist::Indicator;
 ///
 /// let indicator = Indicator::new("name");
@@ -48,7 +47,7 @@ pub(crate) type Indicator = Keyword;
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::Value;
 ///
 /// let value = Value::Atom(Atom::String("John Doe".to_owned()));
@@ -97,6 +96,7 @@ impl KeywordSexp for Sexp {
 /// `Value` pairs.  It features linear complexity for lookups.
 /// `Indicator` represents the keyword as the key and `Value`
 /// represents the associated value.
+#[derive(Default)]
 pub(crate) struct KeywordPlist(Vec<(Indicator, Value)>);
 
 impl KeywordPlist {
@@ -110,7 +110,7 @@ impl KeywordPlist {
     /// # Arguments
     ///
     /// * `vec` - A vector of S-expressions representing the
-    /// indicator-value pairs.
+    ///   indicator-value pairs.
     ///
     /// # Returns
     ///
@@ -120,7 +120,7 @@ impl KeywordPlist {
     ///
     /// # Examples
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// use crate::plist::{KeywordPlist, Indicator, Value};
     /// use sexp::Sexp;
     ///
@@ -133,7 +133,7 @@ impl KeywordPlist {
     /// Returns an error if:
     ///
     /// - The vector has an odd number of items, indicating a
-    /// mismatched indicator-value pair.
+    ///   mismatched indicator-value pair.
     /// - The indicator is not a keyword.
     pub(crate) fn from_vec(vec: Vec<Sexp>) -> Result<Self, Box<dyn Error>> {
         let mut new_vec = vec![];
@@ -156,8 +156,35 @@ impl KeywordPlist {
         Ok(KeywordPlist(new_vec))
     }
 
-    /// Will be removed.
-    pub(crate) fn into_hash_map(mut self) -> HashMap<Indicator, Value> {
-        HashMap::from_iter(self.0.drain(..))
+    /// Returns the value of the `indicator`, if any.
+    pub(crate) fn get(&self, indicator: &Indicator) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(other, _)| other == indicator)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the `indicator` to the `value`.  Replaces the value in
+    /// place if the `indicator` is already there, so that the
+    /// indicator keeps its position.  Otherwise appends the pair.
+    pub(crate) fn insert(&mut self, indicator: Indicator, value: Value) {
+        match self.0.iter_mut().find(|(other, _)| *other == indicator) {
+            Some((_, old)) => *old = value,
+            None => self.0.push((indicator, value)),
+        }
+    }
+
+    /// Returns an iterator over the indicator-value pairs in order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Indicator, &Value)> {
+        self.0.iter().map(|(indicator, value)| (indicator, value))
+    }
+}
+
+impl IntoIterator for KeywordPlist {
+    type Item = (Indicator, Value);
+    type IntoIter = std::vec::IntoIter<(Indicator, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
     }
 }
@@ -16,53 +16,225 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::callback::{Event, Notifier};
+use crate::channel::EventSender;
 use crate::config::{key, section, Config};
-use crate::error::{illegal_state_error, ErrorType};
+use crate::directory::Directories;
+use crate::error::{config_format_error, illegal_state_error, ErrorType};
 use crate::event::EventType;
-use crate::logger::{LocalLogger, LogLevel};
+use crate::kw;
+use crate::logger::{message, LogLevel, Logger};
+use crate::metrics::{Metrics, Outcome};
+use crate::output::{OutputCapture, StderrTail};
+use crate::restart::RestartReason;
 use crate::result::Result;
-use crate::signal::Signal;
-use nix::unistd::Pid;
+use crate::signal::{parse_signal, signal_name, Signal};
+use crate::spare::{spare_endpoints, Spare};
+use crate::state::{StateMachine, TargetState};
+use crate::timeline::Timeline;
+use crate::tree::ProcessTree;
+use nix::errno::Errno;
+use nix::sys::signal::killpg;
+use nix::unistd::{Gid, Group, Pid, Uid, User};
 use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::ExitStatus;
 use std::rc::Rc;
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, timeout, Duration, Instant};
 
-/// Enumerates the possible statuses of the process managed by the
-/// `ProcessManager`.
+/// The default time in seconds to wait for the process to exit after
+/// killing it, before declaring it stuck.
+static DEFAULT_STUCK_TIMEOUT: i64 = 30;
+
+/// The default time in seconds a target that asked for a restart has
+/// to exit after `SIGTERM`, without KILL-TIMEOUT.
+static DEFAULT_RESTART_GRACE: i64 = 10;
+
+/// The environment variable telling the managed process its log
+/// directory.
+static LOG_DIRECTORY_VARIABLE: &str = "HEARTBEAT2_LOG_DIR";
+
+/// The environment variable telling the managed process the endpoint
+/// to listen on, with WARM-SPARE.
+static ENDPOINT_VARIABLE: &str = "HEARTBEAT2_ENDPOINT";
+
+/// The maximum number of signals to queue while there is no running
+/// process.
+static MAX_QUEUED_SIGNALS: usize = 16;
+
+/// Returns the environment variables ENVIRONMENT sets for the managed
+/// process.
 ///
-/// The `Status` enum represents the different states that the managed
-/// process can be in at any given point in time.  It is used to track
-/// and communicate the current status of the managed process.
+/// # Returns
 ///
-/// # Examples
+/// Returns a list of pairs of a name and a value.
+///
+/// # Errors
+///
+/// Returns a configuration error if an entry isn't a pair of a name
+/// and a value, or if a name is empty or contains `=`.
+fn environment(config: &Config) -> Result<Vec<(String, String)>> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.has_key(key::ENVIRONMENT) {
+        return Ok(vec![]);
+    }
+    section
+        .string_lists(key::ENVIRONMENT)?
+        .into_iter()
+        .map(|pair| match <[String; 2]>::try_from(pair) {
+            Ok([name, value]) if !name.is_empty() && !name.contains('=') => Ok((name, value)),
+            Ok([name, _]) => Err(config_format_error(&format!(
+                "{} has an invalid name [{}]",
+                key::ENVIRONMENT,
+                name
+            ))),
+            Err(pair) => Err(config_format_error(&format!(
+                "{} needs a name and a value in {:?}",
+                key::ENVIRONMENT,
+                pair
+            ))),
+        })
+        .collect()
+}
+
+/// Returns the user and the group USER and GROUP configure for the
+/// managed process.  Without GROUP, the group is the primary group of
+/// the user.
+///
+/// # Errors
+///
+/// Returns a configuration error if USER or GROUP names an unknown
+/// user or group.
+fn credentials(config: &Config) -> Result<(Option<Uid>, Option<Gid>)> {
+    let section = config.section(section::HEARTBEAT)?;
+    let user = if section.has_key(key::USER) {
+        let name = section.string(key::USER)?;
+        let user = match name.parse() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid))?,
+            Err(_) => User::from_name(name)?,
+        };
+        Some(user.ok_or_else(|| config_format_error(&format!("unknown {} [{}]", key::USER, name)))?)
+    } else {
+        None
+    };
+    let gid = if section.has_key(key::GROUP) {
+        let name = section.string(key::GROUP)?;
+        let gid = match name.parse() {
+            Ok(gid) => Some(Gid::from_raw(gid)),
+            Err(_) => Group::from_name(name)?.map(|group| group.gid),
+        };
+        Some(gid.ok_or_else(|| config_format_error(&format!("unknown {} [{}]", key::GROUP, name)))?)
+    } else {
+        user.as_ref().map(|user| user.gid)
+    };
+    Ok((user.map(|user| user.uid), gid))
+}
+
+/// An action on the managed process.  Each carries the incarnation
+/// of the process it is meant for, so that it can't hit a process
+/// started in the meantime.
+enum Action {
+    RaiseSignal(Signal, u64, oneshot::Sender<Ack>),
+    Kill(Outcome, u64, oneshot::Sender<Ack>),
+}
+
+impl Action {
+    /// Describes the action for the diagnostics.
+    fn describe(&self) -> String {
+        match self {
+            Action::RaiseSignal(signal, incarnation, _) => {
+                format!("RaiseSignal({:?}) to #{}", signal, incarnation)
+            }
+            Action::Kill(_, incarnation, _) => format!("Kill to #{}", incarnation),
+        }
+    }
+
+    /// Returns the incarnation of the process the action is meant
+    /// for.
+    fn incarnation(&self) -> u64 {
+        match self {
+            Action::RaiseSignal(_, incarnation, _) | Action::Kill(_, incarnation, _) => {
+                *incarnation
+            }
+        }
+    }
+}
+
+/// Acknowledges an action performed on the managed process.
+#[derive(Debug)]
+pub(crate) enum Ack {
+    /// The process has exited with the given status after the
+    /// action.
+    Exited(ExitStatus),
+    /// The signal has been delivered.  `Heartbeat2` doesn't wait for
+    /// the process to exit after this kind of signal.
+    Delivered,
+    /// The process exited by itself before it could act on the
+    /// action.
+    Vanished,
+    /// The process didn't exit even after it was killed.  It may be
+    /// in an uninterruptible sleep.
+    Stuck,
+    /// There was no running process to forward the signal to.  The
+    /// signal will go to the process once it restarts.
+    Queued,
+}
+
+/// Awaits the acknowledgement of an action performed on the managed
+/// process.
+///
+/// [`ProcessManager::kill_process`] and
+/// [`ProcessManager::raise_signal`] return immediately after sending
+/// the action.  The `ActionHandle` they return lets the caller learn
+/// whether the action actually took effect.
 ///
-/// ```rust
-/// use crate::process::Status;
+/// # Examples
 ///
-/// let status = Status::Running;
-/// match status {
-///     Status::Ready => println!("Process is ready to start."),
-///     Status::Running => println!("Process is currently running."),
-///     Status::Terminated => println!("Process has terminated."),
-///     Status::Killed => println!("Process has been killed."),
+/// ```rust,ignore
+/// let handle = process_manager.kill_process(process_manager.incarnation())?;
+/// match handle.wait(Duration::from_secs(10)).await {
+///     Some(ack) => println!("acknowledged: {:?}", ack),
+///     None => println!("the process is still running"),
 /// }
 /// ```
-#[derive(Clone, Copy, Debug)]
-pub(crate) enum Status {
-    /// Indicates that the process is ready to start or restart.
-    Ready,
-    /// Indicates that the process is currently running.
-    Running,
-    /// Indicates that the process has terminated normally.
-    Terminated,
-    /// Indicates that the process has been forcibly killed.
-    Killed,
+pub(crate) struct ActionHandle {
+    action: String,
+    ack: oneshot::Receiver<Ack>,
 }
 
-enum Action {
-    RaiseSignal(Signal),
-    Kill,
+impl ActionHandle {
+    fn new(action: &Action, ack: oneshot::Receiver<Ack>) -> Self {
+        ActionHandle {
+            action: action.describe(),
+            ack,
+        }
+    }
+
+    /// Waits for the acknowledgement of the action for up to
+    /// `bound`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the acknowledgement, or `None` if `bound` elapses
+    /// first.
+    pub(crate) async fn wait(self, bound: Duration) -> Option<Ack> {
+        match timeout(bound, self.ack).await {
+            Ok(Ok(ack)) => Some(ack),
+            Ok(Err(_)) => Some(Ack::Vanished),
+            Err(_elapsed) => None,
+        }
+    }
+}
+
+impl Display for ActionHandle {
+    /// Shows the action the handle awaits the acknowledgement of.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}]", self.action)
+    }
 }
 
 /// Enumerates the possible outcomes of a running process.
@@ -74,13 +246,13 @@ enum Action {
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::process::RunProcess;
 ///
 /// fn handle_process_completion(result: RunProcess) {
 ///     match result {
 ///         RunProcess::Complete => println!("Process completed successfully."),
-///         RunProcess::Abort => println!("Process aborted or encountered an error."),
+///         RunProcess::Abort(reason) => println!("Process aborted: {}.", reason),
 ///     }
 /// }
 /// ```
@@ -88,8 +260,16 @@ pub(crate) enum RunProcess {
     /// Indicates that the process has completed successfully.
     Complete,
     /// Indicates that the process has aborted or encountered an
-    /// error.
-    Abort,
+    /// error for the [`RestartReason`].
+    Abort(RestartReason),
+    /// Indicates that the process was killed to restart it at the
+    /// request of an operator or of the target, as the
+    /// [`RestartReason`] tells.
+    Restart(RestartReason),
+    /// Indicates that the process didn't exit after it was killed.
+    /// `Heartbeat2` stops waiting for it so that it can still report
+    /// the problem.
+    Stuck,
 }
 
 /// Manages the execution and status of a process.
@@ -100,8 +280,9 @@ pub(crate) enum RunProcess {
 /// [`EventHandler`](../event/struct.EventHandler.html) listens to
 /// events like this and handles it according to the specification.
 /// Raising status events is one of the central roles `ProcessManager`
-/// plays in `Heartbeat2`.  `ProcessManager` uses a oneshot channel to
-/// allow manipulation of the process status.  It raises events to
+/// plays in `Heartbeat2`.  `ProcessManager` uses an action channel to
+/// allow manipulation of the process status, and acknowledges each
+/// action with a oneshot channel.  It raises events to
 /// [`EventHandler`](../event/struct.EventHandler.html) via an MPSC
 /// channel.  The orchestrated use of channels helps the state of
 /// `ProcessManager` to stay consistent.
@@ -110,18 +291,59 @@ pub(crate) enum RunProcess {
 /// `Heartbeat2` can do.  You can find it in spec/heartbeat.pdf in the
 /// source repository.
 ///
+/// # Configuration
+///
+/// * CLEAN-ENVIRONMENT: Optional.  When `t`, the managed process
+///   starts with none of the environment variables of `Heartbeat2`,
+///   but only those of ENVIRONMENT and the `HEARTBEAT2_` ones.  This
+///   keeps the secrets of the supervisor from leaking into the
+///   process, and makes its environment reproducible.  The default is
+///   `nil`.
+/// * ENVIRONMENT: Optional.  A list of the environment variables to
+///   set for the managed process, each a pair of a name and a value,
+///   such as `(("RUST_LOG" "info") ("PORT" "8080"))`.
+/// * GROUP: Optional.  The name or the ID of the group to run the
+///   managed process as.  The default is the primary group of USER.
+/// * IGNORE-EXIT-CODES: Optional.  A list of the nonzero exit codes
+///   that count as the completion of the managed process rather than
+///   a crash, such as `(2 78)` for the exits on a configuration error
+///   that a restart won't fix.  `Heartbeat2` then exits instead of
+///   restarting the process.
+/// * IGNORE-EXIT-SIGNALS: Optional.  A list of the names of the
+///   signals that count as the completion of the managed process
+///   when they terminate it, such as `("SIGKILL")` for a process an
+///   operator kills on purpose.  The other signals are crashes.
+/// * KILL-PROCESS-GROUP: Optional.  When `t`, the managed process
+///   starts in a process group of its own, and the signals and the
+///   kills go to the whole group.  The workers a target forks then
+///   don't outlive it as orphans.  The default is `nil`.
+/// * SWEEP-DESCENDANTS: Optional.  When `t`, `ProcessManager` takes a
+///   snapshot of the descendants of the managed process before it
+///   kills it, and kills those still running once it exits.  This
+///   catches the descendants that leave the process group, such as
+///   daemons.  Needs /proc.  The default is `nil`.
+/// * RESTART-ON-EXIT-CODES: Optional.  A list of the exit codes that
+///   count as a crash of the managed process, such as `(0)` for a
+///   target that should never exit by itself.  It takes precedence
+///   over IGNORE-EXIT-CODES.  An exit by a signal is always a crash.
+/// * USER: Optional.  The name or the ID of the user to run the
+///   managed process as, such as `"app"`.  `Heartbeat2` may stay
+///   privileged while the process runs unprivileged.  USER and GROUP
+///   take effect only when `Heartbeat2` runs as root.  Otherwise, the
+///   process runs as the user of `Heartbeat2`.
+///
 /// # Examples
 ///
 /// Creating a new `ProcessManager` and running a process:
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::{ProcessManager, RunProcess};
 ///
 /// async fn run_process_manager() -> Result<(), Box<dyn std::error::Error>> {
 ///     // Create a process manager with event queue, configuration, and logger
-///     let event_queue: mpsc::Sender<EventType> = // Event queue setup
+///     let event_queue: EventSender = // Event queue setup
 ///     let config: Rc<Config> = // Configuration setup
-///     let logger: Rc<LocalLogger> = // Logger setup
+///     let logger: Rc<dyn Logger> = // Logger setup
 ///     let process_manager = ProcessManager::new(event_queue, config, logger);
 ///
 ///     // Run the process
@@ -130,18 +352,34 @@ pub(crate) enum RunProcess {
 ///     // Handle the process outcome
 ///     match result {
 ///         RunProcess::Complete => println!("Process completed successfully."),
-///         RunProcess::Abort => println!("Process aborted or encountered an error."),
+///         RunProcess::Abort(reason) => println!("Process aborted: {}.", reason),
 ///     }
 ///
 ///     Ok(())
 /// }
 /// ```
 pub(crate) struct ProcessManager {
-    status: Cell<Status>,
-    agent: RefCell<Option<oneshot::Sender<Action>>>,
-    event_queue: mpsc::Sender<EventType>,
+    state: Rc<StateMachine>,
+    agent: RefCell<Option<mpsc::UnboundedSender<Action>>>,
+    pid: Cell<Option<u32>>,
+    incarnation: Cell<u64>,
+    last_action: RefCell<Option<String>>,
+    exit_status: Cell<Option<ExitStatus>>,
+    /// The PID of the process the last time it ran.
+    last_pid: Cell<Option<u32>>,
+    /// The time the process ran for until it last exited.
+    uptime: Cell<Duration>,
+    stderr: RefCell<Option<StderrTail>>,
+    queued_signals: RefCell<VecDeque<Signal>>,
+    endpoint: RefCell<Option<String>>,
+    spare: RefCell<Option<Spare>>,
+    promoted: RefCell<Option<Spare>>,
+    event_queue: EventSender,
     config: Rc<Config>,
-    logger: Rc<LocalLogger>,
+    metrics: Rc<Metrics>,
+    timeline: Rc<Timeline>,
+    notifier: Rc<Notifier>,
+    logger: Rc<dyn Logger>,
 }
 
 impl ProcessManager {
@@ -150,22 +388,45 @@ impl ProcessManager {
     /// # Arguments
     ///
     /// * `event_queue` - A sender channel for sending event types.
+    /// * `state` - A shared reference to the target state machine.
     /// * `config` - A shared reference to the configuration.
+    /// * `metrics` - A shared reference to the metrics.
+    /// * `timeline` - A shared reference to the timeline.
+    /// * `notifier` - A shared reference to the notifier of the
+    ///   callbacks.
     /// * `logger` - A shared reference to the logger.
     ///
     /// # Returns
     ///
     /// A new `ProcessManager` instance.
     pub(crate) fn new(
-        event_queue: mpsc::Sender<EventType>,
+        event_queue: EventSender,
+        state: Rc<StateMachine>,
         config: Rc<Config>,
-        logger: Rc<LocalLogger>,
+        metrics: Rc<Metrics>,
+        timeline: Rc<Timeline>,
+        notifier: Rc<Notifier>,
+        logger: Rc<dyn Logger>,
     ) -> Self {
         ProcessManager {
-            status: Cell::new(Status::Ready),
+            state,
             agent: RefCell::new(None),
+            pid: Cell::new(None),
+            incarnation: Cell::new(0),
+            last_action: RefCell::new(None),
+            exit_status: Cell::new(None),
+            last_pid: Cell::new(None),
+            uptime: Cell::new(Duration::ZERO),
+            stderr: RefCell::new(None),
+            queued_signals: Default::default(),
+            endpoint: RefCell::new(None),
+            spare: RefCell::new(None),
+            promoted: RefCell::new(None),
             event_queue,
             config,
+            metrics,
+            timeline,
+            notifier,
             logger,
         }
     }
@@ -183,54 +444,494 @@ impl ProcessManager {
     /// not in a ready state.  You can call [`reset()`](#method.reset)
     /// to prevent or recover from this error.
     pub(crate) async fn run_process(&self) -> Result<RunProcess> {
-        let config_section = self.config.section(section::HEARTBEAT)?;
-        let mut command = config_section.string_list(key::COMMAND)?;
-        let exec: String = command.drain(0..1).collect();
-        let args = command;
-        let wd = config_section.string(key::WORKING_DIRECTORY)?;
         if self.is_ready() {
-            self.logger.log(LogLevel::Info, "start process");
-            self.set_status(Status::Running);
-            let mut child = Command::new(exec).args(args).current_dir(wd).spawn()?;
-            let (send_action, recv_action) = oneshot::channel::<Action>();
+            let promoted = self.promoted.borrow_mut().take();
+            let (child, stderr) = match promoted {
+                Some(spare) => {
+                    self.logger.log_coded(
+                        &message::PM001,
+                        &format!("promote spare on {}", spare.endpoint()),
+                    );
+                    self.transition(TargetState::Running)?;
+                    spare.into_child()
+                }
+                None => {
+                    self.logger.log_coded(&message::PM002, "start process");
+                    self.transition(TargetState::Running)?;
+                    self.spawn(self.primary_endpoint()?.as_deref())?
+                }
+            };
+            // NOTE: The primary runs already, so that a spare that
+            // fails to start leaves it supervised without a spare.
+            if let Err(err) = self.start_spare() {
+                self.logger
+                    .log_coded(&message::PM020, &format!("unable to start spare: {}", err));
+            }
+            if let Some(id) = child.id() {
+                self.timeline.record(kw![spawn], &format!("PID {}", id));
+                self.notifier.notify(Event::Start(id));
+            }
+            self.incarnation.set(self.incarnation.get() + 1);
+            let (send_action, recv_action) = mpsc::unbounded_channel::<Action>();
+            self.replay_queued_signals(&send_action);
             self.agent.borrow_mut().replace(send_action);
+            self.pid.set(child.id());
+            self.last_pid.set(child.id());
+            self.exit_status.set(None);
+            self.stderr.replace(stderr);
+            let result = self.watch_process(child, recv_action).await;
+            self.pid.set(None);
+            self.agent.borrow_mut().take();
+            result
+        } else {
+            Err(illegal_state_error(&self.state.get().to_string()))
+        }
+    }
+
+    /// Spawns an instance of the target.  Tells it the `endpoint` to
+    /// listen on, if any.  Returns the [`StderrTail`] of the instance
+    /// as well if its output is captured.
+    fn spawn(&self, endpoint: Option<&str>) -> Result<(Child, Option<StderrTail>)> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let mut args = section.string_list(key::COMMAND)?;
+        let exec: String = args.drain(0..1).collect();
+        let directories = Directories::prepare(&self.config)?;
+        let mut command = std::process::Command::new(exec);
+        if section.boolean_or(key::KILL_PROCESS_GROUP, false)? {
+            command.process_group(0);
+        }
+        let mut command = Command::from(command);
+        command.args(args).current_dir(&directories.working);
+        if section.boolean_or(key::CLEAN_ENVIRONMENT, false)? {
+            command.env_clear();
+        }
+        command.envs(environment(&self.config)?);
+        let (uid, gid) = credentials(&self.config)?;
+        if uid.is_some() || gid.is_some() {
+            if Uid::effective().is_root() {
+                if let Some(gid) = gid {
+                    command.gid(gid.as_raw());
+                }
+                if let Some(uid) = uid {
+                    command.uid(uid.as_raw());
+                }
+            } else {
+                self.logger.log_coded(
+                    &message::PM015,
+                    &format!("not root; ignore {} and {}", key::USER, key::GROUP),
+                );
+            }
+        }
+        if let Some(log) = &directories.log {
+            command.env(LOG_DIRECTORY_VARIABLE, log);
+        }
+        if let Some(endpoint) = endpoint {
+            command.env(ENDPOINT_VARIABLE, endpoint);
+        }
+        let capture = OutputCapture::from_config(
+            &self.config,
+            directories.log.as_deref(),
+            self.logger.as_ref(),
+        )?;
+        if let Some(capture) = &capture {
+            capture.prepare(&mut command);
+        }
+        let mut child = command.spawn()?;
+        let stderr = capture.and_then(|capture| capture.attach(&mut child));
+        Ok((child, stderr))
+    }
+
+    /// Returns the endpoint of the primary with WARM-SPARE, which is
+    /// TARGET-ENDPOINT until the first promotion of a spare.
+    fn primary_endpoint(&self) -> Result<Option<String>> {
+        if self.endpoint.borrow().is_none() {
+            let endpoints = spare_endpoints(&self.config)?;
+            self.endpoint.replace(endpoints.map(|(primary, _)| primary));
+        }
+        Ok(self.endpoint.borrow().clone())
+    }
+
+    /// Starts a spare on the endpoint the primary doesn't use, if
+    /// WARM-SPARE is on and there is no spare running.
+    fn start_spare(&self) -> Result<()> {
+        let (primary, spare) = match spare_endpoints(&self.config)? {
+            Some(endpoints) => endpoints,
+            None => return Ok(()),
+        };
+        if self
+            .spare
+            .borrow_mut()
+            .as_mut()
+            .is_some_and(Spare::is_running)
+        {
+            return Ok(());
+        }
+        let endpoint = if self.primary_endpoint()?.as_ref() == Some(&primary) {
+            spare
+        } else {
+            primary
+        };
+        let (child, stderr) = self.spawn(Some(&endpoint))?;
+        if let Some(id) = child.id() {
+            self.timeline
+                .record(kw![spare], &format!("PID {} on {}", id, endpoint));
+        }
+        self.logger
+            .log_coded(&message::PM003, &format!("start spare on {}", endpoint));
+        self.spare
+            .replace(Some(Spare::new(child, stderr, endpoint)));
+        Ok(())
+    }
+
+    /// Promotes the spare, if it is running, so that the next
+    /// [`run_process`](ProcessManager::run_process) supervises it
+    /// instead of starting the target from scratch.
+    ///
+    /// # Returns
+    ///
+    /// Returns the endpoint of the promoted spare, or `None` if there
+    /// is no spare running.
+    pub(crate) fn promote_spare(&self) -> Option<String> {
+        let mut spare = self.spare.borrow_mut().take()?;
+        if !spare.is_running() {
+            self.logger
+                .log_coded(&message::PM004, "spare exited before its promotion");
+            return None;
+        }
+        let endpoint = spare.endpoint().to_owned();
+        self.timeline.record(
+            kw![promote],
+            &format!(
+                "PID {} on {}",
+                spare
+                    .pid()
+                    .map_or_else(|| "?".to_owned(), |id| id.to_string()),
+                endpoint
+            ),
+        );
+        self.endpoint.replace(Some(endpoint.clone()));
+        self.promoted.replace(Some(spare));
+        Some(endpoint)
+    }
+
+    /// Kills the spare, if any, as the supervision ends.
+    pub(crate) fn stop_spare(&self) {
+        self.spare.borrow_mut().take();
+        self.promoted.borrow_mut().take();
+    }
+
+    /// Returns the PID of the spare, if it is running.
+    pub(crate) fn spare_pid(&self) -> Option<u32> {
+        self.spare.borrow().as_ref().and_then(Spare::pid)
+    }
+
+    /// Waits for the `child` to exit, performing the actions received
+    /// in the meantime.  Acknowledges the pending action, if any, once
+    /// the `child` exits.  Gives up waiting STUCK-TIMEOUT seconds
+    /// after killing the `child`, as a process in an uninterruptible
+    /// sleep ignores even `SIGKILL`.
+    ///
+    /// With KILL-TIMEOUT, a kill first sends the `child` `SIGTERM`, so
+    /// that it can flush its state, and sends `SIGKILL` only if the
+    /// `child` is still running KILL-TIMEOUT seconds later.  A kill
+    /// that escalates an earlier action sends `SIGKILL` at once.
+    async fn watch_process(
+        &self,
+        mut child: Child,
+        mut recv_action: mpsc::UnboundedReceiver<Action>,
+    ) -> Result<RunProcess> {
+        let started = Instant::now();
+        let incarnation = self.incarnation();
+        let section = self.config.section(section::HEARTBEAT)?;
+        let stuck_timeout = Duration::from_secs(
+            section
+                .integer_or(key::STUCK_TIMEOUT, DEFAULT_STUCK_TIMEOUT)?
+                .try_into()?,
+        );
+        let group = section.boolean_or(key::KILL_PROCESS_GROUP, false)?;
+        let sweep = section.boolean_or(key::SWEEP_DESCENDANTS, false)?;
+        let pid = child.id();
+        let mut tree: Option<ProcessTree> = None;
+        let kill_timeout = if section.has_key(key::KILL_TIMEOUT) {
+            Some(Duration::from_secs(
+                section.integer(key::KILL_TIMEOUT)?.try_into()?,
+            ))
+        } else {
+            None
+        };
+        let mut pending: Option<(Outcome, oneshot::Sender<Ack>)> = None;
+        let mut term_grace = Duration::ZERO;
+        let mut term_deadline: Option<Instant> = None;
+        let mut kill_deadline: Option<Instant> = None;
+        loop {
             tokio::select! {
-                exit_status = child.wait() => if exit_status?.success() {
-                    self.raise_process_event_complete().await?;
-                    Ok(RunProcess::Complete)
-                } else {
-                    self.raise_process_event_abort().await?;
-                    Ok(RunProcess::Abort)
+                exit_status = child.wait() => {
+                    let exit_status = exit_status?;
+                    self.exit_status.set(Some(exit_status));
+                    self.uptime.set(started.elapsed());
+                    self.timeline.record(kw![exit], &exit_status.to_string());
+                    return if let Some((outcome, ack)) = pending {
+                        if let Some(pid) = pid {
+                            self.kill_leftovers(pid, group, tree.as_ref())?;
+                        }
+                        let _ = ack.send(Ack::Exited(exit_status));
+                        self.metrics.add_outcome(outcome);
+                        match outcome {
+                            Outcome::TimeoutKill => Ok(RunProcess::Abort(RestartReason::HeartbeatTimeout)),
+                            Outcome::RestartKill => Ok(RunProcess::Restart(RestartReason::Manual)),
+                            Outcome::RestartRequest => Ok(RunProcess::Restart(RestartReason::TargetRequest)),
+                            _ => Ok(RunProcess::Complete),
+                        }
+                    } else if self.completed(exit_status)? {
+                        self.metrics.add_outcome(Outcome::Complete);
+                        self.raise_process_event_complete().await?;
+                        Ok(RunProcess::Complete)
+                    } else {
+                        self.metrics.add_outcome(Outcome::Abort);
+                        self.raise_process_event_abort().await?;
+                        Ok(RunProcess::Abort(RestartReason::Crash))
+                    };
                 },
-                operation = recv_action => {
-                    match operation? {
-                        Action::RaiseSignal(signal) => {
-                            if let Some(id) = child.id() {
-                                nix::sys::signal::kill(Pid::from_raw(id.try_into()?), Some(signal.into()))?;
-                            } else {
-                                self.logger.log(LogLevel::Warning, &format!("unable to raise signal [{:?}] as child process already exited", signal))
-                            }
-                            Ok(RunProcess::Complete)
+                Some(action) = recv_action.recv() => match self.took(action) {
+                    action if action.incarnation() != incarnation => {
+                        self.logger.log_coded(&message::PM005, &format!("ignore action [{}] on process #{}", action.describe(), incarnation));
+                        let (Action::RaiseSignal(_, _, ack) | Action::Kill(_, _, ack)) = action;
+                        let _ = ack.send(Ack::Vanished);
+                    }
+                    Action::RaiseSignal(signal, _, ack) => {
+                        let detaches = signal.detaches();
+                        let terminates = signal.terminates();
+                        if terminates && sweep && tree.is_none() {
+                            tree = pid.map(ProcessTree::capture);
+                        }
+                        if let Some(id) = child.id() {
+                            self.signal_child(id, signal.into(), group)?;
+                            self.timeline.record(kw![signal], &format!("{:?} to PID {}", nix::sys::signal::Signal::from(signal), id));
+                        } else {
+                            self.logger.log_coded(&message::PM006, &format!("unable to raise signal [{:?}] as child process already exited", signal))
+                        }
+                        if !terminates {
+                            let _ = ack.send(Ack::Delivered);
+                            continue;
                         }
-                        Action::Kill => {
-                            child.start_kill()?;
-                            let _ = child.wait().await;
-                            Ok(RunProcess::Abort)
+                        if detaches {
+                            let _ = ack.send(Ack::Delivered);
+                            self.metrics.add_outcome(Outcome::SignalRelay);
+                            return Ok(RunProcess::Complete);
                         }
+                        pending = Some((Outcome::SignalRelay, ack));
                     }
+                    Action::Kill(outcome, _, ack) => {
+                        if sweep && tree.is_none() {
+                            tree = pid.map(ProcessTree::capture);
+                        }
+                        let grace = match outcome {
+                            Outcome::RestartRequest => Some(self.restart_grace()?),
+                            _ => kill_timeout,
+                        };
+                        match (grace, &pending, child.id()) {
+                            (Some(grace), None, Some(id)) => {
+                                let sigterm = nix::sys::signal::Signal::SIGTERM;
+                                self.signal_child(id, sigterm, group)?;
+                                self.timeline.record(kw![signal], &format!("{:?} to PID {}", sigterm, id));
+                                term_grace = grace;
+                                term_deadline = Some(Instant::now() + grace);
+                            }
+                            _ => {
+                                term_deadline = None;
+                                self.kill_child(&mut child, group)?;
+                                kill_deadline.get_or_insert_with(|| Instant::now() + stuck_timeout);
+                            }
+                        }
+                        let outcome = pending.map_or(outcome, |(outcome, _)| outcome);
+                        pending = Some((outcome, ack));
+                    }
+                },
+                _ = sleep_until(term_deadline.unwrap_or_else(Instant::now)), if term_deadline.is_some() => {
+                    term_deadline = None;
+                    self.logger.log_coded(&message::PM007, &format!(
+                            "process [{}] still running {}s after SIGTERM; killing it",
+                            child.id().map_or_else(|| "?".to_owned(), |id| id.to_string()),
+                            term_grace.as_secs()
+                        ));
+                    self.kill_child(&mut child, group)?;
+                    kill_deadline.get_or_insert_with(|| Instant::now() + stuck_timeout);
+                },
+                _ = sleep_until(kill_deadline.unwrap_or_else(Instant::now)), if kill_deadline.is_some() => {
+                    self.logger.log_coded(&message::PM008, &format!(
+                            "process [{}] still running {}s after kill; it may be stuck in an uninterruptible sleep",
+                            child.id().map_or_else(|| "?".to_owned(), |id| id.to_string()),
+                            stuck_timeout.as_secs()
+                        ));
+                    if let Some((_, ack)) = pending {
+                        let _ = ack.send(Ack::Stuck);
+                    }
+                    self.metrics.add_outcome(Outcome::Stuck);
+                    self.transition(TargetState::Stuck)?;
+                    return Ok(RunProcess::Stuck);
                 }
             }
+        }
+    }
+
+    /// Sends the `signal` to the child process `id`, or to its
+    /// process group if `group` is on.
+    fn signal_child(&self, id: u32, signal: nix::sys::signal::Signal, group: bool) -> Result<()> {
+        let pid = Pid::from_raw(id.try_into()?);
+        if group {
+            killpg(pid, signal)?;
         } else {
-            Err(illegal_state_error(&format!("{:?}", self.status())))
+            nix::sys::signal::kill(pid, signal)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the `child` `SIGKILL`, or its equivalent on the platform,
+    /// and its process group, too, if `group` is on.
+    fn kill_child(&self, child: &mut Child, group: bool) -> Result<()> {
+        if let (true, Some(id)) = (group, child.id()) {
+            match killpg(
+                Pid::from_raw(id.try_into()?),
+                nix::sys::signal::Signal::SIGKILL,
+            ) {
+                Ok(()) | Err(Errno::ESRCH) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        child.start_kill()?;
+        self.timeline.record(
+            kw![kill],
+            &child.id().map_or_else(
+                || "process already exited".to_owned(),
+                |id| format!("PID {}", id),
+            ),
+        );
+        Ok(())
+    }
+
+    /// Kills what is left of the process `pid` after it has been
+    /// killed: the rest of its process group if `group` is on, and the
+    /// descendants in the `tree`, if any.
+    fn kill_leftovers(&self, pid: u32, group: bool, tree: Option<&ProcessTree>) -> Result<()> {
+        if group {
+            match killpg(
+                Pid::from_raw(pid.try_into()?),
+                nix::sys::signal::Signal::SIGKILL,
+            ) {
+                Ok(()) => self.logger.log_coded(
+                    &message::PM016,
+                    &format!("kill the leftovers in process group [{}]", pid),
+                ),
+                Err(Errno::ESRCH) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let swept = tree.map_or(0, ProcessTree::sweep);
+        if swept > 0 {
+            self.logger.log_coded(
+                &message::PM017,
+                &format!("kill {} leftover descendants of process [{}]", swept, pid),
+            );
+        }
+        Ok(())
+    }
+
+    /// Records the `action` the process agent has taken from the
+    /// action channel, for the diagnostics.
+    fn took(&self, action: Action) -> Action {
+        self.last_action.replace(Some(action.describe()));
+        action
+    }
+
+    /// Describes who holds the acknowledgements of the actions, so
+    /// that an action that goes unacknowledged can be diagnosed.
+    pub(crate) fn diagnose(&self) -> String {
+        let last_action = self
+            .last_action
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| "none".to_owned());
+        match (self.agent.borrow().as_ref(), self.pid.get()) {
+            (Some(agent), Some(pid)) if !agent.is_closed() => format!(
+                "the agent of PID {} holds the acknowledgement; last action taken: [{}]",
+                pid, last_action
+            ),
+            _ => format!(
+                "no agent holds the acknowledgement; last action taken: [{}]",
+                last_action
+            ),
+        }
+    }
+
+    /// Returns the process ID of the managed process, or `None` if
+    /// there is no running process.
+    pub(crate) fn pid(&self) -> Option<u32> {
+        self.pid.get()
+    }
+
+    /// Returns the incarnation of the managed process, which counts
+    /// the processes started so far.  The events about the process
+    /// carry it, so that an event about a process that has since been
+    /// replaced can be told apart.
+    pub(crate) fn incarnation(&self) -> u64 {
+        self.incarnation.get()
+    }
+
+    /// Returns whether the managed process is stopped, such as by
+    /// `SIGSTOP` or by a debugger attached to it.  Reads the state of
+    /// the process in /proc/<pid>/stat, as `tokio` reaps the process
+    /// without `WUNTRACED`.  The command name in parentheses may
+    /// contain spaces, so the state is the first field after the last
+    /// closing parenthesis.
+    pub(crate) fn is_stopped(&self) -> bool {
+        let stat = match self
+            .pid()
+            .and_then(|pid| std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok())
+        {
+            Some(stat) => stat,
+            None => return false,
+        };
+        let state = stat
+            .rfind(')')
+            .and_then(|index| stat[index + 1..].split_whitespace().next());
+        matches!(state, Some("T") | Some("t"))
+    }
+
+    /// Returns the exit status of the managed process the last time
+    /// it exited, or `None` if it has never exited while
+    /// `ProcessManager` was watching it.
+    pub(crate) fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status.get()
+    }
+
+    /// Returns the PID of the managed process the last time it ran,
+    /// even after it has exited.
+    pub(crate) fn last_pid(&self) -> Option<u32> {
+        self.last_pid.get()
+    }
+
+    /// Returns the time the managed process ran for until it last
+    /// exited.
+    pub(crate) fn uptime(&self) -> Duration {
+        self.uptime.get()
+    }
+
+    /// Returns the last lines the managed process wrote to its
+    /// standard error before it last exited, if its output is
+    /// captured.  Waits up to `wait` for the lines still in the pipe.
+    pub(crate) async fn stderr_tail(&self, wait: Duration) -> Vec<String> {
+        let stderr = self.stderr.borrow_mut().take();
+        match stderr {
+            Some(mut stderr) => stderr.lines(wait).await,
+            None => vec![],
         }
     }
 
     /// Reset the state of the `ProcessManager`.
     ///
-    /// This method resets the state of the `ProcessManager` to
-    /// `Ready` if it is currently in the `Killed` state.  If the
-    /// `ProcessManager` is not in the `Killed` state, an error is
-    /// returned.
+    /// This method moves the target state to `Ready` if it is
+    /// currently in the `Killed` state.  If the target is not in the
+    /// `Killed` state, an error is returned.
     ///
     /// # Returns
     ///
@@ -243,50 +944,162 @@ impl ProcessManager {
     /// `Killed` state.
     pub(crate) fn reset(&self) -> Result<()> {
         self.logger.log(LogLevel::Trace, "ProcessManager::reset()");
-        if self.is_killed() {
-            self.set_status(Status::Ready);
-            Ok(())
-        } else {
-            Err(illegal_state_error(&format!("{:?}", self.status())))
-        }
+        Ok(self.transition(TargetState::Ready)?)
     }
 
     /// Kills the managed process.
     ///
-    /// Sets the status of the process to `Killed` and sends the kill
-    /// message to the process action channel.  The process action
-    /// channel is useful for performing a specific action to the
-    /// process.  It does this in a synchronous way.  In operating
-    /// systems like Unix, killing a process is sending the process a
-    /// KILL signal.  But `kill_process` is a separate function
-    /// because it uses a platform independent function.
+    /// Moves the target state to `Killed` and sends the kill message
+    /// to the process action channel.  The process action channel is
+    /// useful for performing a specific action to the process.  It
+    /// does this in a synchronous way.  In operating systems like
+    /// Unix, killing a process is sending the process a KILL signal.
+    /// But `kill_process` is a separate function because it uses a
+    /// platform independent function.
+    /// `Heartbeat2` kills the process on a heartbeat timeout only, so
+    /// `kill_process` notifies the callbacks of the timeout.  With
+    /// KILL-TIMEOUT, the process gets `SIGTERM` and that long to exit
+    /// before the kill.
     ///
     /// # Returns
     ///
-    /// A `Result` indicating the success of the operation. If the
-    /// process is successfully killed, `Ok(())` is returned.
+    /// A `Result` containing an [`ActionHandle`] to await the exit of
+    /// the process with.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if there is no running process, if the
+    /// target state can't become `Killed` or if the action sending
+    /// fails.  The target state stays the same if there is no
+    /// running process or the transition is illegal.
+    pub(crate) fn kill_process(
+        &self,
+        incarnation: u64,
+    ) -> std::result::Result<ActionHandle, ErrorType> {
+        self.logger.log(
+            LogLevel::Trace,
+            &format!("ProcessManager::kill_process({})", incarnation),
+        );
+        let handle = self.kill(Outcome::TimeoutKill, incarnation)?;
+        self.notifier.notify(Event::Timeout);
+        Ok(handle)
+    }
+
+    /// Kills the managed process so that `Heartbeat2` starts it
+    /// again, at the request of an operator.
+    ///
+    /// Moves the target state to `Killed` as [`kill_process`] does,
+    /// but the process run ends in [`RunProcess::Restart`] instead of
+    /// [`RunProcess::Abort`], so that the restart doesn't count
+    /// against the retries.
+    ///
+    /// [`kill_process`]: ProcessManager::kill_process
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an [`ActionHandle`] to await the exit of
+    /// the process with.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if there is no running process, if the
+    /// target state can't become `Killed` or if the action sending
+    /// fails.
+    pub(crate) fn restart_process(&self) -> std::result::Result<ActionHandle, ErrorType> {
+        self.logger
+            .log(LogLevel::Trace, "ProcessManager::restart_process()");
+        self.kill(Outcome::RestartKill, self.incarnation())
+    }
+
+    /// Sends the kill action for the `outcome` to the `incarnation` of
+    /// the process, moving the target state to `Killed`.
+    fn kill(
+        &self,
+        outcome: Outcome,
+        incarnation: u64,
+    ) -> std::result::Result<ActionHandle, ErrorType> {
+        let (ack, handle) = oneshot::channel();
+        let action = Action::Kill(outcome, incarnation, ack);
+        let handle = ActionHandle::new(&action, handle);
+        self.send_action(Some(TargetState::Killed), action)?;
+        Ok(handle)
+    }
+
+    /// Stops the `incarnation` of the managed process so that
+    /// `Heartbeat2` starts it again, at the request of the target,
+    /// such as when it has leaked too much memory.
+    ///
+    /// Sends the process `SIGTERM` first, and `SIGKILL` only if it is
+    /// still running after [`restart_grace`](ProcessManager::restart_grace).
+    /// The process run ends in [`RunProcess::Restart`] as for
+    /// [`restart_process`](ProcessManager::restart_process), so that
+    /// the restart doesn't count against the retries.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an [`ActionHandle`] to await the exit of
+    /// the process with.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if there is no running process, if the
+    /// target state can't become `Killed` or if the action sending
+    /// fails.
+    pub(crate) fn request_restart(
+        &self,
+        incarnation: u64,
+    ) -> std::result::Result<ActionHandle, ErrorType> {
+        self.logger.log(
+            LogLevel::Trace,
+            &format!("ProcessManager::request_restart({})", incarnation),
+        );
+        self.kill(Outcome::RestartRequest, incarnation)
+    }
+
+    /// Returns the time a process that asked for a restart has to exit
+    /// after `SIGTERM`.
+    ///
+    /// # Configuration
+    ///
+    /// * KILL-TIMEOUT: Optional.  The default is 10.
+    pub(crate) fn restart_grace(&self) -> Result<Duration> {
+        Ok(Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::KILL_TIMEOUT, DEFAULT_RESTART_GRACE)?
+                .try_into()?,
+        ))
+    }
+
+    /// Kills the managed process without changing the target state.
+    ///
+    /// Escalates an earlier action the process didn't respond to,
+    /// such as a `SIGTERM` it ignored.  The target state has already
+    /// changed as a result of the earlier action.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an [`ActionHandle`] to await the exit of
+    /// the process with.
     ///
     /// # Errors
     ///
     /// An error is returned if there is no running process or if the
     /// action sending fails.
-    pub(crate) fn kill_process(&self) -> std::result::Result<(), ErrorType> {
+    pub(crate) fn force_kill(&self) -> std::result::Result<ActionHandle, ErrorType> {
         self.logger
-            .log(LogLevel::Trace, "ProcessManager::kill_process()");
-        self.set_status(Status::Killed);
-        self.agent
-            .borrow_mut()
-            .take()
-            .ok_or(ErrorType::NoRunningProcess)?
-            .send(Action::Kill)
-            .map_err(|_| ErrorType::NoRunningProcess)?;
-        Ok(())
+            .log(LogLevel::Trace, "ProcessManager::force_kill()");
+        let (ack, handle) = oneshot::channel();
+        let action = Action::Kill(Outcome::TimeoutKill, self.incarnation(), ack);
+        let handle = ActionHandle::new(&action, handle);
+        self.send_action(None, action)?;
+        Ok(handle)
     }
 
     /// Signals the managed process.
     ///
-    /// Sets the status of the process to `Terminated`.  Then sends
-    /// the `RaiseSignal` message to the process action channel.  The
+    /// Moves the target state to `Terminated`.  Then sends the
+    /// `RaiseSignal` message to the process action channel.  The
     /// process action channel is useful for performing a specific
     /// action to the process.  It does this in a synchronous way.  In
     /// operating systems like Unix, killing a process is sending the
@@ -300,81 +1113,346 @@ impl ProcessManager {
     ///
     /// # Returns
     ///
-    /// A `Result` indicating the success of the operation. If the
-    /// signal is successfully raised, `Ok(())` is returned.
+    /// A `Result` containing an [`ActionHandle`].  The handle
+    /// acknowledges the exit of the process, or the delivery of the
+    /// signal if `Heartbeat2` doesn't wait for the process to exit
+    /// after it.
     ///
     /// # Errors
     ///
-    /// An error is returned if there is no running process or if the
-    /// action sending fails.
-    pub(crate) fn raise_signal(&self, signal: Signal) -> std::result::Result<(), ErrorType> {
+    /// An error is returned if there is no running process, if the
+    /// target state can't become `Terminated` or if the action
+    /// sending fails.  The target state stays the same if there is
+    /// no running process or the transition is illegal.
+    pub(crate) fn raise_signal(
+        &self,
+        signal: Signal,
+    ) -> std::result::Result<ActionHandle, ErrorType> {
         self.logger.log(
             LogLevel::Trace,
             &format!("ProcessManager::raise_signal({:?})", signal),
         );
-        self.set_status(Status::Terminated);
-        self.agent
-            .borrow_mut()
-            .take()
-            .ok_or(ErrorType::NoRunningProcess)?
-            .send(Action::RaiseSignal(signal))
-            .map_err(|_| ErrorType::NoRunningProcess)?;
-        Ok(())
+        let (ack, handle) = oneshot::channel();
+        let action = Action::RaiseSignal(signal, self.incarnation(), ack);
+        let handle = ActionHandle::new(&action, handle);
+        self.send_action(Some(TargetState::Terminated), action)?;
+        Ok(handle)
     }
 
-    /// Check if the `ProcessManager` is in the `Killed` state.
+    /// Forwards a signal to the managed process.
+    ///
+    /// Unlike [`raise_signal`](#method.raise_signal), leaves the
+    /// target state as it is.  The managed process may react to the
+    /// signal in any way, and `ProcessManager` keeps watching it.
+    ///
+    /// If there is no running process, such as while the process is
+    /// restarting, queues the signal up to [`MAX_QUEUED_SIGNALS`].
+    /// The queued signals go to the process once it restarts.  The
+    /// signals beyond the limit, and those still queued when
+    /// `Heartbeat2` stops supervising, are dropped and counted in the
+    /// metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `signal` - The signal to be forwarded.
     ///
     /// # Returns
     ///
-    /// `true` if the `ProcessManager` is in the `Killed` state,
-    /// `false` otherwise.
-    pub(crate) fn is_killed(&self) -> bool {
-        matches!(self.status(), Status::Killed)
+    /// A `Result` containing an [`ActionHandle`] that acknowledges
+    /// the delivery or the queueing of the signal.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the action sending fails.
+    pub(crate) fn forward_signal(
+        &self,
+        signal: Signal,
+    ) -> std::result::Result<ActionHandle, ErrorType> {
+        self.logger.log(
+            LogLevel::Trace,
+            &format!("ProcessManager::forward_signal({:?})", signal),
+        );
+        let (ack, handle) = oneshot::channel();
+        let action = Action::RaiseSignal(signal, self.incarnation(), ack);
+        let handle = ActionHandle::new(&action, handle);
+        match self.send_action(None, action) {
+            Ok(()) => Ok(handle),
+            Err(ErrorType::NoRunningProcess) => {
+                self.queue_signal(signal);
+                let (ack, queued) = oneshot::channel();
+                let _ = ack.send(Ack::Queued);
+                Ok(ActionHandle {
+                    ack: queued,
+                    ..handle
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Records that the managed process has aborted.
+    ///
+    /// Moves the target state from `Running`, `Degraded` or
+    /// `Unresponsive` to `Killed`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an illegal transition error if the target is in any
+    /// other state.
+    pub(crate) fn abort(&self) -> std::result::Result<(), ErrorType> {
+        self.transition_from(
+            &[
+                TargetState::Running,
+                TargetState::Degraded,
+                TargetState::Unresponsive,
+            ],
+            TargetState::Killed,
+        )?;
+        self.notifier.notify(Event::Abort(self.exit_status()));
+        Ok(())
     }
 
-    /// Set the status of the `ProcessManager` to `Killed`.
-    pub(crate) fn set_killed(&self) {
-        self.set_status(Status::Killed);
+    /// Records that the managed process has completed.
+    ///
+    /// Moves the target state from `Running`, `Degraded` or
+    /// `Unresponsive` to `Terminated`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an illegal transition error if the target is in any
+    /// other state.
+    pub(crate) fn complete(&self) -> std::result::Result<(), ErrorType> {
+        self.transition_from(
+            &[
+                TargetState::Running,
+                TargetState::Degraded,
+                TargetState::Unresponsive,
+            ],
+            TargetState::Terminated,
+        )?;
+        self.drop_queued_signals();
+        Ok(())
     }
 
-    /// Check if the `ProcessManager` is in the `Terminated` state.
+    /// Records that `Heartbeat2` has given up on the managed process.
     ///
-    /// # Returns
+    /// Moves the target state from `Killed` or `Stuck` to
+    /// `Terminated`.
+    ///
+    /// # Errors
     ///
-    /// `true` if the `ProcessManager` is in the `Terminated` state,
-    /// `false` otherwise.
-    pub(crate) fn is_terminated(&self) -> bool {
-        matches!(self.status(), Status::Terminated)
+    /// Returns an illegal transition error if the target is in any
+    /// other state.
+    pub(crate) fn give_up(&self) -> std::result::Result<(), ErrorType> {
+        self.transition_from(
+            &[TargetState::Killed, TargetState::Stuck],
+            TargetState::Terminated,
+        )?;
+        self.drop_queued_signals();
+        Ok(())
     }
 
-    /// Set the status of the `ProcessManager` to `Terminated`.
-    pub(crate) fn set_terminated(&self) {
-        self.set_status(Status::Terminated);
+    /// Queues a `signal` to forward to the process once it restarts,
+    /// or drops it if the queue is full.
+    fn queue_signal(&self, signal: Signal) {
+        let mut queue = self.queued_signals.borrow_mut();
+        if queue.len() < MAX_QUEUED_SIGNALS {
+            self.logger.log_coded(
+                &message::PM009,
+                &format!("no running process; queue signal [{:?}]", signal),
+            );
+            queue.push_back(signal);
+        } else {
+            self.drop_signal(signal);
+        }
+    }
+
+    /// Sends the queued signals to the process that has just started
+    /// through its action channel.
+    fn replay_queued_signals(&self, agent: &mpsc::UnboundedSender<Action>) {
+        for signal in self.queued_signals.borrow_mut().drain(..) {
+            self.logger
+                .log_coded(&message::PM010, &format!("replay signal [{:?}]", signal));
+            // Nobody awaits the acknowledgement.
+            let (ack, _) = oneshot::channel();
+            let _ = agent.send(Action::RaiseSignal(signal, self.incarnation(), ack));
+        }
+    }
+
+    /// Drops the signals still queued, as there will be no process to
+    /// forward them to.
+    fn drop_queued_signals(&self) {
+        for signal in self.queued_signals.take() {
+            self.drop_signal(signal);
+        }
+    }
+
+    fn drop_signal(&self, signal: Signal) {
+        self.logger
+            .log_coded(&message::PM011, &format!("drop signal [{:?}]", signal));
+        self.metrics.add_dropped_signal(signal.into());
     }
 
     /// Raises an event indicating that the process has completed.
     async fn raise_process_event_complete(&self) -> Result<()> {
-        self.logger.log(LogLevel::Info, "normal process exit");
-        self.event_queue.send(EventType::Complete).await?;
+        self.logger
+            .log_coded(&message::PM012, "normal process exit");
+        self.event_queue
+            .send(EventType::Complete(self.incarnation()))
+            .await?;
         Ok(())
     }
 
+    /// Returns whether the `exit_status` counts as the completion of
+    /// the process rather than a crash, according to
+    /// RESTART-ON-EXIT-CODES and IGNORE-EXIT-CODES, or to
+    /// IGNORE-EXIT-SIGNALS for an exit by a signal.
+    fn completed(&self, exit_status: ExitStatus) -> Result<bool> {
+        let code = match exit_status.code() {
+            Some(code) => i64::from(code),
+            None => return self.completed_by_signal(exit_status),
+        };
+        let section = self.config.section(section::HEARTBEAT)?;
+        let completed = if section
+            .integer_list_or_empty(key::RESTART_ON_EXIT_CODES)?
+            .contains(&code)
+        {
+            false
+        } else if section
+            .integer_list_or_empty(key::IGNORE_EXIT_CODES)?
+            .contains(&code)
+        {
+            true
+        } else {
+            exit_status.success()
+        };
+        if completed != exit_status.success() {
+            self.logger.log_coded(
+                &message::PM018,
+                &format!(
+                    "exit code [{}] counts as {}",
+                    code,
+                    if completed { "completion" } else { "crash" }
+                ),
+            );
+        }
+        Ok(completed)
+    }
+
+    /// Logs the signal that terminated the process, and returns
+    /// whether the signal counts as the completion of the process
+    /// according to IGNORE-EXIT-SIGNALS.
+    fn completed_by_signal(&self, exit_status: ExitStatus) -> Result<bool> {
+        let signal = match exit_status.signal() {
+            Some(signal) => signal,
+            None => return Ok(false),
+        };
+        let name = signal_name(signal);
+        self.logger.log_coded(
+            &message::PM019,
+            &format!(
+                "process terminated by signal [{}]{}",
+                name,
+                if exit_status.core_dumped() {
+                    " (core dumped)"
+                } else {
+                    ""
+                }
+            ),
+        );
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::IGNORE_EXIT_SIGNALS) {
+            return Ok(false);
+        }
+        for ignored in section.string_list(key::IGNORE_EXIT_SIGNALS)? {
+            if parse_signal(&ignored, key::IGNORE_EXIT_SIGNALS)? as i32 == signal {
+                self.logger.log_coded(
+                    &message::PM018,
+                    &format!("signal [{}] counts as completion", name),
+                );
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     /// Raises an event indicating that the process has aborted.
     async fn raise_process_event_abort(&self) -> Result<()> {
-        self.logger.log(LogLevel::Error, "abnormal process exit");
-        self.event_queue.send(EventType::Aborted).await?;
+        self.logger
+            .log_coded(&message::PM013, "abnormal process exit");
+        self.event_queue
+            .send(EventType::Aborted(self.incarnation()))
+            .await?;
         Ok(())
     }
 
-    fn set_status(&self, status: Status) {
-        self.status.set(status);
+    /// Moves the target state to `next`, logging the attempt.
+    fn transition(&self, next: TargetState) -> std::result::Result<(), ErrorType> {
+        self.logger.log(
+            LogLevel::Trace,
+            &format!(
+                "ProcessManager: transition {} -> {}",
+                self.state.get(),
+                next
+            ),
+        );
+        self.state.transition(next)
     }
 
-    fn status(&self) -> Status {
-        self.status.get()
+    /// Moves the target state to `next`, but only if the current
+    /// state is one of `from`.  Narrows down the transitions the
+    /// state machine allows to those that make sense for the caller.
+    fn transition_from(
+        &self,
+        from: &[TargetState],
+        next: TargetState,
+    ) -> std::result::Result<(), ErrorType> {
+        let current = self.state.get();
+        if from.contains(&current) {
+            self.transition(next)
+        } else {
+            self.logger.log_coded(
+                &message::PM014,
+                &format!(
+                    "ProcessManager: rejected transition {} -> {}",
+                    current, next
+                ),
+            );
+            Err(ErrorType::IllegalTransition(
+                current.to_string(),
+                next.to_string(),
+            ))
+        }
+    }
+
+    /// Moves the target state to `next`, if any, and then sends the
+    /// `action` to the process.  Validates both before changing
+    /// anything, so that a failure leaves the target state as it was.
+    /// An action meant for an earlier incarnation of the process is
+    /// refused.
+    fn send_action(
+        &self,
+        next: Option<TargetState>,
+        action: Action,
+    ) -> std::result::Result<(), ErrorType> {
+        let agent = self.agent.borrow();
+        let agent = agent
+            .as_ref()
+            .filter(|agent| !agent.is_closed())
+            .ok_or(ErrorType::NoRunningProcess)?;
+        if action.incarnation() != self.incarnation() {
+            return Err(ErrorType::StaleIncarnation(
+                action.incarnation(),
+                self.incarnation(),
+            ));
+        }
+        if let Some(next) = next {
+            self.transition(next)?;
+        }
+        agent.send(action).map_err(|_| ErrorType::NoRunningProcess)
     }
 
     fn is_ready(&self) -> bool {
-        matches!(self.status(), Status::Ready)
+        matches!(self.state.get(), TargetState::Ready)
     }
 }
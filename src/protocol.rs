@@ -0,0 +1,621 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The wire format of the heartbeat and the naming protocols.
+//!
+//! A frame of a message is either a string or a keyword.  A keyword
+//! travels as its upper-case name, such as `HEARTBEAT`, or with a
+//! leading colon, such as `:HEARTBEAT`.  The protocols consist of the
+//! following messages:
+//!
+//! * [`heartbeat`]: `Heartbeat2` asks the target whether it is alive.
+//!   The target answers with [`not_ready`] if it is alive, but not
+//!   ready to serve, with [`degraded`] if it serves, but impaired,
+//!   with [`busy`] if it may be slow to answer the next heartbeat, and
+//!   with [`restart_me`] if it wants to be restarted.  Any other
+//!   answer, such as [`ok`], tells that the target is ready.  The
+//!   answer may carry the telemetry of the target in the frames after
+//!   the first, such as `OK QUEUE-DEPTH 12 VERSION 1.2.3`; see
+//!   [`ok_with`] and [`telemetry`].  A heartbeat may carry a sequence
+//!   number, such as `HEARTBEAT SEQ 42`; see [`numbered_heartbeat`].
+//!   The target echoes it in its answer, such as `OK SEQ 42`, and
+//!   `Heartbeat2` discards an answer that echoes another number; see
+//!   [`echo`].  A heartbeat to a target that opts in may also carry
+//!   control keywords for the target, such as `HEARTBEAT
+//!   SET-LOG-LEVEL DEBUG`; see [`with_control`] and [`controls`].
+//!   A heartbeat may also ask the target for a lease, so that only
+//!   one supervisor owns the target at a time, such as `HEARTBEAT
+//!   LEASE web-3/app LEASE-TERM 30000`.  The target answers a
+//!   supervisor other than the owner with [`rejected`] until the
+//!   lease expires; see [`with_lease`] and [`Lease`].
+//! * [`get`]: `Heartbeat2` asks the naming service for the endpoint of
+//!   a service.  The naming service answers with [`endpoint`] or
+//!   [`missing_endpoint`].
+//!
+//! # Examples
+//!
+//! Answer the heartbeats in a target application with `tmq`:
+//!
+//! ```rust,ignore
+//! use heartbeat2::protocol::{self, Multipart};
+//!
+//! let mut socket = tmq::reply(&context).bind(endpoint)?;
+//! loop {
+//!     let (request, sender) = socket.recv().await?;
+//!     let request = Multipart::try_from(request)?;
+//!     if protocol::is_heartbeat(&request) {
+//!         socket = sender.send(protocol::ok().into()).await?;
+//!     }
+//! }
+//! ```
+
+use sexp::Sexp;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+/// The name of the sequence number in a heartbeat and its answer.
+static SEQ: &str = "SEQ";
+
+/// The name of the supervisor asking for a lease in a heartbeat.
+static LEASE: &str = "LEASE";
+
+/// The name of the term of a lease in milliseconds in a heartbeat.
+static LEASE_TERM: &str = "LEASE-TERM";
+
+/// The name of the owner of the lease in a rejection.
+static OWNER: &str = "OWNER";
+
+/// Represents an error decoding a message.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// A frame isn't valid UTF-8.
+    StringEncoding,
+    /// A value isn't of the expected type.
+    Type(&'static str),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::StringEncoding => write!(f, "invalid string encoding"),
+            ProtocolError::Type(expected) => write!(f, "type error (expected: {})", expected),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+/// Represents a keyword in Lisp code.
+///
+/// The `Keyword` struct represents a keyword in a piece of Lisp code.
+/// Keywords begin with a colon and identify specific symbols or
+/// values.  In this project, the primary use of keywords is as
+/// configuration keys and as the names of messages.
+///
+/// # Examples
+///
+/// Creating a new keyword:
+///
+/// ```rust
+/// use heartbeat2::protocol::Keyword;
+///
+/// let my_keyword = Keyword::new("my_keyword");
+/// assert_eq!(my_keyword.name(), "my_keyword");
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Keyword(String);
+
+impl Keyword {
+    /// Creates a new `Keyword` with the specified name.
+    ///
+    /// The `new` function creates a new `Keyword` object with the
+    /// given `name`.  The name of a keyword read from Lisp code is in
+    /// upper case.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use heartbeat2::protocol::Keyword;
+    ///
+    /// let my_keyword = Keyword::new("my_keyword");
+    /// assert_eq!(my_keyword.name(), "my_keyword");
+    /// ```
+    pub fn new(name: &str) -> Self {
+        Keyword(name.to_owned())
+    }
+
+    /// Converts an S-expression into a `Keyword`.
+    ///
+    /// The `from_sexp` function converts the provided `sexp` into a
+    /// `Keyword` object. It expects the `sexp` to be a string atom
+    /// that starts with a colon. If it is, `from_sexp` creates a
+    /// `Keyword` containing the upper case of the string atom without
+    /// the colon. Otherwise, it returns a type error.
+    pub fn from_sexp(sexp: Sexp) -> Result<Self, Box<dyn Error>> {
+        match sexp {
+            Sexp::Atom(sexp::Atom::S(s)) => match s.strip_prefix(':') {
+                Some(name) => Ok(Keyword(name.to_uppercase())),
+                None => Err(ProtocolError::Type("keyword").into()),
+            },
+            _ => Err(ProtocolError::Type("string").into()),
+        }
+    }
+
+    /// Returns the name of the keyword.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Keyword {
+    fn from(name: String) -> Self {
+        Keyword(name)
+    }
+}
+
+/// A frame that isn't valid UTF-8 equals no keyword.
+#[cfg(feature = "zmq")]
+impl PartialEq<tmq::Message> for Keyword {
+    fn eq(&self, message: &tmq::Message) -> bool {
+        message.as_str() == Some(self.name())
+    }
+}
+
+/// A frame that isn't valid UTF-8 equals no keyword.
+#[cfg(feature = "zmq")]
+impl PartialEq<Keyword> for tmq::Message {
+    fn eq(&self, message: &Keyword) -> bool {
+        self.as_str() == Some(message.name())
+    }
+}
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":{}", self.0)
+    }
+}
+
+/// Defines a ZMQ message.
+///
+/// A message can be either a string or a keyword.  A keyword is an
+/// upper case string that begins with a colon in the configuration
+/// file.
+#[derive(Clone, Debug)]
+pub enum Message {
+    /// A string message.
+    String(String),
+    /// A keyword message.
+    Keyword(Keyword),
+}
+
+impl Message {
+    /// Returns the string representation of the message.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Message::String(s) => s,
+            Message::Keyword(kw) => kw.name(),
+        }
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl TryFrom<tmq::Message> for Message {
+    type Error = Box<dyn Error>;
+
+    fn try_from(source: tmq::Message) -> Result<Self, Self::Error> {
+        source
+            .as_str()
+            .ok_or_else(|| ProtocolError::StringEncoding.into())
+            .map(|s| {
+                if let Some(name) = s.strip_prefix(':') {
+                    Message::Keyword(Keyword::new(name))
+                } else {
+                    Message::String(s.to_owned())
+                }
+            })
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl From<&Message> for tmq::Message {
+    /// Encodes a keyword as its name.
+    fn from(source: &Message) -> Self {
+        source.as_str().into()
+    }
+}
+
+impl PartialEq<Keyword> for Message {
+    fn eq(&self, rhs: &Keyword) -> bool {
+        match self {
+            Message::String(s) => s == rhs.name(),
+            Message::Keyword(kw) => kw == rhs,
+        }
+    }
+}
+
+impl PartialEq<Message> for Keyword {
+    fn eq(&self, rhs: &Message) -> bool {
+        rhs == self
+    }
+}
+
+/// Represents a ZMQ multipart message.
+///
+/// Contains a sequence of [`Message`]s.  ZMQ either sends the entire
+/// multipart message or not at all.
+#[derive(Clone, Debug)]
+pub struct Multipart(Vec<Message>);
+
+#[cfg(feature = "zmq")]
+impl TryFrom<tmq::Multipart> for Multipart {
+    type Error = Box<dyn Error>;
+
+    fn try_from(source: tmq::Multipart) -> Result<Self, Self::Error> {
+        let mut v = vec![];
+        for msg in source {
+            v.push(msg.try_into()?);
+        }
+        Ok(Multipart(v))
+    }
+}
+
+impl From<Vec<Message>> for Multipart {
+    fn from(messages: Vec<Message>) -> Self {
+        Multipart(messages)
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl From<Multipart> for tmq::Multipart {
+    fn from(source: Multipart) -> Self {
+        source
+            .0
+            .iter()
+            .map(tmq::Message::from)
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl Deref for Multipart {
+    type Target = [Message];
+
+    fn deref(&self) -> &[Message] {
+        &self.0
+    }
+}
+
+/// Builds a message of the keywords.
+fn keywords(names: &[&str]) -> Multipart {
+    Multipart(
+        names
+            .iter()
+            .map(|name| Message::Keyword(Keyword::new(name)))
+            .collect(),
+    )
+}
+
+/// Builds the heartbeat `Heartbeat2` sends the target: `HEARTBEAT`.
+pub fn heartbeat() -> Multipart {
+    keywords(&["HEARTBEAT"])
+}
+
+/// Builds the heartbeat `Heartbeat2` sends the target with the
+/// `sequence` number: `HEARTBEAT SEQ 42`.
+pub fn numbered_heartbeat(sequence: u64) -> Multipart {
+    let mut request = heartbeat();
+    request.0.push(Message::Keyword(Keyword::new(SEQ)));
+    request.0.push(Message::String(sequence.to_string()));
+    request
+}
+
+/// Returns whether the `request` is a heartbeat, with or without a
+/// sequence number and control keywords.
+pub fn is_heartbeat(request: &Multipart) -> bool {
+    request.len() % 2 == 1
+        && request[0] == Keyword::new("HEARTBEAT")
+        && request[1..].chunks_exact(2).all(|pair| {
+            !pair[0].as_str().eq_ignore_ascii_case(SEQ) || pair[1].as_str().parse::<u64>().is_ok()
+        })
+}
+
+/// Adds a control keyword `name` with its `value` to the heartbeat
+/// `request`, such as `HEARTBEAT SET-LOG-LEVEL DEBUG`.  Only a target
+/// that opts in understands a heartbeat with a control keyword.
+pub fn with_control(mut request: Multipart, name: &Keyword, value: &str) -> Multipart {
+    request.0.push(Message::Keyword(name.clone()));
+    request.0.push(Message::String(value.to_owned()));
+    request
+}
+
+/// Returns the control keywords and their values the heartbeat
+/// `request` carries, such as `(SET-LOG-LEVEL, "DEBUG")`.
+pub fn controls(request: &Multipart) -> Vec<(Keyword, String)> {
+    pairs(request)
+        .into_iter()
+        .filter(|(name, _)| name.name() != LEASE && name.name() != LEASE_TERM)
+        .collect()
+}
+
+/// Adds a request for a lease on the target to the heartbeat
+/// `request`, such as `HEARTBEAT LEASE web-3/app LEASE-TERM 30000`.
+/// The supervisor `owner` asks to own the target for the `term` in
+/// milliseconds, and renews the lease with every heartbeat.
+pub fn with_lease(mut request: Multipart, owner: &str, term: u64) -> Multipart {
+    request.0.push(Message::Keyword(Keyword::new(LEASE)));
+    request.0.push(Message::String(owner.to_owned()));
+    request.0.push(Message::Keyword(Keyword::new(LEASE_TERM)));
+    request.0.push(Message::String(term.to_string()));
+    request
+}
+
+/// Returns the supervisor asking for a lease in the heartbeat
+/// `request` and the term of the lease in milliseconds, if any.
+pub fn lease(request: &Multipart) -> Option<(String, u64)> {
+    let pairs = pairs(request);
+    let value = |key: &str| {
+        pairs
+            .iter()
+            .find(|(name, _)| name.name() == key)
+            .map(|(_, value)| value.clone())
+    };
+    Some((value(LEASE)?, value(LEASE_TERM)?.parse().ok()?))
+}
+
+/// Builds an answer of the target that it is leased to the supervisor
+/// `owner`, and so rejects the heartbeat: `REJECTED OWNER web-3/app`.
+pub fn rejected(owner: &str) -> Multipart {
+    let mut reply = keywords(&["REJECTED"]);
+    reply.0.push(Message::Keyword(Keyword::new(OWNER)));
+    reply.0.push(Message::String(owner.to_owned()));
+    reply
+}
+
+/// Returns whether the `reply` to a heartbeat rejects the lease.
+pub fn is_rejected(reply: &Multipart) -> bool {
+    !reply.is_empty() && reply[0] == Keyword::new("REJECTED")
+}
+
+/// Returns the owner of the lease a [`rejected`] `reply` names, if
+/// any.
+pub fn owner(reply: &Multipart) -> Option<String> {
+    pairs(reply)
+        .into_iter()
+        .find(|(name, _)| name.name() == OWNER)
+        .map(|(_, value)| value)
+}
+
+/// Keeps the lease on a target, for a target that answers the
+/// heartbeats on its own.
+///
+/// The first supervisor that asks for the lease owns the target.  The
+/// target rejects any other supervisor until the owner fails to renew
+/// the lease within its term, such as when the owner has died, so
+/// that two supervisors never both act on the target.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use heartbeat2::protocol::{self, Lease};
+///
+/// let mut lease = Lease::new();
+/// let reply = match lease.check(&request) {
+///     Some(owner) => protocol::rejected(&owner),
+///     None => protocol::ok(),
+/// };
+/// ```
+#[derive(Debug, Default)]
+pub struct Lease {
+    holder: Option<(String, Instant, Duration)>,
+}
+
+impl Lease {
+    /// Creates a new `Lease` that nobody owns.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Grants or renews the lease the heartbeat `request` asks for,
+    /// if any, and returns `None`.  Returns the owner of the lease
+    /// instead if another supervisor owns it.
+    pub fn check(&mut self, request: &Multipart) -> Option<String> {
+        let (owner, term) = lease(request)?;
+        let now = Instant::now();
+        match &self.holder {
+            Some((holder, renewed, term))
+                if *holder != owner && now.duration_since(*renewed) < *term =>
+            {
+                Some(holder.clone())
+            }
+            _ => {
+                self.holder = Some((owner, now, Duration::from_millis(term)));
+                None
+            }
+        }
+    }
+}
+
+/// Returns the sequence number the `message` carries, such as a
+/// [`numbered_heartbeat`] or the answer to one, if any.
+pub fn sequence(message: &Multipart) -> Option<u64> {
+    message
+        .get(1..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .find(|pair| pair[0].as_str().eq_ignore_ascii_case(SEQ))
+        .and_then(|pair| pair[1].as_str().parse().ok())
+}
+
+/// Adds the sequence number of the `request`, if any, to the `reply`,
+/// such as `OK SEQ 42` for `HEARTBEAT SEQ 42`.  A target answers a
+/// [`numbered_heartbeat`] with the echo so that `Heartbeat2` accepts
+/// the answer.
+pub fn echo(request: &Multipart, mut reply: Multipart) -> Multipart {
+    if let Some(sequence) = sequence(request) {
+        reply.0.push(Message::Keyword(Keyword::new(SEQ)));
+        reply.0.push(Message::String(sequence.to_string()));
+    }
+    reply
+}
+
+/// Builds an answer of the target that it is alive: `OK`.
+pub fn ok() -> Multipart {
+    keywords(&["OK"])
+}
+
+/// Builds an answer of the target that it is alive, but not ready
+/// to serve: `NOT-READY`.
+pub fn not_ready() -> Multipart {
+    keywords(&["NOT-READY"])
+}
+
+/// Returns whether the `reply` to a heartbeat tells that the target
+/// isn't ready.
+pub fn is_not_ready(reply: &Multipart) -> bool {
+    !reply.is_empty() && reply[0] == Keyword::new("NOT-READY")
+}
+
+/// Builds an answer of the target that it is alive and serving, but
+/// impaired, such as by a lost replica of its database: `DEGRADED`.
+pub fn degraded() -> Multipart {
+    keywords(&["DEGRADED"])
+}
+
+/// Returns whether the `reply` to a heartbeat tells that the target
+/// is degraded.
+pub fn is_degraded(reply: &Multipart) -> bool {
+    !reply.is_empty() && reply[0] == Keyword::new("DEGRADED")
+}
+
+/// Builds an answer of the target that it is alive, but too busy to
+/// answer the next heartbeat in time, such as during a compaction:
+/// `BUSY`.
+pub fn busy() -> Multipart {
+    keywords(&["BUSY"])
+}
+
+/// Returns whether the `reply` to a heartbeat tells that the target
+/// is busy.
+pub fn is_busy(reply: &Multipart) -> bool {
+    !reply.is_empty() && reply[0] == Keyword::new("BUSY")
+}
+
+/// Builds an answer of the target that it is alive, but wants to be
+/// restarted, such as after it has leaked too much memory:
+/// `RESTART-ME`.
+pub fn restart_me() -> Multipart {
+    keywords(&["RESTART-ME"])
+}
+
+/// Returns whether the `reply` to a heartbeat asks for a restart of
+/// the target.
+pub fn is_restart_me(reply: &Multipart) -> bool {
+    !reply.is_empty() && reply[0] == Keyword::new("RESTART-ME")
+}
+
+/// Builds an answer of the target that it is alive, along with the
+/// `telemetry` it reports, such as the depth of its queue or its
+/// version: `OK QUEUE-DEPTH 12 VERSION 1.2.3`.
+pub fn ok_with(telemetry: &[(Keyword, String)]) -> Multipart {
+    let mut reply = ok();
+    for (name, value) in telemetry {
+        reply.0.push(Message::Keyword(name.clone()));
+        reply.0.push(Message::String(value.clone()));
+    }
+    reply
+}
+
+/// Returns the telemetry the target attached to the `reply` to a
+/// heartbeat.  The frames after the first go in pairs of a name and a
+/// value.  A name without a value is ignored, and so is the echo of
+/// the sequence number.
+pub fn telemetry(reply: &Multipart) -> Vec<(Keyword, String)> {
+    pairs(reply)
+}
+
+/// Returns the pairs of a name and a value in the frames of the
+/// `message` after the first, but the sequence number.
+fn pairs(message: &Multipart) -> Vec<(Keyword, String)> {
+    message
+        .get(1..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .filter(|pair| !pair[0].as_str().eq_ignore_ascii_case(SEQ))
+        .map(|pair| {
+            (
+                Keyword::new(&pair[0].as_str().to_uppercase()),
+                pair[1].as_str().to_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Builds a request to the naming service for the endpoint of the
+/// service `id`: `GET ID`.
+pub fn get(id: &Keyword) -> Multipart {
+    Multipart(vec![
+        Message::Keyword(Keyword::new("GET")),
+        Message::Keyword(id.clone()),
+    ])
+}
+
+/// Builds a request to the naming service to register the `address`
+/// of the service `id`: `PUT ID ADDRESS`.  The naming service answers
+/// with [`ok`].
+pub fn put(id: &Keyword, address: &str) -> Multipart {
+    Multipart(vec![
+        Message::Keyword(Keyword::new("PUT")),
+        Message::Keyword(id.clone()),
+        Message::String(address.to_owned()),
+    ])
+}
+
+/// Builds a request to the naming service to forget the service `id`:
+/// `DELETE ID`.  The naming service answers with [`ok`].
+pub fn delete(id: &Keyword) -> Multipart {
+    Multipart(vec![
+        Message::Keyword(Keyword::new("DELETE")),
+        Message::Keyword(id.clone()),
+    ])
+}
+
+/// Builds an answer of the naming service with the `address` of a
+/// service: `ENDPOINT ADDRESS`.
+pub fn endpoint(address: &str) -> Multipart {
+    Multipart(vec![
+        Message::Keyword(Keyword::new("ENDPOINT")),
+        Message::String(address.to_owned()),
+    ])
+}
+
+/// Builds a request to the logging service to record the `line`:
+/// `LOG LINE`.  The logging service answers with [`ok`].
+pub fn log(line: &str) -> Multipart {
+    Multipart(vec![
+        Message::Keyword(Keyword::new("LOG")),
+        Message::String(line.to_owned()),
+    ])
+}
+
+/// Builds an answer of the naming service that it doesn't know the
+/// service: `MISSING ENDPOINT`.
+pub fn missing_endpoint() -> Multipart {
+    keywords(&["MISSING", "ENDPOINT"])
+}
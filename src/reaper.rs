@@ -0,0 +1,189 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::logger::{message, LogLevel, Logger};
+use crate::process::ProcessManager;
+use crate::result::Result;
+use crate::signal;
+use futures::stream::StreamExt;
+use nix::libc::SIGCHLD;
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::Pid;
+use std::collections::BTreeSet;
+use std::fs;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+/// The children `Heartbeat2` spawned itself and waits for, other
+/// than the managed process and its spare.
+static OWN_CHILDREN: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Keeps [`Reaper`] away from a child `Heartbeat2` spawned itself,
+/// such as a probe, a hook or `systemctl`, until dropped.
+///
+/// The code that spawns the child waits for it, and needs its exit
+/// status.  Were `Reaper` to wait for the child first, the status
+/// would be lost, and a failed probe could pass for a healthy one.
+/// Create an `OwnChild` right after the spawn, before anything
+/// awaits, and keep it until the wait is over.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut child = Command::new("true").spawn()?;
+/// let _own = OwnChild::new(child.id());
+/// let status = child.wait().await?;
+/// ```
+pub(crate) struct OwnChild(Option<u32>);
+
+impl OwnChild {
+    /// Registers the child with the process ID `pid`, if any.
+    pub(crate) fn new(pid: Option<u32>) -> Self {
+        if let Some(pid) = pid {
+            own_children().insert(pid);
+        }
+        OwnChild(pid)
+    }
+}
+
+impl Drop for OwnChild {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            own_children().remove(&pid);
+        }
+    }
+}
+
+/// Returns the set of the children `Heartbeat2` spawned itself.  A
+/// thread that panicked with the lock held leaves the set intact.
+fn own_children() -> std::sync::MutexGuard<'static, BTreeSet<u32>> {
+    OWN_CHILDREN
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Reaps zombie processes re-parented to `Heartbeat2`.
+///
+/// When `Heartbeat2` runs as PID 1 in a container, the kernel
+/// re-parents orphaned descendants of the managed process to it.
+/// They turn into zombies when they exit unless `Heartbeat2` waits
+/// for them.  `Reaper` waits for them on every `SIGCHLD`.
+///
+/// `Reaper` leaves the managed process alone.
+/// [`ProcessManager`] waits for it, and needs its exit status.  So do
+/// the probes, hooks and other children `Heartbeat2` spawns itself,
+/// which [`OwnChild`] marks.  `Reaper` therefore finds zombie
+/// children in /proc rather than waiting for any child, which makes
+/// it specific to Linux.  It
+/// needs the `signals` feature to learn of `SIGCHLD`.
+///
+/// # Configuration
+///
+/// * REAP-ZOMBIES: Optional.  Enables `Reaper` when `t`, and
+///   disables it when `nil`.  `Reaper` is enabled by default only
+///   when `Heartbeat2` runs as PID 1 or in the init mode.
+pub(crate) struct Reaper {
+    process_manager: Rc<ProcessManager>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl Reaper {
+    /// Creates a new `Reaper`.
+    ///
+    /// # Arguments
+    ///
+    /// * `process_manager` - The shared `ProcessManager` instance.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(
+        process_manager: Rc<ProcessManager>,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
+    ) -> Self {
+        Reaper {
+            process_manager,
+            config,
+            logger,
+        }
+    }
+
+    /// Returns whether the `Reaper` should run.
+    pub(crate) fn is_enabled(&self) -> Result<bool> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        section.boolean_or(
+            key::REAP_ZOMBIES,
+            section.init()? || std::process::id() == 1,
+        )
+    }
+
+    /// Runs the reaping loop, reaping zombie children on every
+    /// `SIGCHLD`.  Runs until `Heartbeat2` exits.
+    pub(crate) async fn run(&self) -> Result<()> {
+        self.logger
+            .log_coded(&message::RP001, "start zombie reaper");
+        let mut signals = signal::register(&[SIGCHLD])?;
+        while signals.next().await.is_some() {
+            if let Err(err) = self.reap() {
+                self.logger
+                    .log_coded(&message::RP002, &format!("unable to reap zombies: {}", err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for every child in the zombie state except the managed
+    /// process, its spare and the children `Heartbeat2` spawned
+    /// itself.
+    fn reap(&self) -> Result<()> {
+        let me = std::process::id();
+        let managed = [self.process_manager.pid(), self.process_manager.spare_pid()];
+        for entry in fs::read_dir("/proc")? {
+            let pid = match entry?.file_name().to_string_lossy().parse::<u32>() {
+                Ok(pid) if !managed.contains(&Some(pid)) && !own_children().contains(&pid) => pid,
+                _ => continue,
+            };
+            // The process may exit and disappear at any time.
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(stat) => stat,
+                Err(_) => continue,
+            };
+            if Self::is_zombie_child_of(&stat, me) {
+                waitpid(Pid::from_raw(pid.try_into()?), Some(WaitPidFlag::WNOHANG))?;
+                self.logger
+                    .log(LogLevel::Debug, &format!("reaped orphan process [{}]", pid));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the content of /proc/<pid>/stat, and returns whether it
+    /// describes a zombie whose parent is `parent`.  The command name
+    /// in parentheses may contain spaces, so the fields are counted
+    /// from the last closing parenthesis.
+    fn is_zombie_child_of(stat: &str, parent: u32) -> bool {
+        let mut fields = match stat.rfind(')') {
+            Some(index) => stat[index + 1..].split_whitespace(),
+            None => return false,
+        };
+        let state = fields.next();
+        let ppid = fields.next().and_then(|ppid| ppid.parse::<u32>().ok());
+        state == Some("Z") && ppid == Some(parent)
+    }
+}
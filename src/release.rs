@@ -0,0 +1,129 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::logger::{message, LogLevel, Logger};
+use crate::result::Result;
+use std::io::ErrorKind;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use tokio::time::{sleep, Duration, Instant};
+
+/// The time between two checks of the endpoint.
+static POLL_INTERVAL_MILLIS: u64 = 100;
+
+/// Waits for the endpoint of the managed process to become free.
+///
+/// A process that crashes often leaves its socket behind for a
+/// while, such as a TCP port in `TIME_WAIT` or an IPC path still
+/// held by an orphaned child.  The restarted process fails to bind
+/// it, and crashes again, wasting a retry.  `PortRelease` polls the
+/// endpoint before the restart until nothing holds it, or until the
+/// timeout elapses.  It restarts the process in either case.
+///
+/// A TCP endpoint is free if `PortRelease` can bind it with
+/// `SO_REUSEADDR`, as ZeroMQ does.  An IPC endpoint is free if
+/// nothing accepts connections on its path.
+///
+/// # Configuration
+///
+/// * PORT-RELEASE-TIMEOUT: Optional.  The maximum time in seconds to
+///   wait for the endpoint.  `PortRelease` doesn't wait without it.
+/// * RELEASE-ENDPOINT: Optional.  The endpoint the managed process
+///   binds, such as `"tcp://*:5555"` or `"ipc:///tmp/app"`.  The
+///   default is all the endpoints in TARGET-ENDPOINT.
+pub(crate) struct PortRelease {
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl PortRelease {
+    /// Creates a new `PortRelease` instance.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        PortRelease { config, logger }
+    }
+
+    /// Tells whether PORT-RELEASE-TIMEOUT is configured.
+    pub(crate) fn is_enabled(&self) -> Result<bool> {
+        Ok(self
+            .config
+            .section(section::HEARTBEAT)?
+            .has_key(key::PORT_RELEASE_TIMEOUT))
+    }
+
+    /// Waits until the endpoints are free or PORT-RELEASE-TIMEOUT
+    /// elapses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, or if the
+    /// endpoint isn't a TCP or an IPC endpoint.
+    pub(crate) async fn wait(&self) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let timeout = Duration::from_secs(section.integer(key::PORT_RELEASE_TIMEOUT)?.try_into()?);
+        let endpoints = if section.has_key(key::RELEASE_ENDPOINT) {
+            vec![section.string(key::RELEASE_ENDPOINT)?.to_owned()]
+        } else {
+            section.target_endpoints()?
+        };
+
+        let deadline = Instant::now() + timeout;
+        for endpoint in &endpoints {
+            while !is_free(endpoint)? {
+                if Instant::now() >= deadline {
+                    self.logger.log_coded(
+                        &message::PR001,
+                        &format!("{} is still in use after {}s", endpoint, timeout.as_secs()),
+                    );
+                    return Ok(());
+                }
+                sleep(Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+            }
+            self.logger
+                .log(LogLevel::Debug, &format!("{} is free", endpoint));
+        }
+        Ok(())
+    }
+}
+
+/// Tells whether nothing holds the endpoint.
+fn is_free(endpoint: &str) -> Result<bool> {
+    if let Some(address) = endpoint.strip_prefix("tcp://") {
+        let address = match address.strip_prefix("*:") {
+            Some(port) => format!("0.0.0.0:{}", port),
+            None => address.to_owned(),
+        };
+        for address in address.to_socket_addrs()? {
+            match TcpListener::bind(address) {
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::AddrInUse => return Ok(false),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(true)
+    } else if let Some(path) = endpoint.strip_prefix("ipc://") {
+        Ok(UnixStream::connect(path).is_err())
+    } else {
+        Err(config_format_error(&format!(
+            "unsupported endpoint to release: {}",
+            endpoint
+        )))
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::codec;
+use crate::config::{key, section, Config};
+use crate::directory::Directories;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::message::{self, Message};
+use crate::logger::Logger;
+use crate::result::Result;
+use crate::signal::signal_name;
+use chrono::Local;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// A snapshot of what a hung process was doing before `Heartbeat2`
+/// killed it.
+///
+/// Killing a hung process destroys the evidence of what it was
+/// blocked on.  `CrashReport` reads the following out of /proc before
+/// the kill, for the process and each of its threads:
+///
+/// * `status`: The state, the memory usage and the signal masks.
+/// * `wchan`: The kernel function the thread sleeps in.
+/// * `stack`: The kernel stack of the thread.  Reading it takes
+///   `CAP_SYS_ADMIN`, so the report notes it as unavailable otherwise.
+/// * `fd`: The files, sockets and pipes the process has open.
+///
+/// # Configuration
+///
+/// * CRASH-REPORT-DIRECTORY: Optional.  The directory to write the
+///   reports to, as `hang-<pid>-<time>.txt`.  The default is
+///   LOG-DIRECTORY.  Without either, only a summary of the report goes
+///   to the log, such as `hang report of process [1234]: state S
+///   (sleeping), wchan futex_wait_queue, 3 thread(s)`.
+pub(crate) struct CrashReport {
+    pid: u32,
+    text: String,
+    summary: String,
+}
+
+impl CrashReport {
+    /// Takes a snapshot of the process `pid` and its threads.
+    pub(crate) fn capture(pid: u32) -> Self {
+        let proc = PathBuf::from(format!("/proc/{}", pid));
+        let mut text = format!("hang report of process [{}] at {}\n", pid, Local::now());
+        section(&mut text, "status", read(&proc.join("status")));
+        section(&mut text, "wchan", read(&proc.join("wchan")));
+        section(&mut text, "stack", read(&proc.join("stack")));
+        section(&mut text, "fd", fds(&proc.join("fd")));
+        let mut threads = fs::read_dir(proc.join("task"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                    .filter(|tid| *tid != pid)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        threads.sort_unstable();
+        let status = read(&proc.join("status"));
+        let summary = format!(
+            "hang report of process [{}]: state {}, wchan {}, {} thread(s)",
+            pid,
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("State:"))
+                .map_or("unknown", str::trim),
+            read(&proc.join("wchan")).trim(),
+            threads.len() + 1
+        );
+        for tid in threads {
+            let task = proc.join("task").join(tid.to_string());
+            let name = read(&task.join("comm"));
+            section(&mut text, &format!("thread {} {}", tid, name.trim()), {
+                format!(
+                    "wchan: {}\n{}",
+                    read(&task.join("wchan")),
+                    read(&task.join("stack"))
+                )
+            });
+        }
+        CrashReport { pid, text, summary }
+    }
+
+    /// Writes the report to CRASH-REPORT-DIRECTORY or LOG-DIRECTORY,
+    /// or its summary to the log without either.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed.  A failure
+    /// to write the report only gets logged with the summary.
+    pub(crate) fn save(&self, config: &Config, logger: &dyn Logger) -> Result<()> {
+        let name = format!(
+            "hang-{}-{}.txt",
+            self.pid,
+            Local::now().format("%Y%m%dT%H%M%S")
+        );
+        write(
+            config,
+            logger,
+            &name,
+            &self.text,
+            &self.summary,
+            "hang report",
+            [&message::HR001, &message::HR002, &message::HR003],
+        )
+    }
+}
+
+/// A record of how the managed process aborted.
+///
+/// A crash or a hang leaves its traces spread over the log of
+/// `Heartbeat2`.  `ExitReport` gathers them in one file as the
+/// process aborts: the time, the exit code or the signal, how long
+/// the process ran, how many times it has restarted, the round trip
+/// times of the latest heartbeats, and the last lines it wrote to
+/// its standard error, such as:
+///
+/// ```lisp
+/// (:target "app" :time "2024-05-01T12:34:56+00:00" :pid 1234
+///  :exit-code () :signal 11 :signal-name "SIGSEGV" :core-dumped t
+///  :uptime-secs 3600 :restarts 2 :round-trips-ms (3 4 3)
+///  :stderr ("segmentation fault"))
+/// ```
+///
+/// The last lines of the standard error are there only when the
+/// output of the process is captured, as with CAPTURE-OUTPUT.
+///
+/// # Configuration
+///
+/// * CRASH-REPORT-DIRECTORY: Optional.  The directory to write the
+///   reports to, as `crash-<pid>-<time>.sexp` or `.json`.  The default
+///   is LOG-DIRECTORY.  Without either, the report goes to the log.
+/// * CRASH-REPORT-FORMAT: Optional.  `:sexp` or `:json`, the format
+///   of the reports.  The default is `:sexp`.
+/// * CRASH-REPORT-LINES: Optional.  The number of the last lines of
+///   the standard error to report.  The default is 20.
+pub(crate) struct ExitReport {
+    pid: Option<u32>,
+    report: Expression,
+}
+
+impl ExitReport {
+    /// Creates a new `ExitReport`.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The TARGET-ID of the target.
+    /// * `pid` - The PID of the process, if it ever had one.
+    /// * `status` - The exit status of the process, if any.
+    /// * `uptime` - The time the process ran for.
+    /// * `restarts` - The number of times the process has restarted.
+    /// * `round_trips` - The round trip times of the latest
+    ///   heartbeats.
+    /// * `stderr` - The last lines of the standard error.
+    pub(crate) fn new(
+        target: &str,
+        pid: Option<u32>,
+        status: Option<ExitStatus>,
+        uptime: Duration,
+        restarts: u64,
+        round_trips: &[Duration],
+        stderr: &[String],
+    ) -> Self {
+        let optional = |value: Option<i64>| value.map_or(Expression::from(false), Expression::from);
+        let signal = status.and_then(|status| status.signal());
+        let report = Expression::plist(vec![
+            (kw![target], Expression::from(target)),
+            (
+                kw![time],
+                Expression::from(Local::now().to_rfc3339().as_str()),
+            ),
+            (kw![pid], optional(pid.map(i64::from))),
+            (
+                Keyword::new("EXIT-CODE"),
+                optional(status.and_then(|status| status.code()).map(i64::from)),
+            ),
+            (kw![signal], optional(signal.map(i64::from))),
+            (
+                Keyword::new("SIGNAL-NAME"),
+                signal.map_or(Expression::from(false), |signal| {
+                    Expression::from(signal_name(signal).as_str())
+                }),
+            ),
+            (
+                Keyword::new("CORE-DUMPED"),
+                Expression::from(status.is_some_and(|status| status.core_dumped())),
+            ),
+            (
+                Keyword::new("UPTIME-SECS"),
+                Expression::from(uptime.as_secs() as i64),
+            ),
+            (kw![restarts], Expression::from(restarts as i64)),
+            (
+                Keyword::new("ROUND-TRIPS-MS"),
+                Expression::List(
+                    round_trips
+                        .iter()
+                        .map(|round_trip| Expression::from(round_trip.as_millis() as i64))
+                        .collect(),
+                ),
+            ),
+            (
+                kw![stderr],
+                Expression::List(
+                    stderr
+                        .iter()
+                        .map(|line| Expression::from(line.as_str()))
+                        .collect(),
+                ),
+            ),
+        ]);
+        ExitReport { pid, report }
+    }
+
+    /// Writes the report to CRASH-REPORT-DIRECTORY or LOG-DIRECTORY in
+    /// CRASH-REPORT-FORMAT, or to the log without either.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed.  A failure
+    /// to write the report only gets logged.
+    pub(crate) fn save(&self, config: &Config, logger: &dyn Logger) -> Result<()> {
+        let codec = codec::from_config(config, key::CRASH_REPORT_FORMAT)?;
+        let text = codec.encode(&self.report);
+        let name = format!(
+            "crash-{}-{}.{}",
+            self.pid.map_or("none".to_owned(), |pid| pid.to_string()),
+            Local::now().format("%Y%m%dT%H%M%S"),
+            codec.extension()
+        );
+        write(
+            config,
+            logger,
+            &name,
+            &text,
+            &text,
+            "crash report",
+            [&message::HR004, &message::HR005, &message::HR006],
+        )
+    }
+}
+
+/// Writes the `text` of a report to the file by the `name` in
+/// CRASH-REPORT-DIRECTORY or LOG-DIRECTORY, or its `summary` to the
+/// log without either.  The `messages` log the summary, the file, and
+/// a failure to write it, in that order.
+fn write(
+    config: &Config,
+    logger: &dyn Logger,
+    name: &str,
+    text: &str,
+    summary: &str,
+    kind: &str,
+    [logged, written, failed]: [&Message; 3],
+) -> Result<()> {
+    let section = config.section(section::HEARTBEAT)?;
+    let directory = if section.has_key(key::CRASH_REPORT_DIRECTORY) {
+        Some(PathBuf::from(section.string(key::CRASH_REPORT_DIRECTORY)?))
+    } else {
+        Directories::prepare(config)?.log
+    };
+    let directory = match directory {
+        Some(directory) => directory,
+        None => {
+            logger.log_coded(logged, summary);
+            return Ok(());
+        }
+    };
+    let path = directory.join(name);
+    match fs::write(&path, text) {
+        Ok(()) => logger.log_coded(written, &format!("{} written to {}", kind, path.display())),
+        Err(err) => {
+            logger.log_coded(
+                failed,
+                &format!("failed to write [{}]: {}", path.display(), err),
+            );
+            logger.log_coded(logged, summary);
+        }
+    }
+    Ok(())
+}
+
+/// Appends a section by the `title` to the report `text`.
+fn section(text: &mut String, title: &str, body: String) {
+    text.push_str(&format!("\n== {} ==\n{}", title, body));
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Reads the file at `path`, or describes why it can't be read.
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| format!("(unavailable: {})", err))
+}
+
+/// Lists the open file descriptors in the `directory` with their
+/// targets.
+fn fds(directory: &Path) -> String {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => return format!("(unavailable: {})", err),
+    };
+    let mut fds = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let fd = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let target = fs::read_link(entry.path())
+                .map(|target| target.display().to_string())
+                .unwrap_or_else(|err| format!("(unavailable: {})", err));
+            Some((fd, target))
+        })
+        .collect::<Vec<_>>();
+    fds.sort_unstable();
+    fds.iter()
+        .map(|(fd, target)| format!("{} -> {}\n", fd, target))
+        .collect()
+}
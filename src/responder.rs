@@ -0,0 +1,102 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A reference responder for target applications.
+//!
+//! A target application embeds the responder to answer the
+//! heartbeats of `Heartbeat2`, such as:
+//!
+//! ```rust,ignore
+//! let ready = Arc::new(AtomicBool::new(false));
+//! let flag = Arc::clone(&ready);
+//! tokio::spawn(heartbeat2::responder::serve("tcp://*:5555", move || {
+//!     flag.load(Ordering::Relaxed)
+//! }));
+//! ```
+
+use crate::protocol::{self, Keyword, Lease, Message, Multipart};
+use std::error::Error;
+
+/// Answers the heartbeats sent to the `endpoint`.
+///
+/// `serve` binds a REP socket to the `endpoint` and answers each
+/// heartbeat with [`protocol::ok`] if `healthy` returns true, or with
+/// [`protocol::not_ready`] otherwise, echoing the sequence number of
+/// the heartbeat, if any.  `Heartbeat2` restarts a target that doesn't
+/// answer, but only closes the health gate of a target that isn't
+/// ready.  It keeps the [`Lease`] a heartbeat asks for, and answers
+/// the heartbeats of a supervisor other than the owner with
+/// [`protocol::rejected`].  It answers any other request with
+/// `UNKNOWN-REQUEST`.
+///
+/// # Errors
+///
+/// Returns an error if the socket fails to bind, or to receive or
+/// send a message.
+pub async fn serve<F>(endpoint: &str, healthy: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> bool,
+{
+    serve_controlled(endpoint, healthy, |_, _| ()).await
+}
+
+/// Answers the heartbeats sent to the `endpoint` as [`serve`] does,
+/// and hands each control keyword a heartbeat carries, such as
+/// `SET-LOG-LEVEL` with `"DEBUG"`, to `control` before answering.
+/// A target that serves with `serve_controlled` opts in to the
+/// controls, and TARGET-CONTROL lets `Heartbeat2` send them.
+///
+/// # Errors
+///
+/// Returns an error if the socket fails to bind, or to receive or
+/// send a message.
+pub async fn serve_controlled<F, C>(
+    endpoint: &str,
+    mut healthy: F,
+    mut control: C,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> bool,
+    C: FnMut(&Keyword, &str),
+{
+    let context = tmq::Context::new();
+    let mut receiver = tmq::reply(&context).bind(endpoint)?;
+    let mut lease = Lease::new();
+    loop {
+        let (request, sender) = receiver.recv().await?;
+        let reply = match Multipart::try_from(request) {
+            Ok(request) if protocol::is_heartbeat(&request) => {
+                let reply = if let Some(owner) = lease.check(&request) {
+                    protocol::rejected(&owner)
+                } else {
+                    for (name, value) in protocol::controls(&request) {
+                        control(&name, &value);
+                    }
+                    if healthy() {
+                        protocol::ok()
+                    } else {
+                        protocol::not_ready()
+                    }
+                };
+                protocol::echo(&request, reply)
+            }
+            _ => Multipart::from(vec![Message::Keyword(Keyword::new("UNKNOWN-REQUEST"))]),
+        };
+        receiver = sender.send(reply.into()).await?;
+    }
+}
@@ -16,10 +16,68 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::config::section::Section;
 use crate::config::{key, section, Config};
-use crate::logger::{LocalLogger, LogLevel};
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, LogLevel, Logger};
+use crate::metrics::Metrics;
 use crate::result::Result;
+use chrono::{Local, NaiveTime};
+use std::fmt::{self, Display};
 use std::rc::Rc;
+use tokio::time::{Duration, Instant};
+
+/// Enumerates the reasons `Heartbeat2` restarts the managed process
+/// for.
+///
+/// The reason goes with every restart to the log, the metrics, the
+/// callbacks, the webhooks and the [`Timeline`](crate::timeline::Timeline),
+/// so that a crash loop and an operator who restarts the target on
+/// purpose don't look alike.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RestartReason {
+    /// The process exited by itself with a failure status or by a
+    /// signal.
+    Crash,
+    /// `Heartbeat2` killed the process after a heartbeat timeout.
+    HeartbeatTimeout,
+    /// The process exceeded a limit on its resource usage.
+    #[allow(dead_code)]
+    ResourceLimit,
+    /// The process restarted on a schedule.
+    #[allow(dead_code)]
+    Scheduled,
+    /// An operator asked for the restart, such as with the `RESTART`
+    /// control request.
+    Manual,
+    /// The target asked for the restart in its answer to a heartbeat.
+    TargetRequest,
+    /// A target the process depends on restarted.
+    #[allow(dead_code)]
+    DependencyCascade,
+    /// The executable of the process changed.
+    #[allow(dead_code)]
+    BinaryChange,
+}
+
+impl Display for RestartReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RestartReason::*;
+        match self {
+            Crash => write!(f, "crash"),
+            HeartbeatTimeout => write!(f, "heartbeat-timeout"),
+            ResourceLimit => write!(f, "resource-limit"),
+            Scheduled => write!(f, "scheduled"),
+            Manual => write!(f, "manual"),
+            TargetRequest => write!(f, "target-request"),
+            DependencyCascade => write!(f, "dependency-cascade"),
+            BinaryChange => write!(f, "binary-change"),
+        }
+    }
+}
 
 /// Manages the restart behavior of a process.
 ///
@@ -36,36 +94,52 @@ use std::rc::Rc;
 /// The configuration settings `RestartManager` uses to determine the
 /// process restart are as follows:
 ///
+/// * RESTART-POLICY-TYPE: Optional.  The [`RestartPolicy`] that
+///   decides whether the process restarts too often, `:window` for a
+///   [`WindowPolicy`] or `:token-bucket` for a [`TokenBucketPolicy`].
+///   The default is `:window`.
 /// * RETRY-INTERVAL: `RestartManager` determines whether the process
-/// restarts too many times in a period.  `Heartbeat2` gives up
-/// restarting the process in this case.  This integer parameter
-/// configures the period in seconds.
+///   restarts too many times in a period.  `Heartbeat2` gives up
+///   restarting the process in this case.  This integer parameter
+///   configures the period in seconds.
 /// * MAX-RETRIES: Configures the number of restarts before giving up.
+/// * RESTART-DELAY: Optional.  The time in seconds to wait before
+///   every restart, regardless of the restart history.  Some targets
+///   fail to start again until the OS releases their resources, such
+///   as a port in `TIME_WAIT` or a file lock.  The default is 0.
+/// * RESTART-HOURS: Optional.  The hours of the day in the local time
+///   during which the process may restart, such as `("06:00"
+///   "22:00")`.  The hours may span midnight, such as `("22:00"
+///   "06:00")`.  Outside the hours, the restart is pending until they
+///   begin, rather than happening straight away or not at all.  This
+///   suits the environments where a change needs approval out of
+///   hours.  A restart an operator requests isn't held.  By default,
+///   the process may restart at any time.
 ///
 /// # Examples
 ///
 /// Create a `RestartManager`:
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::config::Config;
 /// use crate::logger::{LocalLogger, LogLevel::Info};
 /// use crate::restart::RestartManager;
 ///
 /// // Create a restart manager with configuration and logger
 /// let config: Rc<Config> = // Configuration setup
-/// let logger: Rc<LocalLogger> = // Logger setup
+/// let logger: Rc<dyn Logger> = // Logger setup
 /// let mut restart_manager = RestartManager::new(config, logger);
 /// ```
 ///
 /// Add a new restart in the history:
 ///
-/// ```rust
+/// ```rust,ignore
 /// restart_manager.add_process_abort()?;
 /// ```
 ///
 /// Determine whether to restart the process:
 ///
-/// ```rust
+/// ```rust,ignore
 /// if restart_manager.should_process_restart()? {
 ///     logger.log(INFO, "Restarting process.");
 ///     restart_process().await?;
@@ -74,9 +148,11 @@ use std::rc::Rc;
 /// }
 /// ```
 pub(crate) struct RestartManager {
-    history: Vec<i64>,
+    policy: Box<dyn RestartPolicy>,
+    hours: Option<(NaiveTime, NaiveTime)>,
+    metrics: Option<Rc<Metrics>>,
     config: Rc<Config>,
-    logger: Rc<LocalLogger>,
+    logger: Rc<dyn Logger>,
 }
 
 impl RestartManager {
@@ -90,12 +166,49 @@ impl RestartManager {
     /// # Returns
     ///
     /// A new `RestartManager` instance.
-    pub(crate) fn new(config: Rc<Config>, logger: Rc<LocalLogger>) -> RestartManager {
-        RestartManager {
-            history: Default::default(),
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if RESTART-POLICY-TYPE names an
+    /// unknown policy, if MAX-RETRIES or RETRY-INTERVAL is negative,
+    /// or if RESTART-HOURS isn't a pair of different times of the form
+    /// `HH:MM`.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Result<RestartManager> {
+        let section = config.section(section::HEARTBEAT)?;
+        let policy_type = if section.has_key(key::RESTART_POLICY_TYPE) {
+            section.keyword(key::RESTART_POLICY_TYPE)?.clone()
+        } else {
+            kw![window]
+        };
+        let policy: Box<dyn RestartPolicy> = if policy_type == kw![window] {
+            Box::new(WindowPolicy::new(&config, Rc::clone(&logger))?)
+        } else if policy_type == Keyword::new("TOKEN-BUCKET") {
+            Box::new(TokenBucketPolicy::new(
+                Rc::clone(&config),
+                Rc::clone(&logger),
+            )?)
+        } else {
+            return Err(config_format_error(&format!(
+                "unknown {} [{}]",
+                key::RESTART_POLICY_TYPE,
+                policy_type
+            )));
+        };
+        let hours = restart_hours(&config)?;
+        Ok(RestartManager {
+            policy,
+            hours,
+            metrics: None,
             config,
             logger,
-        }
+        })
+    }
+
+    /// Publishes the tally behind every decision to restart with the
+    /// `metrics`.
+    pub(crate) fn metrics(mut self, metrics: Rc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
     }
 
     /// Determines whether to restart the process.
@@ -112,6 +225,12 @@ impl RestartManager {
     /// The method expects the caller to restart the managed process.
     /// `RestartManager` is unable to restart the process.
     ///
+    /// Every decision goes to the log as a record of the [`Tally`] it
+    /// rests on, such as `(:policy :window :window-secs 60 :aborts 3
+    /// :limit 5 :decision :restart)`, and to the metrics, if any.
+    /// Giving up at fewer aborts than MAX-RETRIES can then be traced
+    /// to the window or the policy.
+    ///
     /// # Returns
     ///
     /// Returns `Ok(true)` to direct that the process must restart.
@@ -123,16 +242,77 @@ impl RestartManager {
     /// Returns an error if there is an issue accessing the
     /// configuration.
     pub(crate) fn should_process_restart(&mut self) -> Result<bool> {
-        Ok(!self.too_many_retries()?)
+        let restart = !self.policy.too_many_retries()?;
+        let tally = self.policy.tally();
+        let record = Expression::plist(vec![
+            (kw![policy], Expression::from(Keyword::new(tally.policy))),
+            (
+                Keyword::new("WINDOW-SECS"),
+                Expression::from(tally.window.as_secs() as i64),
+            ),
+            (kw![aborts], Expression::from(tally.aborts as i64)),
+            (kw![limit], Expression::from(tally.limit as i64)),
+            (
+                kw![decision],
+                Expression::from(if restart {
+                    kw![restart]
+                } else {
+                    Keyword::new("GIVE-UP")
+                }),
+            ),
+        ]);
+        self.logger
+            .log_coded(&message::RS001, &format!("restart decision: {}", record));
+        if let Some(metrics) = &self.metrics {
+            metrics.set_restart_tally(&tally, restart);
+        }
+        Ok(restart)
+    }
+
+    /// Returns RESTART-DELAY, the time to wait before restarting the
+    /// process.
+    pub(crate) fn restart_delay(&self) -> Result<Duration> {
+        Ok(Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::RESTART_DELAY, 0)?
+                .try_into()?,
+        ))
+    }
+
+    /// Tells whether the restart has to wait for RESTART-HOURS to
+    /// begin.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the process may restart now.  Otherwise,
+    /// returns the time to wait and the time of the day the hours
+    /// begin.
+    pub(crate) fn pending_restart(&self) -> Option<(Duration, NaiveTime)> {
+        let (begin, end) = self.hours?;
+        let now = Local::now().time();
+        let permitted = if begin < end {
+            begin <= now && now < end
+        } else {
+            begin <= now || now < end
+        };
+        if permitted {
+            return None;
+        }
+        let wait = begin - now;
+        let wait = if wait < chrono::Duration::zero() {
+            wait + chrono::Duration::days(1)
+        } else {
+            wait
+        };
+        Some((wait.to_std().unwrap_or_default(), begin))
     }
 
     /// Records a restart in the restart history.
     ///
-    /// Adds the current timestamp in the restart history.
+    /// Lets the [`RestartPolicy`] account for the restart.
     /// `Heartbeat2` uses the restart history to decide if the process
-    /// is restarting too often.  Prunes the restart history to
-    /// prevent it from becoming too large.  Large restart history
-    /// wastes the memory and adds latency.
+    /// is restarting too often.
     ///
     /// # Returns
     ///
@@ -149,7 +329,137 @@ impl RestartManager {
     /// terminates.  So the restart history equates to the record of
     /// process aborts in this case.
     pub(crate) fn add_process_abort(&mut self) -> Result<()> {
-        self.prune()?;
+        self.policy.add_process_abort()
+    }
+}
+
+/// Returns the hours RESTART-HOURS permits the restarts in, if any.
+///
+/// # Errors
+///
+/// Returns a configuration error if RESTART-HOURS isn't a pair of
+/// different times of the form `HH:MM`.
+fn restart_hours(config: &Config) -> Result<Option<(NaiveTime, NaiveTime)>> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.has_key(key::RESTART_HOURS) {
+        return Ok(None);
+    }
+    let hours = section
+        .string_list(key::RESTART_HOURS)?
+        .iter()
+        .map(|time| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| {
+                config_format_error(&format!("invalid {} [{}]", key::RESTART_HOURS, time))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    match hours[..] {
+        [begin, end] if begin != end => Ok(Some((begin, end))),
+        _ => Err(config_format_error(&format!(
+            "{} needs two different times",
+            key::RESTART_HOURS
+        ))),
+    }
+}
+
+/// Returns the value of the `key` in the `section`, which counts
+/// something and can't be negative.
+///
+/// # Errors
+///
+/// Returns a configuration error if the value is missing, malformed
+/// or negative.
+fn count(section: &Section, key: &str) -> Result<u64> {
+    section
+        .integer(key)?
+        .try_into()
+        .map_err(|_| config_format_error(&format!("{} must not be negative", key)))
+}
+
+/// Decides whether the managed process restarts too often for
+/// `Heartbeat2` to restart it again.
+///
+/// `RestartManager` tells the policy about every abort of the
+/// process, and asks it right after whether to give up.
+pub(crate) trait RestartPolicy {
+    /// Accounts for an abort of the process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to read from the configuration.
+    fn add_process_abort(&mut self) -> Result<()>;
+
+    /// Returns whether the process has aborted too often to restart.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to read from the configuration.
+    fn too_many_retries(&self) -> Result<bool>;
+
+    /// Returns the [`Tally`] [`too_many_retries`] decides on.
+    ///
+    /// [`too_many_retries`]: RestartPolicy::too_many_retries
+    fn tally(&self) -> Tally;
+}
+
+/// The aborts a [`RestartPolicy`] counts against its limit at the
+/// time of a decision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Tally {
+    /// The name of the policy, such as `WINDOW`.
+    pub(crate) policy: &'static str,
+    /// The window of time the aborts count over.
+    pub(crate) window: Duration,
+    /// The aborts counted in the window.
+    pub(crate) aborts: u64,
+    /// The number of aborts at which the policy gives up.
+    pub(crate) limit: u64,
+}
+
+/// Gives up once the process aborts MAX-RETRIES times within
+/// RETRY-INTERVAL seconds.
+///
+/// The policy keeps the timestamps of the last MAX-RETRIES aborts.
+/// A burst of aborts right after a quiet period exhausts the retries
+/// as quickly as a steady stream of them does.
+pub(crate) struct WindowPolicy {
+    history: Vec<i64>,
+    max_retries: u64,
+    retry_interval: u64,
+    logger: Rc<dyn Logger>,
+}
+
+impl WindowPolicy {
+    /// Creates a new `WindowPolicy` with an empty restart history.
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if MAX-RETRIES or RETRY-INTERVAL
+    /// is missing, malformed or negative.
+    pub(crate) fn new(config: &Config, logger: Rc<dyn Logger>) -> Result<Self> {
+        let section = config.section(section::HEARTBEAT)?;
+        Ok(WindowPolicy {
+            history: Default::default(),
+            max_retries: count(section, key::MAX_RETRIES)?,
+            retry_interval: count(section, key::RETRY_INTERVAL)?,
+            logger,
+        })
+    }
+
+    /// Prunes the restart history to prevent it from becoming too
+    /// large.  Large restart history wastes the memory and adds
+    /// latency.
+    fn prune(&mut self) {
+        let keep = usize::try_from(self.max_retries.saturating_sub(1)).unwrap_or(usize::MAX);
+        if self.history.len() > keep {
+            self.history.drain(..self.history.len() - keep);
+        }
+    }
+}
+
+impl RestartPolicy for WindowPolicy {
+    fn add_process_abort(&mut self) -> Result<()> {
+        self.prune();
         self.history.push(chrono::Utc::now().timestamp());
         self.logger.log(
             LogLevel::Debug,
@@ -159,28 +469,114 @@ impl RestartManager {
     }
 
     fn too_many_retries(&self) -> Result<bool> {
-        let section = self.config.section(section::HEARTBEAT)?;
-        let retry_interval = section.integer(key::RETRY_INTERVAL)?;
-        let max_retries = section.integer(key::MAX_RETRIES)?;
-        let now = chrono::Utc::now().timestamp();
-        let retries: i64 = self
-            .history
-            .iter()
-            .filter(|&&item| item >= now - retry_interval)
-            .count()
-            .try_into()?;
-        Ok(retries >= max_retries)
-    }
-
-    fn prune(&mut self) -> Result<()> {
-        let max_retries = self
-            .config
-            .section(section::HEARTBEAT)?
-            .integer(key::MAX_RETRIES)?
-            .try_into()?;
-        while self.history.len() >= max_retries {
-            self.history.remove(0);
+        let tally = self.tally();
+        Ok(tally.aborts >= tally.limit)
+    }
+
+    fn tally(&self) -> Tally {
+        let since = chrono::Utc::now()
+            .timestamp()
+            .saturating_sub_unsigned(self.retry_interval);
+        let retries = self.history.iter().filter(|&&item| item >= since).count();
+        Tally {
+            policy: "WINDOW",
+            window: Duration::from_secs(self.retry_interval),
+            aborts: retries as u64,
+            limit: self.max_retries,
         }
+    }
+}
+
+/// Gives up once the process runs out of restart tokens.
+///
+/// The bucket holds up to MAX-RETRIES tokens, and starts full.  Every
+/// abort takes a token, and a token comes back every
+/// RESTART-REFILL-INTERVAL seconds.  `Heartbeat2` gives up when the
+/// abort takes the last token.  Unlike a [`WindowPolicy`], the
+/// retries come back gradually rather than all at once as the window
+/// slides past a burst of aborts.
+///
+/// # Configuration
+///
+/// * RESTART-REFILL-INTERVAL: Optional.  The time in seconds it takes
+///   a token to come back.  The default is RETRY-INTERVAL divided by
+///   MAX-RETRIES, which allows the same rate of restarts in the long
+///   run as a `WindowPolicy`.
+pub(crate) struct TokenBucketPolicy {
+    tokens: f64,
+    refilled_at: Instant,
+    capacity: u64,
+    refill_interval: f64,
+    logger: Rc<dyn Logger>,
+}
+
+impl TokenBucketPolicy {
+    /// Creates a new `TokenBucketPolicy` with a full bucket.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed, if
+    /// MAX-RETRIES or RETRY-INTERVAL is negative, or if
+    /// RESTART-REFILL-INTERVAL isn't positive.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Result<Self> {
+        let section = config.section(section::HEARTBEAT)?;
+        let max_retries = count(section, key::MAX_RETRIES)?;
+        let refill_interval = if section.has_key(key::RESTART_REFILL_INTERVAL) {
+            section.integer(key::RESTART_REFILL_INTERVAL)? as f64
+        } else {
+            count(section, key::RETRY_INTERVAL)? as f64 / max_retries.max(1) as f64
+        };
+        if refill_interval <= 0.0 {
+            return Err(config_format_error(&format!(
+                "{} must be positive",
+                key::RESTART_REFILL_INTERVAL
+            )));
+        }
+        Ok(TokenBucketPolicy {
+            tokens: max_retries as f64,
+            refilled_at: Instant::now(),
+            capacity: max_retries,
+            refill_interval,
+            logger,
+        })
+    }
+
+    /// Puts back the tokens that have come back since the last
+    /// refill, up to the capacity of the bucket.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed / self.refill_interval).min(self.capacity as f64);
+        self.refilled_at = now;
+    }
+}
+
+impl RestartPolicy for TokenBucketPolicy {
+    fn add_process_abort(&mut self) -> Result<()> {
+        self.refill();
+        self.tokens = (self.tokens - 1.0).max(0.0);
+        self.logger.log(
+            LogLevel::Debug,
+            &format!("RestartManager: {:.2} restart tokens left", self.tokens),
+        );
         Ok(())
     }
+
+    fn too_many_retries(&self) -> Result<bool> {
+        Ok(self.tokens < 1.0)
+    }
+
+    /// Counts the tokens taken out of the bucket as the aborts, over
+    /// the time the bucket takes to fill up from empty.
+    fn tally(&self) -> Tally {
+        let window = self.capacity as f64 * self.refill_interval;
+        Tally {
+            policy: "TOKEN-BUCKET",
+            window: Duration::try_from_secs_f64(window).unwrap_or(Duration::MAX),
+            // NOTE: The bucket holds between none and `capacity`
+            // tokens, so that the whole tokens left fit in a `u64`.
+            aborts: self.capacity.saturating_sub(self.tokens.floor() as u64),
+            limit: self.capacity,
+        }
+    }
 }
@@ -0,0 +1,110 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use chrono::Local;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use tokio::time::Duration;
+
+/// The default size in bytes at which [`BeatLog`] rotates the file.
+static DEFAULT_BEAT_LOG_MAX_SIZE: i64 = 10 * 1024 * 1024;
+
+/// The header of the file [`BeatLog`] writes.
+static HEADER: &str = "time,latency_ms,outcome\n";
+
+/// Appends a sample of every heartbeat probe to a CSV file.
+///
+/// Each line holds the time of the probe, the time in milliseconds
+/// the target took to answer, and the outcome of the probe: `ok`,
+/// `timeout`, `error`, or with PROBE-COMMAND, `not-ready` or `dead`.
+/// The file allows for an offline analysis of the trends in the
+/// responsiveness of the target, such as a latency creeping up
+/// towards HEARTBEAT-TIMEOUT.
+///
+/// Once the file grows beyond BEAT-LOG-MAX-SIZE, `BeatLog` renames it
+/// with the suffix `.1`, replacing the previous one, and starts a new
+/// file.  The samples take up at most twice BEAT-LOG-MAX-SIZE.
+///
+/// # Configuration
+///
+/// * BEAT-LOG-PATH: Optional.  The path to the CSV file.  `BeatLog`
+///   is disabled without it.
+/// * BEAT-LOG-MAX-SIZE: Optional.  The size in bytes at which to
+///   rotate the file.  The default is 10 MiB.
+pub(crate) struct BeatLog {
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl BeatLog {
+    /// Creates a new `BeatLog`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        BeatLog { config, logger }
+    }
+
+    /// Appends a sample of a probe that took `latency` and ended with
+    /// the `outcome`.  Logs a failure to write rather than failing
+    /// the heartbeat.
+    pub(crate) fn record(&self, latency: Duration, outcome: &str) {
+        if let Err(err) = self.write(latency, outcome) {
+            self.logger.log_coded(
+                &message::BL001,
+                &format!("unable to write beat log: {}", err),
+            );
+        }
+    }
+
+    fn write(&self, latency: Duration, outcome: &str) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::BEAT_LOG_PATH) {
+            return Ok(());
+        }
+        let path = Path::new(section.string(key::BEAT_LOG_PATH)?);
+        let max_size: u64 = section
+            .integer_or(key::BEAT_LOG_MAX_SIZE, DEFAULT_BEAT_LOG_MAX_SIZE)?
+            .try_into()?;
+        let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if size >= max_size {
+            let mut rotated = path.as_os_str().to_owned();
+            rotated.push(".1");
+            fs::rename(path, rotated)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(HEADER.as_bytes())?;
+        }
+        writeln!(
+            file,
+            "{},{},{}",
+            Local::now().to_rfc3339(),
+            latency.as_millis(),
+            outcome
+        )?;
+        Ok(())
+    }
+}
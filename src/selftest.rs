@@ -0,0 +1,193 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(feature = "zmq")]
+use crate::context::Context;
+#[cfg(feature = "zmq")]
+use crate::kw;
+use crate::result::Result;
+#[cfg(feature = "zmq")]
+use crate::socket::SocketBuilder;
+use std::io::Write;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+
+/// The time in milliseconds each check may take.
+static CHECK_TIMEOUT: u64 = 2000;
+
+/// Checks that the host provides what `Heartbeat2` relies on, and
+/// reports a line for each check, such as `PASS  zmq-loopback`.
+///
+/// A new host or a container image may lack a piece of the
+/// environment, such as the ZeroMQ runtime or the permission to fork.
+/// `SelfTest` finds out before a target is supervised on it.  It
+/// checks that:
+///
+/// * `libzmq`: The ZeroMQ library loads, and tells its version.
+/// * `zmq-loopback`: A REQ socket and a REP socket exchange a
+///   heartbeat over TCP on the loopback interface.
+/// * `child-process`: A child process can be spawned and reaped.
+/// * `signal`: A signal `Heartbeat2` raises to itself is caught.
+///
+/// A check the build leaves out, such as the ZeroMQ ones without the
+/// `zmq` feature, is reported as `SKIP`.
+pub(crate) struct SelfTest<W> {
+    output: W,
+    failed: bool,
+}
+
+impl<W: Write> SelfTest<W> {
+    /// Creates a new `SelfTest` reporting on `output`.
+    pub(crate) fn new(output: W) -> Self {
+        SelfTest {
+            output,
+            failed: false,
+        }
+    }
+
+    /// Runs the checks one after another, and reports each.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether all the checks that ran passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the report can't be written.
+    pub(crate) async fn run(mut self) -> Result<bool> {
+        let libzmq = libzmq();
+        self.report("libzmq", libzmq)?;
+        let loopback = within(zmq_loopback()).await;
+        self.report("zmq-loopback", loopback)?;
+        let child = within(child_process()).await;
+        self.report("child-process", child)?;
+        let signal = within(signal()).await;
+        self.report("signal", signal)?;
+        Ok(!self.failed)
+    }
+
+    /// Reports the `outcome` of the check by the `name`.  `Ok(None)`
+    /// stands for a check that was skipped.
+    fn report(&mut self, name: &str, outcome: Result<Option<String>>) -> Result<()> {
+        let (verdict, detail) = match outcome {
+            Ok(Some(detail)) => ("PASS", detail),
+            Ok(None) => ("SKIP", "not in this build".to_owned()),
+            Err(err) => {
+                self.failed = true;
+                ("FAIL", err.to_string())
+            }
+        };
+        writeln!(self.output, "{:<6}{:<16}{}", verdict, name, detail)?;
+        Ok(())
+    }
+}
+
+/// Runs the `check`, and fails it if it takes longer than
+/// [`CHECK_TIMEOUT`].
+async fn within(
+    check: impl std::future::Future<Output = Result<Option<String>>>,
+) -> Result<Option<String>> {
+    timeout(Duration::from_millis(CHECK_TIMEOUT), check)
+        .await
+        .map_err(|_| format!("timed out after {}ms", CHECK_TIMEOUT))?
+}
+
+/// Returns the version of the ZeroMQ library.
+#[cfg(feature = "zmq")]
+fn libzmq() -> Result<Option<String>> {
+    let (major, minor, patch) = libzmq::version();
+    Ok(Some(format!("version {}.{}.{}", major, minor, patch)))
+}
+
+/// Skips the check of the ZeroMQ library without the `zmq` feature.
+#[cfg(not(feature = "zmq"))]
+fn libzmq() -> Result<Option<String>> {
+    Ok(None)
+}
+
+/// Exchanges a heartbeat between a REQ socket and a REP socket on a
+/// free port of the loopback interface.
+#[cfg(feature = "zmq")]
+async fn zmq_loopback() -> Result<Option<String>> {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let endpoint = format!("tcp://127.0.0.1:{}", port);
+    let context = Context::new();
+    let receiver = SocketBuilder::new(context.clone())
+        .endpoint(&endpoint)
+        .timeout(CHECK_TIMEOUT)
+        .linger(false)
+        .rep()
+        .bind()?;
+    let sender = SocketBuilder::new(context)
+        .endpoint(&endpoint)
+        .timeout(CHECK_TIMEOUT)
+        .linger(false)
+        .req()
+        .connect()?;
+    let waiting = sender.send_keyword(kw![heartbeat]).await?;
+    let (request, replier) = receiver.recv_multipart().await?;
+    replier.send_keyword(kw![ok]).await?;
+    let (reply, _) = waiting.recv_multipart().await?;
+    match (request.first(), reply.first()) {
+        (Some(request), Some(reply)) if *request == kw![heartbeat] && *reply == kw![ok] => {
+            Ok(Some(format!("on {}", endpoint)))
+        }
+        _ => Err("unexpected messages on the loopback".into()),
+    }
+}
+
+/// Skips the check of the loopback without the `zmq` feature.
+#[cfg(not(feature = "zmq"))]
+async fn zmq_loopback() -> Result<Option<String>> {
+    Ok(None)
+}
+
+/// Spawns `true`, and reaps it.
+async fn child_process() -> Result<Option<String>> {
+    let mut child = Command::new("true").spawn()?;
+    let pid = child.id().unwrap_or_default();
+    let status = child.wait().await?;
+    if status.success() {
+        Ok(Some(format!("PID {} reaped", pid)))
+    } else {
+        Err(format!("PID {} exited with {}", pid, status).into())
+    }
+}
+
+/// Raises `SIGUSR1` to `Heartbeat2` itself, and waits to catch it.
+#[cfg(feature = "signals")]
+async fn signal() -> Result<Option<String>> {
+    use futures::StreamExt;
+    use nix::sys::signal::{raise, Signal};
+
+    let mut signals = crate::signal::register(&[Signal::SIGUSR1 as i32])?;
+    raise(Signal::SIGUSR1)?;
+    match signals.next().await {
+        Some(caught) if caught == Signal::SIGUSR1 as i32 => Ok(Some("SIGUSR1 caught".to_owned())),
+        Some(caught) => Err(format!("caught signal {} instead", caught).into()),
+        None => Err("signal stream closed".into()),
+    }
+}
+
+/// Skips the check of the signals without the `signals` feature.
+#[cfg(not(feature = "signals"))]
+async fn signal() -> Result<Option<String>> {
+    Ok(None)
+}
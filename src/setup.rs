@@ -0,0 +1,280 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::usage_error;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::result::Result;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// The ways to check the health of the target the wizard offers, as
+/// CHECK-TYPE takes them, and `none` for none.
+static CHECKS: &[&str] = &["zmq", "tcp", "passive", "exec", "none"];
+
+/// Asks a new user about the target to supervise, and writes the
+/// configuration file for it, and optionally a systemd unit that runs
+/// `Heartbeat2` on it.
+///
+/// `Wizard` spares a new user the S-expression format of the
+/// configuration file until they want to tune it.  It asks for the
+/// command, the way to check its health, the intervals of the
+/// heartbeat and the restart policy, offering a default for each
+/// where there is one.  It reads the file it writes back, so that the
+/// file is known to load.  It doesn't overwrite an existing file
+/// without the consent of the user.
+pub(crate) struct Wizard<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Wizard<R, W> {
+    /// Creates a new `Wizard` asking its questions on `output` and
+    /// reading the answers from `input`.
+    pub(crate) fn new(input: R, output: W) -> Self {
+        Wizard { input, output }
+    }
+
+    /// Asks the questions, and writes the configuration file to
+    /// `path`, and the systemd unit next to it if the user wants one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input ends before the questions do, or
+    /// if a file can't be written.
+    pub(crate) fn run(&mut self, path: &Path) -> Result<()> {
+        writeln!(
+            self.output,
+            "This writes {} for supervising your program with heartbeat2.\n\
+             Press Enter to take the default in brackets.\n",
+            path.display()
+        )?;
+        if path.exists() && !self.confirm(&format!("Overwrite {}?", path.display()), false)? {
+            return Ok(());
+        }
+        let mut items = vec![];
+        let command = loop {
+            let command = words(&self.ask("Command to run, with its arguments", None)?);
+            if !command.is_empty() {
+                break command;
+            }
+        };
+        let program = Path::new(&command[0]).file_name().map_or_else(
+            || "target".to_owned(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let target_id = self.ask("Target ID", Some(&program.to_lowercase()))?;
+        let working = std::env::current_dir()?;
+        let working = self.ask("Working directory", Some(&working.to_string_lossy()))?;
+        items.push((key::TARGET_ID, keyword(&target_id)));
+        items.push((key::COMMAND, list(&command)));
+        items.push((key::WORKING_DIRECTORY, Expression::from(working.as_str())));
+        let check = self.choose("Health check", CHECKS, "tcp")?;
+        match check {
+            "none" => {
+                // NOTE: The probe stands in for the heartbeat, which
+                // has nowhere to go.  The crashes still lead to
+                // restarts.
+                items.push((key::PROBE_COMMAND, list(&["true"])));
+            }
+            "exec" => {
+                let probe = loop {
+                    let probe = words(&self.ask("Check command, exiting 0 if healthy", None)?);
+                    if !probe.is_empty() {
+                        break probe;
+                    }
+                };
+                items.push((key::CHECK_TYPE, keyword(check)));
+                items.push((key::CHECK_COMMAND, list(&probe)));
+            }
+            _ => {
+                let endpoint = self.ask("Endpoint of the target", Some("tcp://127.0.0.1:8080"))?;
+                items.push((key::CHECK_TYPE, keyword(check)));
+                items.push((key::TARGET_ENDPOINT, Expression::from(endpoint.as_str())));
+            }
+        }
+        items.push((
+            key::HEARTBEAT_INTERVAL,
+            self.integer("Seconds between heartbeats", 5)?,
+        ));
+        items.push((
+            key::HEARTBEAT_TIMEOUT,
+            self.integer("Milliseconds to wait for a reply", 3000)?,
+        ));
+        items.push((
+            key::MAX_RETRIES,
+            self.integer("Restarts to allow before giving up", 3)?,
+        ));
+        items.push((
+            key::RETRY_INTERVAL,
+            self.integer("... within how many seconds", 60)?,
+        ));
+        items.push((
+            key::RESTART_DELAY,
+            self.integer("Seconds to wait before a restart", 1)?,
+        ));
+        items.push((key::LOGGER, keyword("local")));
+        fs::write(path, render(&items))?;
+        Config::new()
+            .section_mut(section::HEARTBEAT)
+            .load_from_path(path)?;
+        writeln!(self.output, "Wrote {}.", path.display())?;
+        if self.confirm("Write a systemd unit, too?", false)? {
+            let unit = path.with_file_name(format!("heartbeat2-{}.service", target_id));
+            fs::write(&unit, systemd_unit(&target_id, path, &working)?)?;
+            writeln!(
+                self.output,
+                "Wrote {}.  Copy it to /etc/systemd/system to install it.",
+                unit.display()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Asks the `question`, and returns the answer, or the `default`
+    /// if the answer is empty.  Asks again on an empty answer without
+    /// a default.
+    fn ask(&mut self, question: &str, default: Option<&str>) -> Result<String> {
+        loop {
+            match default {
+                Some(default) => write!(self.output, "{} [{}]: ", question, default)?,
+                None => write!(self.output, "{}: ", question)?,
+            }
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(usage_error("setup ended before the questions"));
+            }
+            match (line.trim(), default) {
+                ("", Some(default)) => return Ok(default.to_owned()),
+                ("", None) => continue,
+                (answer, _) => return Ok(answer.to_owned()),
+            }
+        }
+    }
+
+    /// Asks the `question` until the answer is one of the `choices`,
+    /// and returns it.
+    fn choose(
+        &mut self,
+        question: &str,
+        choices: &[&'static str],
+        default: &str,
+    ) -> Result<&'static str> {
+        let question = format!("{} ({})", question, choices.join(", "));
+        loop {
+            let answer = self.ask(&question, Some(default))?;
+            if let Some(choice) = choices.iter().find(|&&choice| choice == answer) {
+                return Ok(choice);
+            }
+        }
+    }
+
+    /// Asks the yes-or-no `question`, and returns whether the answer
+    /// is yes.
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let answer = self.choose(question, &["y", "n"], if default { "y" } else { "n" })?;
+        Ok(answer == "y")
+    }
+
+    /// Asks the `question` until the answer is a positive integer,
+    /// and returns it.
+    fn integer(&mut self, question: &str, default: i64) -> Result<Expression> {
+        loop {
+            let answer = self.ask(question, Some(&default.to_string()))?;
+            if let Some(value) = answer.parse::<i64>().ok().filter(|&value| value > 0) {
+                return Ok(Expression::from(value));
+            }
+        }
+    }
+}
+
+/// Returns the `name` as a keyword value.
+fn keyword(name: &str) -> Expression {
+    Expression::from(Keyword::from(name.to_owned()))
+}
+
+/// Returns the `strings` as a list value.
+fn list<S: AsRef<str>>(strings: &[S]) -> Expression {
+    Expression::List(
+        strings
+            .iter()
+            .map(|string| Expression::from(string.as_ref()))
+            .collect(),
+    )
+}
+
+/// Renders the configuration `items` as the contents of a
+/// configuration file, an item per line.
+fn render(items: &[(&str, Expression)]) -> String {
+    let mut text = String::from(";; -*- lisp -*-\n(\n");
+    for (key, value) in items {
+        text.push_str(&format!(" :{} {}\n", key.to_lowercase(), value));
+    }
+    text.push_str(" )\n");
+    text
+}
+
+/// Renders a systemd unit that runs `Heartbeat2` on the configuration
+/// file at `path` for the target `target_id`.
+fn systemd_unit(target_id: &str, path: &Path, working: &str) -> Result<String> {
+    let exe = std::env::current_exe()?;
+    let config = path.canonicalize().unwrap_or_else(|_| PathBuf::from(path));
+    Ok(format!(
+        "[Unit]\n\
+         Description=Heartbeat2 supervising {}\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         ExecStart={} {}\n\
+         WorkingDirectory={}\n\
+         Restart=always\n\
+         RestartSec=5\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        target_id,
+        exe.display(),
+        config.display(),
+        working
+    ))
+}
+
+/// Splits the `line` into words at the whitespace, as a shell does,
+/// except in quotes.
+fn words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
@@ -0,0 +1,145 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::logger::{message, LogLevel, Logger};
+use crate::reaper::OwnChild;
+use crate::result::Result;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::cell::Cell;
+use std::path::Path;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+/// The default time in seconds `Heartbeat2` may spend shutting down.
+static DEFAULT_SHUTDOWN_TIMEOUT: i64 = 60;
+
+/// The exit code of `Heartbeat2` when its shutdown times out.  It is
+/// the exit code timeout(1) uses for the same purpose.
+static SHUTDOWN_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Bounds the time `Heartbeat2` spends shutting down.
+///
+/// Once armed, `ShutdownTimer` gives `Heartbeat2` SHUTDOWN-TIMEOUT
+/// seconds to exit.  If `Heartbeat2` is still around by then, it
+/// kills the managed process and exits `Heartbeat2` with
+/// [`SHUTDOWN_TIMEOUT_EXIT_CODE`].  A configuration mistake, such as
+/// an EXIT-TIMEOUT too long, can't make `Heartbeat2` hang on stop.
+///
+/// The timer runs on a thread of its own, so that it fires even if
+/// the event loop of `Heartbeat2` is stuck.
+///
+/// # Configuration
+///
+/// * SHUTDOWN-TIMEOUT: Optional.  The time in seconds `Heartbeat2`
+///   may spend shutting down.  The default is 60 seconds.
+pub(crate) struct ShutdownTimer {
+    armed: Cell<bool>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl ShutdownTimer {
+    /// Creates a new `ShutdownTimer` that isn't armed yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        ShutdownTimer {
+            armed: Cell::new(false),
+            config,
+            logger,
+        }
+    }
+
+    /// Arms the timer as `Heartbeat2` starts shutting down.  Does
+    /// nothing if the timer is already armed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pid` - The process ID of the managed process to kill when
+    ///   the timer fires, or `None` to leave the process alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if SHUTDOWN-TIMEOUT is invalid, or if it
+    /// fails to start the timer thread.
+    pub(crate) fn arm(&self, pid: Option<u32>) -> Result<()> {
+        if self.armed.get() {
+            return Ok(());
+        }
+        let bound = Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer_or(key::SHUTDOWN_TIMEOUT, DEFAULT_SHUTDOWN_TIMEOUT)?
+                .try_into()?,
+        );
+        let logger = self.logger.local();
+        thread::Builder::new()
+            .name("shutdown-timer".to_owned())
+            .spawn(move || {
+                thread::sleep(bound);
+                logger.log_coded(
+                    &message::SD001,
+                    &format!("shutdown still going after {}s; exiting", bound.as_secs()),
+                );
+                if let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+                    let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+                }
+                std::process::exit(SHUTDOWN_TIMEOUT_EXIT_CODE);
+            })?;
+        self.armed.set(true);
+        self.logger.log(
+            LogLevel::Debug,
+            &format!("shutdown must complete in {}s", bound.as_secs()),
+        );
+        Ok(())
+    }
+}
+
+/// Returns whether the host is shutting down, as systemd reports by
+/// `systemctl is-system-running`.  Restarting a target that fails
+/// while the host goes down is futile, as the host stops it again or
+/// loses it in the middle of the start.  A host without systemd never
+/// reports a shutdown this way, but `SIGPWR` and the `SIGTERM` of
+/// init still stop the supervision.
+pub(crate) async fn host_is_shutting_down() -> bool {
+    if !Path::new("/run/systemd/system").exists() {
+        return false;
+    }
+    let child = match tokio::process::Command::new("systemctl")
+        .arg("is-system-running")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    let _own = OwnChild::new(child.id());
+    child
+        .wait_with_output()
+        .await
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "stopping")
+}
@@ -16,15 +16,35 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::channel::EventSender;
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
 use crate::event::EventType;
-use crate::logger::{LocalLogger, LogLevel};
+use crate::hook;
+use crate::kw;
+use crate::logger::{message, LogLevel, Logger};
 use crate::result::Result;
-use futures::stream::StreamExt;
-use signal_hook::consts::signal::{SIGQUIT, SIGTERM};
-use signal_hook_tokio::{Handle, Signals};
+use futures::stream::{Stream, StreamExt};
+use nix::libc::{
+    SIGALRM, SIGCHLD, SIGCONT, SIGHUP, SIGINT, SIGIO, SIGPROF, SIGPWR, SIGQUIT, SIGTERM, SIGTSTP,
+    SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2, SIGVTALRM, SIGWINCH, SIGXCPU, SIGXFSZ,
+};
+#[cfg(feature = "signals")]
+use signal_hook_tokio::Signals;
 use std::cell::RefCell;
 use std::rc::Rc;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc, watch};
+
+/// The signals [`SignalHandler`] forwards to the managed process in
+/// the init mode.  These are all the catchable signals, except those
+/// `Heartbeat2` handles itself and those the kernel raises for a
+/// fault in `Heartbeat2`.  `SIGCHLD` belongs to
+/// [`Reaper`](crate::reaper::Reaper), and `SIGPIPE` to the sockets
+/// of `Heartbeat2`.
+static FORWARDED_SIGNALS: &[i32] = &[
+    SIGHUP, SIGUSR1, SIGUSR2, SIGALRM, SIGCONT, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGXCPU,
+    SIGXFSZ, SIGVTALRM, SIGPROF, SIGWINCH, SIGIO,
+];
 
 /// Represents UNIX signals that [`SignalHandler`] actions on.
 ///
@@ -46,11 +66,46 @@ use tokio::sync::mpsc::Sender;
 /// will exit after `SIGTERM`.  But `SIGQUIT` causes only the
 /// `Heartbeat2` process to exit.  The managed process will still be
 /// running after `SIGQUIT`.
+///
+/// `Heartbeat2` handles `SIGINT` as either `SIGTERM` or `SIGQUIT`,
+/// depending on the configuration.  It handles `SIGPWR`, which tells
+/// that the host is about to lose power, as `SIGTERM`.  It forwards
+/// the signals the configuration lists to the managed process without
+/// affecting the supervision.  In the init mode, it forwards all the other
+/// catchable signals this way.
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum Signal {
     /// Indicates the `Heartbeat2` process has received a `SIGQUIT`.
     Quit,
     /// Indicates the `Heartbeat2` process has received a `SIGTERM`.
     Term,
+    /// Indicates the `Heartbeat2` process has received a signal to
+    /// forward to the managed process.
+    Forward(nix::sys::signal::Signal),
+    /// Indicates the `Heartbeat2` process has received a signal that
+    /// only its hook handles.
+    Hooked(nix::sys::signal::Signal),
+}
+
+impl Signal {
+    /// Returns whether `Heartbeat2` exits straight after relaying the
+    /// signal, rather than waiting for the managed process to exit.
+    pub(crate) fn detaches(&self) -> bool {
+        matches!(self, Signal::Quit)
+    }
+
+    /// Returns whether relaying the signal ends the supervision of
+    /// the managed process.  `Heartbeat2` keeps supervising the
+    /// process after forwarding it a signal.
+    pub(crate) fn terminates(&self) -> bool {
+        !matches!(self, Signal::Forward(_) | Signal::Hooked(_))
+    }
+
+    /// Returns whether `Heartbeat2` relays the signal to the managed
+    /// process.  A signal with only a hook isn't relayed.
+    pub(crate) fn relays(&self) -> bool {
+        !matches!(self, Signal::Hooked(_))
+    }
 }
 
 impl From<Signal> for nix::sys::signal::Signal {
@@ -58,68 +113,259 @@ impl From<Signal> for nix::sys::signal::Signal {
         match source {
             Signal::Quit => Self::SIGQUIT,
             Signal::Term => Self::SIGTERM,
+            Signal::Forward(signal) | Signal::Hooked(signal) => signal,
         }
     }
 }
 
+/// Parses the `name` of a signal, such as `"SIGHUP"`, that the
+/// configuration item `key` refers to.
+///
+/// # Errors
+///
+/// Returns a configuration error if there is no signal by the `name`.
+pub(crate) fn parse_signal(name: &str, key: &str) -> Result<nix::sys::signal::Signal> {
+    name.to_uppercase()
+        .parse()
+        .map_err(|_| config_format_error(&format!("unknown signal [{}] in {}", name, key)))
+}
+
+/// Returns the name of the `signal` number, such as `"SIGSEGV"`, or
+/// the number itself if it names no signal.
+pub(crate) fn signal_name(signal: i32) -> String {
+    nix::sys::signal::Signal::try_from(signal)
+        .map_or_else(|_| signal.to_string(), |signal| signal.as_str().to_owned())
+}
+
+/// Registers for the `signals`.
+///
+/// # Returns
+///
+/// Returns the stream of the signals as they arrive.
+#[cfg(feature = "signals")]
+pub(crate) fn register(signals: &[i32]) -> Result<impl Stream<Item = i32> + Unpin> {
+    Ok(Signals::new(signals)?)
+}
+
+/// Stands in for the registration for the `signals` without the
+/// `signals` feature.  No signal arrives on the stream.
+#[cfg(not(feature = "signals"))]
+pub(crate) fn register(_signals: &[i32]) -> Result<impl Stream<Item = i32> + Unpin> {
+    Ok(futures::stream::pending())
+}
+
 /// Forwards signal to [`EventHandler`](crate::event::EventHandler).
 ///
 /// Actions on signal by raising an appropriate event to
 /// [`EventHandler`](crate::event::EventHandler).  [`Signal`] defines
 /// the subset of UNIX signals `SignalHandler` reacts to.
+///
+/// `SignalHandler` registers for the signals once, and keeps the
+/// registration for as long as [`run`](#method.run) runs, across the
+/// restarts of the managed process.  Instead of closing it,
+/// [`EventHandler`](crate::event::EventHandler) disables it while
+/// there is no process to act on.  `SignalHandler` holds on to the
+/// signals that arrive in the meantime, and raises their events once
+/// enabled again.  No signal gets lost in a restart.
+///
+/// # Configuration
+///
+/// * SIGINT-ACTION: Optional.  `:terminate` handles `SIGINT` as
+///   `SIGTERM`, and `:detach` handles it as `SIGQUIT`.  The default
+///   is `:terminate`, so that `Ctrl-C` in an interactive session
+///   stops both the managed process and `Heartbeat2`.
+/// * RELAY-SIGNALS: Optional.  A list of the names of the signals to
+///   forward to the managed process, such as `("SIGHUP" "SIGUSR1")`.
+///   Daemons that reload their configuration on `SIGHUP`, for
+///   example, keep working under `Heartbeat2`.  The list may not
+///   name the signals `Heartbeat2` handles itself.
+/// * INIT: Optional.  When `t`, `SignalHandler` also reacts to
+///   [`FORWARDED_SIGNALS`].
+/// * SIGNAL-HOOKS: Optional.  `SignalHandler` also reacts to the
+///   signals with a hook.  See [`HookRunner`](crate::hook::HookRunner).
+///
+/// A target in a [`Fleet`](crate::fleet::Fleet) leaves `SIGTERM`,
+/// `SIGQUIT` and `SIGINT` to the fleet, which passes them on to the
+/// targets one by one.
+///
+/// Without the `signals` feature, `SignalHandler` receives the
+/// signals from the fleet only.
 pub(crate) struct SignalHandler {
-    event_sender: Sender<EventType>,
-    signal_handle: RefCell<Option<Handle>>,
-    logger: Rc<LocalLogger>,
+    event_sender: EventSender,
+    enabled: watch::Sender<bool>,
+    held: watch::Sender<Option<Signal>>,
+    fleet_signals: RefCell<Option<mpsc::UnboundedReceiver<Signal>>>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
 }
 
 impl SignalHandler {
-    /// Creates a new `SignalHandler` with the specified event sender
-    /// and logger.
-    pub(crate) fn new(event_sender: Sender<EventType>, logger: Rc<LocalLogger>) -> Self {
+    /// Creates a new `SignalHandler` with the specified event sender,
+    /// configuration and logger.  The new `SignalHandler` is
+    /// disabled.
+    pub(crate) fn new(
+        event_sender: EventSender,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
+    ) -> Self {
+        let (enabled, _) = watch::channel(false);
+        let (held, _) = watch::channel(None);
         Self {
             event_sender,
-            signal_handle: RefCell::new(None),
+            enabled,
+            held,
+            fleet_signals: RefCell::new(None),
+            config,
             logger,
         }
     }
 
+    /// Makes the `SignalHandler` take the termination signals from
+    /// the fleet instead of registering for them.  Must precede
+    /// [`run`](#method.run).
+    ///
+    /// # Returns
+    ///
+    /// Returns the sender the fleet passes the signals on with.
+    pub(crate) fn join_fleet(&self) -> mpsc::UnboundedSender<Signal> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.fleet_signals.replace(Some(receiver));
+        sender
+    }
+
     /// Runs the signal handling loop, waiting for signals and sending
-    /// corresponding event types to the event sender.
+    /// corresponding event types to the event sender while enabled.
+    /// Runs until `Heartbeat2` exits.
     pub(crate) async fn run(&self) -> Result<()> {
-        let mut signals = Signals::new(&[SIGQUIT, SIGTERM])?;
-        let old_handle = self.signal_handle.replace(Some(signals.handle()));
-        // NOTE: Close the old handle before calling run().
-        debug_assert!(matches!(old_handle, None));
-        while let Some(signal) = signals.next().await {
-            match signal {
-                SIGQUIT => {
-                    self.event_sender
-                        .send(EventType::Signalled(Signal::Quit))
-                        .await?
-                }
-                SIGTERM => {
-                    self.event_sender
-                        .send(EventType::Signalled(Signal::Term))
-                        .await?
+        let mut forwarded = self.relayed_signals()?;
+        if self.config.section(section::HEARTBEAT)?.init()? {
+            forwarded.extend_from_slice(FORWARDED_SIGNALS);
+        }
+        let mut fleet_signals = self.fleet_signals.borrow_mut().take();
+        let mut registered = if fleet_signals.is_some() {
+            vec![]
+        } else {
+            vec![SIGQUIT, SIGTERM, SIGINT, SIGPWR]
+        };
+        registered.extend(&forwarded);
+        registered.extend(
+            hook::signal_hooks(&self.config)?
+                .into_iter()
+                .map(|(signal, _)| signal as i32),
+        );
+        registered.sort_unstable();
+        registered.dedup();
+        let sigint = self.sigint()?;
+        let mut signals = register(&registered)?;
+        let mut enabled = self.enabled.subscribe();
+        loop {
+            let signal = tokio::select! {
+                Some(signal) = signals.next() => match signal {
+                    SIGQUIT => Signal::Quit,
+                    SIGTERM => Signal::Term,
+                    SIGINT => sigint,
+                    SIGPWR => {
+                        self.logger.log_coded(&message::SG001, "power failure; the host is going down");
+                        Signal::Term
+                    }
+                    other if forwarded.contains(&other) => Signal::Forward(other.try_into()?),
+                    other => Signal::Hooked(other.try_into()?),
+                },
+                Some(signal) = async { fleet_signals.as_mut()?.recv().await } => signal,
+                else => break,
+            };
+            if !*enabled.borrow_and_update() {
+                self.held.send_replace(Some(signal));
+                while !*enabled.borrow_and_update() {
+                    enabled.changed().await?;
                 }
-                _ => unreachable!("unhandled signal"),
+                self.held.send_replace(None);
             }
+            self.event_sender.send(EventType::Signalled(signal)).await?;
         }
         Ok(())
     }
 
-    /// Closes the `SignalHandler`.
+    /// Waits until the `SignalHandler` holds a signal that ends the
+    /// supervision while it is disabled, such as while a restart is
+    /// pending.
+    ///
+    /// # Returns
+    ///
+    /// Returns the signal, which the `SignalHandler` goes on holding.
+    pub(crate) async fn held_termination(&self) -> Signal {
+        let mut held = self.held.subscribe();
+        loop {
+            if let Some(signal) = *held.borrow_and_update() {
+                if signal.terminates() {
+                    return signal;
+                }
+            }
+            // NOTE: The sender lives as long as `self`.
+            let _ = held.changed().await;
+        }
+    }
+
+    /// Returns the signals RELAY-SIGNALS lists.
+    fn relayed_signals(&self) -> Result<Vec<i32>> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::RELAY_SIGNALS) {
+            return Ok(vec![]);
+        }
+        section
+            .string_list(key::RELAY_SIGNALS)?
+            .iter()
+            .map(|name| {
+                let signal = parse_signal(name, key::RELAY_SIGNALS)? as i32;
+                if [SIGQUIT, SIGTERM, SIGINT, SIGCHLD].contains(&signal) {
+                    Err(config_format_error(&format!(
+                        "{} may not relay [{}]",
+                        key::RELAY_SIGNALS,
+                        name
+                    )))
+                } else {
+                    Ok(signal)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the [`Signal`] that `SIGINT` stands for according to
+    /// SIGINT-ACTION.
+    fn sigint(&self) -> Result<Signal> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        if !section.has_key(key::SIGINT_ACTION) {
+            return Ok(Signal::Term);
+        }
+        let action = section.keyword(key::SIGINT_ACTION)?;
+        if *action == kw![terminate] {
+            Ok(Signal::Term)
+        } else if *action == kw![detach] {
+            Ok(Signal::Quit)
+        } else {
+            Err(config_format_error(&format!(
+                "unknown {} [{}]",
+                key::SIGINT_ACTION,
+                action
+            )))
+        }
+    }
+
+    /// Enables the `SignalHandler`, so that it forwards signals to
+    /// the [`EventHandler`](crate::event::EventHandler), including
+    /// those it held on to while disabled.
+    pub(crate) fn enable(&self) {
+        self.logger.log(LogLevel::Trace, "SignalHandler::enable()");
+        self.enabled.send_replace(true);
+    }
+
+    /// Disables the `SignalHandler`.
     ///
-    /// Closing the `SignalHandler` means it will no longer forward
-    /// signal to the [`EventHandler`](crate::event::EventHandler).
-    pub(crate) fn close(&self) {
-        self.logger.log(LogLevel::Trace, "SignalHandler::close()");
-        let handle = self
-            .signal_handle
-            .borrow_mut()
-            .take()
-            .expect("signal handle missing");
-        handle.close();
+    /// The disabled `SignalHandler` no longer forwards signals to the
+    /// [`EventHandler`](crate::event::EventHandler).  It holds on to
+    /// the signals until enabled again.
+    pub(crate) fn disable(&self) {
+        self.logger.log(LogLevel::Trace, "SignalHandler::disable()");
+        self.enabled.send_replace(false);
     }
 }
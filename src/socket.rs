@@ -16,11 +16,13 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::error::Error;
+use crate::error::{illegal_state_error, Error};
 use crate::keyword::Keyword;
+use crate::protocol::Multipart;
 use crate::result::Result;
+use futures::{SinkExt, StreamExt};
 use std::fmt::{self, Display};
-use std::ops::Deref;
+use std::marker::PhantomData;
 use tmq::request_reply::{RequestReceiver, RequestSender};
 use tmq::{self, Context};
 use tokio::time::Duration;
@@ -28,107 +30,154 @@ use tokio::time::Duration;
 /// The default socket communications timeout in milliseconds.
 static DEFAULT_SOCKET_TIMEOUT: u64 = 3000;
 
-/// Defines a ZMQ message.
-///
-/// A message can be either a string or a keyword.  A keyword is an
-/// upper case string that begins with a colon in the configuration
-/// file.
-pub(crate) enum Message {
-    /// A string message.
-    String(String),
-    /// A keyword message.
-    Keyword(Keyword),
+/// Represents an error that may occur during a message reception.
+#[derive(Debug)]
+pub(crate) enum RecvError {
+    /// A timeout occurred waiting for a message to arrive.
+    Timeout,
+    /// Some other kind of error occurred waiting for or receiving a
+    /// message.
+    Other(Error),
 }
 
-impl Message {
-    /// Returns the string representation of the message.
-    pub(crate) fn as_str(&self) -> &str {
+impl std::error::Error for RecvError {}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Message::String(s) => s,
-            Message::Keyword(kw) => kw.name(),
+            RecvError::Timeout => write!(f, "timeout"),
+            RecvError::Other(e) => write!(f, "other({})", e),
         }
     }
 }
 
-impl TryFrom<tmq::Message> for Message {
-    type Error = Box<dyn std::error::Error>;
-
-    fn try_from(source: tmq::Message) -> Result<Self> {
-        source
-            .as_str()
-            .ok_or_else(crate::error::string_encoding_error)
-            .map(|s| {
-                if let Some(name) = s.strip_prefix(':') {
-                    Message::Keyword(Keyword::new(name))
-                } else {
-                    Message::String(s.to_owned())
-                }
-            })
-    }
-}
+/// Represents the type of socket to build with [`SocketBuilder`].
+///
+/// Each type of ZeroMQ socket is a marker type, such as [`Req`], that
+/// tells what [`SocketBuilder::connect`] and [`SocketBuilder::bind`]
+/// produce.  A REQ socket produces a [`SocketSender`], as it sends
+/// first, while a PUB socket produces a [`Publisher`].
+pub(crate) trait SocketType {
+    /// The socket a `SocketBuilder` of this type produces.
+    type Socket;
 
-impl PartialEq<Keyword> for Message {
-    fn eq(&self, rhs: &Keyword) -> bool {
-        match self {
-            Message::String(s) => s == rhs.name(),
-            Message::Keyword(kw) => kw == rhs,
-        }
-    }
+    /// Builds the socket the `builder` configures, and connects it
+    /// to the endpoint, or binds it to the endpoint if `bind` is
+    /// `true`.
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket>
+    where
+        Self: Sized;
 }
 
-/// Represents a ZMQ multipart message.
-///
-/// Contains a sequence of [`Message`]s.  ZMQ either sends the entire
-/// multipart message or not at all.
-pub(crate) struct Multipart(Vec<Message>);
+/// The REQ socket.
+pub(crate) struct Req;
+
+/// The REP socket.
+pub(crate) struct Rep;
+
+// NOTE: Nothing builds the PUB and PUSH sockets.  They are the
+// sockets of the targets in the passive mode of the heartbeat.
 
-impl TryFrom<tmq::Multipart> for Multipart {
-    type Error = Box<dyn std::error::Error>;
+/// The PUB socket.
+#[allow(dead_code)]
+pub(crate) struct Pub;
 
-    fn try_from(source: tmq::Multipart) -> Result<Self> {
-        let mut v = vec![];
-        for msg in source {
-            v.push(msg.try_into()?);
+/// The SUB socket.
+pub(crate) struct Sub;
+
+/// The PUSH socket.
+#[allow(dead_code)]
+pub(crate) struct Push;
+
+/// The PULL socket.
+pub(crate) struct Pull;
+
+/// Applies the options of the `SocketBuilder` `$builder` to the tmq
+/// socket builder `$socket`, and connects or binds it as `$bind`
+/// says.
+macro_rules! open {
+    ($socket:expr, $builder:expr, $bind:expr) => {{
+        let mut socket = $socket;
+        if let Some(linger) = $builder.linger {
+            socket = socket.set_linger(if linger { 1 } else { 0 });
         }
-        Ok(Multipart(v))
+        if $bind {
+            socket.bind(&$builder.endpoint)
+        } else {
+            socket.connect(&$builder.endpoint)
+        }
+    }};
+}
+
+impl SocketType for Req {
+    type Socket = SocketSender;
+
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        Ok(SocketSender {
+            socket: open!(tmq::request(&builder.context), builder, bind)?,
+            timeout: builder.timeout,
+        })
     }
 }
 
-impl Deref for Multipart {
-    type Target = [Message];
+impl SocketType for Rep {
+    type Socket = SocketReceiver;
 
-    fn deref(&self) -> &[Message] {
-        &self.0
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        Ok(SocketReceiver {
+            socket: open!(tmq::reply(&builder.context), builder, bind)?,
+            timeout: builder.timeout,
+        })
     }
 }
 
-/// Represents an error that may occur during a message reception.
-#[derive(Debug)]
-pub(crate) enum RecvError {
-    /// A timeout occurred waiting for a message to arrive.
-    Timeout,
-    /// Some other kind of error occurred waiting for or receiving a
-    /// message.
-    Other(Error),
+impl SocketType for Pub {
+    type Socket = Publisher;
+
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        Ok(Publisher {
+            socket: open!(tmq::publish(&builder.context), builder, bind)?,
+        })
+    }
 }
 
-impl std::error::Error for RecvError {}
+impl SocketType for Sub {
+    type Socket = Subscriber;
 
-impl Display for RecvError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            RecvError::Timeout => write!(f, "timeout"),
-            RecvError::Other(e) => write!(f, "other({})", e),
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        let mut socket = open!(tmq::subscribe(&builder.context), builder, bind)?.subscribe(b"")?;
+        if !builder.topics.is_empty() {
+            socket.unsubscribe(b"")?;
+            for topic in &builder.topics {
+                socket.subscribe(topic.as_bytes())?;
+            }
         }
+        Ok(Subscriber {
+            socket,
+            timeout: builder.timeout,
+        })
     }
 }
 
-/// Represents the type of socket to build with [`SocketBuilder`].
-pub(crate) enum SocketType {
-    /// The REQ socket.
-    Req,
-    // The REP socket.
-    // Rep,
+impl SocketType for Push {
+    type Socket = Pusher;
+
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        Ok(Pusher {
+            socket: open!(tmq::push(&builder.context), builder, bind)?,
+        })
+    }
+}
+
+impl SocketType for Pull {
+    type Socket = Puller;
+
+    fn open(builder: SocketBuilder<Self>, bind: bool) -> Result<Self::Socket> {
+        Ok(Puller {
+            socket: open!(tmq::pull(&builder.context), builder, bind)?,
+            timeout: builder.timeout,
+        })
+    }
 }
 
 /// Configures and builds a ZeroMQ socket.
@@ -139,11 +188,14 @@ pub(crate) enum SocketType {
 /// configure.  You can configure your socket by calling one or more
 /// modifier methods provided.  Once the configuration is complete,
 /// invoking the [`connect`](#method.connect) method establishes a
-/// connection.
+/// connection, and the [`bind`](#method.bind) method listens for
+/// them.  Any type of socket can do either.
 ///
 /// Some modifiers are mandatory, such as
 /// [`endpoint`](#method.endpoint).  Calling
 /// [`connect`](#method.connect) before mandatory modifiers will fail.
+/// The type of the socket is REQ unless a modifier such as
+/// [`subscribe`](#method.subscribe) changes it.
 ///
 /// # Example
 ///
@@ -162,16 +214,28 @@ pub(crate) enum SocketType {
 ///     .connect()?
 ///
 /// let socket = socket.send_keyword(kw!["hello"]).await?;
-/// let (response, socket) = socket.recv_string().await?;
-/// println!("{}", response);
+/// let (response, socket) = socket.recv_multipart().await?;
+/// println!("{}", response[0].as_str());
 /// // Send more message with the returned socket.
 /// ```
-pub(crate) struct SocketBuilder {
+///
+/// Create a `SocketBuilder` to produce a SUB socket:
+///
+/// ```rust
+/// let mut socket = SocketBuilder::new(context)
+///     .endpoint("tcp://127.0.0.1:8889")
+///     .subscribe()
+///     .topic("beat")
+///     .connect()?;
+/// let message = socket.recv_multipart().await?;
+/// ```
+pub(crate) struct SocketBuilder<T = Req> {
     context: Context,
     endpoint: String,
     timeout: Option<u64>,
     linger: Option<bool>,
-    socket_type: SocketType,
+    topics: Vec<String>,
+    socket_type: PhantomData<T>,
 }
 
 impl SocketBuilder {
@@ -183,10 +247,13 @@ impl SocketBuilder {
             endpoint: Default::default(),
             timeout: None,
             linger: None,
-            socket_type: SocketType::Req,
+            topics: vec![],
+            socket_type: PhantomData,
         }
     }
+}
 
+impl<T: SocketType> SocketBuilder<T> {
     /// Sets the endpoint for the socket.
     pub(crate) fn endpoint(mut self, endpoint: &str) -> Self {
         self.endpoint = endpoint.to_owned();
@@ -206,27 +273,71 @@ impl SocketBuilder {
     }
 
     /// Sets the socket type to REQ (request).
-    pub(crate) fn req(mut self) -> Self {
-        self.socket_type = SocketType::Req;
-        self
+    pub(crate) fn req(self) -> SocketBuilder<Req> {
+        self.retype()
     }
 
-    /// Connects to the configured endpoint and returns a
-    /// `SocketSender` for sending messages.
-    pub(crate) fn connect(self) -> Result<SocketSender> {
-        use SocketType::*;
-        let mut builder = match self.socket_type {
-            Req => tmq::request(&self.context),
-            // _ => unimplemented!(),
-        };
-        if let Some(linger) = self.linger {
-            builder = builder.set_linger(if linger { 1 } else { 0 });
-        }
-        let socket = builder.connect(&self.endpoint)?;
-        Ok(SocketSender {
-            socket,
+    /// Sets the socket type to REP (reply).
+    pub(crate) fn rep(self) -> SocketBuilder<Rep> {
+        self.retype()
+    }
+
+    /// Sets the socket type to PUB (publish).
+    #[allow(dead_code)]
+    pub(crate) fn publish(self) -> SocketBuilder<Pub> {
+        self.retype()
+    }
+
+    /// Sets the socket type to SUB (subscribe).  The socket receives
+    /// all messages unless [`topic`](#method.topic) narrows them.
+    pub(crate) fn subscribe(self) -> SocketBuilder<Sub> {
+        self.retype()
+    }
+
+    /// Sets the socket type to PUSH.
+    #[allow(dead_code)]
+    pub(crate) fn push(self) -> SocketBuilder<Push> {
+        self.retype()
+    }
+
+    /// Sets the socket type to PULL.
+    pub(crate) fn pull(self) -> SocketBuilder<Pull> {
+        self.retype()
+    }
+
+    /// Connects to the configured endpoint and returns the socket
+    /// for the type, such as a `SocketSender` for REQ.
+    pub(crate) fn connect(self) -> Result<T::Socket> {
+        T::open(self, false)
+    }
+
+    /// Binds to the configured endpoint and returns the socket for
+    /// the type, such as a `SocketReceiver` for REP.
+    pub(crate) fn bind(self) -> Result<T::Socket> {
+        T::open(self, true)
+    }
+
+    /// Changes the type of the socket to build, keeping the rest of
+    /// the configuration.
+    fn retype<U: SocketType>(self) -> SocketBuilder<U> {
+        SocketBuilder {
+            context: self.context,
+            endpoint: self.endpoint,
             timeout: self.timeout,
-        })
+            linger: self.linger,
+            topics: self.topics,
+            socket_type: PhantomData,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl SocketBuilder<Sub> {
+    /// Adds a topic to subscribe to.  The socket receives the
+    /// messages whose first frame starts with the topic.
+    pub(crate) fn topic(mut self, topic: &str) -> Self {
+        self.topics.push(topic.to_owned());
+        self
     }
 }
 
@@ -251,8 +362,8 @@ impl SocketBuilder {
 /// let socket = // build a REQ socket with SocketBuilder.
 ///
 /// let socket = socket.send_keyword(kw!["hello"]).await?;
-/// let (response, socket) = socket.recv_string().await?;
-/// println!("{}", response);
+/// let (response, socket) = socket.recv_multipart().await?;
+/// println!("{}", response[0].as_str());
 /// // Send more message with the returned socket.
 /// ```
 pub(crate) struct SocketSender {
@@ -280,8 +391,9 @@ impl SocketSender {
     /// ```rust
     /// use crate::keyword::kw;
     /// let socket = socket.send_keyword(kw!["hello"]).await?;
-    /// println!("{}", socket.recv_string().await?);
+    /// println!("{}", socket.recv_multipart().await?.0[0].as_str());
     /// ```
+    #[allow(dead_code)]
     pub(crate) async fn send_keyword(self, keyword: Keyword) -> Result<SocketReceiver> {
         Ok(SocketReceiver {
             socket: self.socket.send(vec![keyword.name()].into()).await?,
@@ -309,8 +421,9 @@ impl SocketSender {
     /// ```rust
     /// use crate::keyword::kw;
     /// let socket = socket.send_keywords(&[kw!["hello"], kw!["world"]]).await?;
-    /// println!("{}", socket.recv_string().await?);
+    /// println!("{}", socket.recv_multipart().await?.0[0].as_str());
     /// ```
+    #[allow(dead_code)]
     pub(crate) async fn send_keywords(self, keywords: &[Keyword]) -> Result<SocketReceiver> {
         let socket = self
             .socket
@@ -327,6 +440,54 @@ impl SocketSender {
             timeout: self.timeout,
         })
     }
+
+    /// Sends a multipart message, such as one of the messages the
+    /// [`protocol`](crate::protocol) module builds.  Consumes the
+    /// socket, but produces a new socket for waiting for and
+    /// receiving the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `multipart` - The multipart message to send.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`Ok`] with a [`SocketReceiver`] for receiving the
+    /// response if successful.  Otherwise returns [`Err`] with the
+    /// error object.
+    pub(crate) async fn send_multipart(self, multipart: Multipart) -> Result<SocketReceiver> {
+        Ok(SocketReceiver {
+            socket: self.socket.send(multipart.into()).await?,
+            timeout: self.timeout,
+        })
+    }
+
+    /// Sends a string.  Consumes the socket, but produces a new
+    /// socket for receiving the next message.
+    ///
+    /// # Arguments
+    ///
+    /// * `string` - The string to send.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`Ok`] with a [`SocketReceiver`] for receiving the
+    /// next message if successful.  Otherwise returns [`Err`] with
+    /// the error object.
+    ///
+    /// # Examples
+    ///
+    /// Reply to a request with a string:
+    /// ```rust
+    /// let (request, socket) = socket.recv_request().await?;
+    /// let socket = socket.send_string("(:result :ok)").await?;
+    /// ```
+    pub(crate) async fn send_string(self, string: &str) -> Result<SocketReceiver> {
+        Ok(SocketReceiver {
+            socket: self.socket.send(vec![string].into()).await?,
+            timeout: self.timeout,
+        })
+    }
 }
 
 /// Represents a ZeroMQ socket for receiving a message.
@@ -350,8 +511,8 @@ impl SocketSender {
 /// // Initialise a ZMQ context.
 /// let socket = // build a REP socket with SocketBuilder.
 ///
-/// let (message, socket) = socket.recv_string().await?;
-/// println!("Received message: {}", message);
+/// let (message, socket) = socket.recv_multipart().await?;
+/// println!("Received message: {}", message[0].as_str());
 /// let socket = socket.send_keyword(kw!["ok"]).await?;
 /// // Receive more message with the returned socket.
 /// ```
@@ -361,52 +522,6 @@ pub(crate) struct SocketReceiver {
 }
 
 impl SocketReceiver {
-    /// Receives a message as a string.  Consumes the socket, but
-    /// produces a new socket for sending a response.
-    ///
-    /// # Returns
-    ///
-    /// Returns [`Ok`] with a tuple if successful.  The first element
-    /// of the tuple is the message received as a string.  The second
-    /// is a [`SocketSender`] for sending the response. On failure,
-    /// returns [`Err`] with the error object.
-    ///
-    /// # Examples
-    ///
-    /// Receive a string and return the response:
-    /// ```rust
-    /// use crate::keyword::kw;
-    /// let socket = // Prepare an REP socket.
-    /// let (message, socket) = socket.recv_string().await?;
-    /// println!("Message received: {}", message);
-    /// let socket = socket.send_keyword(kw!["ok"]).await?;
-    /// // Use socket to receive further message.
-    /// ```
-    pub(crate) async fn recv_string(
-        self,
-    ) -> std::result::Result<(String, SocketSender), RecvError> {
-        let timeout = if let Some(timeout) = self.timeout {
-            timeout
-        } else {
-            DEFAULT_SOCKET_TIMEOUT
-        };
-
-        match tokio::time::timeout(Duration::from_millis(timeout), self.socket.recv()).await {
-            Ok(result) => result
-                .map(|(multipart, sender)| {
-                    (
-                        multipart[0].as_str().unwrap().to_owned(),
-                        SocketSender {
-                            socket: sender,
-                            timeout: self.timeout,
-                        },
-                    )
-                })
-                .map_err(|err| RecvError::Other(Box::new(err))),
-            Err(_elapsed) => Err(RecvError::Timeout),
-        }
-    }
-
     /// Receives a multipart message.  Consumes the socket, but
     /// produces a new socket for sending a response.
     ///
@@ -451,4 +566,144 @@ impl SocketReceiver {
             Err(_elapsed) => Err(RecvError::Timeout),
         }
     }
+
+    /// Waits for a request as long as it takes, and receives it as a
+    /// multipart message.  Unlike the other `recv_*` methods, doesn't
+    /// time out, as a REP socket has no idea when the next request
+    /// comes.  Consumes the socket, but produces a new socket for
+    /// sending the response.
+    ///
+    /// # Examples
+    ///
+    /// Serve requests with a REP socket:
+    /// ```rust
+    /// use crate::keyword::kw;
+    /// let mut socket = // Prepare an REP socket.
+    /// loop {
+    ///     let (request, sender) = socket.recv_request().await?;
+    ///     socket = sender.send_keyword(kw!["ok"]).await?;
+    /// }
+    /// ```
+    pub(crate) async fn recv_request(self) -> Result<(Multipart, SocketSender)> {
+        let (multipart, sender) = self.socket.recv().await?;
+        Ok((
+            multipart.try_into()?,
+            SocketSender {
+                socket: sender,
+                timeout: self.timeout,
+            },
+        ))
+    }
+}
+
+/// Represents a PUB socket, which sends messages to all of its
+/// subscribers.
+///
+/// A message that has no subscriber to take it is dropped.  The
+/// first frame of a message is its topic, which the subscribers
+/// filter the messages by.
+#[allow(dead_code)]
+pub(crate) struct Publisher {
+    socket: tmq::publish::Publish,
+}
+
+#[allow(dead_code)]
+impl Publisher {
+    /// Publishes a multipart message, whose first frame is the topic.
+    pub(crate) async fn send_multipart(&mut self, multipart: Multipart) -> Result<()> {
+        Ok(self.socket.send(tmq::Multipart::from(multipart)).await?)
+    }
+}
+
+/// Represents a SUB socket, which receives the messages of the topics
+/// it subscribes to.
+pub(crate) struct Subscriber {
+    socket: tmq::subscribe::Subscribe,
+    #[allow(dead_code)]
+    timeout: Option<u64>,
+}
+
+impl Subscriber {
+    /// Adds a topic to subscribe to.
+    #[allow(dead_code)]
+    pub(crate) fn subscribe(&mut self, topic: &str) -> Result<()> {
+        Ok(self.socket.subscribe(topic.as_bytes())?)
+    }
+
+    /// Receives the next message of the topics subscribed to, or
+    /// times out.
+    #[allow(dead_code)]
+    pub(crate) async fn recv_multipart(&mut self) -> std::result::Result<Multipart, RecvError> {
+        recv_next(&mut self.socket, self.timeout).await
+    }
+
+    /// Receives the next message, or times out after `timeout`
+    /// milliseconds in place of the timeout of the socket.
+    pub(crate) async fn recv_within(
+        &mut self,
+        timeout: u64,
+    ) -> std::result::Result<Multipart, RecvError> {
+        recv_next(&mut self.socket, Some(timeout)).await
+    }
+}
+
+/// Represents a PUSH socket, which sends each message to one of its
+/// pullers in turn.
+#[allow(dead_code)]
+pub(crate) struct Pusher {
+    socket: tmq::push::Push,
+}
+
+#[allow(dead_code)]
+impl Pusher {
+    /// Sends a multipart message to the next puller.
+    pub(crate) async fn send_multipart(&mut self, multipart: Multipart) -> Result<()> {
+        Ok(self.socket.send(tmq::Multipart::from(multipart)).await?)
+    }
+}
+
+/// Represents a PULL socket, which receives the messages of the
+/// pushers.
+pub(crate) struct Puller {
+    socket: tmq::pull::Pull,
+    #[allow(dead_code)]
+    timeout: Option<u64>,
+}
+
+impl Puller {
+    /// Receives the next message, or times out.
+    #[allow(dead_code)]
+    pub(crate) async fn recv_multipart(&mut self) -> std::result::Result<Multipart, RecvError> {
+        recv_next(&mut self.socket, self.timeout).await
+    }
+
+    /// Receives the next message, or times out after `timeout`
+    /// milliseconds in place of the timeout of the socket.
+    pub(crate) async fn recv_within(
+        &mut self,
+        timeout: u64,
+    ) -> std::result::Result<Multipart, RecvError> {
+        recv_next(&mut self.socket, Some(timeout)).await
+    }
+}
+
+/// Receives the next message out of the `stream` of a SUB or PULL
+/// socket within the `timeout` in milliseconds, or
+/// [`DEFAULT_SOCKET_TIMEOUT`] if there is none.
+async fn recv_next<S>(
+    stream: &mut S,
+    timeout: Option<u64>,
+) -> std::result::Result<Multipart, RecvError>
+where
+    S: futures::Stream<Item = tmq::Result<tmq::Multipart>> + Unpin,
+{
+    let timeout = timeout.unwrap_or(DEFAULT_SOCKET_TIMEOUT);
+    match tokio::time::timeout(Duration::from_millis(timeout), stream.next()).await {
+        Ok(Some(result)) => result
+            .map_err(|err| RecvError::Other(Box::new(err)))?
+            .try_into()
+            .map_err(RecvError::Other),
+        Ok(None) => Err(RecvError::Other(illegal_state_error("socket closed"))),
+        Err(_elapsed) => Err(RecvError::Timeout),
+    }
 }
@@ -0,0 +1,120 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::output::StderrTail;
+use crate::result::Result;
+use tokio::process::Child;
+
+/// Returns the two endpoints the instances of the target alternate
+/// between, TARGET-ENDPOINT and SPARE-ENDPOINT, if WARM-SPARE is on.
+///
+/// # Configuration
+///
+/// * WARM-SPARE: Optional.  `t` keeps a standby instance of the
+///   target running on the other endpoint.  The default is `nil`.
+/// * SPARE-ENDPOINT: Required by WARM-SPARE.  The endpoint of the
+///   standby instance, such as `"tcp://127.0.0.1:5556"`.
+///
+/// # Errors
+///
+/// Returns a configuration error if WARM-SPARE is on, but
+/// TARGET-ENDPOINT isn't a single endpoint or SPARE-ENDPOINT is
+/// missing.
+pub(crate) fn spare_endpoints(config: &Config) -> Result<Option<(String, String)>> {
+    let section = config.section(section::HEARTBEAT)?;
+    if !section.boolean_or(key::WARM_SPARE, false)? {
+        return Ok(None);
+    }
+    match section.target_endpoints()?.as_slice() {
+        [primary] => Ok(Some((
+            primary.to_owned(),
+            section.string(key::SPARE_ENDPOINT)?.to_owned(),
+        ))),
+        _ => Err(config_format_error(&format!(
+            "{} needs a single {}",
+            key::WARM_SPARE,
+            key::TARGET_ENDPOINT
+        ))),
+    }
+}
+
+/// A standby instance of the target.
+///
+/// A slow-booting target takes a long time to recover from a
+/// restart.  With WARM-SPARE, `ProcessManager` keeps a second
+/// instance running on an endpoint that receives no traffic.  When
+/// the primary instance fails, `Heartbeat2` promotes the spare to
+/// the primary at once, and starts a new spare on the endpoint the
+/// failed instance has left.
+///
+/// A spare that is dropped without being promoted gets killed, so
+/// that no standby instance outlives the supervision.
+pub(crate) struct Spare {
+    child: Option<Child>,
+    stderr: Option<StderrTail>,
+    endpoint: String,
+}
+
+impl Spare {
+    /// Creates a new `Spare` of the running `child` that listens on
+    /// the `endpoint`.  `stderr` is the [`StderrTail`] of the `child`,
+    /// if its output is captured.
+    pub(crate) fn new(child: Child, stderr: Option<StderrTail>, endpoint: String) -> Self {
+        Spare {
+            child: Some(child),
+            stderr,
+            endpoint,
+        }
+    }
+
+    /// Returns the PID of the spare, or `None` if it has exited.
+    pub(crate) fn pid(&self) -> Option<u32> {
+        self.child.as_ref().and_then(|child| child.id())
+    }
+
+    /// Returns the endpoint of the spare.
+    pub(crate) fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Returns whether the spare is still running.
+    pub(crate) fn is_running(&mut self) -> bool {
+        self.child
+            .as_mut()
+            .is_some_and(|child| matches!(child.try_wait(), Ok(None)))
+    }
+
+    /// Promotes the spare, and returns its process to supervise as
+    /// the primary with its [`StderrTail`], if any.
+    pub(crate) fn into_child(mut self) -> (Child, Option<StderrTail>) {
+        (
+            self.child.take().expect("spare already promoted"),
+            self.stderr.take(),
+        )
+    }
+}
+
+impl Drop for Spare {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.start_kill();
+        }
+    }
+}
@@ -0,0 +1,420 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::error::ErrorType;
+use crate::kw;
+use crate::logger::{message, LogLevel, Logger};
+use crate::metrics::Metrics;
+use crate::timeline::Timeline;
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use tokio::sync::watch;
+
+/// Represents the state of the supervised target as a whole.
+///
+/// `TargetState` combines the status of the managed process with the
+/// status of the heartbeat into a single state.  A running process
+/// that doesn't answer heartbeats, for example, is `Unresponsive`.
+/// The specification under spec/heartbeat.pdf describes the
+/// behaviour of the components that drive the state.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use crate::state::TargetState;
+///
+/// assert!(TargetState::Ready.can_become(TargetState::Running));
+/// assert!(!TargetState::Terminated.can_become(TargetState::Running));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TargetState {
+    /// The process is ready to start or restart.
+    Ready,
+    /// The process is running and answering heartbeats.
+    Running,
+    /// The process is running, but it isn't ready to serve.
+    Degraded,
+    /// The process is running, but it has missed a heartbeat.
+    Unresponsive,
+    /// The process has aborted or has been forcibly killed.
+    Killed,
+    /// The process has terminated normally, or `Heartbeat2` has
+    /// given up on it.
+    Terminated,
+    /// The process didn't exit after it was killed.
+    Stuck,
+}
+
+/// The transitions of the [`TargetState`] the specification allows,
+/// each with the event that causes it.
+static TRANSITIONS: &[(TargetState, TargetState, &str)] = {
+    use TargetState::*;
+    &[
+        (Ready, Running, "the process starts"),
+        (Running, Degraded, "the probe says the process isn't ready"),
+        (Running, Unresponsive, "a heartbeat times out"),
+        (Running, Killed, "the process aborts or gets killed"),
+        (
+            Running,
+            Terminated,
+            "the process completes or a signal is relayed",
+        ),
+        (
+            Degraded,
+            Running,
+            "the probe says the process is ready again",
+        ),
+        (Degraded, Unresponsive, "a heartbeat times out"),
+        (Degraded, Killed, "the process aborts or gets killed"),
+        (
+            Degraded,
+            Terminated,
+            "the process completes or a signal is relayed",
+        ),
+        (Unresponsive, Killed, "the process gets killed"),
+        (Unresponsive, Terminated, "a signal is relayed"),
+        (Killed, Ready, "the process is about to restart"),
+        (Killed, Terminated, "Heartbeat2 gives up"),
+        (
+            Killed,
+            Stuck,
+            "the process doesn't exit after it was killed",
+        ),
+        (
+            Terminated,
+            Stuck,
+            "the process doesn't exit after it was killed",
+        ),
+        (Stuck, Terminated, "Heartbeat2 gives up"),
+    ]
+};
+
+impl TargetState {
+    /// All the states.
+    const ALL: [TargetState; 7] = [
+        TargetState::Ready,
+        TargetState::Running,
+        TargetState::Degraded,
+        TargetState::Unresponsive,
+        TargetState::Killed,
+        TargetState::Terminated,
+        TargetState::Stuck,
+    ];
+
+    /// Returns whether the state can change to `next`, as the table of
+    /// the transitions the specification allows says.
+    pub(crate) fn can_become(self, next: TargetState) -> bool {
+        TRANSITIONS
+            .iter()
+            .any(|&(from, to, _)| from == self && to == next)
+    }
+
+    /// Returns the states reachable from the state in any number of
+    /// transitions, including the state itself.
+    fn reachable(self) -> Vec<TargetState> {
+        let mut reached = vec![self];
+        let mut index = 0;
+        while let Some(&state) = reached.get(index) {
+            for &(from, to, _) in TRANSITIONS {
+                if from == state && !reached.contains(&to) {
+                    reached.push(to);
+                }
+            }
+            index += 1;
+        }
+        reached
+    }
+
+    /// Returns whether the process has stopped, either by being
+    /// killed or by terminating.  A stuck process counts as stopped,
+    /// as `Heartbeat2` no longer supervises it.
+    pub(crate) fn is_stopped(self) -> bool {
+        matches!(
+            self,
+            TargetState::Killed | TargetState::Terminated | TargetState::Stuck
+        )
+    }
+}
+
+/// Model-checks the table of the transitions against the properties
+/// the specification demands of the supervision as a whole, and
+/// returns the properties it violates:
+///
+/// * No state moves to itself, so that every transition is an event.
+/// * Every state is reachable from `Ready`, the initial state.
+/// * `Terminated` is reachable from every state, so that the
+///   supervision can always end.
+/// * Only `Ready` starts the process, and only `Degraded` recovers
+///   to `Running`.
+/// * A stopped process only leaves the stopped states to restart.
+fn spec_violations() -> Vec<String> {
+    use TargetState::*;
+    let mut violations = vec![];
+    for &(from, to, _) in TRANSITIONS {
+        if from == to {
+            violations.push(format!("{} moves to itself", from));
+        }
+        if to == Running && !matches!(from, Ready | Degraded) {
+            violations.push(format!("{} starts the process", from));
+        }
+        if from.is_stopped() && !to.is_stopped() && to != Ready {
+            violations.push(format!("{} revives the process as {}", from, to));
+        }
+    }
+    let initial = Ready.reachable();
+    for state in TargetState::ALL {
+        if !initial.contains(&state) {
+            violations.push(format!("{} is unreachable", state));
+        }
+        if !state.reachable().contains(&Terminated) {
+            violations.push(format!("{} never terminates", state));
+        }
+    }
+    violations
+}
+
+impl Display for TargetState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TargetState::*;
+        match self {
+            Ready => write!(f, "ready"),
+            Running => write!(f, "running"),
+            Degraded => write!(f, "degraded"),
+            Unresponsive => write!(f, "unresponsive"),
+            Killed => write!(f, "killed"),
+            Terminated => write!(f, "terminated"),
+            Stuck => write!(f, "stuck"),
+        }
+    }
+}
+
+/// Tracks the [`TargetState`] and validates its transitions.
+///
+/// Components of `Heartbeat2` share a `StateMachine` to move the
+/// target from one state to another.  `StateMachine` rejects
+/// transitions [`TargetState::can_become`] doesn't allow.  In a debug
+/// build, it model-checks the table of the transitions against the
+/// specification as it starts, and panics at a divergence.  It logs
+/// every transition, counts it in the metrics and publishes the new
+/// state to its subscribers.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use crate::state::{StateMachine, TargetState};
+///
+/// let state = StateMachine::new(metrics, timeline, logger);
+/// let mut subscriber = state.subscribe();
+/// state.transition(TargetState::Running)?;
+/// assert_eq!(*subscriber.borrow_and_update(), TargetState::Running);
+/// ```
+pub(crate) struct StateMachine {
+    state: Cell<TargetState>,
+    publisher: watch::Sender<TargetState>,
+    metrics: Rc<Metrics>,
+    timeline: Rc<Timeline>,
+    logger: Rc<dyn Logger>,
+}
+
+impl StateMachine {
+    /// Creates a new `StateMachine` in the `Ready` state.
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - A shared reference to the metrics.
+    /// * `timeline` - A shared reference to the timeline.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(
+        metrics: Rc<Metrics>,
+        timeline: Rc<Timeline>,
+        logger: Rc<dyn Logger>,
+    ) -> Self {
+        debug_assert!(
+            spec_violations().is_empty(),
+            "state transitions diverge from the specification: {:?}",
+            spec_violations()
+        );
+        let (publisher, _) = watch::channel(TargetState::Ready);
+        StateMachine {
+            state: Cell::new(TargetState::Ready),
+            publisher,
+            metrics,
+            timeline,
+            logger,
+        }
+    }
+
+    /// Returns the current state.
+    pub(crate) fn get(&self) -> TargetState {
+        self.state.get()
+    }
+
+    /// Moves the target to the `next` state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an illegal transition error if the current state can't
+    /// become `next`.  The state stays the same in this case.
+    pub(crate) fn transition(&self, next: TargetState) -> Result<(), ErrorType> {
+        let current = self.get();
+        if current.can_become(next) {
+            self.logger.log(
+                LogLevel::Debug,
+                &format!("target state: {} -> {}", current, next),
+            );
+            self.state.set(next);
+            self.metrics.add_transition(current, next);
+            self.timeline
+                .record(kw![state], &format!("{} -> {}", current, next));
+            self.publisher.send_replace(next);
+            Ok(())
+        } else {
+            self.logger.log_coded(
+                &message::ST001,
+                &format!("illegal target state transition: {} -> {}", current, next),
+            );
+            Err(ErrorType::IllegalTransition(
+                current.to_string(),
+                next.to_string(),
+            ))
+        }
+    }
+
+    /// Subscribes to the changes of the state.
+    ///
+    /// # Returns
+    ///
+    /// Returns a receiver that observes the latest state.
+    #[allow(dead_code)]
+    pub(crate) fn subscribe(&self) -> watch::Receiver<TargetState> {
+        self.publisher.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{key, section, Config};
+    use crate::logger::LocalLogger;
+    use proptest::prelude::*;
+    use TargetState::*;
+
+    fn state_machine() -> StateMachine {
+        let logger: Rc<dyn Logger> = Rc::new(LocalLogger::new("heartbeat2-test"));
+        let mut config = Config::new();
+        config
+            .section_mut(section::HEARTBEAT)
+            .set(key::TARGET_ID, kw![target]);
+        let metrics = Metrics::new(Rc::new(config), Rc::clone(&logger)).expect("metrics");
+        StateMachine::new(Rc::new(metrics), Rc::new(Timeline::new()), logger)
+    }
+
+    /// Returns whether the table of the transitions lists the move
+    /// from `from` to `to`.
+    fn listed(from: TargetState, to: TargetState) -> bool {
+        TRANSITIONS.iter().any(|&(f, t, _)| f == from && t == to)
+    }
+
+    /// Replays the `events`, each the state it moves the target to,
+    /// and checks each outcome against [`TRANSITIONS`].  Returns the
+    /// final state.
+    fn replay(events: &[TargetState]) -> TargetState {
+        let state = state_machine();
+        let mut subscriber = state.subscribe();
+        for &next in events {
+            let current = state.get();
+            let result = state.transition(next);
+            if listed(current, next) {
+                assert!(result.is_ok(), "{} -> {} rejected", current, next);
+                assert_eq!(state.get(), next);
+            } else {
+                assert!(
+                    matches!(result, Err(ErrorType::IllegalTransition(..))),
+                    "{} -> {} accepted",
+                    current,
+                    next
+                );
+                assert_eq!(state.get(), current);
+            }
+            assert_eq!(*subscriber.borrow_and_update(), state.get());
+        }
+        state.get()
+    }
+
+    #[test]
+    fn transitions_meet_the_specification() {
+        assert_eq!(spec_violations(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn lifecycle_with_a_restart() {
+        assert_eq!(
+            replay(&[
+                Running,
+                Degraded,
+                Running,
+                Unresponsive,
+                Killed,
+                Ready,
+                Running,
+                Terminated
+            ]),
+            Terminated
+        );
+    }
+
+    #[test]
+    fn give_up_on_a_stuck_process() {
+        assert_eq!(replay(&[Running, Killed, Stuck, Terminated]), Terminated);
+    }
+
+    #[test]
+    fn stopped_process_is_not_revived() {
+        for stopped in [Killed, Terminated, Stuck] {
+            for next in [Running, Degraded, Unresponsive] {
+                assert!(!stopped.can_become(next), "{} -> {}", stopped, next);
+            }
+        }
+        assert_eq!(replay(&[Running, Terminated, Running, Ready]), Terminated);
+        assert_eq!(replay(&[Running, Killed, Running, Degraded]), Killed);
+    }
+
+    #[test]
+    fn no_state_moves_to_itself() {
+        for state in TargetState::ALL {
+            assert!(!state.can_become(state), "{}", state);
+        }
+        assert_eq!(replay(&[Ready, Running, Running]), Running);
+    }
+
+    #[test]
+    fn recovery_only_from_degraded() {
+        assert_eq!(replay(&[Running, Unresponsive, Running]), Unresponsive);
+        assert_eq!(replay(&[Running, Degraded, Running]), Running);
+    }
+
+    proptest! {
+        #[test]
+        fn every_transition_follows_the_table(
+            events in prop::collection::vec(prop::sample::select(TargetState::ALL.to_vec()), 0..32),
+        ) {
+            replay(&events);
+        }
+    }
+}
@@ -16,14 +16,30 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::config::section::Section;
 use crate::config::{key, section, Config};
-use crate::error::{mapping_missing_error, unknown_response_error};
+use crate::context::Context;
+use crate::error::{
+    mapping_missing_error, missing_section_error, service_unreachable_error, unknown_response_error,
+};
 use crate::keyword::Keyword;
 use crate::kw;
+use crate::logger::{message, Logger};
+use crate::naming::Naming;
+use crate::protocol::{self, Multipart};
 use crate::result::Result;
 use crate::socket::SocketBuilder;
+use futures::future::LocalBoxFuture;
+use std::cell::RefCell;
 use std::rc::Rc;
-use tmq::Context;
+use tokio::time::{sleep, Duration};
+
+/// The default number of retries of a lookup Sup doesn't answer.
+static DEFAULT_RETRIES: i64 = 3;
+
+/// The default time in milliseconds to wait before the first retry of
+/// a lookup.  The wait doubles for each retry after.
+static DEFAULT_RETRY_BACKOFF: i64 = 500;
 
 /// Acts as a proxy for Sup.
 ///
@@ -34,29 +50,56 @@ use tmq::Context;
 /// # Examples
 ///
 /// Create a new Sup proxy and query it for a service:
-/// ```rust
+/// ```rust,ignore
 /// use crate::keyword::kw;
 /// use crate::sup::Sup;
 ///
 /// let context = // Create a ZMQ context.
-/// let config = // Load the SUP section of the configuration.
+/// let config = // Load the configuration.
 /// let sup = Sup::with_context(context, config);
 /// let endpoint = sup.sget(kw!["logger"]).await?;
 /// ```
+///
+/// Register a service, and remove it again:
+/// ```rust,ignore
+/// sup.sput(&kw!["app"], "tcp://10.0.0.5:5555").await?;
+/// sup.sdel(&kw!["app"]).await?;
+/// ```
+///
+/// # Configuration
+///
+/// In the SUP section, which [`SupConfig`] resolves the first time Sup
+/// is asked, and again after a reload of the configuration:
+///
+/// * ENDPOINT: Required.  The endpoint of Sup.
+/// * COMMS-TIMEOUT: Required.  The time in milliseconds to wait for
+///   Sup to answer.
+/// * RETRIES: Optional.  The number of times to retry a lookup that
+///   Sup doesn't answer.  The default is 3.
+/// * RETRY-BACKOFF: Optional.  The time in milliseconds to wait
+///   before the first retry.  The wait doubles for each retry after.
+///   The default is 500.
 pub(crate) struct Sup {
     context: Context,
-    config: Rc<Config>,
+    config: SupConfig,
 }
 
 impl Sup {
     /// Creates a new Sup proxy with the given ZMQ context.
     ///
-    /// The provided configuration must contain the configuration for
-    /// Sup under the section [`section::SUP`].  Invoking
-    /// [`sget`](#method.sget) without having done so will result in
-    /// an error.
+    /// The configuration of Sup is resolved only when Sup is first
+    /// asked.  See [`SupConfig`].
     pub(crate) fn with_context(context: Context, config: Rc<Config>) -> Self {
-        Sup { context, config }
+        Sup {
+            context,
+            config: SupConfig::new(config),
+        }
+    }
+
+    /// Logs the loading of the configuration of Sup to the `logger`.
+    pub(crate) fn logger(mut self, logger: Rc<dyn Logger>) -> Self {
+        self.config = self.config.logger(logger);
+        self
     }
 
     /// Queries Sup to resolve the name of a service to its endpoint.
@@ -68,31 +111,242 @@ impl Sup {
     /// # Error
     ///
     /// Raises an error if:
-    /// * the configuration is missing under [`section::SUP`]; or
-    /// * a required configuration item is missing.
+    /// * the configuration of Sup can't be resolved;
+    /// * a required configuration item is missing;
+    /// * Sup doesn't know the service; or
+    /// * Sup doesn't answer, even after RETRIES retries.
     ///
     /// # Examples
     ///
     /// See the struct documentation.
     pub(crate) async fn sget(&self, id: &Keyword) -> Result<String> {
-        let comms_timeout = self
-            .config
-            .section(section::SUP)?
-            .integer(key::COMMS_TIMEOUT)?;
+        let reply = self.call(protocol::get(id)).await?;
+        if reply[0] == kw![endpoint] {
+            Ok(reply[1].as_str().to_owned())
+        } else if reply[0] == kw![missing] && reply[1] == kw![endpoint] {
+            Err(mapping_missing_error(id.name()))
+        } else {
+            Err(unknown_response_error(reply[0].as_str()))
+        }
+    }
+
+    /// Registers the `endpoint` of the service `id` with Sup, so that
+    /// [`sget`](#method.sget) resolves the service to it.  Replaces
+    /// the endpoint Sup knew for the service, if any.
+    ///
+    /// # Error
+    ///
+    /// Raises an error as [`sget`](#method.sget) does, except that
+    /// Sup knows any service, or if Sup doesn't acknowledge the
+    /// registration.
+    pub(crate) async fn sput(&self, id: &Keyword, endpoint: &str) -> Result<()> {
+        acknowledge(self.call(protocol::put(id, endpoint)).await?)
+    }
+
+    /// Removes the service `id` from Sup, so that Sup no longer
+    /// resolves it.
+    ///
+    /// # Error
+    ///
+    /// Raises an error as [`sput`](#method.sput) does.
+    pub(crate) async fn sdel(&self, id: &Keyword) -> Result<()> {
+        acknowledge(self.call(protocol::delete(id)).await?)
+    }
+
+    /// Sends the `request` to Sup, and returns its answer.  Retries
+    /// the `request` up to RETRIES times while Sup doesn't answer.
+    async fn call(&self, request: Multipart) -> Result<Multipart> {
+        let Settings {
+            endpoint,
+            timeout,
+            retries,
+            mut backoff,
+        } = self.config.settings()?;
+        let mut attempt = 0;
+        loop {
+            match self.request(&request, &endpoint, timeout).await {
+                Ok(reply) if reply.is_empty() => return Err(unknown_response_error("")),
+                Ok(reply) => return Ok(reply),
+                Err(_) if attempt < retries => {
+                    attempt += 1;
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => {
+                    return Err(service_unreachable_error(&format!(
+                        "Sup at {} after {} retries: {}",
+                        endpoint, retries, err
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Sends the `request` to Sup at the `endpoint` once, waiting up
+    /// to `timeout` milliseconds for the answer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the answer of Sup, which may tell of an error itself,
+    /// such as a missing mapping.  Returns an error instead if Sup
+    /// can't be reached, or doesn't answer in time, which is worth a
+    /// retry.
+    async fn request(
+        &self,
+        request: &Multipart,
+        endpoint: &str,
+        timeout: u64,
+    ) -> Result<Multipart> {
         let socket = SocketBuilder::new(self.context.clone())
-            .endpoint(self.config.section(section::SUP)?.string(key::ENDPOINT)?)
+            .endpoint(endpoint)
             .linger(false)
-            .timeout(comms_timeout.try_into()?)
+            .timeout(timeout)
             .req()
             .connect()?;
-        let recv_sock = socket.send_keywords(&[kw![get], id.clone()]).await?;
+        let recv_sock = socket.send_multipart(request.clone()).await?;
         let (multipart, _) = recv_sock.recv_multipart().await?;
-        if multipart[0] == kw![endpoint] {
-            Ok(multipart[1].as_str().to_owned())
-        } else if multipart[0] == kw![missing] && multipart[1] == kw![endpoint] {
-            Err(mapping_missing_error(id.name()))
-        } else {
-            Err(unknown_response_error(multipart[0].as_str()))
+        Ok(multipart)
+    }
+}
+
+/// Resolves the configuration of Sup when Sup is first asked.
+///
+/// Whether the supervision needs Sup at all depends on the endpoints
+/// the configuration leaves to it, which may change when the
+/// configuration is reloaded.  Instead of deciding at startup,
+/// `SupConfig` takes the SUP section of the configuration if there is
+/// one, and otherwise loads it from sup.cfg in the configuration
+/// directory of the user.  It validates the section when it first
+/// needs it, and keeps it for the later requests until the next
+/// [`reload`](Self::reload).  A supervision that never asks Sup
+/// doesn't need sup.cfg.
+pub(crate) struct SupConfig {
+    config: RefCell<Rc<Config>>,
+    loaded: RefCell<Option<Settings>>,
+    logger: Option<Rc<dyn Logger>>,
+}
+
+impl SupConfig {
+    /// Creates a resolver that prefers the SUP section of the
+    /// `config`.
+    pub(crate) fn new(config: Rc<Config>) -> Self {
+        SupConfig {
+            config: RefCell::new(config),
+            loaded: RefCell::new(None),
+            logger: None,
+        }
+    }
+
+    /// Logs the path of sup.cfg to the `logger` when it is loaded.
+    pub(crate) fn logger(mut self, logger: Rc<dyn Logger>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Takes the reloaded `config`, and forgets sup.cfg, so that the
+    /// next request resolves and validates the SUP section again.
+    pub(crate) fn reload(&self, config: Rc<Config>) {
+        *self.config.borrow_mut() = config;
+        self.loaded.take();
+    }
+
+    /// Returns the settings in the SUP section, resolving the section
+    /// on the first call after a [`reload`](Self::reload), if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the platform has no configuration
+    /// directory, if sup.cfg can't be loaded, or if the section lacks
+    /// ENDPOINT or COMMS-TIMEOUT, or gives the options the wrong
+    /// types.  A failed resolution is tried again on the next call.
+    fn settings(&self) -> Result<Settings> {
+        let config = Rc::clone(&self.config.borrow());
+        if let Ok(section) = config.section(section::SUP) {
+            return Settings::from_section(section);
         }
+        if let Some(settings) = self.loaded.borrow().as_ref() {
+            return Ok(settings.clone());
+        }
+        let settings = Settings::from_section(&self.load()?)?;
+        *self.loaded.borrow_mut() = Some(settings.clone());
+        Ok(settings)
+    }
+
+    /// Loads the SUP section from sup.cfg.
+    fn load(&self) -> Result<Section> {
+        let mut path = dirs::config_dir().ok_or_else(|| missing_section_error(section::SUP))?;
+        path.push("sup");
+        path.push("sup.cfg");
+        if let Some(logger) = &self.logger {
+            logger.log_coded(
+                &message::SV011,
+                &format!("sup config: {}", path.to_string_lossy()),
+            );
+        }
+        let mut section = Section::new();
+        section.load_from_path(&path)?;
+        Ok(section)
+    }
+}
+
+/// The options in the SUP section that every request to Sup needs.
+#[derive(Clone)]
+struct Settings {
+    endpoint: String,
+    timeout: u64,
+    retries: i64,
+    backoff: Duration,
+}
+
+impl Settings {
+    /// Reads the settings from the SUP `section`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `section` lacks ENDPOINT or
+    /// COMMS-TIMEOUT, or gives the options the wrong types.
+    fn from_section(section: &Section) -> Result<Self> {
+        Ok(Settings {
+            endpoint: section.string(key::ENDPOINT)?.to_owned(),
+            timeout: section.integer(key::COMMS_TIMEOUT)?.try_into()?,
+            retries: section.integer_or(key::RETRIES, DEFAULT_RETRIES)?,
+            backoff: Duration::from_millis(
+                section
+                    .integer_or(key::RETRY_BACKOFF, DEFAULT_RETRY_BACKOFF)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+/// Accepts the `reply` of Sup to a request that changes its mappings
+/// if it is `OK`.
+fn acknowledge(reply: Multipart) -> Result<()> {
+    if reply[0] == kw![ok] {
+        Ok(())
+    } else {
+        Err(unknown_response_error(reply[0].as_str()))
+    }
+}
+
+impl Naming for Sup {
+    fn resolve<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(self.sget(id))
+    }
+
+    fn register<'a>(
+        &'a self,
+        id: &'a Keyword,
+        endpoint: &'a str,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(self.sput(id, endpoint))
+    }
+
+    fn deregister<'a>(&'a self, id: &'a Keyword) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(self.sdel(id))
+    }
+
+    fn reload(&self, config: &Rc<Config>) {
+        self.config.reload(Rc::clone(config));
     }
 }
@@ -0,0 +1,1004 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::adhoc::{AdHoc, Check, RestartPolicy};
+use crate::callback::{Callbacks, Event, Notifier};
+use crate::channel::event_queue;
+use crate::config::{key, section, Config};
+use crate::context::Context;
+use crate::control::ControlServer;
+use crate::device::WatchdogDevice;
+use crate::error::{usage_error, Error};
+use crate::event::EventHandler;
+use crate::expression::Expression;
+use crate::fleet::{is_manifest, Fleet};
+use crate::heartbeat::Heartbeat;
+use crate::history::{self, History};
+use crate::hook::HookRunner;
+use crate::keyword::Keyword;
+use crate::kw;
+use crate::logger::{message, LocalLogger, LogLevel, Logger, RemoteLogger};
+use crate::metrics::{Decision, Metrics, MetricsPusher};
+use crate::naming::{naming_service, Advertiser, LoadConfig};
+use crate::notify::Webhooks;
+use crate::process::{ProcessManager, RunProcess};
+use crate::reaper::Reaper;
+use crate::release::PortRelease;
+use crate::restart::RestartManager;
+use crate::result::Result;
+use crate::selftest::SelfTest;
+use crate::setup::Wizard;
+use crate::shutdown::host_is_shutting_down;
+use crate::signal::SignalHandler;
+use crate::state::StateMachine;
+#[cfg(feature = "sup")]
+use crate::sup::Sup;
+use crate::timeline::Timeline;
+use crate::usage::UsageMonitor;
+use crate::watchdog::Watchdog;
+use futures::future::join_all;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, ExitStatus};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// The unique app identifier
+static APP_ID: &str = "HEARTBEAT";
+
+/// The path to the configuration file.
+static DEFAULT_CONFIG_FILE_NAME: &str = "heartbeat.cfg";
+
+/// The size of the event queue
+static EVENT_QUEUE_SIZE: usize = 1;
+
+/// The command-line option for the init mode.
+static INIT_OPTION: &str = "--init";
+
+/// The command-line option printing the reference table of the codes
+/// of the log messages.
+static MESSAGES_OPTION: &str = "--messages";
+
+/// The command that supervises an ad-hoc command without a
+/// configuration file.
+static RUN_COMMAND: &str = "run";
+
+/// The command that writes a configuration file by asking the user.
+static SETUP_COMMAND: &str = "setup";
+
+/// The command that checks the environment of `Heartbeat2`.
+static SELFTEST_COMMAND: &str = "selftest";
+
+/// The command that summarises the history of the target.
+static HISTORY_COMMAND: &str = "history";
+
+/// The command-line option selecting the number of days `history`
+/// summarises.
+static DAYS_OPTION: &str = "--days";
+
+/// The number of days `history` summarises without `--days`.
+static DEFAULT_HISTORY_DAYS: u32 = 7;
+
+/// The command that resolves a service ID with Sup, and prints its
+/// endpoint.
+static SUP_LOOKUP_COMMAND: &str = "sup-lookup";
+
+/// The command-line option selecting a configuration profile.
+static PROFILE_OPTION: &str = "--profile";
+
+/// The environment variable selecting a configuration profile.
+static PROFILE_VARIABLE: &str = "HEARTBEAT2_PROFILE";
+
+/// A configuration profile to apply, by the way it was selected.
+#[derive(Clone)]
+enum Profile {
+    /// A profile selected with `--profile`, or by the library user.
+    /// The configuration must have it.
+    Explicit(String),
+    /// A profile selected with `HEARTBEAT2_PROFILE`.  The variable
+    /// may be set for the whole host, so a configuration without
+    /// PROFILES ignores it.
+    Environment(String),
+}
+
+impl Profile {
+    /// Returns the name of the profile.
+    fn name(&self) -> &str {
+        match self {
+            Profile::Explicit(name) | Profile::Environment(name) => name,
+        }
+    }
+}
+
+/// Supervises the target the `config` describes.  Registers the
+/// target with the `fleet`, if any.  Reloads the configuration with
+/// `load`, if any, on the `RELOAD` control request.
+async fn main_impl(
+    config: Config,
+    load: Option<LoadConfig>,
+    logger: Rc<dyn Logger>,
+    fleet: Option<&Fleet>,
+    callbacks: Rc<Callbacks>,
+) -> Result<ExitCode> {
+    let started = Instant::now();
+    let config = Rc::new(config);
+    let context = Context::new();
+    let naming = naming_service(context.clone(), Rc::clone(&config), Rc::clone(&logger))?;
+    let local = logger
+        .local()
+        .with_instance(&config.section(section::HEARTBEAT)?.instance_id()?);
+    let remote_logger = Rc::new(RemoteLogger::new(
+        context.clone(),
+        Rc::clone(&naming),
+        Rc::clone(&config),
+        local.clone(),
+    ));
+    let log_remotely = remote_logger.is_enabled()?;
+    let logger: Rc<dyn Logger> = if log_remotely {
+        remote_logger.clone()
+    } else {
+        Rc::new(local)
+    };
+    let mut notifier = Notifier::new(callbacks);
+    if let Some(webhooks) = Webhooks::from_config(&config, Rc::clone(&logger))? {
+        notifier = notifier.webhooks(webhooks);
+    }
+    let notifier = Rc::new(notifier);
+    logger.log_coded(
+        &message::SV001,
+        &format!(
+            "start heartbeat process (PID {}) for target [{}]",
+            std::process::id(),
+            config.section(section::HEARTBEAT)?.target_id()?
+        ),
+    );
+    let heartbeat_section = config.section(section::HEARTBEAT)?;
+    let advertiser = Rc::new(Advertiser::new(
+        Rc::clone(&naming),
+        Rc::clone(&config),
+        Rc::clone(&logger),
+    ));
+    if heartbeat_section.has_key(key::ADVERTISE_ENDPOINT) && !advertiser.is_enabled()? {
+        let endpoint = heartbeat_section.string(key::ADVERTISE_ENDPOINT)?;
+        logger.log_coded(
+            &message::SV002,
+            &format!("advertise endpoint: {}", endpoint),
+        );
+        naming
+            .register(heartbeat_section.target_id()?, endpoint)
+            .await?;
+    }
+
+    let metrics = Rc::new(Metrics::new(Rc::clone(&config), Rc::clone(&logger))?);
+    let timeline = Rc::new(Timeline::new());
+    let history = Rc::new(History::new(Rc::clone(&config), Rc::clone(&logger)));
+    let state = Rc::new(StateMachine::new(
+        Rc::clone(&metrics),
+        Rc::clone(&timeline),
+        Rc::clone(&logger),
+    ));
+
+    let (event_sender, event_receiver) = event_queue(EVENT_QUEUE_SIZE, Rc::clone(&logger));
+    let process_manager = Rc::new(ProcessManager::new(
+        event_sender.named("ProcessManager"),
+        Rc::clone(&state),
+        Rc::clone(&config),
+        Rc::clone(&metrics),
+        Rc::clone(&timeline),
+        Rc::clone(&notifier),
+        Rc::clone(&logger),
+    ));
+    let mut heartbeat = Heartbeat::new(
+        context.clone(),
+        event_sender.named("Heartbeat"),
+        Rc::clone(&state),
+        Rc::clone(&config),
+        Rc::clone(&naming),
+        Rc::clone(&timeline),
+        Rc::clone(&logger),
+    )
+    .process_manager(Rc::clone(&process_manager))
+    .metrics(Rc::clone(&metrics))
+    .history(Rc::clone(&history))
+    .advertiser(Rc::clone(&advertiser))
+    .notifier(Rc::clone(&notifier));
+    if let Some(load) = load {
+        heartbeat = heartbeat.config_loader(load);
+    }
+    let heartbeat = Rc::new(heartbeat);
+    let signal_handler = Rc::new(SignalHandler::new(
+        event_sender.named("SignalHandler"),
+        Rc::clone(&config),
+        Rc::clone(&logger),
+    ));
+
+    let mut event_handler = EventHandler::new(
+        event_receiver,
+        Rc::clone(&state),
+        Rc::clone(&process_manager),
+        Rc::clone(&heartbeat),
+        Rc::clone(&signal_handler),
+        Rc::clone(&config),
+        Rc::clone(&logger),
+    );
+
+    let mut restart_manager =
+        RestartManager::new(Rc::clone(&config), Rc::clone(&logger))?.metrics(Rc::clone(&metrics));
+    let port_release = PortRelease::new(Rc::clone(&config), Rc::clone(&logger));
+    let hook_runner = HookRunner::new(Rc::clone(&config), Rc::clone(&logger));
+
+    let reaper = Reaper::new(
+        Rc::clone(&process_manager),
+        Rc::clone(&config),
+        Rc::clone(&logger),
+    );
+
+    let watchdog_device =
+        WatchdogDevice::new(Rc::clone(&state), Rc::clone(&config), Rc::clone(&logger));
+
+    let usage_monitor =
+        UsageMonitor::new(Rc::clone(&metrics), Rc::clone(&config), Rc::clone(&logger));
+
+    let metrics_pusher =
+        MetricsPusher::new(Rc::clone(&metrics), Rc::clone(&config), Rc::clone(&logger));
+
+    let watchdog = Watchdog::new(
+        event_sender.named("Watchdog"),
+        Rc::clone(&state),
+        Rc::clone(&config),
+        Rc::clone(&logger),
+    );
+
+    let control_server = Rc::new(
+        ControlServer::new(
+            context.clone(),
+            Rc::clone(&state),
+            Rc::clone(&process_manager),
+            Rc::clone(&metrics),
+            Rc::clone(&timeline),
+            Rc::clone(&config),
+            Rc::clone(&logger),
+        )
+        .event_sender(event_sender.named("ControlServer")),
+    );
+    if let Some(fleet) = fleet {
+        fleet.register(
+            config.section(section::HEARTBEAT)?.target_id()?,
+            Rc::clone(&control_server),
+            signal_handler.join_fleet(),
+        );
+    }
+
+    let supervise = async {
+        // NOTE: The permit holds the turn of the target to restart in
+        // the fleet until the process is about to start again.
+        let mut restart_permit = None;
+        let outcome = loop {
+            drop(restart_permit.take());
+            let (_, run_process, _) = tokio::try_join!(
+                heartbeat.run(),
+                process_manager.run_process(),
+                event_handler.run(),
+            )?;
+            match run_process {
+                RunProcess::Abort(reason) | RunProcess::Restart(reason) => {
+                    // NOTE: A restart an operator or the target
+                    // requested doesn't count against the retries.
+                    let requested = matches!(run_process, RunProcess::Restart(_));
+                    if !requested {
+                        restart_manager.add_process_abort()?;
+                        if host_is_shutting_down().await {
+                            logger.log_coded(
+                                &message::SV003,
+                                "host is shutting down; don't restart process",
+                            );
+                            metrics.add_decision(Decision::GaveUp);
+                            timeline.record(Keyword::new("GIVE-UP"), "host shutting down");
+                            notifier.notify(Event::GiveUp("host shutting down".to_owned()));
+                            process_manager.give_up()?;
+                            break run_process;
+                        }
+                    }
+                    if requested || restart_manager.should_process_restart()? {
+                        let pending = if requested {
+                            None
+                        } else {
+                            restart_manager.pending_restart()
+                        };
+                        if let Some((wait, begin)) = pending {
+                            let at = begin.format("%H:%M").to_string();
+                            logger
+                                .log_coded(&message::SV013, &format!("restart pending at {}", at));
+                            metrics.add_decision(Decision::Pending);
+                            timeline.record(Keyword::new("RESTART-PENDING"), &at);
+                            notifier.notify(Event::RestartPending(at.clone()));
+                            tokio::select! {
+                                _ = sleep(wait) => {}
+                                signal = signal_handler.held_termination() => {
+                                    logger.log_coded(
+                                        &message::SV014,
+                                        &format!("restart pending at {} cancelled on [{:?}]", at, signal),
+                                    );
+                                    process_manager.give_up()?;
+                                    break run_process;
+                                }
+                            }
+                        }
+                        logger.log_coded(
+                            &message::SV004,
+                            &format!("attempt to restart process after {}", reason),
+                        );
+                        metrics.add_decision(Decision::Restarted);
+                        metrics.add_restart(reason);
+                        history.add_restart();
+                        timeline.record(kw![restart], &reason.to_string());
+                        notifier.notify(Event::Restart(reason));
+                        if let Some(fleet) = fleet {
+                            let id = config.section(section::HEARTBEAT)?.target_id()?;
+                            restart_permit = fleet.restart_permit(id).await?;
+                        }
+                        // NOTE: A spare listens on the other endpoint,
+                        // so it needn't wait for the failed process to
+                        // release its resources.
+                        if let Some(endpoint) = process_manager.promote_spare() {
+                            heartbeat.redirect(endpoint);
+                        } else {
+                            let delay = restart_manager.restart_delay()?;
+                            if !delay.is_zero() {
+                                logger.log_coded(
+                                    &message::SV005,
+                                    &format!("wait {}s before restart", delay.as_secs()),
+                                );
+                                sleep(delay).await;
+                            }
+                            if port_release.is_enabled()? {
+                                port_release.wait().await?;
+                            }
+                        }
+                        process_manager.reset()?;
+                        heartbeat.reset();
+                        event_handler.reset();
+                        // Drop through to the beginning of the loop.
+                    } else {
+                        logger.log_coded(&message::SV006, "giving up due to too many retries");
+                        metrics.add_decision(Decision::GaveUp);
+                        timeline.record(Keyword::new("GIVE-UP"), "too many retries");
+                        notifier.notify(Event::GiveUp("too many retries".to_owned()));
+                        process_manager.give_up()?;
+                        hook_runner.run_escalation("too many retries").await?;
+                        break run_process;
+                    }
+                }
+                RunProcess::Complete => {
+                    break run_process;
+                }
+                RunProcess::Stuck => {
+                    logger.log_coded(&message::SV007, "giving up on a stuck process");
+                    metrics.add_decision(Decision::GaveUp);
+                    timeline.record(Keyword::new("GIVE-UP"), "process stuck");
+                    notifier.notify(Event::GiveUp("process stuck".to_owned()));
+                    process_manager.give_up()?;
+                    hook_runner.run_escalation("process stuck").await?;
+                    break run_process;
+                }
+            }
+        };
+        process_manager.stop_spare();
+        Ok::<RunProcess, Error>(outcome)
+    };
+
+    // NOTE: SignalHandler, Reaper, Watchdog, WatchdogDevice,
+    // UsageMonitor, MetricsPusher, History and ControlServer outlive the
+    // restarts of the managed process.  They only return early on an
+    // error.
+    let reap_zombies = reaper.is_enabled()?;
+    let watch_self = watchdog.is_enabled();
+    let pet_device = watchdog_device.is_enabled();
+    let serve_control = control_server.is_enabled();
+    let push_metrics = metrics_pusher.is_enabled();
+    let keep_history = history.is_enabled()?;
+    let notify = notifier.is_enabled();
+    let outcome = tokio::select! {
+        result = supervise => result,
+        Err(err) = signal_handler.run() => Err(err),
+        Err(err) = reaper.run(), if reap_zombies => Err(err),
+        Err(err) = watchdog.run(), if watch_self => Err(err),
+        Err(err) = watchdog_device.run(), if pet_device => Err(err),
+        Err(err) = usage_monitor.run() => Err(err),
+        Err(err) = metrics_pusher.run(), if push_metrics => Err(err),
+        Err(err) = history.run(), if keep_history => Err(err),
+        Err(err) = control_server.run(), if serve_control => Err(err),
+        Err(err) = notifier.run(), if notify => Err(err),
+        Err(err) = remote_logger.run(), if log_remotely => Err(err),
+    };
+    logger.log_coded(
+        &message::SV008,
+        &format!(
+            "summary: {}",
+            summary(
+                started.elapsed(),
+                &outcome,
+                &state,
+                &metrics,
+                process_manager.exit_status()
+            )
+        ),
+    );
+    // NOTE: The watchdog device stays armed after a give-up, so that
+    // it reboots the machine.
+    if matches!(outcome, Ok(RunProcess::Complete)) {
+        watchdog_device.disarm();
+    }
+    advertiser.deregister().await;
+    notifier.flush().await;
+    metrics_pusher.flush().await;
+    history.flush();
+    remote_logger.flush().await;
+    let outcome = outcome?;
+
+    let section = config.section(section::HEARTBEAT)?;
+    if section.boolean_or(key::MIRROR_EXIT_STATUS, section.init()?)? {
+        Ok(mirror_exit_status(&outcome, process_manager.exit_status()))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Summarises a supervision session in a single record for the
+/// review of the logs, such as `(:uptime 3600 :restarts 2 :reasons
+/// (:abort 2) :outcome :complete :state :terminated :exit-code 0
+/// :signal ())`.  The reasons count the outcomes of the runs of the
+/// managed process.
+///
+/// # Arguments
+///
+/// * `uptime` - How long the supervision lasted.
+/// * `outcome` - The outcome of the last run of the managed process,
+///   or the error that ended the supervision.
+/// * `state` - The state machine of the target.
+/// * `metrics` - The counters of the supervision.
+/// * `status` - The exit status of the last run, if any.
+fn summary(
+    uptime: Duration,
+    outcome: &Result<RunProcess>,
+    state: &StateMachine,
+    metrics: &Metrics,
+    status: Option<ExitStatus>,
+) -> Expression {
+    let outcome = match outcome {
+        Ok(RunProcess::Complete) => Expression::from(kw![complete]),
+        Ok(RunProcess::Abort(_)) => Expression::from(Keyword::new("GIVE-UP")),
+        Ok(RunProcess::Restart(_)) => Expression::from(kw![restart]),
+        Ok(RunProcess::Stuck) => Expression::from(kw![stuck]),
+        Err(err) => Expression::from(err.to_string().as_str()),
+    };
+    let reasons = metrics
+        .outcomes()
+        .into_iter()
+        .map(|(name, count)| {
+            (
+                Keyword::new(&name.to_uppercase()),
+                Expression::from(count as i64),
+            )
+        })
+        .collect();
+    let optional =
+        |value: Option<i32>| value.map_or(Expression::from(false), |v| Expression::from(v as i64));
+    Expression::plist(vec![
+        (kw![uptime], Expression::from(uptime.as_secs() as i64)),
+        (kw![restarts], Expression::from(metrics.restarts() as i64)),
+        (kw![reasons], Expression::plist(reasons)),
+        (kw![outcome], outcome),
+        (
+            kw![state],
+            Expression::from(Keyword::new(&state.get().to_string().to_uppercase())),
+        ),
+        (
+            Keyword::new("EXIT-CODE"),
+            optional(status.and_then(|status| status.code())),
+        ),
+        (
+            kw![signal],
+            optional(status.and_then(|status| status.signal())),
+        ),
+    ])
+}
+
+/// Encodes the final exit status of the managed process as the exit
+/// code of `Heartbeat2`, so that a container runtime or a CI system
+/// sees the exit code of the managed process.  A death by signal N
+/// is encoded as 128+N, as in shells.
+///
+/// A process that got stuck or that `Heartbeat2` detached from has
+/// no exit status.  The former yields a failure, and the latter a
+/// success.
+///
+/// # Arguments
+///
+/// * `outcome` - The outcome of the last run of the managed process.
+/// * `status` - The exit status of the last run, if any.
+fn mirror_exit_status(outcome: &RunProcess, status: Option<ExitStatus>) -> ExitCode {
+    match (outcome, status) {
+        (RunProcess::Stuck, _) => ExitCode::FAILURE,
+        (_, Some(status)) => match (status.code(), status.signal()) {
+            (Some(code), _) => ExitCode::from(code as u8),
+            (None, Some(signal)) => ExitCode::from((128 + signal) as u8),
+            (None, None) => ExitCode::FAILURE,
+        },
+        (_, None) => ExitCode::SUCCESS,
+    }
+}
+
+/// Supervises a target, or a fleet of targets, as a configuration
+/// file describes, or a command without a configuration file.
+///
+/// `Supervisor` is the entry point of `Heartbeat2` as a library.  The
+/// supervision runs on a single thread, so the future of
+/// [`run`](Self::run) isn't `Send`.  Run it on a current-thread
+/// runtime or in a `LocalSet`.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use heartbeat2::Supervisor;
+///
+/// let exit_code = Supervisor::new("heartbeat.cfg")
+///     .profile("production")
+///     .run()
+///     .await?;
+/// ```
+///
+/// Supervise a command, restarting it when it stops accepting TCP
+/// connections:
+///
+/// ```rust,ignore
+/// use heartbeat2::callback::Callbacks;
+/// use heartbeat2::{Check, RestartPolicy, Supervisor};
+/// use std::time::Duration;
+///
+/// let exit_code = Supervisor::with_command(["./server", "--port", "8080"])
+///     .check(Check::Tcp("tcp://127.0.0.1:8080".to_owned()))
+///     .restart_policy(RestartPolicy::new(3, Duration::from_secs(600)))
+///     .callbacks(Callbacks::new().on_restart(|reason| async move {
+///         eprintln!("restarting after {}", reason)
+///     }))
+///     .run()
+///     .await?;
+/// ```
+pub struct Supervisor {
+    config_path: PathBuf,
+    profile: Option<Profile>,
+    init: bool,
+    messages: bool,
+    adhoc: Option<AdHoc>,
+    setup: bool,
+    selftest: bool,
+    history: Option<u32>,
+    sup_lookup: Option<String>,
+    callbacks: Callbacks,
+}
+
+impl Supervisor {
+    /// Creates a new `Supervisor` of the configuration file at
+    /// `config_path`.
+    pub fn new(config_path: impl Into<PathBuf>) -> Self {
+        Supervisor {
+            config_path: config_path.into(),
+            profile: None,
+            init: false,
+            messages: false,
+            adhoc: None,
+            setup: false,
+            selftest: false,
+            history: None,
+            sup_lookup: None,
+            callbacks: Callbacks::new(),
+        }
+    }
+
+    /// Creates a new `Supervisor` of the `command`, its program
+    /// followed by its arguments, without a configuration file.
+    ///
+    /// The command runs in the current directory with the defaults of
+    /// `heartbeat2 run`: it is only restarted when it crashes, up to 5
+    /// times within a minute, and the exit code mirrors that of the
+    /// command.  [`check`](Self::check),
+    /// [`restart_policy`](Self::restart_policy) and
+    /// [`heartbeat`](Self::heartbeat) replace the defaults.
+    pub fn with_command<I, S>(command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut supervisor = Supervisor::new(DEFAULT_CONFIG_FILE_NAME);
+        supervisor.adhoc = Some(AdHoc::new(command.into_iter().map(Into::into).collect()));
+        supervisor
+    }
+
+    /// Creates a new `Supervisor` from the command-line arguments
+    /// `args` without the name of the program.
+    ///
+    /// # Usage
+    ///
+    /// `heartbeat2 [--init] [--profile NAME] [--messages] [CONFIG-FILE]`
+    ///
+    /// `heartbeat2 [--init] run [OPTION ...] -- COMMAND [ARG ...]`
+    ///
+    /// `heartbeat2 setup [CONFIG-FILE]`
+    ///
+    /// `heartbeat2 selftest`
+    ///
+    /// `heartbeat2 [--profile NAME] history [--days N] [CONFIG-FILE]`
+    ///
+    /// `heartbeat2 sup-lookup ID`
+    ///
+    /// `--messages` prints the reference table of the codes of the log
+    /// messages instead of supervising.  Without `--profile`, the
+    /// environment variable `HEARTBEAT2_PROFILE` selects the profile,
+    /// if set, unless the configuration file has no PROFILES.  Without
+    /// a `CONFIG-FILE`, `Supervisor` reads heartbeat.cfg in the
+    /// current directory.  `run` supervises the `COMMAND` without a
+    /// configuration file, with the defaults [`AdHoc`] describes.
+    /// `setup` asks the user about the target, and writes the
+    /// `CONFIG-FILE` for it, as [`Wizard`] describes.  `selftest`
+    /// checks the environment, as [`SelfTest`] describes.  `history`
+    /// summarises the last `N` days in the history of the target, 7
+    /// without `--days`, as [`History`] describes.  `sup-lookup`
+    /// resolves the service `ID` with Sup, and prints its endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Returns a usage error if `--profile` lacks a profile name, if
+    /// `--days` lacks a number of days, if the arguments to `run` are
+    /// invalid, or if `sup-lookup` lacks a service ID.
+    pub fn from_args(
+        mut args: impl Iterator<Item = String>,
+    ) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        let mut init = false;
+        let mut messages = false;
+        let mut profile = std::env::var(PROFILE_VARIABLE)
+            .ok()
+            .map(Profile::Environment);
+        let mut config_path = None;
+        let mut adhoc = None;
+        let mut setup = false;
+        let mut selftest = false;
+        let mut history = None;
+        let mut sup_lookup = None;
+        while let Some(arg) = args.next() {
+            if arg == INIT_OPTION {
+                init = true;
+            } else if arg == MESSAGES_OPTION {
+                messages = true;
+            } else if arg == PROFILE_OPTION {
+                profile =
+                    Some(Profile::Explicit(args.next().ok_or_else(|| {
+                        usage_error("--profile requires a profile name")
+                    })?));
+            } else if arg == RUN_COMMAND && config_path.is_none() {
+                adhoc = Some(AdHoc::from_args(args.by_ref())?);
+            } else if arg == SUP_LOOKUP_COMMAND && config_path.is_none() {
+                sup_lookup = Some(
+                    args.next()
+                        .ok_or_else(|| usage_error("sup-lookup requires a service ID"))?,
+                );
+            } else if arg == SETUP_COMMAND && !setup && config_path.is_none() {
+                setup = true;
+            } else if arg == SELFTEST_COMMAND && !selftest && config_path.is_none() {
+                selftest = true;
+            } else if arg == HISTORY_COMMAND && history.is_none() && config_path.is_none() {
+                history = Some(DEFAULT_HISTORY_DAYS);
+            } else if arg == DAYS_OPTION && history.is_some() {
+                history = Some(
+                    args.next()
+                        .and_then(|days| days.parse().ok())
+                        .ok_or_else(|| usage_error("--days requires a number of days"))?,
+                );
+            } else if config_path.is_none() {
+                config_path = Some(arg);
+            }
+        }
+        let mut supervisor =
+            Supervisor::new(config_path.unwrap_or_else(|| DEFAULT_CONFIG_FILE_NAME.to_owned()));
+        supervisor.profile = profile;
+        supervisor.init = init;
+        supervisor.messages = messages;
+        supervisor.adhoc = adhoc;
+        supervisor.setup = setup;
+        supervisor.selftest = selftest;
+        supervisor.history = history;
+        supervisor.sup_lookup = sup_lookup;
+        Ok(supervisor)
+    }
+
+    /// Applies the profile by the `name` from PROFILES in the
+    /// configuration file over the rest of the configuration.
+    pub fn profile(mut self, name: &str) -> Self {
+        self.profile = Some(Profile::Explicit(name.to_owned()));
+        self
+    }
+
+    /// Turns the init mode on or off.
+    pub fn init(mut self, init: bool) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// Checks the health of the command with the `check`.  Applies
+    /// only to a `Supervisor` of a command.
+    pub fn check(mut self, check: Check) -> Self {
+        if let Some(adhoc) = &mut self.adhoc {
+            adhoc.check(check);
+        }
+        self
+    }
+
+    /// Restarts the command as the `policy` says.  Applies only to a
+    /// `Supervisor` of a command.
+    pub fn restart_policy(mut self, policy: RestartPolicy) -> Self {
+        if let Some(adhoc) = &mut self.adhoc {
+            adhoc.restart_policy(policy);
+        }
+        self
+    }
+
+    /// Checks the health of the command every `interval`, in whole
+    /// seconds, waiting up to `timeout` for each check.  Applies only
+    /// to a `Supervisor` of a command.
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        if let Some(adhoc) = &mut self.adhoc {
+            adhoc.heartbeat(interval, timeout);
+        }
+        self
+    }
+
+    /// Sets the `callbacks` on the events of the supervision.
+    pub fn callbacks(mut self, callbacks: Callbacks) -> Self {
+        self.callbacks = callbacks;
+        self
+    }
+
+    /// Supervises the target, or the fleet of targets, until the
+    /// supervision ends.
+    ///
+    /// # Returns
+    ///
+    /// Returns the exit code for `Heartbeat2`, which mirrors the exit
+    /// status of the managed process if MIRROR-EXIT-STATUS says so.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid, or if the
+    /// supervision fails.
+    pub async fn run(self) -> std::result::Result<ExitCode, Box<dyn std::error::Error>> {
+        if self.messages {
+            print!("{}", message::table());
+            return Ok(ExitCode::SUCCESS);
+        }
+        if self.setup {
+            Wizard::new(std::io::stdin().lock(), std::io::stdout()).run(&self.config_path)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        if self.selftest {
+            return Ok(if SelfTest::new(std::io::stdout()).run().await? {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        let logger: Rc<dyn Logger> = Rc::new(LocalLogger::new(APP_ID));
+        if let Some(id) = &self.sup_lookup {
+            sup_lookup(id, Rc::clone(&logger)).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        let profile = self.profile.as_ref();
+        if let Some(days) = self.history {
+            let config = load_config(&self.config_path, profile, false, logger.as_ref())?;
+            if is_manifest(&config)? {
+                return Err(usage_error(
+                    "history needs the configuration file of a target",
+                ));
+            }
+            history::summarise(&config, days, std::io::stdout())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        let callbacks = Rc::new(self.callbacks);
+        if let Some(adhoc) = &self.adhoc {
+            let config = adhoc.config(self.init)?;
+            for (key, value) in config.section(section::HEARTBEAT)?.iter() {
+                logger.log(LogLevel::Debug, &format!("config: {} {}", key, value));
+            }
+            return main_impl(config, None, logger, None, callbacks).await;
+        }
+        let config = load_config(&self.config_path, profile, self.init, logger.as_ref())?;
+        if is_manifest(&config)? {
+            run_fleet(
+                config,
+                &self.config_path,
+                profile,
+                self.init,
+                logger,
+                callbacks,
+            )
+            .await
+        } else {
+            let load = config_loader(&self.config_path, profile, self.init, Rc::clone(&logger));
+            main_impl(config, Some(load), logger, None, callbacks).await
+        }
+    }
+}
+
+/// Loads the configuration from the file at `path`.  Applies the
+/// `profile`, if any, and the `--init` option over it, and moves the
+/// METRICS options into a section of their own.  Ignores a profile
+/// from the environment with a warning if the configuration has no
+/// PROFILES.  Leaves a manifest as it is.  The configuration of Sup
+/// is loaded by Sup itself when it is first asked.
+fn load_config(
+    path: &Path,
+    profile: Option<&Profile>,
+    init: bool,
+    logger: &dyn Logger,
+) -> Result<Config> {
+    let mut config = Config::new();
+    logger.log_coded(
+        &message::SV009,
+        &format!("Load config from path: {}", path.to_string_lossy()),
+    );
+    config
+        .section_mut(section::HEARTBEAT)
+        .load_from_path(path)?;
+    if is_manifest(&config)? {
+        return Ok(config);
+    }
+    match profile {
+        Some(Profile::Environment(name))
+            if !config.section(section::HEARTBEAT)?.has_key(key::PROFILES) =>
+        {
+            logger.log_coded(
+                &message::SV015,
+                &format!(
+                    "ignore {} [{}]: no {} in config",
+                    PROFILE_VARIABLE,
+                    name,
+                    key::PROFILES
+                ),
+            );
+        }
+        Some(profile) => {
+            logger.log_coded(
+                &message::SV010,
+                &format!("Apply config profile: {}", profile.name()),
+            );
+            config
+                .section_mut(section::HEARTBEAT)
+                .apply_profile(profile.name())?;
+        }
+        None => {}
+    }
+    if init {
+        config.section_mut(section::HEARTBEAT).set(key::INIT, true);
+    }
+    for (key, value) in config.section(section::HEARTBEAT)?.iter() {
+        logger.log(LogLevel::Debug, &format!("config: {} {}", key, value));
+    }
+    if config.section(section::HEARTBEAT)?.has_key(key::METRICS) {
+        *config.section_mut(section::METRICS) = config
+            .section(section::HEARTBEAT)?
+            .subsection(key::METRICS)?;
+    }
+    Ok(config)
+}
+
+/// Returns the loader of the configuration from the file at `path`
+/// for a reload, which loads it as [`load_config`] does.
+fn config_loader(
+    path: &Path,
+    profile: Option<&Profile>,
+    init: bool,
+    logger: Rc<dyn Logger>,
+) -> LoadConfig {
+    let path = path.to_owned();
+    let profile = profile.cloned();
+    Box::new(move || load_config(&path, profile.as_ref(), init, logger.as_ref()))
+}
+
+/// Resolves the service `id` with Sup the way the supervision does,
+/// with the same configuration and timeouts, and prints its endpoint.
+/// This debugs the resolution of an endpoint apart from the
+/// supervision.  Sup only serves the lookup; nothing changes.
+#[cfg(feature = "sup")]
+async fn sup_lookup(id: &str, logger: Rc<dyn Logger>) -> Result<()> {
+    let id = Keyword::from(id.trim_start_matches(':').to_uppercase());
+    let endpoint = Sup::with_context(Context::new(), Rc::new(Config::new()))
+        .logger(logger)
+        .sget(&id)
+        .await?;
+    println!("{} {}", id, endpoint);
+    Ok(())
+}
+
+/// Stands in for the lookup with Sup without the `sup` feature.
+#[cfg(not(feature = "sup"))]
+async fn sup_lookup(_id: &str, _logger: Rc<dyn Logger>) -> Result<()> {
+    Err(usage_error("sup-lookup needs the sup feature"))
+}
+
+/// Supervises the targets the `manifest` lists concurrently, until
+/// all of them finish.  Each target logs under its TARGET-ID.  A
+/// target that fails doesn't affect the others.
+///
+/// # Returns
+///
+/// Returns success if all the targets finish without an error.
+async fn run_fleet(
+    manifest: Config,
+    manifest_path: &Path,
+    profile: Option<&Profile>,
+    init: bool,
+    logger: Rc<dyn Logger>,
+    callbacks: Rc<Callbacks>,
+) -> Result<ExitCode> {
+    let fleet = Fleet::new(Context::new(), Rc::new(manifest), Rc::clone(&logger))?;
+    let mut targets = vec![];
+    for path in fleet.target_paths(manifest_path)? {
+        let config = load_config(&path, profile, init, logger.as_ref())?;
+        let id = config.section(section::HEARTBEAT)?.target_id()?.clone();
+        fleet.add(id.clone());
+        let load = config_loader(&path, profile, init, Rc::clone(&logger));
+        targets.push((id, config, load));
+    }
+    // Finds a mistake in SHUTDOWN-ORDER before it is too late.
+    fleet.shutdown_order()?;
+    let supervise = join_all(targets.into_iter().map(|(id, config, load)| {
+        let logger: Rc<dyn Logger> = Rc::new(LocalLogger::new(&format!("{}{}", APP_ID, id)));
+        let fleet = &fleet;
+        let callbacks = Rc::clone(&callbacks);
+        async move {
+            let result = main_impl(
+                config,
+                Some(load),
+                Rc::clone(&logger),
+                Some(fleet),
+                callbacks,
+            )
+            .await;
+            match &result {
+                Ok(_) => fleet.finish(&id, "finished".to_owned()),
+                Err(err) => {
+                    logger.log_coded(&message::SV012, &format!("supervision failed: {}", err));
+                    fleet.finish(&id, format!("failed ({})", err));
+                }
+            }
+            result.is_ok()
+        }
+    }));
+    let serve_control = fleet.is_enabled();
+    let succeeded = tokio::select! {
+        succeeded = supervise => succeeded,
+        Err(err) = fleet.run(), if serve_control => return Err(err),
+        Err(err) = fleet.stop_on_signal() => return Err(err),
+    };
+    fleet.log_summary();
+    if succeeded.into_iter().all(|ok| ok) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::kw;
+use chrono::{DateTime, Local};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// The maximum number of entries a [`Timeline`] keeps.  The oldest
+/// entries make way for new ones beyond this.
+static MAX_ENTRIES: usize = 1000;
+
+/// An entry in the [`Timeline`].
+struct Entry {
+    time: DateTime<Local>,
+    event: Keyword,
+    detail: String,
+}
+
+/// Records what happens to the managed process over a supervision
+/// session.
+///
+/// Components of `Heartbeat2` record the spawns and exits of the
+/// managed process, the signals and kills, the transitions of the
+/// target state, the heartbeats missed and the restart decisions.
+/// Rather than an entry per heartbeat answered, `Timeline` keeps a
+/// count, and records a summary before the next entry.  Reconstructing
+/// an incident then takes a copy of the timeline rather than a search
+/// through the logs.  [`ControlServer`](crate::control::ControlServer)
+/// exports the timeline.
+///
+/// `Timeline` keeps up to [`MAX_ENTRIES`] entries.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let timeline = Timeline::new();
+/// timeline.record(kw![spawn], "PID 1234");
+/// timeline.beat();
+/// println!("{}", timeline.to_text());
+/// ```
+pub(crate) struct Timeline {
+    entries: RefCell<VecDeque<Entry>>,
+    beats: Cell<u64>,
+}
+
+impl Timeline {
+    /// Creates a new, empty `Timeline`.
+    pub(crate) fn new() -> Self {
+        Timeline {
+            entries: Default::default(),
+            beats: Cell::new(0),
+        }
+    }
+
+    /// Records the `event` with the `detail`.
+    pub(crate) fn record(&self, event: Keyword, detail: &str) {
+        self.summarize_beats();
+        self.push(event, detail.to_owned());
+    }
+
+    /// Counts a heartbeat the target answered.
+    pub(crate) fn beat(&self) {
+        self.beats.set(self.beats.get() + 1);
+    }
+
+    /// Returns the timeline as a list of property lists, such as
+    /// `((:time "..." :event :spawn :detail "PID 1234"))`.
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    pub(crate) fn to_expression(&self) -> Expression {
+        self.summarize_beats();
+        Expression::List(
+            self.entries
+                .borrow()
+                .iter()
+                .map(|entry| {
+                    Expression::plist(vec![
+                        (
+                            kw![time],
+                            Expression::from(entry.time.to_rfc3339().as_str()),
+                        ),
+                        (kw![event], Expression::from(entry.event.clone())),
+                        (kw![detail], Expression::from(entry.detail.as_str())),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the timeline as text, an entry per line.
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    pub(crate) fn to_text(&self) -> String {
+        self.summarize_beats();
+        let mut text = String::new();
+        for entry in self.entries.borrow().iter() {
+            let _ = writeln!(
+                text,
+                "{} {} {}",
+                entry.time,
+                entry.event.name().to_lowercase(),
+                entry.detail
+            );
+        }
+        text
+    }
+
+    /// Records the heartbeats answered since the last entry, if any.
+    fn summarize_beats(&self) {
+        let beats = self.beats.replace(0);
+        if beats > 0 {
+            self.push(kw![beats], format!("{} answered", beats));
+        }
+    }
+
+    fn push(&self, event: Keyword, detail: String) {
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            time: Local::now(),
+            event,
+            detail,
+        });
+    }
+}
@@ -0,0 +1,707 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(not(feature = "zmq"))]
+use crate::config::key;
+use crate::context::Context;
+#[cfg(not(feature = "zmq"))]
+use crate::error::config_format_error;
+use crate::keyword::Keyword;
+#[cfg(any(test, feature = "zmq"))]
+use crate::protocol;
+use crate::protocol::Multipart;
+use crate::result::Result;
+#[cfg(feature = "zmq")]
+use crate::socket::{Puller, RecvError, SocketBuilder, Subscriber};
+use futures::future::LocalBoxFuture;
+#[cfg(any(test, feature = "zmq"))]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::collections::VecDeque;
+
+/// The reply of the target to a heartbeat.  An answer carries the
+/// telemetry the target attached to it, if any.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Reply {
+    /// The target answered, and is ready.
+    Ready(Vec<(Keyword, String)>),
+    /// The target answered, but is not ready yet.
+    NotReady(Vec<(Keyword, String)>),
+    /// The target answered, and serves, but is impaired.
+    Degraded(Vec<(Keyword, String)>),
+    /// The target answered, but may be slow to answer the next
+    /// heartbeat.
+    Busy(Vec<(Keyword, String)>),
+    /// The target answered, but wants to be restarted.
+    RestartMe(Vec<(Keyword, String)>),
+    /// The target rejected the heartbeat, as another supervisor owns
+    /// its lease, with the owner it named, if any.
+    Rejected(Option<String>),
+    /// The target didn't answer within the timeout.
+    Timeout,
+    /// The target echoed a sequence number other than the one of the
+    /// heartbeat, with the number it echoed, if any.
+    Stale(Option<u64>),
+}
+
+impl Reply {
+    /// Tells the status of the target by its `answer` to a heartbeat
+    /// or by its liveness message.
+    #[cfg(feature = "zmq")]
+    fn from_answer(answer: &Multipart) -> Self {
+        if protocol::is_rejected(answer) {
+            return Reply::Rejected(protocol::owner(answer));
+        }
+        let telemetry = protocol::telemetry(answer);
+        if protocol::is_not_ready(answer) {
+            Reply::NotReady(telemetry)
+        } else if protocol::is_degraded(answer) {
+            Reply::Degraded(telemetry)
+        } else if protocol::is_busy(answer) {
+            Reply::Busy(telemetry)
+        } else if protocol::is_restart_me(answer) {
+            Reply::RestartMe(telemetry)
+        } else {
+            Reply::Ready(telemetry)
+        }
+    }
+}
+
+/// The kind of socket that receives the liveness messages of the
+/// target in the passive mode of the heartbeat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PassiveSocket {
+    /// A SUB socket, for a target that publishes its liveness.
+    Sub,
+    /// A PULL socket, for a target that pushes its liveness.
+    Pull,
+}
+
+/// Carries a heartbeat to the target and its reply back.
+///
+/// [`Heartbeat`](crate::heartbeat::Heartbeat) decides when to beat and
+/// what a reply means for the target; `Transport` only moves the
+/// messages.  Keeping the wire behind the trait lets the decisions be
+/// driven without a socket, by `ChannelTransport` in the tests.
+pub(crate) trait Transport {
+    /// Sends the heartbeat `request` to the `endpoint`, and waits up
+    /// to `timeout` milliseconds for the reply.  If the `request`
+    /// carries a sequence number, a reply that doesn't echo it is
+    /// [`Reply::Stale`].  The reply comes on the socket the `request`
+    /// went out on, so that the check is only a defensive one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the heartbeat can't be sent.  A reply that
+    /// never comes is [`Reply::Timeout`] rather than an error.
+    fn beat<'a>(
+        &'a self,
+        endpoint: &'a str,
+        request: Multipart,
+        timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>>;
+
+    /// Waits for the liveness messages the target sends on its own,
+    /// in the passive mode of the heartbeat.  Takes all the messages
+    /// that arrived since the last call, and replies with the latest.
+    /// Waits up to `timeout` milliseconds for one if none has.  The
+    /// `socket` connects to the `endpoint`, or binds to it if `bind`
+    /// is `true`, and stays open for the next call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket can't be opened.  A message that
+    /// never comes is [`Reply::Timeout`] rather than an error.
+    fn listen<'a>(
+        &'a self,
+        endpoint: &'a str,
+        socket: PassiveSocket,
+        bind: bool,
+        timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>>;
+}
+
+/// The socket of a [`ZmqTransport`] in the passive mode, with the
+/// endpoint it is open on.
+#[cfg(feature = "zmq")]
+enum Listening {
+    Sub(String, Subscriber),
+    Pull(String, Puller),
+}
+
+#[cfg(feature = "zmq")]
+impl Listening {
+    /// Returns the endpoint the socket is open on.
+    fn endpoint(&self) -> &str {
+        match self {
+            Listening::Sub(endpoint, _) | Listening::Pull(endpoint, _) => endpoint,
+        }
+    }
+
+    /// Receives the next message, or times out after `timeout`
+    /// milliseconds.
+    async fn recv(&mut self, timeout: u64) -> std::result::Result<Multipart, RecvError> {
+        match self {
+            Listening::Sub(_, socket) => socket.recv_within(timeout).await,
+            Listening::Pull(_, socket) => socket.recv_within(timeout).await,
+        }
+    }
+}
+
+/// Sends the heartbeats over a ZeroMQ REQ socket, or receives the
+/// liveness messages of the target over a SUB or PULL socket.
+pub(crate) struct ZmqTransport {
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    context: Context,
+    #[cfg(feature = "zmq")]
+    listening: RefCell<Option<Listening>>,
+}
+
+impl ZmqTransport {
+    /// Creates a new `ZmqTransport` on the ZeroMQ `context`.
+    pub(crate) fn new(context: Context) -> Self {
+        ZmqTransport {
+            context,
+            #[cfg(feature = "zmq")]
+            listening: RefCell::new(None),
+        }
+    }
+
+    /// Sends the `request` over a REQ socket of its own, which closes
+    /// with the reply.  A late answer to an earlier heartbeat goes to
+    /// the socket that sent it, and is dropped with it.
+    #[cfg(feature = "zmq")]
+    async fn request(&self, endpoint: &str, request: Multipart, timeout: u64) -> Result<Reply> {
+        let sequence = protocol::sequence(&request);
+        let socket = SocketBuilder::new(self.context.clone())
+            .endpoint(endpoint)
+            .timeout(timeout)
+            .linger(false)
+            .req()
+            .connect()?;
+        let recv_sock = socket.send_multipart(request).await?;
+        match recv_sock.recv_multipart().await {
+            Ok((reply, _)) if sequence.is_some() && protocol::sequence(&reply) != sequence => {
+                Ok(Reply::Stale(protocol::sequence(&reply)))
+            }
+            Ok((reply, _)) => Ok(Reply::from_answer(&reply)),
+            Err(RecvError::Timeout) => Ok(Reply::Timeout),
+            Err(RecvError::Other(err)) => Err(err),
+        }
+    }
+
+    /// Receives the liveness messages of the target, opening the
+    /// socket on the `endpoint` first unless it is open there
+    /// already.
+    #[cfg(feature = "zmq")]
+    async fn receive(
+        &self,
+        endpoint: &str,
+        socket: PassiveSocket,
+        bind: bool,
+        timeout: u64,
+    ) -> Result<Reply> {
+        // NOTE: The socket leaves the cell while it waits, so that the
+        // cell isn't borrowed across the await.
+        let mut listening = match self.listening.take() {
+            Some(listening) if listening.endpoint() == endpoint => listening,
+            _ => {
+                let builder = SocketBuilder::new(self.context.clone())
+                    .endpoint(endpoint)
+                    .linger(false);
+                match (socket, bind) {
+                    (PassiveSocket::Sub, false) => {
+                        Listening::Sub(endpoint.to_owned(), builder.subscribe().connect()?)
+                    }
+                    (PassiveSocket::Sub, true) => {
+                        Listening::Sub(endpoint.to_owned(), builder.subscribe().bind()?)
+                    }
+                    (PassiveSocket::Pull, false) => {
+                        Listening::Pull(endpoint.to_owned(), builder.pull().connect()?)
+                    }
+                    (PassiveSocket::Pull, true) => {
+                        Listening::Pull(endpoint.to_owned(), builder.pull().bind()?)
+                    }
+                }
+            }
+        };
+        let mut latest = None;
+        let result = loop {
+            // NOTE: Only the first message is waited for.  The rest
+            // have arrived already, or belong to the next call.
+            match listening
+                .recv(if latest.is_some() { 0 } else { timeout })
+                .await
+            {
+                Ok(message) => latest = Some(message),
+                Err(RecvError::Timeout) => break Ok(()),
+                Err(RecvError::Other(err)) => break Err(err),
+            }
+        };
+        self.listening.replace(Some(listening));
+        result?;
+        Ok(match latest {
+            Some(message) => Reply::from_answer(&message),
+            None => Reply::Timeout,
+        })
+    }
+
+    /// Stands in for the passive heartbeat over ZeroMQ without the
+    /// `zmq` feature.
+    #[cfg(not(feature = "zmq"))]
+    async fn receive(
+        &self,
+        endpoint: &str,
+        _socket: PassiveSocket,
+        _bind: bool,
+        _timeout: u64,
+    ) -> Result<Reply> {
+        Err(config_format_error(&format!(
+            "passive heartbeat on {} needs the zmq feature",
+            endpoint
+        )))
+    }
+
+    /// Stands in for the heartbeat over ZeroMQ without the `zmq`
+    /// feature.
+    #[cfg(not(feature = "zmq"))]
+    async fn request(&self, endpoint: &str, _request: Multipart, _timeout: u64) -> Result<Reply> {
+        Err(config_format_error(&format!(
+            "heartbeat to {} needs the zmq feature; set {} instead",
+            endpoint,
+            key::PROBE_COMMAND
+        )))
+    }
+}
+
+impl Transport for ZmqTransport {
+    fn beat<'a>(
+        &'a self,
+        endpoint: &'a str,
+        request: Multipart,
+        timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>> {
+        Box::pin(self.request(endpoint, request, timeout))
+    }
+
+    fn listen<'a>(
+        &'a self,
+        endpoint: &'a str,
+        socket: PassiveSocket,
+        bind: bool,
+        timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>> {
+        Box::pin(self.receive(endpoint, socket, bind, timeout))
+    }
+}
+
+/// Answers the heartbeats from a script of replies instead of a
+/// socket.
+///
+/// Each heartbeat takes the next reply off the script, whatever the
+/// endpoint; an exhausted script times out.  Handing a
+/// `ChannelTransport` to [`Heartbeat`](crate::heartbeat::Heartbeat)
+/// replays a sequence of replies, such as a target that answers twice
+/// and then hangs, without a target or a timer.
+#[cfg(test)]
+pub(crate) struct ChannelTransport {
+    replies: RefCell<VecDeque<Reply>>,
+}
+
+#[cfg(test)]
+impl ChannelTransport {
+    /// Creates a new `ChannelTransport` that answers with the
+    /// `replies` in order.
+    pub(crate) fn new(replies: impl IntoIterator<Item = Reply>) -> Self {
+        ChannelTransport {
+            replies: RefCell::new(replies.into_iter().collect()),
+        }
+    }
+
+    /// Appends the `reply` to the script.
+    pub(crate) fn push(&self, reply: Reply) {
+        self.replies.borrow_mut().push_back(reply);
+    }
+
+    /// Returns the number of replies left in the script.
+    pub(crate) fn remaining(&self) -> usize {
+        self.replies.borrow().len()
+    }
+}
+
+#[cfg(test)]
+impl Transport for ChannelTransport {
+    fn beat<'a>(
+        &'a self,
+        _endpoint: &'a str,
+        _request: Multipart,
+        _timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>> {
+        Box::pin(async move {
+            // NOTE: The reply comes on the next turn of the runtime, as
+            // it would over a socket, so that the process starts before
+            // the heartbeat reads its incarnation.
+            tokio::task::yield_now().await;
+            Ok(self
+                .replies
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Reply::Timeout))
+        })
+    }
+
+    fn listen<'a>(
+        &'a self,
+        endpoint: &'a str,
+        _socket: PassiveSocket,
+        _bind: bool,
+        timeout: u64,
+    ) -> LocalBoxFuture<'a, Result<Reply>> {
+        self.beat(endpoint, protocol::heartbeat(), timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::{Callbacks, Notifier};
+    use crate::channel::event_queue;
+    use crate::config::{key, section, Config};
+    use crate::event::{EventHandler, EventType};
+    use crate::heartbeat::Heartbeat;
+    use crate::kw;
+    use crate::logger::{LocalLogger, Logger};
+    use crate::metrics::Metrics;
+    use crate::naming::naming_service;
+    use crate::process::{ProcessManager, RunProcess};
+    use crate::restart::RestartManager;
+    use crate::signal::SignalHandler;
+    use crate::state::{StateMachine, TargetState};
+    use crate::timeline::Timeline;
+    use proptest::prelude::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::{Builder, Runtime};
+
+    /// Numbers the files the tests plan the runs of the process in.
+    static PLANS: AtomicUsize = AtomicUsize::new(0);
+
+    /// How a run of the managed process ends.
+    #[derive(Clone, Debug)]
+    enum Ending {
+        /// The target misses the heartbeat with the reply, a timeout
+        /// or a stale answer.
+        Miss(Reply),
+        /// The target answers that it wants to be restarted.
+        RestartMe,
+        /// The process exits on its own with the code.
+        Exit(i32),
+    }
+
+    /// Generates a reply of the target other than a rejection, which
+    /// depends on the term of the lease.
+    fn reply() -> impl Strategy<Value = Reply> {
+        prop_oneof![
+            Just(Reply::Ready(vec![])),
+            Just(Reply::NotReady(vec![])),
+            Just(Reply::Degraded(vec![(
+                kw![reason],
+                "replica lost".to_owned()
+            )])),
+            Just(Reply::Busy(vec![])),
+            Just(Reply::RestartMe(vec![])),
+            Just(Reply::Timeout),
+            any::<Option<u64>>().prop_map(Reply::Stale),
+        ]
+    }
+
+    /// Generates the ending of a run of the managed process.
+    fn ending() -> impl Strategy<Value = Ending> {
+        prop_oneof![
+            Just(Ending::Miss(Reply::Timeout)),
+            any::<Option<u64>>().prop_map(|echo| Ending::Miss(Reply::Stale(echo))),
+            Just(Ending::RestartMe),
+            (1..4).prop_map(Ending::Exit),
+        ]
+    }
+
+    fn logger() -> Rc<dyn Logger> {
+        Rc::new(LocalLogger::new("heartbeat2-test"))
+    }
+
+    /// Returns the configuration of a target that runs the `command`,
+    /// and answers the heartbeats every second from the start.
+    fn config(command: &[String], retries: i64, max_retries: i64) -> Rc<Config> {
+        let mut config = Config::new();
+        let section = config.section_mut(section::HEARTBEAT);
+        section.set(key::TARGET_ID, kw![target]);
+        section.set_string(key::TARGET_ENDPOINT, "tcp://127.0.0.1:5555");
+        section.set_list(key::COMMAND, command);
+        section.set_string(key::WORKING_DIRECTORY, ".");
+        section.set_integer(key::HEARTBEAT_INTERVAL, 1);
+        section.set_integer(key::HEARTBEAT_TIMEOUT, 100);
+        section.set_integer(key::HEARTBEAT_RETRIES, retries);
+        section.set_integer(key::STARTUP_GRACE, 0);
+        section.set_integer(key::MAX_RETRIES, max_retries);
+        section.set_integer(key::RETRY_INTERVAL, 3600);
+        Rc::new(config)
+    }
+
+    /// Returns a runtime for a single test case.  A `paused` clock
+    /// advances as soon as every task waits, so that the heartbeats
+    /// take no time.
+    fn runtime(paused: bool) -> Runtime {
+        Builder::new_current_thread()
+            .enable_all()
+            .start_paused(paused)
+            .build()
+            .unwrap()
+    }
+
+    /// Replays the `replies` the way `Heartbeat` should, retrying a
+    /// missed heartbeat up to `retries` times.
+    ///
+    /// # Returns
+    ///
+    /// Returns whether the target asks for a restart rather than
+    /// misses a heartbeat in the end, the number of the replies taken
+    /// by then, and the state of the target.
+    fn expect(replies: &[Reply], retries: usize) -> (bool, usize, TargetState) {
+        let mut taken = 0;
+        let mut state = TargetState::Running;
+        loop {
+            let mut reply = Reply::Timeout;
+            for _ in 0..=retries {
+                reply = replies.get(taken).cloned().unwrap_or(Reply::Timeout);
+                taken = (taken + 1).min(replies.len());
+                if !matches!(reply, Reply::Timeout | Reply::Stale(_)) {
+                    break;
+                }
+            }
+            match reply {
+                Reply::RestartMe(_) => return (true, taken, state),
+                Reply::Timeout | Reply::Stale(_) => {
+                    return (false, taken, TargetState::Unresponsive)
+                }
+                Reply::NotReady(_) if state == TargetState::Running => {
+                    state = TargetState::Degraded
+                }
+                Reply::Ready(_) | Reply::Degraded(_) | Reply::Busy(_)
+                    if state == TargetState::Degraded =>
+                {
+                    state = TargetState::Running
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs `Heartbeat` against a target that gives the `replies`,
+    /// and checks the event it ends with, the replies it takes and
+    /// the state it leaves the target in against [`expect`].
+    async fn replay(replies: Vec<Reply>, retries: usize) -> crate::result::Result<()> {
+        let (restart, taken, expected) = expect(&replies, retries);
+        let logger = logger();
+        let config = config(&["true".to_owned()], retries.try_into()?, 3);
+        let metrics = Rc::new(Metrics::new(Rc::clone(&config), Rc::clone(&logger))?);
+        let timeline = Rc::new(Timeline::new());
+        let state = Rc::new(StateMachine::new(
+            metrics,
+            Rc::clone(&timeline),
+            Rc::clone(&logger),
+        ));
+        state.transition(TargetState::Running)?;
+        let (sender, mut receiver) = event_queue(1, Rc::clone(&logger));
+        let transport = Rc::new(ChannelTransport::new(replies.iter().cloned()));
+        let heartbeat = Heartbeat::new(
+            Context::new(),
+            sender,
+            Rc::clone(&state),
+            Rc::clone(&config),
+            naming_service(Context::new(), Rc::clone(&config), Rc::clone(&logger))?,
+            timeline,
+            logger,
+        )
+        .transport(transport.clone());
+        heartbeat.run().await?;
+        let event = receiver.try_recv()?;
+        if restart {
+            assert!(
+                matches!(event, EventType::RestartRequested(0)),
+                "{:?} after {:?}",
+                event,
+                replies
+            );
+        } else {
+            assert!(
+                matches!(event, EventType::Timeout(0)),
+                "{:?} after {:?}",
+                event,
+                replies
+            );
+        }
+        assert_eq!(transport.remaining(), replies.len() - taken);
+        assert_eq!(state.get(), expected);
+        Ok(())
+    }
+
+    /// Supervises a process through runs that end with the `endings`
+    /// the way `Heartbeat2` does, and checks that each run ends as
+    /// planned, and that `RestartManager` gives up at the abort that
+    /// reaches `max_retries`.  The process reads from a file how to
+    /// end each run: it either exits with a code at once, or hangs
+    /// until the heartbeat ends it.
+    async fn supervise(endings: Vec<Ending>, max_retries: i64) -> crate::result::Result<()> {
+        let plan: PathBuf = std::env::temp_dir().join(format!(
+            "heartbeat2-plan-{}-{}",
+            std::process::id(),
+            PLANS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let command = [
+            "sh".to_owned(),
+            "-c".to_owned(),
+            format!(
+                "code=$(cat '{}'); [ \"$code\" = hang ] && exec sleep 60; exit \"$code\"",
+                plan.display()
+            ),
+        ];
+        let logger = logger();
+        let config = config(&command, 0, max_retries);
+        let metrics = Rc::new(Metrics::new(Rc::clone(&config), Rc::clone(&logger))?);
+        let timeline = Rc::new(Timeline::new());
+        let state = Rc::new(StateMachine::new(
+            Rc::clone(&metrics),
+            Rc::clone(&timeline),
+            Rc::clone(&logger),
+        ));
+        let (sender, receiver) = event_queue(8, Rc::clone(&logger));
+        let process_manager = Rc::new(ProcessManager::new(
+            sender.named("ProcessManager"),
+            Rc::clone(&state),
+            Rc::clone(&config),
+            Rc::clone(&metrics),
+            Rc::clone(&timeline),
+            Rc::new(Notifier::new(Rc::new(Callbacks::new()))),
+            Rc::clone(&logger),
+        ));
+        let transport = Rc::new(ChannelTransport::new([]));
+        let heartbeat = Rc::new(
+            Heartbeat::new(
+                Context::new(),
+                sender.named("Heartbeat"),
+                Rc::clone(&state),
+                Rc::clone(&config),
+                naming_service(Context::new(), Rc::clone(&config), Rc::clone(&logger))?,
+                timeline,
+                Rc::clone(&logger),
+            )
+            .process_manager(Rc::clone(&process_manager))
+            .transport(transport.clone()),
+        );
+        let mut event_handler = EventHandler::new(
+            receiver,
+            Rc::clone(&state),
+            Rc::clone(&process_manager),
+            Rc::clone(&heartbeat),
+            Rc::new(SignalHandler::new(
+                sender.named("SignalHandler"),
+                Rc::clone(&config),
+                Rc::clone(&logger),
+            )),
+            Rc::clone(&config),
+            Rc::clone(&logger),
+        );
+        let mut restart_manager = RestartManager::new(Rc::clone(&config), logger)?.metrics(metrics);
+        let mut aborts = 0;
+        let mut result = Ok(());
+        for (run, ending) in endings.iter().enumerate() {
+            // NOTE: The target that exits answers the first heartbeat,
+            // and exits long before the second.
+            let (code, reply) = match ending {
+                Ending::Miss(reply) => ("hang".to_owned(), reply.clone()),
+                Ending::RestartMe => ("hang".to_owned(), Reply::RestartMe(vec![])),
+                Ending::Exit(code) => (code.to_string(), Reply::Ready(vec![])),
+            };
+            fs::write(&plan, code)?;
+            transport.push(reply);
+            let (_, outcome, _) = match tokio::try_join!(
+                heartbeat.run(),
+                process_manager.run_process(),
+                event_handler.run(),
+            ) {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+            assert_eq!(transport.remaining(), 0, "run {} of {:?}", run, endings);
+            match (ending, outcome) {
+                (Ending::RestartMe, RunProcess::Restart(_)) => {}
+                (Ending::Miss(_) | Ending::Exit(_), RunProcess::Abort(_)) => {
+                    aborts += 1;
+                    restart_manager.add_process_abort()?;
+                    let restart = restart_manager.should_process_restart()?;
+                    assert_eq!(
+                        restart,
+                        aborts < max_retries,
+                        "run {} of {:?}",
+                        run,
+                        endings
+                    );
+                    if !restart {
+                        process_manager.give_up()?;
+                        assert_eq!(state.get(), TargetState::Terminated);
+                        break;
+                    }
+                }
+                _ => panic!("run {} of {:?} ended otherwise", run, endings),
+            }
+            process_manager.reset()?;
+            heartbeat.reset();
+            event_handler.reset();
+        }
+        let _ = fs::remove_file(&plan);
+        result
+    }
+
+    proptest! {
+        #[test]
+        fn heartbeat_ends_at_the_first_miss_or_restart_request(
+            replies in prop::collection::vec(reply(), 0..16),
+            retries in 0..3usize,
+        ) {
+            runtime(true).block_on(replay(replies, retries)).unwrap();
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn supervision_restarts_until_max_retries(
+            endings in prop::collection::vec(ending(), 1..6),
+            max_retries in 1..4i64,
+        ) {
+            runtime(false).block_on(supervise(endings, max_retries)).unwrap();
+        }
+    }
+}
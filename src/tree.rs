@@ -0,0 +1,98 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::fs;
+
+/// A snapshot of the descendants of a process, taken out of /proc.
+///
+/// A descendant that starts a session of its own escapes the signals
+/// to the process group of the managed process.  Once the managed
+/// process exits, its orphans move to another parent, so they can no
+/// longer be told apart.  `ProcessTree` therefore takes the snapshot
+/// before the kill, and [`sweep`](Self::sweep) kills the descendants
+/// that outlive the managed process.  Each descendant is identified
+/// by its PID and its start time, so that a PID the OS has reused for
+/// another process is left alone.
+#[derive(Default)]
+pub(crate) struct ProcessTree {
+    descendants: Vec<(u32, u64)>,
+}
+
+impl ProcessTree {
+    /// Takes a snapshot of the descendants of the process `root`.  A
+    /// process that exits during the snapshot is left out.
+    pub(crate) fn capture(root: u32) -> Self {
+        let mut children: HashMap<u32, Vec<(u32, u64)>> = HashMap::new();
+        if let Ok(entries) = fs::read_dir("/proc") {
+            for entry in entries.flatten() {
+                let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+                    Ok(pid) => pid,
+                    Err(_) => continue,
+                };
+                if let Some((ppid, started)) = parent_and_start(pid) {
+                    children.entry(ppid).or_default().push((pid, started));
+                }
+            }
+        }
+        let mut descendants = vec![];
+        let mut pending = vec![root];
+        while let Some(parent) = pending.pop() {
+            for &(pid, started) in children.get(&parent).into_iter().flatten() {
+                descendants.push((pid, started));
+                pending.push(pid);
+            }
+        }
+        ProcessTree { descendants }
+    }
+
+    /// Kills the descendants in the snapshot that are still running.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of the processes killed.
+    pub(crate) fn sweep(&self) -> usize {
+        self.descendants
+            .iter()
+            .filter(|&&(pid, started)| {
+                parent_and_start(pid).is_some_and(|(_, now)| now == started)
+                    && i32::try_from(pid)
+                        .is_ok_and(|pid| kill(Pid::from_raw(pid), Signal::SIGKILL).is_ok())
+            })
+            .count()
+    }
+}
+
+/// Reads the parent and the start time of the process `pid` out of
+/// /proc/<pid>/stat, unless it is a zombie.  The command name in
+/// parentheses may contain spaces, so the fields are counted from the
+/// last closing parenthesis.
+fn parent_and_start(pid: u32) -> Option<(u32, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    // NOTE: The state is the third field of the stat, the parent the
+    // fourth and the start time the twenty-second.
+    if *fields.first()? == "Z" {
+        return None;
+    }
+    let ppid = fields.get(1)?.parse().ok()?;
+    let started = fields.get(19)?.parse().ok()?;
+    Some((ppid, started))
+}
@@ -0,0 +1,209 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::config::{key, section, Config};
+use crate::error::config_format_error;
+use crate::expression::Expression;
+use crate::keyword::Keyword;
+use crate::logger::{message, Logger};
+use crate::metrics::Metrics;
+use crate::result::Result;
+use nix::sys::resource::{getrusage, UsageWho};
+use nix::sys::time::TimeValLike;
+use std::cell::Cell;
+use std::fs;
+use std::rc::Rc;
+use tokio::time::{sleep, Duration};
+
+/// The default time in seconds between the samples of the resource
+/// usage.
+static DEFAULT_USAGE_INTERVAL: i64 = 60;
+
+/// The default factor over the first sample that the resident memory
+/// or the open file descriptors may grow by before `UsageMonitor`
+/// warns.
+static DEFAULT_USAGE_GROWTH_LIMIT: i64 = 2;
+
+/// The resource usage of `Heartbeat2` itself.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Usage {
+    /// The resident memory in bytes.
+    pub(crate) rss_bytes: u64,
+    /// The CPU time in the user and the kernel modes in milliseconds.
+    pub(crate) cpu_millis: u64,
+    /// The number of open file descriptors.
+    pub(crate) open_fds: u64,
+}
+
+impl Usage {
+    /// Samples the resource usage of `Heartbeat2` out of /proc/self and
+    /// `getrusage(2)`.
+    pub(crate) fn sample() -> Result<Self> {
+        let status = fs::read_to_string("/proc/self/status")?;
+        let rss_kilobytes = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .unwrap_or_default();
+        let rusage = getrusage(UsageWho::RUSAGE_SELF)?;
+        let cpu_millis = rusage.user_time().num_milliseconds() as u64
+            + rusage.system_time().num_milliseconds() as u64;
+        let open_fds = fs::read_dir("/proc/self/fd")?.count() as u64;
+        Ok(Usage {
+            rss_bytes: rss_kilobytes * 1024,
+            cpu_millis,
+            open_fds,
+        })
+    }
+
+    /// Returns the usage as a property list, such as `(:rss-bytes
+    /// 4194304 :cpu-ms 120 :open-fds 14)`.
+    pub(crate) fn to_expression(self) -> Expression {
+        Expression::plist(vec![
+            (
+                Keyword::new("RSS-BYTES"),
+                Expression::from(self.rss_bytes as i64),
+            ),
+            (
+                Keyword::new("CPU-MS"),
+                Expression::from(self.cpu_millis as i64),
+            ),
+            (
+                Keyword::new("OPEN-FDS"),
+                Expression::from(self.open_fds as i64),
+            ),
+        ])
+    }
+}
+
+/// Watches the resource usage of `Heartbeat2` itself.
+///
+/// A supervisor that runs for months and leaks memory or file
+/// descriptors is as bad as a target that does.  `UsageMonitor`
+/// samples the [`Usage`] of `Heartbeat2` every USAGE-INTERVAL seconds,
+/// and publishes it with [`Metrics`].  It takes the first sample as
+/// the baseline, and warns when the resident memory or the open file
+/// descriptors grow past USAGE-GROWTH-LIMIT times the baseline.  Each
+/// warning raises the bar by the same factor, so that a steady leak
+/// keeps warning without flooding the log.
+///
+/// # Configuration
+///
+/// * USAGE-INTERVAL: Optional.  The time in seconds between the
+///   samples.  The default is 60.
+/// * USAGE-GROWTH-LIMIT: Optional.  The factor the usage may grow by
+///   over the baseline before `UsageMonitor` warns.  The default is
+///   2.
+pub(crate) struct UsageMonitor {
+    metrics: Rc<Metrics>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+    rss_limit: Cell<u64>,
+    fds_limit: Cell<u64>,
+}
+
+impl UsageMonitor {
+    /// Creates a new `UsageMonitor`.
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics` - A shared reference to the metrics.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(metrics: Rc<Metrics>, config: Rc<Config>, logger: Rc<dyn Logger>) -> Self {
+        UsageMonitor {
+            metrics,
+            config,
+            logger,
+            rss_limit: Cell::new(0),
+            fds_limit: Cell::new(0),
+        }
+    }
+
+    /// Samples the usage until `Heartbeat2` exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is malformed.  A failure
+    /// to sample the usage only gets logged.
+    pub(crate) async fn run(&self) -> Result<()> {
+        let section = self.config.section(section::HEARTBEAT)?;
+        let interval = Duration::from_secs(
+            section
+                .integer_or(key::USAGE_INTERVAL, DEFAULT_USAGE_INTERVAL)?
+                .try_into()?,
+        );
+        let factor: u64 = section
+            .integer_or(key::USAGE_GROWTH_LIMIT, DEFAULT_USAGE_GROWTH_LIMIT)?
+            .try_into()?;
+        if factor < 1 {
+            return Err(config_format_error(&format!(
+                "{} must be at least 1",
+                key::USAGE_GROWTH_LIMIT
+            )));
+        }
+        loop {
+            match Usage::sample() {
+                Ok(usage) => self.check(usage, factor),
+                Err(err) => self.logger.log_coded(
+                    &message::US001,
+                    &format!("unable to sample resource usage: {}", err),
+                ),
+            }
+            sleep(interval).await;
+        }
+    }
+
+    /// Publishes the `usage`, and warns if it has grown past the
+    /// limits.
+    fn check(&self, usage: Usage, factor: u64) {
+        self.metrics.set_usage(&usage);
+        if self.rss_limit.get() == 0 {
+            self.rss_limit.set(usage.rss_bytes * factor);
+            self.fds_limit.set(usage.open_fds * factor);
+            return;
+        }
+        if usage.rss_bytes > self.rss_limit.get() {
+            self.logger.log_coded(
+                &message::US002,
+                &format!(
+                    "heartbeat2 resident memory grew to {} bytes; possible leak",
+                    usage.rss_bytes
+                ),
+            );
+            self.rss_limit.set(usage.rss_bytes * factor);
+        }
+        if usage.open_fds > self.fds_limit.get() {
+            self.logger.log_coded(
+                &message::US003,
+                &format!(
+                    "heartbeat2 open file descriptors grew to {}; possible leak",
+                    usage.open_fds
+                ),
+            );
+            self.fds_limit.set(usage.open_fds * factor);
+        }
+    }
+}
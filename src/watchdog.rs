@@ -0,0 +1,159 @@
+/*
+ * Heartbeat2: Monitors & restarts software on crashes or deadlocks.
+ * Copyright (C) 2022-2023  Hee Shin
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::channel::EventSender;
+use crate::config::{key, section, Config};
+use crate::event::EventType;
+use crate::logger::{message, Logger};
+use crate::result::Result;
+use crate::state::{StateMachine, TargetState};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
+
+/// The number of times `Watchdog` pings `Heartbeat2` in a
+/// WATCHDOG-TIMEOUT.
+static PINGS_PER_TIMEOUT: u32 = 4;
+
+/// Watches `Heartbeat2` itself for a wedged event loop.
+///
+/// `Heartbeat2` restarts the managed process when it stops answering
+/// heartbeats.  But nothing restarts `Heartbeat2` when its own event
+/// loop stops making progress, such as after a logic error leaves a
+/// task waiting forever.  `Watchdog` pings
+/// [`EventHandler`](crate::event::EventHandler) through the event
+/// queue while the managed process is running, and records the time
+/// of each answer.  A thread of its own checks that the answers keep
+/// coming.  If there is no answer for WATCHDOG-TIMEOUT seconds,
+/// `Watchdog` logs a fatal error and aborts `Heartbeat2`, so that an
+/// outer supervisor, such as systemd, can restart it.
+///
+/// # Configuration
+///
+/// * WATCHDOG-TIMEOUT: Optional.  The time in seconds `Heartbeat2`
+///   may go without making progress.  `Watchdog` is disabled without
+///   it.
+pub(crate) struct Watchdog {
+    started: Instant,
+    last_beat: Arc<AtomicU64>,
+    event_sender: EventSender,
+    state: Rc<StateMachine>,
+    config: Rc<Config>,
+    logger: Rc<dyn Logger>,
+}
+
+impl Watchdog {
+    /// Creates a new `Watchdog`.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_sender` - The channel to ping `EventHandler` through.
+    /// * `state` - A shared reference to the target state machine.
+    /// * `config` - A shared reference to the configuration.
+    /// * `logger` - A shared reference to the logger.
+    pub(crate) fn new(
+        event_sender: EventSender,
+        state: Rc<StateMachine>,
+        config: Rc<Config>,
+        logger: Rc<dyn Logger>,
+    ) -> Self {
+        Watchdog {
+            started: Instant::now(),
+            last_beat: Arc::new(AtomicU64::new(0)),
+            event_sender,
+            state,
+            config,
+            logger,
+        }
+    }
+
+    /// Returns whether the `Watchdog` should run.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config
+            .section(section::HEARTBEAT)
+            .is_ok_and(|section| section.has_key(key::WATCHDOG_TIMEOUT))
+    }
+
+    /// Starts the checking thread, and pings `EventHandler` until
+    /// `Heartbeat2` exits.
+    pub(crate) async fn run(&self) -> Result<()> {
+        let bound = Duration::from_secs(
+            self.config
+                .section(section::HEARTBEAT)?
+                .integer(key::WATCHDOG_TIMEOUT)?
+                .try_into()?,
+        );
+        self.logger.log_coded(
+            &message::WD001,
+            &format!("start watchdog with a timeout of {}s", bound.as_secs()),
+        );
+        self.beat();
+        self.spawn_checker(bound)?;
+        loop {
+            sleep(bound / PINGS_PER_TIMEOUT).await;
+            // NOTE: EventHandler only runs while the process runs.
+            if matches!(
+                self.state.get(),
+                TargetState::Running | TargetState::Degraded | TargetState::Unresponsive
+            ) {
+                let (pong, answer) = oneshot::channel();
+                self.event_sender.send(EventType::Ping(pong)).await?;
+                // EventHandler drops the ping when it resets, which
+                // counts as progress, too.
+                let _ = answer.await;
+            }
+            self.beat();
+        }
+    }
+
+    /// Records the progress of `Heartbeat2`.
+    fn beat(&self) {
+        self.last_beat
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Starts the thread that aborts `Heartbeat2` if it goes without
+    /// progress for longer than `bound`.
+    fn spawn_checker(&self, bound: Duration) -> Result<()> {
+        let started = self.started;
+        let last_beat = Arc::clone(&self.last_beat);
+        let logger = self.logger.local();
+        thread::Builder::new()
+            .name("watchdog".to_owned())
+            .spawn(move || loop {
+                thread::sleep(bound / PINGS_PER_TIMEOUT);
+                let last_beat = Duration::from_millis(last_beat.load(Ordering::Relaxed));
+                let silence = started.elapsed().saturating_sub(last_beat);
+                if silence > bound {
+                    logger.log_coded(
+                        &message::WD002,
+                        &format!(
+                            "no progress in heartbeat2 for {}s; aborting",
+                            silence.as_secs()
+                        ),
+                    );
+                    std::process::abort();
+                }
+            })?;
+        Ok(())
+    }
+}